@@ -0,0 +1,49 @@
+-- Authorization tables for the sql backend, compatible with the FreeRADIUS schema.
+--
+-- radcheck / radgroupcheck: credentials and check items of users and groups
+-- radreply / radgroupreply: reply attributes of users and groups
+-- radusergroup: group membership, lowest priority first
+
+CREATE TABLE IF NOT EXISTS radcheck (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '==',
+    value VARCHAR(253) NOT NULL DEFAULT '',
+    KEY radcheck_username (username)
+);
+
+CREATE TABLE IF NOT EXISTS radreply (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '=',
+    value VARCHAR(253) NOT NULL DEFAULT '',
+    KEY radreply_username (username)
+);
+
+CREATE TABLE IF NOT EXISTS radgroupcheck (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    groupname VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '==',
+    value VARCHAR(253) NOT NULL DEFAULT '',
+    KEY radgroupcheck_groupname (groupname)
+);
+
+CREATE TABLE IF NOT EXISTS radgroupreply (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    groupname VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '=',
+    value VARCHAR(253) NOT NULL DEFAULT '',
+    KEY radgroupreply_groupname (groupname)
+);
+
+CREATE TABLE IF NOT EXISTS radusergroup (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    username VARCHAR(64) NOT NULL DEFAULT '',
+    groupname VARCHAR(64) NOT NULL DEFAULT '',
+    priority INTEGER NOT NULL DEFAULT 1,
+    KEY radusergroup_username (username)
+);
@@ -0,0 +1,49 @@
+-- Authorization tables for the sql backend, compatible with the FreeRADIUS schema.
+--
+-- radcheck / radgroupcheck: credentials and check items of users and groups
+-- radreply / radgroupreply: reply attributes of users and groups
+-- radusergroup: group membership, lowest priority first
+
+CREATE TABLE IF NOT EXISTS radcheck (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '==',
+    value VARCHAR(253) NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS radcheck_username ON radcheck (username);
+
+CREATE TABLE IF NOT EXISTS radreply (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '=',
+    value VARCHAR(253) NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS radreply_username ON radreply (username);
+
+CREATE TABLE IF NOT EXISTS radgroupcheck (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    groupname VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '==',
+    value VARCHAR(253) NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS radgroupcheck_groupname ON radgroupcheck (groupname);
+
+CREATE TABLE IF NOT EXISTS radgroupreply (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    groupname VARCHAR(64) NOT NULL DEFAULT '',
+    attribute VARCHAR(64) NOT NULL DEFAULT '',
+    op VARCHAR(2) NOT NULL DEFAULT '=',
+    value VARCHAR(253) NOT NULL DEFAULT ''
+);
+CREATE INDEX IF NOT EXISTS radgroupreply_groupname ON radgroupreply (groupname);
+
+CREATE TABLE IF NOT EXISTS radusergroup (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username VARCHAR(64) NOT NULL DEFAULT '',
+    groupname VARCHAR(64) NOT NULL DEFAULT '',
+    priority INTEGER NOT NULL DEFAULT 1
+);
+CREATE INDEX IF NOT EXISTS radusergroup_username ON radusergroup (username);
@@ -0,0 +1,184 @@
+// admin.rs - Admin HTTP API for rust-radius
+//
+// This module serves a small JSON API for managing the MAC registry. Changes
+// are written to the registry file; the MAB backend picks them up through the
+// file watcher, just like edits made by hand or from the CLI.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use axum::extract::{Path, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, put};
+use axum::{Json, Router};
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+
+use crate::config::AdminConfig;
+use crate::mac::MacAddr;
+use crate::mac_registry::{DeviceEntry, MacPattern, MacRegistry, Oui, VendorRule};
+use crate::store::JsonFile;
+use crate::Result;
+
+/// Shared state of the admin API
+struct AdminState {
+    /// Bearer token required on every request
+    token: String,
+    
+    /// MAC registry file
+    macs_file: Option<PathBuf>,
+    
+    /// Serializes read-modify-write cycles on the registry file
+    lock: Mutex<()>,
+}
+
+/// Error response of the admin API
+struct ApiError(StatusCode, String);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(serde_json::json!({ "error": self.1 }))).into_response()
+    }
+}
+
+type ApiResult<T> = std::result::Result<T, ApiError>;
+
+/// Build the admin API router
+///
+/// # Arguments
+///
+/// * `config` - Admin API configuration
+pub fn router(config: &AdminConfig) -> Router {
+    let state = Arc::new(AdminState {
+        token: config.token.clone(),
+        macs_file: config.macs_file.clone(),
+        lock: Mutex::new(()),
+    });
+    
+    Router::new()
+        .route("/api/macs", get(list_macs))
+        .route("/api/macs/devices/:mac", put(put_device).delete(delete_device))
+        .route("/api/macs/vendors/:oui", put(put_vendor).delete(delete_vendor))
+        .route("/api/macs/deny/:pattern", put(put_deny).delete(delete_deny))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state)
+}
+
+/// Serve the admin API until the process exits
+///
+/// # Arguments
+///
+/// * `config` - Admin API configuration
+///
+/// # Errors
+///
+/// Returns an error if the listener cannot be bound
+pub async fn serve(config: AdminConfig) -> Result<()> {
+    let addr = format!("{}:{}", config.host, config.port);
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    tracing::info!(addr = addr, "Admin API listening");
+    
+    axum::serve(listener, router(&config)).await?;
+    Ok(())
+}
+
+/// Reject requests without the configured bearer token
+async fn require_token(State(state): State<Arc<AdminState>>, request: Request, next: Next) -> Response {
+    let token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    
+    match token {
+        Some(token) if bool::from(token.as_bytes().ct_eq(state.token.as_bytes())) => next.run(request).await,
+        _ => ApiError(StatusCode::UNAUTHORIZED, "Missing or invalid token".to_string()).into_response(),
+    }
+}
+
+/// Parse a path segment
+fn parse<T: std::str::FromStr<Err = String>>(value: &str) -> ApiResult<T> {
+    value.parse().map_err(|e| ApiError(StatusCode::BAD_REQUEST, e))
+}
+
+/// Load the registry, apply a change and save it
+///
+/// The change returns whether it found something to change; nothing is
+/// written otherwise and the request gets a 404.
+async fn update<F>(state: &AdminState, change: F) -> ApiResult<StatusCode>
+where
+    F: FnOnce(&mut MacRegistry) -> bool,
+{
+    let path = state.macs_file.as_ref()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "No MAC registry file is configured".to_string()))?;
+    let internal = |e: Box<dyn std::error::Error + Send + Sync>| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+    
+    let _guard = state.lock.lock().await;
+    let mut registry = MacRegistry::load_or_default(path).map_err(internal)?;
+    
+    if !change(&mut registry) {
+        return Err(ApiError(StatusCode::NOT_FOUND, "No such entry".to_string()));
+    }
+    
+    // An invalid entry is the client's fault, a failed write is ours
+    registry.validate().map_err(|e| ApiError(StatusCode::BAD_REQUEST, e.to_string()))?;
+    registry.save(path).map_err(internal)?;
+    
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// `GET /api/macs` - the whole registry
+async fn list_macs(State(state): State<Arc<AdminState>>) -> ApiResult<Json<MacRegistry>> {
+    let path = state.macs_file.as_ref()
+        .ok_or_else(|| ApiError(StatusCode::NOT_FOUND, "No MAC registry file is configured".to_string()))?;
+    
+    MacRegistry::load_or_default(path)
+        .map(Json)
+        .map_err(|e| ApiError(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// `PUT /api/macs/devices/:mac` - register or replace a device
+async fn put_device(State(state): State<Arc<AdminState>>, Path(mac): Path<String>, Json(device): Json<DeviceEntry>) -> ApiResult<StatusCode> {
+    let mac: MacAddr = parse(&mac)?;
+    update(&state, |registry| {
+        registry.devices.insert(mac, device);
+        true
+    }).await
+}
+
+/// `DELETE /api/macs/devices/:mac` - forget a device
+async fn delete_device(State(state): State<Arc<AdminState>>, Path(mac): Path<String>) -> ApiResult<StatusCode> {
+    let mac: MacAddr = parse(&mac)?;
+    update(&state, |registry| registry.devices.remove(&mac).is_some()).await
+}
+
+/// `PUT /api/macs/vendors/:oui` - add or replace a vendor rule
+async fn put_vendor(State(state): State<Arc<AdminState>>, Path(oui): Path<String>, Json(rule): Json<VendorRule>) -> ApiResult<StatusCode> {
+    let oui: Oui = parse(&oui)?;
+    update(&state, |registry| {
+        registry.vendors.insert(oui, rule);
+        true
+    }).await
+}
+
+/// `DELETE /api/macs/vendors/:oui` - remove a vendor rule
+async fn delete_vendor(State(state): State<Arc<AdminState>>, Path(oui): Path<String>) -> ApiResult<StatusCode> {
+    let oui: Oui = parse(&oui)?;
+    update(&state, |registry| registry.vendors.remove(&oui).is_some()).await
+}
+
+/// `PUT /api/macs/deny/:pattern` - deny a device or vendor
+async fn put_deny(State(state): State<Arc<AdminState>>, Path(pattern): Path<String>) -> ApiResult<StatusCode> {
+    let pattern: MacPattern = parse(&pattern)?;
+    update(&state, |registry| {
+        registry.deny.insert(pattern);
+        true
+    }).await
+}
+
+/// `DELETE /api/macs/deny/:pattern` - lift a denial
+async fn delete_deny(State(state): State<Arc<AdminState>>, Path(pattern): Path<String>) -> ApiResult<StatusCode> {
+    let pattern: MacPattern = parse(&pattern)?;
+    update(&state, |registry| registry.deny.remove(&pattern)).await
+}
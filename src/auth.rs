@@ -5,13 +5,41 @@
 // "Modern Public WiFi Features" goals.
 
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_trait::async_trait;
-use tokio::sync::RwLock;
+use chrono::{Local, Utc};
+use tokio::sync::{Mutex, RwLock};
 
-use crate::config::{Config, AuthBackendConfig};
-use crate::protocol::{Packet, Attribute};
+use crate::authorize::Authorizer;
+use crate::config::{Config, AuthBackendConfig, CaptivePortalConfig, ChainPolicy, ErrorPolicy, NotFoundPolicy, RejectPolicy};
+use crate::conversation::{Conversations, Resumption};
+#[cfg(feature = "ldap-auth")]
+use crate::ldap::{LdapDirectory, LdapOutcome};
+use crate::lockout::{Keys, LockoutGuard};
+use crate::eap::{self, CertificateIdentity, EapKeys, EapOutcome, EapServer, InnerAuthenticator};
+use crate::mac::MacAddr;
+#[cfg(feature = "oauth-auth")]
+use crate::oauth::{OAuthOutcome, OAuthProvider};
+use crate::mac_registry::{DeviceEntry, Lookup, MacRegistry};
+use crate::mfa::MfaStage;
+use crate::mschap::{self, MsChapResult};
+use crate::password::{Credential, Scheme};
+use crate::quotas::QuotaEnforcer;
+use crate::sessions::{SessionLimiter, SessionTable};
+use crate::store::{self, JsonFile};
+use crate::users::{self, LocalUser, UsersFormat};
+use crate::vouchers::VoucherStore;
+use crate::redirect::GuestRedirect;
+#[cfg(feature = "rest-auth")]
+use crate::rest::RestClient;
+#[cfg(feature = "sql-auth")]
+use crate::sql::SqlStore;
+use crate::protocol::{self as radius, Packet, Attribute, VENDOR_MICROSOFT};
+#[cfg(feature = "wasm-plugins")]
+use crate::plugins::BackendPlugin;
 use crate::Result;
 
 /// Authentication result
@@ -21,6 +49,10 @@ pub enum AuthResult {
     Accept {
         /// Optional attributes to include in the response
         attributes: Vec<Attribute>,
+        
+        /// Groups of the user that only the login knows, such as those in a
+        /// token's claims; after authorization, all of the user's groups
+        groups: Vec<String>,
     },
     
     /// Authentication failed
@@ -32,6 +64,9 @@ pub enum AuthResult {
         attributes: Vec<Attribute>,
     },
     
+    /// The backend does not know the user, so the next backend may
+    NotFound,
+    
     /// Authentication requires additional information (challenge)
     Challenge {
         /// Challenge message
@@ -65,6 +100,11 @@ pub trait AuthBackend: Send + Sync {
     
     /// Authenticate a request
     /// 
+    /// A backend that needs another round answers with a Challenge. Its state
+    /// is kept server-side and the client gets a random State instead. When the
+    /// client answers, the request is passed to the same backend with its State
+    /// replaced by the backend's state.
+    /// 
     /// # Arguments
     /// 
     /// * `request` - RADIUS request packet
@@ -74,10 +114,155 @@ pub trait AuthBackend: Send + Sync {
     /// Authentication result
     async fn authenticate(&self, request: &Packet) -> Result<AuthResult>;
     
-    /// Get the backend's priority (lower is higher priority)
+    /// Get the backend's default position in the chain (lower runs first)
+    /// 
+    /// The `order` option of the backend's configuration takes precedence.
     fn priority(&self) -> u32 {
         100
     }
+    
+    /// Authorize a peer that authenticated with a client certificate (EAP-TLS)
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - RADIUS request packet
+    /// * `certificate` - Identity from the peer's certificate
+    /// 
+    /// # Returns
+    /// 
+    /// None if the backend has no opinion about the certificate, otherwise
+    /// an Accept (with reply attributes) or a Reject
+    async fn authorize_certificate(&self, _request: &Packet, _certificate: &CertificateIdentity) -> Result<Option<AuthResult>> {
+        Ok(None)
+    }
+    
+    /// Groups a user belongs to, according to this backend
+    /// 
+    /// Asked for every accepted user, whichever backend checked the credential,
+    /// so group profiles apply the same way to all of them.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request that was accepted
+    /// * `username` - Authenticated user
+    async fn groups(&self, _request: &Packet, _username: &str) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+    
+    /// Record an Accounting-Request, for backends that keep usage
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Accounting-Request received
+    async fn account(&self, _request: &Packet) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Reload the backend's data (users file, MAC list, ...)
+    /// 
+    /// The new data must replace the old in one step; if loading fails the
+    /// previous data stays in place and the error is returned.
+    async fn reload(&self) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Files whose changes should trigger a `reload`
+    fn watched_files(&self) -> Vec<PathBuf> {
+        Vec::new()
+    }
+}
+
+/// Conversation owner of the one-time code stage
+const MFA_OWNER: &str = "mfa";
+
+/// Build a Reject without reply attributes
+fn reject(reason: &str) -> AuthResult {
+    AuthResult::Reject {
+        reason: reason.to_string(),
+        attributes: vec![],
+    }
+}
+
+/// Verify a CHAP-MD5 response (RFC 1994, RFC 2865 section 2.2)
+///
+/// The challenge is CHAP-Challenge if present, otherwise the Request Authenticator.
+///
+/// # Returns
+///
+/// None if the request carries no CHAP-Password, otherwise whether the response matches
+fn verify_chap(request: &Packet, password: &str) -> Option<bool> {
+    use md5::{Digest, Md5};
+    
+    let response = match request.get_attribute("CHAP-Password") {
+        Some(Attribute::Binary(_, value)) => value,
+        _ => return None,
+    };
+    
+    // CHAP Ident(1) followed by the 16-byte response
+    if response.len() != 17 {
+        return Some(false);
+    }
+    
+    let challenge = match request.get_attribute("CHAP-Challenge") {
+        Some(Attribute::Binary(_, challenge)) => challenge.as_slice(),
+        _ => request.authenticator().as_slice(),
+    };
+    
+    let mut hasher = Md5::new();
+    hasher.update([response[0]]);
+    hasher.update(password.as_bytes());
+    hasher.update(challenge);
+    
+    Some(mschap::constant_time_eq(&hasher.finalize(), &response[1..]))
+}
+
+/// Verify the password, CHAP or MS-CHAP response of a request against a stored credential
+///
+/// # Returns
+///
+/// The MS-CHAP reply attributes if the credential matches, otherwise the reason to reject
+async fn verify_credential(request: &Packet, username: &str, credential: &Credential) -> Result<std::result::Result<Vec<Attribute>, String>> {
+    // PAP; Argon2 and bcrypt are deliberately slow, so keep them off the runtime threads
+    if let Some(Attribute::String(_, password)) = request.get_attribute("User-Password") {
+        let valid = {
+            let (password, credential) = (password.clone(), credential.clone());
+            tokio::task::spawn_blocking(move || credential.verify_password(&password)).await?
+        };
+        
+        return Ok(if valid { Ok(Vec::new()) } else { Err("Invalid password".to_string()) });
+    }
+    
+    // CHAP-MD5 needs the cleartext password
+    if request.get_attribute("CHAP-Password").is_some() {
+        let valid = credential.cleartext()
+            .and_then(|password| verify_chap(request, password));
+        
+        return Ok(match valid {
+            Some(true) => Ok(Vec::new()),
+            Some(false) => Err("Invalid CHAP response".to_string()),
+            None => Err("CHAP requires a cleartext password".to_string()),
+        });
+    }
+    
+    // MS-CHAPv2 (also used by PEAP and EAP-TTLS inner authentication), then MS-CHAPv1
+    if !mschap::is_present(request) {
+        return Ok(Err("Missing or invalid password".to_string()));
+    }
+    
+    let password_hash = match credential.nt_hash() {
+        Some(hash) => hash,
+        None => return Ok(Err("MS-CHAP requires a cleartext or NT-hash credential".to_string())),
+    };
+    
+    Ok(match mschap::verify_v2_request(request, username, &password_hash) {
+        MsChapResult::Accepted(reply) => Ok(reply),
+        MsChapResult::Rejected => Err("Invalid MS-CHAPv2 response".to_string()),
+        MsChapResult::NotPresent => match mschap::verify_v1_request(request, &password_hash) {
+            MsChapResult::Accepted(reply) => Ok(reply),
+            MsChapResult::Rejected => Err("Invalid MS-CHAP response".to_string()),
+            MsChapResult::NotPresent => Err("Missing or invalid password".to_string()),
+        },
+    })
 }
 
 /// Local user database authentication backend
@@ -91,8 +276,14 @@ pub struct LocalAuthBackend {
     /// Path to users file
     users_file: String,
     
-    /// Cached users (username -> password hash)
-    users: RwLock<HashMap<String, String>>,
+    /// Whether bcrypt and SHA-512-crypt hashes are replaced by Argon2id hashes on login
+    rehash_legacy: bool,
+    
+    /// Cached users (username -> user)
+    users: RwLock<HashMap<String, LocalUser>>,
+    
+    /// Serializes rewrites of the users file
+    rewriting: Mutex<()>,
 }
 
 impl LocalAuthBackend {
@@ -114,11 +305,18 @@ impl LocalAuthBackend {
             _ => return Err("Local authentication backend requires users_file".into()),
         };
         
+        let rehash_legacy = match config.config.get("rehash_legacy") {
+            Some(toml::Value::Boolean(flag)) => *flag,
+            _ => true,
+        };
+        
         let backend = Self {
             name,
             enabled,
             users_file,
+            rehash_legacy,
             users: RwLock::new(HashMap::new()),
+            rewriting: Mutex::new(()),
         };
         
         // Load users if enabled
@@ -135,9 +333,16 @@ impl LocalAuthBackend {
         let content = tokio::fs::read_to_string(&self.users_file).await
             .map_err(|e| format!("Failed to read users file {}: {}", self.users_file, e))?;
         
-        let users: HashMap<String, String> = serde_json::from_str(&content)
+        let records = users::parse(&content, UsersFormat::from_path(Path::new(&self.users_file)))
             .map_err(|e| format!("Failed to parse users file {}: {}", self.users_file, e))?;
         
+        let mut users = HashMap::with_capacity(records.len());
+        for (username, record) in records {
+            let user = LocalUser::from_record(&record)
+                .map_err(|e| format!("Invalid entry for user {} in {}: {}", username, self.users_file, e))?;
+            users.insert(username, user);
+        }
+
         // Update cache
         let mut cache = self.users.write().await;
         *cache = users;
@@ -146,6 +351,56 @@ impl LocalAuthBackend {
         
         Ok(())
     }
+    
+    /// Replace a legacy credential with an Argon2id hash and save the users file
+    ///
+    /// The credential is swapped in memory first, so logins are never held up
+    /// by the file. The file is then read again and only the user's credential
+    /// is changed, so edits made since it was loaded are kept; it is written
+    /// readable only by its owner on Unix.
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - User who just logged in
+    /// * `verified` - Credential the password was verified against
+    /// * `password` - Verified cleartext password
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if hashing fails or the users file cannot be written
+    async fn rehash(&self, username: &str, verified: &Credential, password: &str) -> Result<()> {
+        let password = password.to_string();
+        let credential = tokio::task::spawn_blocking(move || Credential::hash(Scheme::Argon2id, &password)).await??;
+        
+        // Leave the entry alone if it was changed since the password was verified
+        match self.users.write().await.get_mut(username) {
+            Some(user) if user.credential == *verified => user.credential = credential.clone(),
+            _ => return Ok(()),
+        }
+        
+        let _rewriting = self.rewriting.lock().await;
+        let format = UsersFormat::from_path(Path::new(&self.users_file));
+        let content = tokio::fs::read_to_string(&self.users_file).await
+            .map_err(|e| format!("Failed to read users file {}: {}", self.users_file, e))?;
+        let mut records = users::parse(&content, format)?;
+        
+        match records.get_mut(username) {
+            Some(record) if Credential::parse(record.password()).ok().as_ref() == Some(verified) => {
+                record.set_password(credential.to_string());
+            },
+            _ => return Ok(()),
+        }
+        
+        let content = users::serialize(&records, format)?;
+        let path = PathBuf::from(&self.users_file);
+        tokio::task::spawn_blocking(move || store::replace(&path, content.as_bytes(), true, "users file"))
+            .await
+            .map_err(|e| format!("Failed to write users file: {}", e))??;
+        
+        tracing::info!(backend = self.name, user = username, "Rehashed legacy credential with Argon2id");
+        
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -172,43 +427,68 @@ impl AuthBackend for LocalAuthBackend {
         };
         
         let password = match _request.get_attribute("User-Password") {
-            Some(Attribute::String(_, password)) => password,
-            _ => return Ok(AuthResult::Reject {
-                reason: "Missing or invalid password".to_string(),
-                attributes: vec![],
-            }),
+            Some(Attribute::String(_, password)) => Some(password),
+            _ => None,
         };
         
         // Check if user exists
-        let users = self.users.read().await;
-        let stored_password = match users.get(username) {
-            Some(password) => password,
-            None => return Ok(AuthResult::Reject {
-                reason: format!("User {} not found", username),
-                attributes: vec![],
-            }),
+        let user = match self.users.read().await.get(username) {
+            Some(user) => user.clone(),
+            None => return Ok(AuthResult::NotFound),
         };
+        let credential = user.credential.clone();
+
+        let mut attributes = vec![
+            Attribute::String("Reply-Message".to_string(), 
+                format!("Welcome, {}!", username)),
+        ];
+        attributes.extend(user.reply.iter().cloned());
         
-        // Verify password (in a real implementation, this would use a secure hash comparison)
-        if password != stored_password {
-            return Ok(AuthResult::Reject {
-                reason: "Invalid password".to_string(),
-                attributes: vec![],
-            });
+        let reject = |reason: &str| Ok(AuthResult::Reject {
+            reason: reason.to_string(),
+            attributes: vec![],
+        });
+        
+        // Expiry and check items are only evaluated once the credential is proven
+        let check = |attributes: Vec<Attribute>| match user.check(_request, Local::now()) {
+            Ok(()) => Ok(AuthResult::Accept { attributes, groups: vec![] }),
+            Err(reason) => reject(&reason),
+        };
+
+        match verify_credential(_request, username, &credential).await? {
+            Ok(reply) => attributes.extend(reply),
+            Err(reason) => return reject(&reason),
+        }
+        
+        if let Some(password) = password {
+            if self.rehash_legacy && credential.needs_rehash() {
+                if let Err(e) = self.rehash(username, &credential, password).await {
+                    tracing::warn!(backend = self.name, user = username, error = %e, "Failed to rehash legacy credential");
+                }
+            }
         }
         
         // Authentication successful
-        Ok(AuthResult::Accept {
-            attributes: vec![
-                Attribute::String("Reply-Message".to_string(), 
-                    format!("Welcome, {}!", username)),
-            ],
-        })
+        check(attributes)
     }
     
     fn priority(&self) -> u32 {
         10
     }
+    
+    async fn groups(&self, _request: &Packet, username: &str) -> Result<Vec<String>> {
+        Ok(self.users.read().await.get(username)
+            .map(|user| user.groups.clone())
+            .unwrap_or_default())
+    }
+    
+    async fn reload(&self) -> Result<()> {
+        self.reload_users().await
+    }
+    
+    fn watched_files(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(&self.users_file)]
+    }
 }
 
 /// MAC Authentication backend
@@ -219,11 +499,14 @@ pub struct MacAuthBackend {
     /// Whether the backend is enabled
     enabled: bool,
     
-    /// Whether to accept unknown MAC addresses
-    accept_unknown: bool,
+    /// Guest access for unknown MAC addresses; unknown devices are rejected without it
+    guest: Option<GuestRedirect>,
+
+    /// Optional MAC registry file, reloaded when it changes
+    macs_file: Option<String>,
     
-    /// Known MAC addresses and their attributes
-    known_macs: RwLock<HashMap<String, Vec<Attribute>>>,
+    /// Known devices, vendor rules and deny-list
+    registry: RwLock<MacRegistry>,
 }
 
 impl MacAuthBackend {
@@ -232,11 +515,12 @@ impl MacAuthBackend {
     /// # Arguments
     /// 
     /// * `config` - Authentication backend configuration
+    /// * `portal` - Captive portal configuration, for guest redirects
     /// 
     /// # Returns
     /// 
     /// New MAC authentication backend
-    pub fn new(name: String, config: &AuthBackendConfig) -> Result<Self> {
+    pub fn new(name: String, config: &AuthBackendConfig, portal: Option<&CaptivePortalConfig>) -> Result<Self> {
         // GOAL: Modern Public WiFi Features
         // Implement MAC Authentication Bypass for IoT and simplified onboarding
         
@@ -248,23 +532,60 @@ impl MacAuthBackend {
             _ => false,
         };
         
+        let macs_file = match config.config.get("macs_file") {
+            Some(toml::Value::String(path)) => Some(path.clone()),
+            _ => None,
+        };
+        
+        let registry = match &macs_file {
+            Some(path) if enabled => MacRegistry::load_or_default(path)?,
+            _ => MacRegistry::default(),
+        };
+        
+        let guest = match accept_unknown && enabled {
+            true => Some(GuestRedirect::from_config(config, portal)?),
+            false => None,
+        };
+        
         Ok(Self {
             name,
             enabled,
-            accept_unknown,
-            known_macs: RwLock::new(HashMap::new()),
+            guest,
+            macs_file,
+            registry: RwLock::new(registry),
         })
     }
     
-    /// Add a known MAC address
+    /// Register a device
+    /// 
+    /// The device is written to the registry file, if there is one, so it
+    /// survives a restart.
     /// 
     /// # Arguments
     /// 
     /// * `mac` - MAC address
-    /// * `attributes` - Attributes to include in the response
-    pub async fn add_mac(&self, mac: String, attributes: Vec<Attribute>) {
-        let mut macs = self.known_macs.write().await;
-        macs.insert(mac, attributes);
+    /// * `device` - Device entry, with the attributes to include in the response
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the entry is invalid or the registry file cannot be updated
+    pub async fn add_mac(&self, mac: MacAddr, device: DeviceEntry) -> Result<()> {
+        let mut registry = self.registry.write().await;
+        
+        // Start from the file so concurrent edits by hand or from the CLI are kept
+        let mut updated = match &self.macs_file {
+            Some(path) => MacRegistry::load_or_default(path)?,
+            None => registry.clone(),
+        };
+        updated.devices.insert(mac, device);
+        updated.validate()?;
+        
+        if let Some(path) = &self.macs_file {
+            updated.save(path)?;
+        }
+        
+        *registry = updated;
+        Ok(())
     }
 }
 
@@ -279,59 +600,156 @@ impl AuthBackend for MacAuthBackend {
     }
     
     async fn authenticate(&self, _request: &Packet) -> Result<AuthResult> {
-        // Get MAC address from username attribute
-        let mac = match _request.get_attribute("User-Name") {
-            Some(Attribute::String(_, mac)) => mac,
-            _ => return Ok(AuthResult::Reject {
+        // Get MAC address from User-Name, or Calling-Station-Id for NASes that send another identity
+        let macs = MacAddr::from_request(_request);
+        let mac = match macs.first() {
+            Some(mac) => *mac,
+            None => return Ok(AuthResult::Reject {
                 reason: "Missing or invalid MAC address".to_string(),
                 attributes: vec![],
             }),
         };
         
-        // Check if MAC is known
-        let macs = self.known_macs.read().await;
+        // NASes that send a password for MAB send the MAC itself
+        if let Some(password) = _request.get_text("User-Password") {
+            if password.trim_end_matches('\0').parse::<MacAddr>() != Ok(mac) {
+                return Ok(AuthResult::Reject {
+                    reason: format!("User-Password does not match MAC address {}", mac),
+                    attributes: vec![],
+                });
+            }
+        }
+        
+        // Check the registry for every identity of the device; the deny-list wins
+        let known = {
+            let registry = self.registry.read().await;
+            let now = Utc::now();
+            let lookups: Vec<Lookup> = macs.iter().map(|mac| registry.lookup(mac, now)).collect();
+            
+            if let Some(Lookup::Denied(pattern)) = lookups.iter().find(|lookup| matches!(lookup, Lookup::Denied(_))) {
+                return Ok(AuthResult::Reject {
+                    reason: format!("MAC address {} is denied by {}", mac, pattern),
+                    attributes: vec![],
+                });
+            }
+            
+            lookups.iter().find_map(|lookup| match lookup {
+                Lookup::Device(device) => Some(users::reply_attributes(&device.reply)),
+                Lookup::Vendor(_, rule) => Some(users::reply_attributes(&rule.reply)),
+                _ => None,
+            })
+        };
         
         // If MAC is known, authenticate with stored attributes
-        if let Some(attributes) = macs.get(mac) {
-            return Ok(AuthResult::Accept {
-                attributes: attributes.clone(),
-            });
+        if let Some(attributes) = known {
+            return Ok(AuthResult::Accept { attributes, groups: vec![] });
         }
-        
+
         // If we accept unknown MACs, authenticate with captive portal redirect
-        if self.accept_unknown {
+        if let Some(guest) = &self.guest {
             // GOAL: Modern Public WiFi Features
             // Enable captive portal integration
-            
-            // Create redirect URL for captive portal
-            let redirect_url = format!("http://portal.example.com/login?mac={}", mac);
-            
-            // Return accept with captive portal attributes
             return Ok(AuthResult::Accept {
-                attributes: vec![
-                    // Example for Ubiquiti/UniFi
-                    Attribute::String("Tunnel-Type".to_string(), "VLAN".to_string()),
-                    Attribute::Integer("Tunnel-Medium-Type".to_string(), 6), // IEEE-802
-                    Attribute::Integer("Tunnel-Private-Group-Id".to_string(), 99), // Guest VLAN
-                    // URL Redirection (vendor-specific attribute)
-                    Attribute::String("WISPr-Redirection-URL".to_string(), redirect_url),
-                ],
+                attributes: guest.attributes(&mac),
+                groups: vec![],
             });
         }
         
-        // Otherwise reject
-        Ok(AuthResult::Reject {
-            reason: format!("Unknown MAC address: {}", mac),
-            attributes: vec![],
-        })
+        // Otherwise leave the device to the next backend
+        Ok(AuthResult::NotFound)
     }
     
     fn priority(&self) -> u32 {
         20
     }
+    
+    async fn reload(&self) -> Result<()> {
+        let path = match &self.macs_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        
+        let loaded = MacRegistry::load(path)?;
+        
+        let mut registry = self.registry.write().await;
+        *registry = loaded;
+        
+        tracing::info!(
+            backend = self.name,
+            devices = registry.devices.len(),
+            vendors = registry.vendors.len(),
+            deny = registry.deny.len(),
+            "Loaded MAC registry"
+        );
+        
+        Ok(())
+    }
+    
+    fn watched_files(&self) -> Vec<PathBuf> {
+        self.macs_file.iter().map(PathBuf::from).collect()
+    }
+}
+
+/// Guest voucher authentication backend
+pub struct VoucherAuthBackend {
+    /// Backend name
+    name: String,
+    
+    /// Whether the backend is enabled
+    enabled: bool,
+    
+    /// Vouchers file
+    store: VoucherStore,
+}
+
+impl VoucherAuthBackend {
+    /// Create a new voucher authentication backend
+    /// 
+    /// # Arguments
+    /// 
+    /// * `config` - Authentication backend configuration
+    /// 
+    /// # Returns
+    /// 
+    /// New voucher authentication backend
+    pub fn new(name: String, config: &AuthBackendConfig) -> Result<Self> {
+        let enabled = config.enabled;
+        let store = VoucherStore::new(config.options()?)?;
+        
+        Ok(Self {
+            name,
+            enabled,
+            store,
+        })
+    }
+}
+
+#[async_trait]
+impl AuthBackend for VoucherAuthBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    
+    async fn authenticate(&self, request: &Packet) -> Result<AuthResult> {
+        // The file is read on every login, so it is not watched
+        self.store.authenticate(request).await
+    }
+    
+    fn priority(&self) -> u32 {
+        25
+    }
+    
+    async fn account(&self, request: &Packet) -> Result<()> {
+        self.store.account(request).await
+    }
 }
 
 /// LDAP authentication backend
+#[cfg(feature = "ldap-auth")]
 pub struct LdapAuthBackend {
     /// Backend name
     name: String,
@@ -339,10 +757,11 @@ pub struct LdapAuthBackend {
     /// Whether the backend is enabled
     enabled: bool,
     
-    // LDAP configuration and connection would be here
-    // This is just a stub implementation
+    /// Directory client and connection pool
+    directory: LdapDirectory,
 }
 
+#[cfg(feature = "ldap-auth")]
 impl LdapAuthBackend {
     /// Create a new LDAP authentication backend
     /// 
@@ -355,17 +774,17 @@ impl LdapAuthBackend {
     /// New LDAP authentication backend
     pub fn new(name: String, config: &AuthBackendConfig) -> Result<Self> {
         let enabled = config.enabled;
-        
-        // In a real implementation, we would validate LDAP connection parameters
-        // and establish a connection pool
+        let directory = LdapDirectory::new(config.options()?)?;
         
         Ok(Self {
             name,
             enabled,
+            directory,
         })
     }
 }
 
+#[cfg(feature = "ldap-auth")]
 #[async_trait]
 impl AuthBackend for LdapAuthBackend {
     fn name(&self) -> &str {
@@ -377,27 +796,46 @@ impl AuthBackend for LdapAuthBackend {
     }
     
     async fn authenticate(&self, _request: &Packet) -> Result<AuthResult> {
-        // This is a stub implementation
-        // In a real implementation, we would:
-        // 1. Extract username and password from the request
-        // 2. Bind to LDAP server with these credentials
-        // 3. If successful, query for user attributes
-        // 4. Convert LDAP attributes to RADIUS attributes
-        // 5. Return Accept with those attributes
-        
-        // For now, we'll just reject all requests
-        Ok(AuthResult::Reject {
-            reason: "LDAP authentication not implemented".to_string(),
+        let reject = |reason: String| Ok(AuthResult::Reject {
+            reason,
             attributes: vec![],
-        })
+        });
+        
+        let username = match _request.get_text("User-Name") {
+            Some(username) => username,
+            None => return reject("Missing or invalid username".to_string()),
+        };
+        
+        // The directory verifies the password by binding, so only PAP (or EAP-TTLS/PAP) works
+        let password = match _request.get_attribute("User-Password") {
+            Some(Attribute::String(_, password)) => password,
+            _ => return reject("LDAP authentication requires PAP".to_string()),
+        };
+        
+        match self.directory.authenticate(&username, password).await? {
+            LdapOutcome::Authenticated(user) => {
+                tracing::debug!(backend = self.name, dn = user.dn, groups = ?user.groups, "LDAP bind succeeded");
+                Ok(AuthResult::Accept {
+                    attributes: self.directory.reply_attributes(&user.groups),
+                    groups: vec![],
+                })
+            },
+            LdapOutcome::NotFound => Ok(AuthResult::NotFound),
+            LdapOutcome::Rejected(reason) => reject(reason),
+        }
     }
     
     fn priority(&self) -> u32 {
         30
     }
+    
+    async fn groups(&self, _request: &Packet, username: &str) -> Result<Vec<String>> {
+        self.directory.groups(username).await
+    }
 }
 
 /// OAuth authentication backend
+#[cfg(feature = "oauth-auth")]
 pub struct OAuthAuthBackend {
     /// Backend name
     name: String,
@@ -405,10 +843,11 @@ pub struct OAuthAuthBackend {
     /// Whether the backend is enabled
     enabled: bool,
     
-    // OAuth configuration would be here
-    // This is just a stub implementation
+    /// Identity provider client
+    provider: OAuthProvider,
 }
 
+#[cfg(feature = "oauth-auth")]
 impl OAuthAuthBackend {
     /// Create a new OAuth authentication backend
     /// 
@@ -424,17 +863,17 @@ impl OAuthAuthBackend {
         // Implement integration with modern identity providers
         
         let enabled = config.enabled;
-        
-        // In a real implementation, we would validate OAuth parameters
-        // and set up the OAuth client
+        let provider = OAuthProvider::new(config.options()?)?;
         
         Ok(Self {
             name,
             enabled,
+            provider,
         })
     }
 }
 
+#[cfg(feature = "oauth-auth")]
 #[async_trait]
 impl AuthBackend for OAuthAuthBackend {
     fn name(&self) -> &str {
@@ -446,19 +885,30 @@ impl AuthBackend for OAuthAuthBackend {
     }
     
     async fn authenticate(&self, _request: &Packet) -> Result<AuthResult> {
-        // This is a stub implementation
-        // In a real implementation, we would:
-        // 1. Extract token from the request
-        // 2. Validate the token with the OAuth provider
-        // 3. If valid, extract user information
-        // 4. Convert OAuth claims to RADIUS attributes
-        // 5. Return Accept with those attributes
-        
-        // For now, we'll just reject all requests
-        Ok(AuthResult::Reject {
-            reason: "OAuth authentication not implemented".to_string(),
+        let reject = |reason: String| Ok(AuthResult::Reject {
+            reason,
             attributes: vec![],
-        })
+        });
+        
+        let username = match _request.get_text("User-Name") {
+            Some(username) => username,
+            None => return reject("Missing or invalid username".to_string()),
+        };
+        
+        // The password (or token) is passed on to the IdP, so only PAP (or EAP-TTLS/PAP) works
+        let password = match _request.get_attribute("User-Password") {
+            Some(Attribute::String(_, password)) => password,
+            _ => return reject("OAuth authentication requires PAP".to_string()),
+        };
+        
+        match self.provider.authenticate(&username, password).await? {
+            // Claims only exist while the token is checked, so their groups go with this login's Accept
+            OAuthOutcome::Authenticated(claims) => Ok(AuthResult::Accept {
+                attributes: self.provider.reply_attributes(&claims),
+                groups: self.provider.groups(&claims),
+            }),
+            OAuthOutcome::Rejected(reason) => reject(reason),
+        }
     }
     
     fn priority(&self) -> u32 {
@@ -466,110 +916,773 @@ impl AuthBackend for OAuthAuthBackend {
     }
 }
 
-/// Authentication manager
-/// 
-/// This struct manages authentication backends and routes requests to the appropriate backend.
-pub struct AuthManager {
-    /// Server configuration
-    config: Arc<Config>,
+/// REST authentication backend
+#[cfg(feature = "rest-auth")]
+pub struct RestAuthBackend {
+    /// Backend name
+    name: String,
     
-    /// Authentication backends
-    backends: Vec<Arc<dyn AuthBackend>>,
+    /// Whether the backend is enabled
+    enabled: bool,
+    
+    /// Authentication service client
+    client: RestClient,
 }
 
-impl AuthManager {
-    /// Create a new authentication manager
+#[cfg(feature = "rest-auth")]
+impl RestAuthBackend {
+    /// Create a new REST authentication backend
     /// 
     /// # Arguments
     /// 
-    /// * `config` - Server configuration
+    /// * `config` - Authentication backend configuration
     /// 
     /// # Returns
     /// 
-    /// New authentication manager
-    /// 
-    /// # Errors
-    /// 
-    /// Returns an error if any backend fails to initialize
-    pub async fn new(config: Arc<Config>) -> Result<Self> {
-        // GOAL: Federation and Zero-Trust Integration
-        // Support multiple authentication backends
-        
-        let mut backends: Vec<Arc<dyn AuthBackend>> = Vec::new();
+    /// New REST authentication backend
+    pub fn new(name: String, config: &AuthBackendConfig) -> Result<Self> {
+        let enabled = config.enabled;
+        let client = RestClient::new(config.options()?)?;
         
-        // Initialize authentication backends
-        for (name, backend_config) in &config.auth_backends {
-            if !backend_config.enabled {
-                continue;
-            }
-            
-            // Create backend based on type
-            let backend: Arc<dyn AuthBackend> = match backend_config.backend_type.as_str() {
-                "local" => {
-                    Arc::new(LocalAuthBackend::new(name.clone(), backend_config).await?)
-                },
-                "mac" => {
-                    Arc::new(MacAuthBackend::new(name.clone(), backend_config)?)
-                },
-                "ldap" => {
-                    Arc::new(LdapAuthBackend::new(name.clone(), backend_config)?)
+        Ok(Self {
+            name,
+            enabled,
+            client,
+        })
+    }
+}
+
+#[cfg(feature = "rest-auth")]
+#[async_trait]
+impl AuthBackend for RestAuthBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    
+    async fn authenticate(&self, _request: &Packet) -> Result<AuthResult> {
+        // The service sees the request attributes named in the body template and decides
+        self.client.authenticate(_request).await
+    }
+    
+    fn priority(&self) -> u32 {
+        50
+    }
+}
+
+/// WebAssembly plugin authentication backend
+#[cfg(feature = "wasm-plugins")]
+pub struct WasmAuthBackend {
+    /// Backend name
+    name: String,
+    
+    /// Whether the backend is enabled
+    enabled: bool,
+    
+    /// Plugin implementing the `backend` world
+    plugin: BackendPlugin,
+}
+
+#[cfg(feature = "wasm-plugins")]
+impl WasmAuthBackend {
+    /// Create a new WebAssembly plugin authentication backend
+    /// 
+    /// # Arguments
+    /// 
+    /// * `config` - Authentication backend configuration
+    /// 
+    /// # Returns
+    /// 
+    /// New WebAssembly plugin authentication backend
+    pub fn new(name: String, config: &AuthBackendConfig) -> Result<Self> {
+        let enabled = config.enabled;
+        let plugin = BackendPlugin::load(&name, config.options()?)?;
+        
+        Ok(Self {
+            name,
+            enabled,
+            plugin,
+        })
+    }
+}
+
+#[cfg(feature = "wasm-plugins")]
+#[async_trait]
+impl AuthBackend for WasmAuthBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    
+    async fn authenticate(&self, request: &Packet) -> Result<AuthResult> {
+        self.plugin.authenticate(request).await
+    }
+    
+    fn priority(&self) -> u32 {
+        60
+    }
+}
+
+/// SQL authentication backend
+#[cfg(feature = "sql-auth")]
+pub struct SqlAuthBackend {
+    /// Backend name
+    name: String,
+    
+    /// Whether the backend is enabled
+    enabled: bool,
+    
+    /// User database
+    store: SqlStore,
+}
+
+#[cfg(feature = "sql-auth")]
+impl SqlAuthBackend {
+    /// Create a new SQL authentication backend
+    /// 
+    /// # Arguments
+    /// 
+    /// * `config` - Authentication backend configuration
+    /// 
+    /// # Returns
+    /// 
+    /// New SQL authentication backend
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the database cannot be reached or migrated
+    pub async fn new(name: String, config: &AuthBackendConfig) -> Result<Self> {
+        let enabled = config.enabled;
+        let store = SqlStore::connect(config.options()?).await?;
+        
+        Ok(Self {
+            name,
+            enabled,
+            store,
+        })
+    }
+}
+
+#[cfg(feature = "sql-auth")]
+#[async_trait]
+impl AuthBackend for SqlAuthBackend {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+    
+    async fn authenticate(&self, _request: &Packet) -> Result<AuthResult> {
+        let reject = |reason: String| Ok(AuthResult::Reject {
+            reason,
+            attributes: vec![],
+        });
+        
+        let username = match _request.get_text("User-Name") {
+            Some(username) => username,
+            None => return reject("Missing or invalid username".to_string()),
+        };
+        
+        let user = match self.store.lookup(&username).await? {
+            Some(user) => user,
+            None => return Ok(AuthResult::NotFound),
+        };
+        
+        let credential = match &user.credential {
+            Some(credential) => credential,
+            None => return reject(format!("No password stored for user {}", username)),
+        };
+        
+        let mut attributes = match verify_credential(_request, &username, credential).await? {
+            Ok(reply) => reply,
+            Err(reason) => return reject(reason),
+        };
+        
+        // Check items are only evaluated once the credential is proven
+        let now = Local::now();
+        if let Err(reason) = user.check(_request, now) {
+            return reject(reason);
+        }
+        
+        attributes.extend(user.reply_attributes(_request, now));
+        Ok(AuthResult::Accept { attributes, groups: vec![] })
+    }
+    
+    fn priority(&self) -> u32 {
+        15
+    }
+    
+    async fn groups(&self, _request: &Packet, username: &str) -> Result<Vec<String>> {
+        self.store.groups(username).await
+    }
+}
+
+/// Authentication manager
+/// 
+/// This struct manages authentication backends and routes requests to the appropriate backend.
+pub struct AuthManager {
+    /// Server configuration
+    config: Arc<Config>,
+    
+    /// Authentication backends
+    backends: Vec<Arc<dyn AuthBackend>>,
+    
+    /// EAP conversation handler
+    eap: EapServer,
+    
+    /// One-time password stage run after a backend accepts
+    mfa: Option<MfaStage>,
+    
+    /// Challenges waiting for the client's answer
+    conversations: Conversations<Vec<u8>>,
+    
+    /// Group profiles applied to accepted users
+    authorizer: Authorizer,
+    
+    /// Open sessions, and the Simultaneous-Use check against them
+    sessions: SessionLimiter,
+    
+    /// Data and time quotas
+    quotas: QuotaEnforcer,
+    
+    /// Lockout after repeated failures
+    lockout: LockoutGuard,
+}
+
+impl AuthManager {
+    /// Create a new authentication manager
+    /// 
+    /// # Arguments
+    /// 
+    /// * `config` - Server configuration
+    /// 
+    /// # Returns
+    /// 
+    /// New authentication manager
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if any backend fails to initialize
+    pub async fn new(config: Arc<Config>) -> Result<Self> {
+        // GOAL: Federation and Zero-Trust Integration
+        // Support multiple authentication backends
+        
+        let mut chain: Vec<(u32, Arc<dyn AuthBackend>)> = Vec::new();
+        
+        // Initialize authentication backends
+        for (name, backend_config) in &config.auth_backends {
+            if !backend_config.enabled {
+                continue;
+            }
+            
+            // Create backend based on type
+            let backend: Arc<dyn AuthBackend> = match backend_config.backend_type.as_str() {
+                "local" => {
+                    Arc::new(LocalAuthBackend::new(name.clone(), backend_config).await?)
+                },
+                "mac" => {
+                    Arc::new(MacAuthBackend::new(name.clone(), backend_config, config.captive_portal.as_ref())?)
+                },
+                "voucher" => {
+                    Arc::new(VoucherAuthBackend::new(name.clone(), backend_config)?)
+                },
+                #[cfg(feature = "sql-auth")]
+                "sql" => {
+                    Arc::new(SqlAuthBackend::new(name.clone(), backend_config).await?)
+                },
+                #[cfg(not(feature = "sql-auth"))]
+                "sql" => {
+                    return Err("SQL backends require the sql-auth feature".into());
                 },
+                #[cfg(feature = "ldap-auth")]
+                "ldap" => {
+                    Arc::new(LdapAuthBackend::new(name.clone(), backend_config)?)
+                },
+                #[cfg(not(feature = "ldap-auth"))]
+                "ldap" => {
+                    return Err("LDAP backends require the ldap-auth feature".into());
+                },
+                #[cfg(feature = "oauth-auth")]
                 "oauth" => {
                     Arc::new(OAuthAuthBackend::new(name.clone(), backend_config)?)
                 },
+                #[cfg(not(feature = "oauth-auth"))]
+                "oauth" => {
+                    return Err("OAuth backends require the oauth-auth feature".into());
+                },
+                #[cfg(feature = "rest-auth")]
+                "rest" => {
+                    Arc::new(RestAuthBackend::new(name.clone(), backend_config)?)
+                },
+                #[cfg(not(feature = "rest-auth"))]
+                "rest" => {
+                    return Err("REST backends require the rest-auth feature".into());
+                },
+                #[cfg(feature = "wasm-plugins")]
+                "wasm" => {
+                    Arc::new(WasmAuthBackend::new(name.clone(), backend_config)?)
+                },
+                #[cfg(not(feature = "wasm-plugins"))]
+                "wasm" => {
+                    return Err("WebAssembly backends require the wasm-plugins feature".into());
+                },
                 _ => {
                     return Err(format!("Unknown authentication backend type: {}", 
                         backend_config.backend_type).into());
                 }
             };
             
-            tracing::info!(
-                backend = backend.name(),
-                enabled = backend.is_enabled(),
-                "Initialized authentication backend"
-            );
+            let order = backend_config.chain.order.unwrap_or_else(|| backend.priority());
+            tracing::info!(
+                backend = backend.name(),
+                enabled = backend.is_enabled(),
+                order = order,
+                "Initialized authentication backend"
+            );
+            
+            chain.push((order, backend));
+        }
+        
+        // Sort backends by their configured order, then the type's priority; names break ties
+        chain.sort_by(|(a, a_backend), (b, b_backend)| (a, a_backend.name()).cmp(&(b, b_backend.name())));
+        let backends = chain.into_iter().map(|(_, backend)| backend).collect();
+        
+        // GOAL: Modern Public WiFi Features
+        // EAP methods for WPA2/WPA3 Enterprise
+        #[allow(unused_mut)]
+        let mut eap = EapServer::new(&config);
+        
+        #[cfg(feature = "eap-tls")]
+        if eap.is_configured(eap::EapType::Tls) {
+            let tls_config = config.eap.tls.as_ref()
+                .ok_or("eap.tls must be configured when eap-tls is enabled")?;
+            eap.register_method(Arc::new(eap::tls::EapTlsMethod::new(tls_config)?));
+        }
+        
+        // PEAP and EAP-TTLS are in the default protocol list, so only offer
+        // them once a server certificate has been configured
+        #[cfg(feature = "eap-tls")]
+        for method in [eap::EapType::Peap, eap::EapType::Ttls] {
+            if !eap.is_configured(method) {
+                continue;
+            }
+            
+            match &config.eap.tls {
+                Some(tls_config) if method == eap::EapType::Peap => {
+                    eap.register_method(Arc::new(eap::peap::PeapMethod::new(tls_config)?));
+                },
+                Some(tls_config) => {
+                    eap.register_method(Arc::new(eap::ttls::TtlsMethod::new(tls_config)?));
+                },
+                None => {
+                    tracing::warn!(method = ?method, "EAP method requires eap.tls; not offering it");
+                },
+            }
+        }
+
+        let mfa = match config.mfa.as_ref().filter(|mfa| mfa.enabled) {
+            Some(mfa_config) => Some(MfaStage::new(mfa_config)?),
+            None => None,
+        };
+        let conversations = Conversations::from_config(&config.conversations);
+        let authorizer = Authorizer::new(&config.authorization);
+        let sessions = SessionLimiter::new(config.clone(), Arc::new(SessionTable::new()));
+        let quotas = QuotaEnforcer::new(config.clone())?;
+        let lockout = LockoutGuard::new(&config.lockout)?;
+        
+        Ok(Self {
+            config,
+            backends,
+            eap,
+            mfa,
+            conversations,
+            authorizer,
+            sessions,
+            quotas,
+            lockout,
+        })
+    }
+    
+    /// Get the EAP server, e.g. to register additional EAP methods
+    pub fn eap_mut(&mut self) -> &mut EapServer {
+        &mut self.eap
+    }
+    
+    /// Continue the EAP conversations, pending challenges, open sessions, quota usage and failure counts of the manager this one replaces
+    /// 
+    /// Pending challenges are only kept if the conversation store settings are unchanged.
+    pub fn adopt_conversations(&mut self, previous: &AuthManager) {
+        self.eap.adopt_conversations(&previous.eap);
+        self.sessions.adopt(&previous.sessions);
+        self.quotas.adopt(&previous.quotas);
+        self.lockout.adopt(&previous.lockout);
+        if self.config.conversations == previous.config.conversations {
+            self.conversations.adopt(&previous.conversations);
+        }
+    }
+    
+    /// Write state kept in memory to its files, e.g. before shutting down
+    pub async fn flush(&self) {
+        if let Err(e) = self.quotas.flush().await {
+            tracing::error!(error = %e, "Failed to write quotas");
+        }
+        if let Err(e) = self.lockout.flush().await {
+            tracing::error!(error = %e, "Failed to save lockouts");
+        }
+    }
+    
+    /// Get the lockout guard
+    pub fn lockout(&self) -> &LockoutGuard {
+        &self.lockout
+    }
+    
+    /// Get the authentication backends, in the order they are tried
+    pub fn backends(&self) -> &[Arc<dyn AuthBackend>] {
+        &self.backends
+    }
+    
+    /// Reload the data of every backend
+    /// 
+    /// A backend that fails to reload keeps its previous data; the error is logged.
+    /// 
+    /// # Returns
+    /// 
+    /// The number of backends that failed to reload
+    pub async fn reload_backends(&self) -> usize {
+        let mut failures = 0;
+        
+        for backend in &self.backends {
+            if let Err(e) = backend.reload().await {
+                tracing::error!(backend = backend.name(), error = %e, "Failed to reload backend; keeping previous data");
+                failures += 1;
+            }
+        }
+        
+        failures
+    }
+    
+    /// Authenticate a request
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - RADIUS request packet
+    /// 
+    /// # Returns
+    /// 
+    /// Authentication response packet
+    pub async fn authenticate(&self, request: &Packet) -> Result<Packet> {
+        self.authenticate_with(request, None).await
+    }
+    
+    /// Authenticate a request, optionally with a single backend
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - RADIUS request packet
+    /// * `backend` - Backend that replaces the chain, as chosen by the policy
+    /// 
+    /// # Returns
+    /// 
+    /// Authentication response packet
+    pub async fn authenticate_with(&self, request: &Packet, backend: Option<&str>) -> Result<Packet> {
+        self.guarded(request, Decider::Backends(backend)).await
+    }
+    
+    /// Accept a request the pre-auth policy accepted, without asking a backend
+    /// 
+    /// The Accept goes through the same checks as one from a backend: lockout,
+    /// the Message-Authenticator, group profiles, MFA, the session limit and quotas.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - RADIUS request packet
+    /// 
+    /// # Returns
+    /// 
+    /// Authentication response packet
+    pub async fn accept(&self, request: &Packet) -> Result<Packet> {
+        self.guarded(request, Decider::Policy).await
+    }
+    
+    /// Answer a request unless it is locked out, and count the outcome
+    async fn guarded(&self, request: &Packet, decider: Decider<'_>) -> Result<Packet> {
+        // The User-Name of an EAP request is only the outer identity; the user is
+        // checked once the tunnel gives up the inner identity
+        let keys = if request.get_attribute("EAP-Message").is_some() { Keys::Outer } else { Keys::All };
+        
+        // Locked-out keys are rejected before any backend sees the attempt
+        if let Some((key, until)) = self.lockout.locked(request, keys) {
+            tracing::info!(
+                username = ?request.get_text("User-Name"),
+                key = key,
+                until = %until,
+                "Rejecting locked-out request"
+            );
+            return self.create_reject_response(request, "Too many failed attempts", vec![]);
+        }
+        
+        let response = self.decide(request, decider).await?;
+        match response.code() {
+            Packet::ACCESS_ACCEPT => self.lockout.record(request, keys, true),
+            Packet::ACCESS_REJECT => self.lockout.record(request, keys, false),
+            _ => {},
+        }
+        
+        Ok(response)
+    }
+    
+    /// Answer a request that is not locked out
+    async fn decide(&self, request: &Packet, decider: Decider<'_>) -> Result<Packet> {
+        // GOAL: Federation and Zero-Trust Integration
+        // Route authentication requests to appropriate backends
+        
+        // Check if the request has a Message-Authenticator attribute
+        if self.config.security.require_message_authenticator && 
+            request.get_attribute("Message-Authenticator").is_none() {
+            // Reject requests without Message-Authenticator if required
+            return self.create_reject_response(
+                request, 
+                "Missing Message-Authenticator attribute", 
+                vec![]
+            );
+        }
+        
+        let backend = match decider {
+            Decider::Backends(backend) => backend,
+            Decider::Policy => return self.finish(request, AuthResult::Accept { attributes: vec![], groups: vec![] }, None, 0).await,
+        };
+        
+        // EAP conversations are driven by the EAP server rather than the backends
+        if request.get_attribute("EAP-Message").is_some() {
+            return self.authenticate_eap(request, backend).await;
+        }
+        
+        // A State hands the answer to whoever issued the challenge
+        let (result, owner, rounds) = match self.conversations.resume(request).await? {
+            Resumption::NoState => {
+                let (result, owner) = self.authenticate_backends(request, backend).await?;
+                (result, owner, 0)
+            },
+            Resumption::Refused(reason) => {
+                tracing::info!(
+                    username = ?request.get_attribute("User-Name"),
+                    reason = reason,
+                    "Challenge answer refused"
+                );
+                return self.create_reject_response(request, &reason, vec![]);
+            },
+            Resumption::Resumed(conversation) => {
+                let result = self.continue_conversation(request, &conversation.owner, conversation.context).await?;
+                (result, Some(conversation.owner), conversation.rounds)
+            },
+        };
+        
+        self.finish(request, result, owner, rounds).await
+    }
+    
+    /// Authorize an Accept, hold it back for a one-time code if needed, and build the response
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request being answered
+    /// * `result` - Result of the backend that decided, or of the policy
+    /// * `owner` - Backend that decided, or the MFA stage
+    /// * `rounds` - Challenges issued so far in this exchange
+    async fn finish(&self, request: &Packet, result: AuthResult, owner: Option<String>, rounds: u32) -> Result<Packet> {
+        // The Accept held back for a one-time code was authorized before the challenge
+        let username = request.get_text("User-Name").unwrap_or_default();
+        let result = match result {
+            AuthResult::Accept { attributes, groups } if owner.as_deref() != Some(MFA_OWNER) => {
+                match self.authorize(request, &username, attributes, groups).await {
+                    Ok((attributes, groups)) => AuthResult::Accept { attributes, groups },
+                    Err(reason) => reject(&reason),
+                }
+            },
+            other => other,
+        };
+        let result = self.hold_challenges(request, result, owner.as_deref(), rounds).await?;
+        
+        // Sessions and quotas are only taken by a login that gets its Accept
+        let result = match result {
+            AuthResult::Accept { attributes, groups } => {
+                match self.admit(request, &username, &groups, attributes).await {
+                    Ok(attributes) => AuthResult::Accept { attributes, groups },
+                    Err(reason) => reject(&reason),
+                }
+            },
+            other => other,
+        };
+        
+        self.respond(request, result)
+    }
+    
+    /// Build the response packet for a result
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request being answered
+    /// * `result` - Result to send
+    pub fn respond(&self, request: &Packet, result: AuthResult) -> Result<Packet> {
+        match result {
+            AuthResult::Accept { attributes, .. } => self.create_accept_response(request, attributes),
+            AuthResult::Reject { reason, attributes } => self.create_reject_response(request, &reason, attributes),
+            AuthResult::Challenge { message, state, attributes } => {
+                self.create_challenge_response(request, &message, &state, attributes)
+            },
+            AuthResult::NotFound => self.create_reject_response(request, "Unknown user", vec![]),
+            AuthResult::Forward { target } => self.create_reject_response(
+                request,
+                &format!("Cannot forward request to {}", target),
+                vec![]
+            ),
+        }
+    }
+    
+    /// Pass the answer to a challenge to the backend or stage that issued it
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Access-Request answering the challenge
+    /// * `owner` - Backend name, or "mfa" for the one-time code stage
+    /// * `context` - What the owner stored with the challenge
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the MFA stage cannot read or update its tokens
+    async fn continue_conversation(&self, request: &Packet, owner: &str, context: Vec<u8>) -> Result<AuthResult> {
+        if owner == MFA_OWNER {
+            let mfa = match &self.mfa {
+                Some(mfa) => mfa,
+                None => return Ok(reject("MFA is no longer enabled")),
+            };
+            
+            // The conversation is bound to the user, so the code is checked against the same token
+            let username = request.get_text("User-Name").unwrap_or_default();
+            let (attributes, groups): (Vec<Attribute>, Vec<String>) = serde_json::from_slice(&context)?;
             
-            backends.push(backend);
+            return Ok(match mfa.verify(request, &username).await? {
+                Ok(()) => {
+                    tracing::info!(username = username, "One-time code accepted");
+                    AuthResult::Accept { attributes, groups }
+                },
+                Err(reason) => {
+                    tracing::info!(username = username, reason = reason, "One-time code rejected");
+                    reject(&reason)
+                },
+            });
         }
         
-        // Sort backends by priority
-        backends.sort_by_key(|b| b.priority());
+        let backend = match self.backends.iter().find(|backend| backend.name() == owner) {
+            Some(backend) => backend,
+            None => return Ok(reject("The backend that issued the challenge is gone")),
+        };
+        
+        // The backend sees its own state rather than the one the client was given
+        let mut request = request.clone();
+        request.remove_attributes("State");
+        request.add_attribute(Attribute::Binary("State".to_string(), context));
         
-        Ok(Self {
-            config,
-            backends,
-        })
+        // Mid-exchange there is no next backend to continue with
+        match backend.authenticate(&request).await {
+            Ok(result) => Ok(result),
+            Err(e) if self.chain_policy(owner).on_error == ErrorPolicy::Fail => {
+                Err(format!("Authentication backend {} failed: {}", owner, e).into())
+            },
+            Err(e) => {
+                tracing::error!(backend = owner, error = ?e, "Authentication backend error");
+                Ok(reject("Authentication backend error"))
+            },
+        }
     }
     
-    /// Authenticate a request
+    /// Keep the state of a challenge server-side, and challenge enrolled users for a one-time code
     /// 
     /// # Arguments
     /// 
-    /// * `request` - RADIUS request packet
+    /// * `request` - Request being answered
+    /// * `result` - Result from the backends or the conversation the request continued
+    /// * `owner` - Backend or stage that produced the result
+    /// * `rounds` - Challenges already issued in this exchange
     /// 
-    /// # Returns
+    /// # Errors
     /// 
-    /// Authentication response packet
-    pub async fn authenticate(&self, request: &Packet) -> Result<Packet> {
-        // GOAL: Federation and Zero-Trust Integration
-        // Route authentication requests to appropriate backends
-        
-        // Check if the request has a Message-Authenticator attribute
-        if self.config.security.require_message_authenticator && 
-            request.get_attribute("Message-Authenticator").is_none() {
-            // Reject requests without Message-Authenticator if required
-            return self.create_reject_response(
-                request, 
-                "Missing Message-Authenticator attribute", 
-                vec![]
-            );
+    /// Returns an error if the conversation store or the MFA tokens cannot be used
+    async fn hold_challenges(&self, request: &Packet, result: AuthResult, owner: Option<&str>, rounds: u32) -> Result<AuthResult> {
+        match result {
+            AuthResult::Challenge { message, state, attributes } => {
+                let owner = owner.ok_or("Challenge without a backend")?;
+                match self.conversations.issue(request, owner, state, rounds).await? {
+                    Some(state) => Ok(AuthResult::Challenge { message, state, attributes }),
+                    None => Ok(reject("Too many challenge rounds")),
+                }
+            },
+            AuthResult::Accept { attributes, groups } if owner != Some(MFA_OWNER) => {
+                let username = request.get_text("User-Name").unwrap_or_default();
+                let mfa = match &self.mfa {
+                    Some(mfa) if mfa.is_enrolled(&username)? => mfa,
+                    _ => return Ok(AuthResult::Accept { attributes, groups }),
+                };
+                
+                // The Accept is held back until the code is checked, with the groups it was authorized under
+                let context = serde_json::to_vec(&(&attributes, &groups))?;
+                match self.conversations.issue(request, MFA_OWNER, context, rounds).await? {
+                    Some(state) => {
+                        tracing::info!(username = username, "Challenging for a one-time code");
+                        Ok(AuthResult::Challenge {
+                            message: mfa.prompt().to_string(),
+                            state,
+                            attributes: vec![],
+                        })
+                    },
+                    None => Ok(reject("Too many challenge rounds")),
+                }
+            },
+            other => Ok(other),
         }
+    }
+    
+    /// Get the chain policy of a backend
+    fn chain_policy(&self, backend: &str) -> ChainPolicy {
+        self.config.auth_backends.get(backend)
+            .map(|config| config.chain)
+            .unwrap_or_default()
+    }
+    
+    /// Run a request through the backends
+    /// 
+    /// Each backend's `on_reject`, `on_not_found` and `on_error` policy decides
+    /// whether the next one is tried.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request to authenticate
+    /// * `only` - Backend that replaces the chain, as chosen by the policy
+    /// 
+    /// # Returns
+    /// 
+    /// The first Accept or Challenge from a backend, or the Reject that ended
+    /// the chain, along with the name of the deciding backend
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if a backend with `on_error = "fail"` fails, so no answer is sent
+    async fn authenticate_backends(&self, request: &Packet, only: Option<&str>) -> Result<(AuthResult, Option<String>)> {
+        // First Reject from a backend that lets the chain continue, sent if no later backend accepts
+        let mut rejection = None;
         
-        // Try each backend in order until one accepts or rejects
         for backend in &self.backends {
-            if !backend.is_enabled() {
+            if !backend.is_enabled() || only.is_some_and(|only| only != backend.name()) {
                 continue;
             }
+            let policy = self.chain_policy(backend.name());
             
             // GOAL: Comprehensive Observability
             // Log authentication requests and responses with details
@@ -581,7 +1694,7 @@ impl AuthManager {
             
             // Authenticate with this backend
             match backend.authenticate(request).await {
-                Ok(AuthResult::Accept { attributes }) => {
+                Ok(AuthResult::Accept { attributes, groups }) => {
                     // Authentication succeeded
                     tracing::info!(
                         backend = backend.name(),
@@ -589,7 +1702,7 @@ impl AuthManager {
                         "Authentication accepted"
                     );
                     
-                    return self.create_accept_response(request, attributes);
+                    return Ok((AuthResult::Accept { attributes, groups }, Some(backend.name().to_string())));
                 },
                 Ok(AuthResult::Reject { reason, attributes }) => {
                     // Authentication rejected
@@ -600,7 +1713,25 @@ impl AuthManager {
                         "Authentication rejected"
                     );
                     
-                    return self.create_reject_response(request, &reason, attributes);
+                    let result = (AuthResult::Reject { reason, attributes }, Some(backend.name().to_string()));
+                    if policy.on_reject == RejectPolicy::Continue {
+                        rejection.get_or_insert(result);
+                        continue;
+                    }
+                    return Ok(result);
+                },
+                Ok(AuthResult::NotFound) => {
+                    // The user may be known to the next backend
+                    tracing::debug!(
+                        backend = backend.name(),
+                        username = ?request.get_attribute("User-Name"),
+                        "User not found"
+                    );
+                    
+                    if policy.on_not_found == NotFoundPolicy::Reject {
+                        return Ok((reject("Unknown user"), Some(backend.name().to_string())));
+                    }
+                    continue;
                 },
                 Ok(AuthResult::Challenge { message, state, attributes }) => {
                     // Authentication challenge
@@ -611,7 +1742,7 @@ impl AuthManager {
                         "Authentication challenge"
                     );
                     
-                    return self.create_challenge_response(request, &message, &state, attributes);
+                    return Ok((AuthResult::Challenge { message, state, attributes }, Some(backend.name().to_string())));
                 },
                 Ok(AuthResult::Forward { target }) => {
                     // Forward to another backend
@@ -631,35 +1762,307 @@ impl AuthManager {
                         backend = backend.name(),
                         username = ?request.get_attribute("User-Name"),
                         error = ?e,
+                        policy = ?policy.on_error,
                         "Authentication backend error"
                     );
                     
-                    // Continue to next backend
-                    continue;
+                    match policy.on_error {
+                        ErrorPolicy::Continue => continue,
+                        ErrorPolicy::Reject => {
+                            return Ok((reject("Authentication backend error"), Some(backend.name().to_string())));
+                        },
+                        ErrorPolicy::Fail => {
+                            return Err(format!("Authentication backend {} failed: {}", backend.name(), e).into());
+                        },
+                    }
                 }
             }
         }
         
+        if let Some(rejection) = rejection {
+            return Ok(rejection);
+        }
+        
         // If we get here, no backend accepted or rejected the request
         tracing::warn!(
             username = ?request.get_attribute("User-Name"),
             "No authentication backend handled the request"
         );
         
-        self.create_reject_response(
-            request, 
-            "No authentication backend accepted the request", 
-            vec![]
-        )
+        Ok((reject("No authentication backend accepted the request"), None))
+    }
+    
+    /// Authenticate a request carrying an EAP-Message
+    async fn authenticate_eap(&self, request: &Packet, backend: Option<&str>) -> Result<Packet> {
+        let inner = RoutedInner { manager: self, backend };
+        match self.eap.handle(request, &inner).await? {
+            EapOutcome::Challenge { eap, state } => {
+                let mut response = request.create_response(Packet::ACCESS_CHALLENGE);
+                
+                for attr in eap::fragment(&eap) {
+                    response.add_attribute(attr);
+                }
+                response.add_attribute(Attribute::Binary("State".to_string(), state));
+                
+                Ok(response)
+            },
+            EapOutcome::Accept { eap, identity, success } => {
+                let mut attributes = success.attributes;
+                let failure = || {
+                    let identifier = eap::EapPacket::parse(&eap).map(|p| p.identifier).unwrap_or(0);
+                    eap::fragment(&eap::EapPacket::failure(identifier).to_bytes())
+                };
+                
+                // Give backends a chance to authorize certificate-based peers
+                if let Some(certificate) = &success.certificate {
+                    match self.authorize_certificate(request, certificate).await {
+                        Ok(extra) => attributes.extend(extra),
+                        Err(reason) => {
+                            tracing::info!(
+                                identity = identity,
+                                subject = certificate.subject,
+                                reason = reason,
+                                "EAP certificate authorization rejected"
+                            );
+                            
+                            return self.create_reject_response(request, &reason, failure());
+                        }
+                    }
+                }
+                
+                let username = success.inner_identity.as_deref().unwrap_or(&identity);
+                let authorized = match self.authorize(request, username, attributes, success.groups).await {
+                    Ok((attributes, groups)) => self.admit(request, username, &groups, attributes).await,
+                    Err(reason) => Err(reason),
+                };
+                let attributes = match authorized {
+                    Ok(attributes) => attributes,
+                    Err(reason) => {
+                        tracing::info!(identity = username, reason = reason, "EAP authorization rejected");
+                        return self.create_reject_response(request, &reason, failure());
+                    },
+                };
+                
+                tracing::info!(
+                    outer_identity = identity,
+                    inner_identity = ?success.inner_identity,
+                    username = ?request.get_attribute("User-Name"),
+                    subject = ?success.certificate.as_ref().map(|c| &c.subject),
+                    "EAP authentication accepted"
+                );
+                
+                let mut reply = eap::fragment(&eap);
+                if let Some(keys) = &success.keys {
+                    reply.extend(self.mppe_key_attributes(keys));
+                }
+                
+                // The NAS echoes this User-Name in accounting, so tunneled methods
+                // report the real user rather than the anonymous outer identity
+                if let Some(inner_identity) = &success.inner_identity {
+                    reply.push(Attribute::String("User-Name".to_string(), inner_identity.clone()));
+                }
+                reply.extend(attributes);
+                
+                self.create_accept_response(request, reply)
+            },
+            EapOutcome::Reject { eap, reason } => {
+                tracing::info!(
+                    username = ?request.get_attribute("User-Name"),
+                    reason = reason,
+                    "EAP authentication rejected"
+                );
+                
+                self.create_reject_response(request, &reason, eap::fragment(&eap))
+            },
+        }
+    }
+    
+    /// Get the sessions open according to accounting
+    pub fn sessions(&self) -> &Arc<SessionTable> {
+        self.sessions.table()
+    }
+    
+    /// Record an Accounting-Request in the session table and quotas, and pass it to every backend
+    /// 
+    /// A backend that fails to record it is logged and does not stop the others.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Accounting-Request received
+    pub async fn account(&self, request: &Packet) {
+        self.sessions.table().record(request, Instant::now());
+        self.quotas.account(request);
+
+        for backend in self.backends.iter().filter(|backend| backend.is_enabled()) {
+            if let Err(e) = backend.account(request).await {
+                tracing::warn!(backend = backend.name(), error = %e, "Failed to record accounting");
+            }
+        }
+    }
+    
+    /// Collect a user's groups from every backend
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request being processed
+    /// * `username` - User to look up
+    pub async fn groups(&self, request: &Packet, username: &str) -> Vec<String> {
+        self.authorizer.groups(&self.backends, request, username).await
+    }
+    
+    /// Get the groups a user's profiles are looked up under
+    async fn user_groups(&self, request: &Packet, username: &str) -> Vec<String> {
+        // Without group profiles there is nothing to look groups up for
+        if self.authorizer.is_empty() {
+            return Vec::new();
+        }
+        self.authorizer.groups(&self.backends, request, username).await
+    }
+    
+    /// Apply the group profiles of an accepted user
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request that was accepted
+    /// * `username` - Authenticated user
+    /// * `attributes` - Reply attributes from the backend
+    /// * `found` - Groups the backend found with the credential, e.g. in a token's claims
+    /// 
+    /// # Returns
+    /// 
+    /// The reply attributes and the user's groups, or the reason to reject if
+    /// one of the user's groups rejects its members
+    async fn authorize(&self, request: &Packet, username: &str, attributes: Vec<Attribute>, found: Vec<String>) -> std::result::Result<(Vec<Attribute>, Vec<String>), String> {
+        let mut groups = found;
+        for group in self.user_groups(request, username).await {
+            if !groups.iter().any(|known| known.eq_ignore_ascii_case(&group)) {
+                groups.push(group);
+            }
+        }
+        tracing::debug!(username = username, groups = ?groups, "Authorizing user");
+        
+        let attributes = self.authorizer.apply(&groups, attributes)?;
+        Ok((attributes, groups))
+    }
+    
+    /// Check the session limit and quota of a user about to be sent an Access-Accept
+    /// 
+    /// Runs after every other check, a one-time code included, so a login that
+    /// is rejected never takes a session or starts its quota period.
+    /// 
+    /// # Arguments
+    /// 
+    /// * `request` - Request that was accepted
+    /// * `username` - Authenticated user
+    /// * `groups` - User's groups
+    /// * `attributes` - Reply attributes
+    /// 
+    /// # Returns
+    /// 
+    /// The reply attributes, or the reason to reject if the user has too many
+    /// sessions or their quota is used up
+    async fn admit(&self, request: &Packet, username: &str, groups: &[String], attributes: Vec<Attribute>) -> std::result::Result<Vec<Attribute>, String> {
+        let limit = self.authorizer.max_sessions(groups).or(self.config.sessions.max_sessions);
+        if let Some(limit) = limit {
+            self.sessions.admit(request, username, limit).await?;
+        }
+        
+        if self.quotas.is_empty() {
+            return Ok(attributes);
+        }
+        let admitted = self.quotas.admit(username, self.authorizer.quota(groups), attributes);
+        
+        // The login admitted against the session limit is not going ahead
+        if admitted.is_err() && limit.is_some() {
+            self.sessions.release(request, username);
+        }
+        admitted
+    }
+    
+    /// Ask the backends to authorize a certificate-authenticated peer
+    /// 
+    /// # Returns
+    /// 
+    /// Reply attributes from the first backend that accepts, or the rejection
+    /// reason of the first backend that rejects
+    async fn authorize_certificate(&self, request: &Packet, certificate: &CertificateIdentity) -> std::result::Result<Vec<Attribute>, String> {
+        for backend in &self.backends {
+            if !backend.is_enabled() {
+                continue;
+            }
+            
+            match backend.authorize_certificate(request, certificate).await {
+                Ok(Some(AuthResult::Accept { attributes, .. })) => return Ok(attributes),
+                Ok(Some(AuthResult::Reject { reason, .. })) => return Err(reason),
+                Ok(_) => continue,
+                Err(e) => {
+                    tracing::error!(
+                        backend = backend.name(),
+                        subject = certificate.subject,
+                        error = ?e,
+                        "Certificate authorization error"
+                    );
+                    
+                    return Err("Certificate authorization failed".to_string());
+                }
+            }
+        }
+        
+        Ok(vec![])
+    }
+    
+    /// Create MS-MPPE-Recv-Key and MS-MPPE-Send-Key attributes from an EAP MSK (RFC 3579, section 3.3)
+    /// 
+    /// The keys are in plaintext; `create_accept_response` encrypts them.
+    fn mppe_key_attributes(&self, keys: &EapKeys) -> Vec<Attribute> {
+        if keys.msk.len() < 64 {
+            tracing::warn!(len = keys.msk.len(), "EAP MSK too short for MPPE keys");
+            return vec![];
+        }
+        
+        vec![
+            Attribute::VendorSpecific(VENDOR_MICROSOFT, vec![Attribute::Binary(
+                "MS-MPPE-Recv-Key".to_string(),
+                keys.msk[..32].to_vec(),
+            )]),
+            Attribute::VendorSpecific(VENDOR_MICROSOFT, vec![Attribute::Binary(
+                "MS-MPPE-Send-Key".to_string(),
+                keys.msk[32..64].to_vec(),
+            )]),
+        ]
     }
     
     /// Create an Access-Accept response
+    /// 
+    /// MS-MPPE-Send-Key and MS-MPPE-Recv-Key values from backends and EAP
+    /// methods are plaintext; they are encrypted here with the shared secret
+    /// and the request authenticator (RFC 2548, section 2.4).
     fn create_accept_response(&self, request: &Packet, attributes: Vec<Attribute>) -> Result<Packet> {
         // Create an Access-Accept response
         let mut response = request.create_response(Packet::ACCESS_ACCEPT);
         
+        let secret = self.config.server.secret.as_bytes();
+        let authenticator = request.authenticator();
+        
         // Add attributes
         for attr in attributes {
+            let attr = match attr {
+                Attribute::VendorSpecific(VENDOR_MICROSOFT, attrs) => {
+                    Attribute::VendorSpecific(VENDOR_MICROSOFT, attrs.into_iter()
+                        .map(|sub| match sub {
+                            Attribute::Binary(name, key) if name == "MS-MPPE-Send-Key" || name == "MS-MPPE-Recv-Key" => {
+                                Attribute::Binary(name, radius::encrypt_mppe_key(secret, authenticator, &key))
+                            },
+                            Attribute::Binary(name, keys) if name == "MS-CHAP-MPPE-Keys" => {
+                                Attribute::Binary(name, radius::encrypt_password(secret, authenticator, &keys))
+                            },
+                            other => other,
+                        })
+                        .collect())
+                },
+                other => other,
+            };
+            
             response.add_attribute(attr);
         }
         
@@ -710,3 +2113,507 @@ impl AuthManager {
         Ok(response)
     }
 }
+
+/// Who decides a request that is not locked out
+enum Decider<'a> {
+    /// The backend chain, or the single backend the policy chose
+    Backends(Option<&'a str>),
+    
+    /// The pre-auth policy, which already accepted it
+    Policy,
+}
+
+/// Checks tunneled credentials with the backends, or with the one the policy chose
+struct RoutedInner<'a> {
+    manager: &'a AuthManager,
+    backend: Option<&'a str>,
+}
+
+#[async_trait]
+impl InnerAuthenticator for RoutedInner<'_> {
+    async fn authenticate_inner(&self, request: &Packet) -> Result<AuthResult> {
+        // The inner request names the real user, so user lockout applies here
+        let lockout = &self.manager.lockout;
+        if let Some((key, until)) = lockout.locked(request, Keys::Inner) {
+            tracing::info!(
+                username = ?request.get_text("User-Name"),
+                key = key,
+                until = %until,
+                "Rejecting locked-out inner identity"
+            );
+            return Ok(reject("Too many failed attempts"));
+        }
+        
+        let result = self.manager.authenticate_backends(request, self.backend).await?.0;
+        match &result {
+            AuthResult::Accept { .. } => lockout.record(request, Keys::Inner, true),
+            AuthResult::Reject { .. } => lockout.record(request, Keys::Inner, false),
+            _ => {},
+        }
+        
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use md5::{Digest, Md5};
+    
+    #[test]
+    fn chap() {
+        // CHAP with the Request Authenticator as the challenge
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [7u8; 16]);
+        let mut hasher = Md5::new();
+        hasher.update([42u8]);
+        hasher.update(b"secret");
+        hasher.update([7u8; 16]);
+        let mut value = vec![42u8];
+        value.extend_from_slice(&hasher.finalize());
+        request.add_attribute(Attribute::Binary("CHAP-Password".to_string(), value));
+        
+        assert_eq!(verify_chap(&request, "secret"), Some(true));
+        assert_eq!(verify_chap(&request, "wrong"), Some(false));
+        
+        // An explicit CHAP-Challenge takes precedence
+        request.add_attribute(Attribute::Binary("CHAP-Challenge".to_string(), vec![1, 2, 3]));
+        assert_eq!(verify_chap(&request, "secret"), Some(false));
+    }
+    
+    #[tokio::test]
+    async fn legacy_password_rehashed_on_login() {
+        let users_file = std::env::temp_dir().join(format!("rust-radius-users-{}.json", std::process::id()));
+        let legacy = Credential::hash(Scheme::Sha512Crypt, "secret").unwrap();
+        std::fs::write(&users_file, format!(r#"{{
+            "alice": "{}",
+            "bob": "{{cleartext}}chap",
+            "carol": {{ "password": "pw", "reply": {{ "Session-Timeout": 60 }} }}
+        }}"#, legacy)).unwrap();
+        
+        let mut config = HashMap::new();
+        config.insert("users_file".to_string(), toml::Value::String(users_file.display().to_string()));
+        let backend = LocalAuthBackend::new("local".to_string(), &AuthBackendConfig {
+            backend_type: "local".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config,
+        }).await.unwrap();
+        
+        let login = |username: &str, password: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+            request
+        };
+        
+        assert!(matches!(backend.authenticate(&login("alice", "wrong")).await.unwrap(), AuthResult::Reject { .. }));
+        assert!(matches!(backend.authenticate(&login("alice", "secret")).await.unwrap(), AuthResult::Accept { .. }));
+        assert!(matches!(backend.authenticate(&login("bob", "chap")).await.unwrap(), AuthResult::Accept { .. }));
+        
+        match backend.authenticate(&login("carol", "pw")).await.unwrap() {
+            AuthResult::Accept { attributes, .. } => {
+                assert!(attributes.contains(&Attribute::Integer("Session-Timeout".to_string(), 60)));
+            },
+            other => panic!("unexpected result: {:?}", other),
+        }
+        
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&users_file).unwrap()).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&users_file).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        std::fs::remove_file(&users_file).unwrap();
+        
+        assert!(saved["alice"].as_str().unwrap().starts_with("$argon2id$"));
+        assert_eq!(saved["bob"], "{cleartext}chap");
+        assert_eq!(saved["carol"]["password"], "pw");
+        assert_eq!(saved["carol"]["reply"]["Session-Timeout"], 60);
+        assert!(matches!(backend.authenticate(&login("alice", "secret")).await.unwrap(), AuthResult::Accept { .. }));
+        
+        // Cleartext entries are kept, so MS-CHAP still works after a PAP login
+        let (challenge, peer_challenge) = ([7u8; 16], [9u8; 16]);
+        let nt_response = mschap::generate_nt_response(&challenge, &peer_challenge, "carol", &mschap::nt_password_hash("pw"));
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), "carol".to_string()));
+        for attribute in mschap::v2_request_attributes(1, &challenge, &peer_challenge, &nt_response) {
+            request.add_attribute(attribute);
+        }
+        assert!(matches!(backend.authenticate(&request).await.unwrap(), AuthResult::Accept { .. }));
+    }
+    
+    #[tokio::test]
+    async fn mab_identity_sources() {
+        let backend = MacAuthBackend::new("mac".to_string(), &AuthBackendConfig {
+            backend_type: "mac".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: HashMap::new(),
+        }, None).unwrap();
+        let vlan = vec![Attribute::Integer("Tunnel-Private-Group-Id".to_string(), 20)];
+        let mut device = DeviceEntry::default();
+        device.reply.insert("Tunnel-Private-Group-Id".to_string(), users::ReplyValue::Integer(20));
+        backend.add_mac("aa:bb:cc:dd:ee:ff".parse().unwrap(), device).await.unwrap();
+        
+        let request = |attributes: &[(&str, &str)]| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            for (name, value) in attributes {
+                request.add_attribute(Attribute::String(name.to_string(), value.to_string()));
+            }
+            request
+        };
+        
+        // Cisco, Aruba and UniFi formats of the same device
+        for mac in ["aabb.ccdd.eeff", "AABBCCDDEEFF", "aa-bb-cc-dd-ee-ff"] {
+            let result = backend.authenticate(&request(&[("User-Name", mac), ("User-Password", mac)])).await.unwrap();
+            assert!(matches!(result, AuthResult::Accept { attributes, .. } if attributes == vlan), "{}", mac);
+        }
+        
+        // The device is found by Calling-Station-Id when User-Name is not a MAC
+        let result = backend.authenticate(&request(&[("User-Name", "printer"), ("Calling-Station-Id", "AA-BB-CC-DD-EE-FF")])).await.unwrap();
+        assert!(matches!(result, AuthResult::Accept { .. }));
+        
+        // A password that is not the MAC is refused
+        let result = backend.authenticate(&request(&[("User-Name", "aabbccddeeff"), ("User-Password", "secret")])).await.unwrap();
+        assert!(matches!(result, AuthResult::Reject { .. }));
+    }
+    
+    #[tokio::test]
+    async fn backend_chain_policies() {
+        let dir = std::env::temp_dir().join(format!("rust-radius-chain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("staff.json"), r#"{ "alice": "{cleartext}secret" }"#).unwrap();
+        std::fs::write(dir.join("guests.json"), r#"{ "alice": "{cleartext}guest", "bob": "{cleartext}guest" }"#).unwrap();
+        
+        let manager = |policy: &str| {
+            let mut config = Config::default();
+            config.security.require_message_authenticator = false;
+            config.auth_backends = toml::from_str(&format!(r#"
+                [guests]
+                backend_type = "local"
+                order = 2
+                users_file = {:?}
+                
+                [staff]
+                backend_type = "local"
+                order = 1
+                users_file = {:?}
+                {}
+            "#, dir.join("guests.json"), dir.join("staff.json"), policy)).unwrap();
+            AuthManager::new(Arc::new(config))
+        };
+        let login = |username: &str, password: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+            request
+        };
+        
+        // Users unknown to the first backend fall through, but its Reject is final
+        let chain = manager("").await.unwrap();
+        assert_eq!(chain.backends().iter().map(|backend| backend.name()).collect::<Vec<_>>(), ["staff", "guests"]);
+        assert_eq!(chain.authenticate(&login("bob", "guest")).await.unwrap().code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(chain.authenticate(&login("alice", "guest")).await.unwrap().code(), Packet::ACCESS_REJECT);
+        assert_eq!(chain.authenticate(&login("carol", "guest")).await.unwrap().code(), Packet::ACCESS_REJECT);
+        
+        let chain = manager("on_reject = \"continue\"").await.unwrap();
+        assert_eq!(chain.authenticate(&login("alice", "guest")).await.unwrap().code(), Packet::ACCESS_ACCEPT);
+        let rejected = chain.authenticate(&login("alice", "wrong")).await.unwrap();
+        assert_eq!(rejected.get_text("Reply-Message").as_deref(), Some("Invalid password"));
+        
+        let chain = manager("on_not_found = \"reject\"").await.unwrap();
+        assert_eq!(chain.authenticate(&login("bob", "guest")).await.unwrap().code(), Packet::ACCESS_REJECT);
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[cfg(all(feature = "ldap-auth", feature = "sql-auth"))]
+    #[tokio::test]
+    async fn backend_errors_follow_on_error() {
+        let dir = std::env::temp_dir().join(format!("rust-radius-backend-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("guests.json"), r#"{ "bob": "{cleartext}guest" }"#).unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        
+        // A directory nobody listens for, and a database whose password query fails
+        let failing = [
+            format!(r#"
+                backend_type = "ldap"
+                server = "ldap://127.0.0.1:{}"
+                user_base_dn = "ou=users,dc=example,dc=com"
+            "#, closed),
+            format!(r#"
+                backend_type = "sql"
+                url = "sqlite://{}?mode=rwc"
+                password_query = "SELECT password FROM missing WHERE username = ?"
+            "#, dir.join("radius.db").display()),
+        ];
+        let login = {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), "bob".to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), "guest".to_string()));
+            request
+        };
+        
+        for backend in &failing {
+            let manager = |policy: &str| {
+                let mut config = Config::default();
+                config.security.require_message_authenticator = false;
+                config.auth_backends = toml::from_str(&format!(r#"
+                    [broken]
+                    order = 1
+                    {}
+                    {}
+                    
+                    [guests]
+                    backend_type = "local"
+                    order = 2
+                    users_file = {:?}
+                "#, backend, policy, dir.join("guests.json"))).unwrap();
+                AuthManager::new(Arc::new(config))
+            };
+            
+            // By default the next backend answers instead
+            let chain = manager("").await.unwrap();
+            assert_eq!(chain.authenticate(&login).await.unwrap().code(), Packet::ACCESS_ACCEPT, "{}", backend);
+            
+            let chain = manager("on_error = \"reject\"").await.unwrap();
+            let rejected = chain.authenticate(&login).await.unwrap();
+            assert_eq!(rejected.code(), Packet::ACCESS_REJECT, "{}", backend);
+            assert_eq!(rejected.get_text("Reply-Message").as_deref(), Some("Authentication backend error"));
+            
+            // Failing leaves the request unanswered, so the NAS retries or moves to another server
+            let chain = manager("on_error = \"fail\"").await.unwrap();
+            let error = chain.authenticate(&login).await.unwrap_err();
+            assert!(error.to_string().starts_with("Authentication backend broken failed"), "{}", error);
+        }
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn group_profiles_from_any_backend() {
+        let dir = std::env::temp_dir().join(format!("rust-radius-groups-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("passwords.json"), r#"{ "alice": "{cleartext}secret", "mallory": "{cleartext}secret" }"#).unwrap();
+        std::fs::write(dir.join("groups.json"), r#"{
+            "alice": { "password": "{cleartext}unused", "groups": ["staff"] },
+            "mallory": { "password": "{cleartext}unused", "groups": ["staff", "suspended"] }
+        }"#).unwrap();
+        
+        let mut config = Config::default();
+        config.security.require_message_authenticator = false;
+        config.auth_backends = toml::from_str(&format!(r#"
+            [passwords]
+            backend_type = "local"
+            order = 1
+            users_file = {:?}
+            
+            [groups]
+            backend_type = "local"
+            order = 2
+            users_file = {:?}
+        "#, dir.join("passwords.json"), dir.join("groups.json"))).unwrap();
+        config.authorization = toml::from_str(r#"
+            [groups.staff]
+            reply = { Tunnel-Private-Group-Id = "20", Mikrotik-Rate-Limit = "10M/10M" }
+            
+            [groups.suspended]
+            reject = "Account suspended"
+        "#).unwrap();
+        let manager = AuthManager::new(Arc::new(config)).await.unwrap();
+        
+        let login = |username: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), "secret".to_string()));
+            request
+        };
+        
+        // The first backend checks the password, the second knows the groups
+        let accept = manager.authenticate(&login("alice")).await.unwrap();
+        assert_eq!(accept.code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(accept.get_text("Tunnel-Private-Group-Id").as_deref(), Some("20"));
+        
+        // Vendor attributes of profiles are sent as Vendor-Specific
+        let codec = radius::PacketProcessor::new(Arc::new(Config::default()));
+        let encoded = codec.encode(&accept).unwrap();
+        let decoded = codec.parse(&encoded, "192.0.2.1:1812".parse().unwrap()).unwrap();
+        assert_eq!(
+            decoded.get_vendor_attribute(radius::VENDOR_MIKROTIK, "Mikrotik-Rate-Limit"),
+            Some(&Attribute::Binary("Mikrotik-Rate-Limit".to_string(), b"10M/10M".to_vec()))
+        );
+        
+        let rejected = manager.authenticate(&login("mallory")).await.unwrap();
+        assert_eq!(rejected.get_text("Reply-Message").as_deref(), Some("Account suspended"));
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[cfg(feature = "oauth-auth")]
+    #[tokio::test]
+    async fn claim_groups_stay_with_their_login() {
+        let dir = std::env::temp_dir().join(format!("rust-radius-claim-groups-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("users.json"), r#"{ "alice": "{cleartext}secret" }"#).unwrap();
+        std::fs::write(dir.join("mfa.json"), "{}").unwrap();
+        
+        let idp = crate::oauth::tests::MockIdp::new();
+        let token = idp.token("alice", 300);
+        let url = idp.serve().await;
+        
+        let mut config = Config::default();
+        config.security.require_message_authenticator = false;
+        config.auth_backends = toml::from_str(&format!(r#"
+            [local]
+            backend_type = "local"
+            order = 1
+            users_file = {:?}
+            on_reject = "continue"
+            
+            [idp]
+            backend_type = "oauth"
+            order = 2
+            mode = "bearer"
+            client_id = "radius"
+            issuer = "https://idp.test"
+            audience = "radius"
+            jwks_url = "{}/jwks"
+            group_claim = "realm_access.roles"
+        "#, dir.join("users.json"), url)).unwrap();
+        config.authorization = toml::from_str(r#"
+            [groups.staff]
+            reply = { Tunnel-Private-Group-Id = "20" }
+        "#).unwrap();
+        config.mfa = Some(toml::from_str(&format!("enabled = true\ntokens_file = {:?}", dir.join("mfa.json"))).unwrap());
+        let manager = AuthManager::new(Arc::new(config)).await.unwrap();
+        
+        let login = |password: &str, state: Option<&[u8]>| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.set_source("192.0.2.1:1645".parse().unwrap());
+            request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+            if let Some(state) = state {
+                request.add_attribute(Attribute::Binary("State".to_string(), state.to_vec()));
+            }
+            request
+        };
+        
+        // The token's roles put this login in staff, the password login right after is not
+        let accept = manager.authenticate(&login(&token, None)).await.unwrap();
+        assert_eq!(accept.code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(accept.get_text("Tunnel-Private-Group-Id").as_deref(), Some("20"));
+        let accept = manager.authenticate(&login("secret", None)).await.unwrap();
+        assert_eq!(accept.code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(accept.get_attribute("Tunnel-Private-Group-Id"), None);
+        
+        // The groups are held with the Accept while the one-time code is asked for
+        std::fs::write(dir.join("mfa.json"), r#"{ "alice": { "type": "hotp", "secret": "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ" } }"#).unwrap();
+        let challenge = manager.authenticate(&login(&token, None)).await.unwrap();
+        assert_eq!(challenge.code(), Packet::ACCESS_CHALLENGE);
+        let state = match challenge.get_attribute("State") {
+            Some(Attribute::Binary(_, state)) => state.clone(),
+            other => panic!("no State: {:?}", other),
+        };
+        let accept = manager.authenticate(&login("755224", Some(&state))).await.unwrap();
+        assert_eq!(accept.code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(accept.get_text("Tunnel-Private-Group-Id").as_deref(), Some("20"));
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn mfa_challenge_after_accept() {
+        let dir = std::env::temp_dir().join(format!("rust-radius-mfa-flow-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("users.json"), r#"{ "alice": { "password": "{cleartext}secret", "reply": { "Session-Timeout": 60 } } }"#).unwrap();
+        std::fs::write(dir.join("mfa.json"), r#"{ "alice": { "type": "hotp", "secret": "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ" } }"#).unwrap();
+        
+        let mut config = Config::default();
+        config.security.require_message_authenticator = false;
+        let mut options = HashMap::new();
+        options.insert("users_file".to_string(), toml::Value::String(dir.join("users.json").display().to_string()));
+        config.auth_backends.insert("local".to_string(), AuthBackendConfig {
+            backend_type: "local".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: options,
+        });
+        config.mfa = Some(toml::from_str(&format!("enabled = true\ntokens_file = {:?}", dir.join("mfa.json"))).unwrap());
+        let manager = AuthManager::new(Arc::new(config.clone())).await.unwrap();
+        
+        let login = |password: &str, state: Option<&[u8]>| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.set_source("192.0.2.1:1645".parse().unwrap());
+            request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+            if let Some(state) = state {
+                request.add_attribute(Attribute::Binary("State".to_string(), state.to_vec()));
+            }
+            request
+        };
+        let state = |response: &Packet| match response.get_attribute("State") {
+            Some(Attribute::Binary(_, state)) => state.clone(),
+            other => panic!("no State: {:?}", other),
+        };
+        
+        // The password earns a challenge, and the code releases the held-back reply
+        let challenge = manager.authenticate(&login("secret", None)).await.unwrap();
+        assert_eq!(challenge.code(), Packet::ACCESS_CHALLENGE);
+        let accept = manager.authenticate(&login("755224", Some(&state(&challenge)))).await.unwrap();
+        assert_eq!(accept.code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(accept.get_attribute("Session-Timeout"), Some(&Attribute::Integer("Session-Timeout".to_string(), 60)));
+        
+        // A wrong code burns the State
+        let challenge = manager.authenticate(&login("secret", None)).await.unwrap();
+        let rejected = manager.authenticate(&login("000000", Some(&state(&challenge)))).await.unwrap();
+        assert_eq!(rejected.code(), Packet::ACCESS_REJECT);
+        let replayed = manager.authenticate(&login("287082", Some(&state(&challenge)))).await.unwrap();
+        assert_eq!(replayed.code(), Packet::ACCESS_REJECT);
+        
+        // The session limit is only checked once the code is in
+        std::fs::write(dir.join("mfa.json"), r#"{ "alice": { "type": "hotp", "secret": "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ" } }"#).unwrap();
+        config.sessions.max_sessions = Some(0);
+        let manager = AuthManager::new(Arc::new(config)).await.unwrap();
+        let challenge = manager.authenticate(&login("secret", None)).await.unwrap();
+        assert_eq!(challenge.code(), Packet::ACCESS_CHALLENGE);
+        let rejected = manager.authenticate(&login("755224", Some(&state(&challenge)))).await.unwrap();
+        assert_eq!(rejected.code(), Packet::ACCESS_REJECT);
+        assert_eq!(rejected.get_text("Reply-Message").as_deref(), Some("Too many sessions (0 allowed)"));
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn policy_accept_is_enforced() {
+        let mut config = Config::default();
+        config.sessions.max_sessions = Some(1);
+        let manager = AuthManager::new(Arc::new(config)).await.unwrap();
+        
+        let login = |station: &str, authenticated: bool| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.set_source("192.0.2.1:1645".parse().unwrap());
+            request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+            request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), station.to_string()));
+            if authenticated {
+                request.add_attribute(Attribute::Binary("Message-Authenticator".to_string(), vec![0; 16]));
+            }
+            request
+        };
+        
+        // The policy cannot accept a request the server would refuse anyway
+        assert_eq!(manager.accept(&login("AA-BB-CC-DD-EE-01", false)).await.unwrap().code(), Packet::ACCESS_REJECT);
+        assert_eq!(manager.accept(&login("AA-BB-CC-DD-EE-01", true)).await.unwrap().code(), Packet::ACCESS_ACCEPT);
+        
+        // Nor let a user past the session limit
+        let mut start = Packet::new(Packet::ACCOUNTING_REQUEST, 2, [0u8; 16]);
+        start.set_source("192.0.2.1:1646".parse().unwrap());
+        start.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), 1));
+        start.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+        start.add_attribute(Attribute::String("Acct-Session-Id".to_string(), "s1".to_string()));
+        start.add_attribute(Attribute::String("Calling-Station-Id".to_string(), "AA-BB-CC-DD-EE-01".to_string()));
+        manager.account(&start).await;
+        let rejected = manager.accept(&login("AA-BB-CC-DD-EE-02", true)).await.unwrap();
+        assert_eq!(rejected.get_text("Reply-Message").as_deref(), Some("Too many sessions (1 allowed)"));
+    }
+}
@@ -0,0 +1,250 @@
+// authorize.rs - Authorization phase for rust-radius
+//
+// Backends prove who a user is. Once one accepts, the authorizer asks every
+// backend which groups the user belongs to (users file, SQL radusergroup, LDAP
+// groups), which join those the accepting backend found with the credential
+// (token claims), and applies the group profiles of the configuration.
+// A profile can reject members or add reply attributes such as a VLAN, a
+// Filter-Id, a Session-Timeout or bandwidth limits, so the Access-Accept is
+// the same whichever backend checked the credential.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use crate::auth::AuthBackend;
+use crate::config::{AuthorizationConfig, GroupProfile};
+use crate::protocol::{Attribute, Packet};
+use crate::users;
+
+/// Applies group profiles to accepted users
+pub struct Authorizer {
+    /// Profiles in the order they are applied, with lowercase group names
+    profiles: Vec<(String, GroupProfile)>,
+}
+
+impl Authorizer {
+    /// Create an authorizer
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Authorization configuration
+    pub fn new(config: &AuthorizationConfig) -> Self {
+        let mut profiles: Vec<(String, GroupProfile)> = config.groups.iter()
+            .map(|(name, profile)| (name.to_lowercase(), profile.clone()))
+            .collect();
+        profiles.sort_by(|(a_name, a), (b_name, b)| (a.priority, a_name).cmp(&(b.priority, b_name)));
+        
+        Self { profiles }
+    }
+    
+    /// Check whether any group profile is configured
+    pub fn is_empty(&self) -> bool {
+        self.profiles.is_empty()
+    }
+    
+    /// Collect a user's groups from every backend
+    ///
+    /// A backend that fails to answer is logged and skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `backends` - Backends to ask
+    /// * `request` - Request that was accepted
+    /// * `username` - Authenticated user
+    pub async fn groups(&self, backends: &[Arc<dyn AuthBackend>], request: &Packet, username: &str) -> Vec<String> {
+        let mut groups: Vec<String> = Vec::new();
+        
+        for backend in backends.iter().filter(|backend| backend.is_enabled()) {
+            match backend.groups(request, username).await {
+                Ok(found) => {
+                    for group in found {
+                        if !groups.iter().any(|known| known.eq_ignore_ascii_case(&group)) {
+                            groups.push(group);
+                        }
+                    }
+                },
+                Err(e) => tracing::warn!(backend = backend.name(), user = username, error = %e, "Failed to look up groups"),
+            }
+        }
+        
+        groups
+    }
+    
+    /// Apply the profiles of a user's groups to the attributes of an Accept
+    ///
+    /// The attributes from the backend come first. A profile only adds an
+    /// attribute that neither the backend nor an earlier profile has set.
+    ///
+    /// # Arguments
+    ///
+    /// * `groups` - Groups of the user
+    /// * `attributes` - Reply attributes from the backend
+    ///
+    /// # Returns
+    ///
+    /// The reply attributes, or the reason to reject if a group rejects its members
+    pub fn apply(&self, groups: &[String], mut attributes: Vec<Attribute>) -> std::result::Result<Vec<Attribute>, String> {
+        let groups: HashSet<String> = groups.iter().map(|group| group.to_lowercase()).collect();
+        
+        for (name, profile) in self.profiles.iter().filter(|(name, _)| groups.contains(name)) {
+            if let Some(reason) = &profile.reject {
+                tracing::info!(group = name, reason = reason, "Group rejects its members");
+                return Err(reason.clone());
+            }
+            
+            let present: HashSet<String> = attributes.iter().map(|attr| attr.name().to_string()).collect();
+            attributes.extend(users::reply_attributes(&profile.reply).into_iter()
+                .filter(|attr| !present.contains(attr.name())));
+        }
+        
+        Ok(attributes)
+    }
+    
+    /// Get the session limit of a user's groups
+    ///
+    /// # Returns
+    ///
+    /// The `max_sessions` of the first profile, in priority order, that sets one
+    pub fn max_sessions(&self, groups: &[String]) -> Option<u32> {
+        self.profiles.iter()
+            .filter(|(name, _)| groups.iter().any(|group| group.eq_ignore_ascii_case(name)))
+            .find_map(|(_, profile)| profile.max_sessions)
+    }
+    
+    /// Get the quota plan of a user's groups
+    ///
+    /// # Returns
+    ///
+    /// The `quota` of the first profile, in priority order, that sets one
+    pub fn quota(&self, groups: &[String]) -> Option<&str> {
+        self.profiles.iter()
+            .filter(|(name, _)| groups.iter().any(|group| group.eq_ignore_ascii_case(name)))
+            .find_map(|(_, profile)| profile.quota.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use crate::auth::AuthResult;
+    use crate::Result;
+    
+    /// Backend that only answers group lookups
+    struct Directory {
+        name: &'static str,
+        enabled: bool,
+        groups: std::result::Result<Vec<&'static str>, &'static str>,
+    }
+    
+    #[async_trait]
+    impl AuthBackend for Directory {
+        fn name(&self) -> &str {
+            self.name
+        }
+        
+        fn is_enabled(&self) -> bool {
+            self.enabled
+        }
+        
+        async fn authenticate(&self, _request: &Packet) -> Result<AuthResult> {
+            Ok(AuthResult::NotFound)
+        }
+        
+        async fn groups(&self, _request: &Packet, _username: &str) -> Result<Vec<String>> {
+            match &self.groups {
+                Ok(groups) => Ok(groups.iter().map(|group| group.to_string()).collect()),
+                Err(e) => Err((*e).into()),
+            }
+        }
+    }
+    
+    #[test]
+    fn profiles_by_priority() {
+        let config: AuthorizationConfig = toml::from_str(r#"
+            [groups.Staff]
+            priority = 10
+            reply = { Tunnel-Private-Group-Id = "20", Session-Timeout = 28800 }
+            
+            [groups.guests]
+            max_sessions = 1
+            reply = { Tunnel-Private-Group-Id = "30", Filter-Id = ["guest", "throttled"] }
+            
+            [groups.suspended]
+            reject = "Account suspended"
+        "#).unwrap();
+        let authorizer = Authorizer::new(&config);
+        let groups = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        
+        // The backend's Session-Timeout and the higher priority VLAN win
+        let backend = vec![Attribute::Integer("Session-Timeout".to_string(), 600)];
+        assert_eq!(authorizer.apply(&groups(&["guests", "staff"]), backend), Ok(vec![
+            Attribute::Integer("Session-Timeout".to_string(), 600),
+            Attribute::String("Tunnel-Private-Group-Id".to_string(), "20".to_string()),
+            Attribute::String("Filter-Id".to_string(), "guest".to_string()),
+            Attribute::String("Filter-Id".to_string(), "throttled".to_string()),
+        ]));
+        
+        assert_eq!(authorizer.apply(&groups(&["staff", "Suspended"]), vec![]), Err("Account suspended".to_string()));
+        assert_eq!(authorizer.apply(&groups(&["visitors"]), vec![]), Ok(vec![]));
+        
+        assert_eq!(authorizer.max_sessions(&groups(&["staff", "Guests"])), Some(1));
+        assert_eq!(authorizer.max_sessions(&groups(&["staff"])), None);
+    }
+    
+    #[tokio::test]
+    async fn groups_from_every_backend() {
+        let backends: Vec<Arc<dyn AuthBackend>> = vec![
+            Arc::new(Directory { name: "local", enabled: true, groups: Ok(vec!["Staff", "wifi"]) }),
+            Arc::new(Directory { name: "ldap", enabled: true, groups: Err("Server unreachable") }),
+            Arc::new(Directory { name: "sql", enabled: true, groups: Ok(vec!["staff", "VPN"]) }),
+            Arc::new(Directory { name: "rest", enabled: false, groups: Ok(vec!["admins"]) }),
+        ];
+        let authorizer = Authorizer::new(&AuthorizationConfig::default());
+        let request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        
+        // A failing backend is skipped, disabled ones are not asked, and names are kept as first seen
+        assert_eq!(authorizer.groups(&backends, &request, "alice").await, vec!["Staff", "wifi", "VPN"]);
+        assert!(authorizer.is_empty());
+    }
+    
+    #[test]
+    fn profile_merging() {
+        let config: AuthorizationConfig = toml::from_str(r#"
+            [groups.beta]
+            priority = 20
+            quota = "beta"
+            reply = { Filter-Id = "beta", Reply-Message = "Welcome" }
+            
+            [groups.alpha]
+            priority = 20
+            reply = { Filter-Id = "alpha", Class = "alpha" }
+            
+            [groups.contractors]
+            max_sessions = 3
+            quota = "contractors"
+            reply = { Session-Timeout = 3600 }
+            
+            [groups.expired]
+            priority = 50
+            reject = "Contract ended"
+        "#).unwrap();
+        let authorizer = Authorizer::new(&config);
+        let groups = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<_>>();
+        
+        // Equal priorities are applied by name, and lower priority profiles only fill gaps
+        assert_eq!(authorizer.apply(&groups(&["contractors", "beta", "alpha"]), vec![]), Ok(vec![
+            Attribute::String("Class".to_string(), "alpha".to_string()),
+            Attribute::String("Filter-Id".to_string(), "alpha".to_string()),
+            Attribute::String("Reply-Message".to_string(), "Welcome".to_string()),
+            Attribute::Integer("Session-Timeout".to_string(), 3600),
+        ]));
+        
+        // A rejecting group wins even after other profiles have added attributes
+        assert_eq!(authorizer.apply(&groups(&["alpha", "expired", "contractors"]), vec![]), Err("Contract ended".to_string()));
+        
+        assert_eq!(authorizer.quota(&groups(&["contractors", "BETA"])), Some("beta"));
+        assert_eq!(authorizer.quota(&groups(&["alpha"])), None);
+        assert_eq!(authorizer.max_sessions(&groups(&["beta", "contractors"])), Some(3));
+    }
+}
@@ -2,6 +2,7 @@
 //! This is a stub version for development purposes
 
 /// The captive portal module handles the web interface for guest access
+#[derive(Default)]
 pub struct CaptivePortal;
 
 impl CaptivePortal {
@@ -4,14 +4,14 @@
 // It implements the "Simplified Deployment and Configuration" goal by providing sensible
 // defaults and deployment templates for common scenarios.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 use toml;
 
+use crate::users::ReplyValue;
 use crate::Result;
 
 /// Server configuration
@@ -113,6 +113,56 @@ pub struct MetricsConfig {
     pub interval_secs: u64,
 }
 
+/// Admin API configuration
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdminConfig {
+    /// Whether the admin API is enabled
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+    
+    /// Host to bind the admin API to (default: 127.0.0.1)
+    #[serde(default = "default_metrics_host")]
+    pub host: String,
+    
+    /// HTTP port for the admin API (default: 8081)
+    #[serde(default = "default_admin_port")]
+    pub port: u16,
+    
+    /// Bearer token required on every request (at least 16 characters)
+    pub token: String,
+    
+    /// MAC registry file managed through the API
+    pub macs_file: Option<PathBuf>,
+}
+
+/// One-time password (second factor) configuration
+///
+/// Users with a token in the tokens file are challenged for a code after a
+/// backend accepts them; the challenge is kept like any other conversation.
+/// EAP logins are not challenged.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MfaConfig {
+    /// Whether enrolled users are challenged
+    #[serde(default = "default_false")]
+    pub enabled: bool,
+    
+    /// OTP tokens file, managed with `rust-radius mfa` (default: config/mfa.json)
+    #[serde(default = "default_mfa_tokens_file")]
+    pub tokens_file: PathBuf,
+    
+    /// Reply-Message of the challenge
+    #[serde(default = "default_mfa_prompt")]
+    pub prompt: String,
+    
+    /// TOTP time steps accepted before and after the current one, for clock drift (default: 1)
+    #[serde(default = "default_mfa_totp_drift")]
+    pub totp_drift: u64,
+    
+    /// HOTP counters accepted ahead of the expected one, for unused codes (default: 10)
+    #[serde(default = "default_mfa_hotp_window")]
+    pub hotp_window: u64,
+}
+
 /// Main configuration structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -134,6 +184,46 @@ pub struct Config {
     /// Captive portal configuration (optional)
     pub captive_portal: Option<CaptivePortalConfig>,
     
+    /// Admin API configuration
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    
+    /// EAP configuration
+    #[serde(default)]
+    pub eap: EapConfig,
+    
+    /// Challenge exchange configuration
+    #[serde(default)]
+    pub conversations: ConversationConfig,
+    
+    /// One-time password configuration
+    #[serde(default)]
+    pub mfa: Option<MfaConfig>,
+    
+    /// Group profiles applied after authentication
+    #[serde(default)]
+    pub authorization: AuthorizationConfig,
+    
+    /// Policy rules run in the request pipeline
+    #[serde(default)]
+    pub policy: Option<PolicyConfig>,
+    
+    /// Session table and Simultaneous-Use
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    
+    /// Disconnect and CoA requests sent to NASes
+    #[serde(default)]
+    pub dynamic_authorization: DynamicAuthConfig,
+    
+    /// Data and time quotas
+    #[serde(default)]
+    pub quotas: QuotasConfig,
+    
+    /// Lockout after repeated failures
+    #[serde(default)]
+    pub lockout: LockoutConfig,
+
     /// Deployment template (optional)
     #[serde(skip)]
     pub template: Option<DeploymentTemplate>,
@@ -149,11 +239,74 @@ pub struct AuthBackendConfig {
     #[serde(default = "default_true")]
     pub enabled: bool,
     
+    /// Position in the chain and what happens after this backend answers
+    #[serde(flatten)]
+    pub chain: ChainPolicy,
+    
     /// Backend-specific configuration
     #[serde(flatten)]
     pub config: HashMap<String, toml::Value>,
 }
 
+/// Where a backend sits in the chain and when the next one is tried
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ChainPolicy {
+    /// Position in the chain, lowest first (default: the backend type's priority)
+    #[serde(default)]
+    pub order: Option<u32>,
+    
+    /// What to do when the backend rejects (default: stop)
+    #[serde(default)]
+    pub on_reject: RejectPolicy,
+    
+    /// What to do when the backend does not know the user (default: continue)
+    #[serde(default)]
+    pub on_not_found: NotFoundPolicy,
+    
+    /// What to do when the backend fails, e.g. its server is down (default: continue)
+    #[serde(default)]
+    pub on_error: ErrorPolicy,
+}
+
+/// Action after a backend rejects
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RejectPolicy {
+    /// Send the Access-Reject
+    #[default]
+    Stop,
+    
+    /// Try the next backend, and send the Reject only if none accepts
+    Continue,
+}
+
+/// Action after a backend does not know the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotFoundPolicy {
+    /// Try the next backend
+    #[default]
+    Continue,
+    
+    /// Send an Access-Reject
+    Reject,
+}
+
+/// Action after a backend fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    /// Try the next backend
+    #[default]
+    Continue,
+    
+    /// Send an Access-Reject
+    Reject,
+    
+    /// Send no answer, so the NAS fails over to another RADIUS server
+    Fail,
+}
+
 /// Captive portal configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CaptivePortalConfig {
@@ -199,6 +352,370 @@ pub struct PortalBrandingConfig {
     pub background_image: Option<PathBuf>,
 }
 
+/// EAP configuration
+///
+/// The EAP methods offered to clients are the EAP entries of
+/// `security.auth_protocols`, in order of preference.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EapConfig {
+    /// Seconds an EAP conversation may stay idle before its State expires (default: 60)
+    #[serde(default = "default_eap_state_timeout")]
+    pub state_timeout_secs: u64,
+    
+    /// Maximum number of concurrent EAP conversations (default: 4096)
+    #[serde(default = "default_eap_max_conversations")]
+    pub max_conversations: usize,
+    
+    /// Maximum number of Access-Challenges in one EAP conversation (default: 50)
+    #[serde(default = "default_eap_max_rounds")]
+    pub max_rounds: u32,
+    
+    /// TLS settings for TLS-based EAP methods (required for eap-tls)
+    pub tls: Option<EapTlsConfig>,
+}
+
+impl Default for EapConfig {
+    fn default() -> Self {
+        Self {
+            state_timeout_secs: default_eap_state_timeout(),
+            max_conversations: default_eap_max_conversations(),
+            max_rounds: default_eap_max_rounds(),
+            tls: None,
+        }
+    }
+}
+
+/// Settings of challenge exchanges outside EAP (one-time codes, backend challenges)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ConversationConfig {
+    /// File keeping pending exchanges across restarts (default: memory only)
+    pub file: Option<PathBuf>,
+    
+    /// Seconds a challenge may stay unanswered (default: 120)
+    #[serde(default = "default_conversation_timeout")]
+    pub timeout_secs: u64,
+    
+    /// Maximum number of pending exchanges (default: 4096)
+    #[serde(default = "default_eap_max_conversations")]
+    pub max_conversations: usize,
+    
+    /// Maximum number of Access-Challenges in one exchange (default: 5)
+    #[serde(default = "default_conversation_max_rounds")]
+    pub max_rounds: u32,
+}
+
+impl Default for ConversationConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            timeout_secs: default_conversation_timeout(),
+            max_conversations: default_eap_max_conversations(),
+            max_rounds: default_conversation_max_rounds(),
+        }
+    }
+}
+
+/// Authorization settings
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuthorizationConfig {
+    /// Profiles by group name (compared without regard to case)
+    #[serde(default)]
+    pub groups: BTreeMap<String, GroupProfile>,
+}
+
+/// What membership of a group means for a user
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupProfile {
+    /// Profiles are applied lowest first; the first to set an attribute wins (default: 100)
+    #[serde(default = "default_group_priority")]
+    pub priority: u32,
+    
+    /// Reject members with this reason
+    #[serde(default)]
+    pub reject: Option<String>,
+    
+    /// Attributes added to the Access-Accept of members, by attribute name
+    #[serde(default)]
+    pub reply: BTreeMap<String, ReplyValue>,
+    
+    /// Concurrent sessions allowed to members, overriding `sessions.max_sessions`
+    #[serde(default)]
+    pub max_sessions: Option<u32>,
+    
+    /// Quota plan of members, from `quotas.plans`
+    #[serde(default)]
+    pub quota: Option<String>,
+}
+
+/// Session table kept from accounting, and the Simultaneous-Use check run during authorization
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionsConfig {
+    /// Concurrent sessions allowed per user, unless a group profile sets its own (default: unlimited)
+    #[serde(default)]
+    pub max_sessions: Option<u32>,
+    
+    /// What happens to a login over the limit (default: reject)
+    #[serde(default)]
+    pub at_limit: LimitAction,
+    
+    /// Seconds without accounting after which a session is stale (default: 7200)
+    #[serde(default = "default_session_stale_after")]
+    pub stale_after_secs: u64,
+    
+    /// Ask the NAS whether a stale session is still up instead of dropping it (default: false)
+    #[serde(default)]
+    pub confirm_stale: bool,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: None,
+            at_limit: LimitAction::default(),
+            stale_after_secs: default_session_stale_after(),
+            confirm_stale: false,
+        }
+    }
+}
+
+/// Action when a user already has the maximum number of sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitAction {
+    /// Reject the new login
+    #[default]
+    Reject,
+    
+    /// Send a Disconnect-Request for the oldest session, and reject only if the NAS refuses
+    DisconnectOldest,
+}
+
+/// Dynamic authorization (RFC 5176) requests sent to NASes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DynamicAuthConfig {
+    /// Port NASes listen on for Disconnect and CoA requests (default: 3799)
+    #[serde(default = "default_dynamic_auth_port")]
+    pub port: u16,
+    
+    /// Milliseconds to wait for each answer (default: 2000)
+    #[serde(default = "default_dynamic_auth_timeout")]
+    pub timeout_ms: u64,
+    
+    /// Retransmissions after a timeout (default: 2)
+    #[serde(default = "default_dynamic_auth_retries")]
+    pub retries: u32,
+}
+
+impl Default for DynamicAuthConfig {
+    fn default() -> Self {
+        Self {
+            port: default_dynamic_auth_port(),
+            timeout_ms: default_dynamic_auth_timeout(),
+            retries: default_dynamic_auth_retries(),
+        }
+    }
+}
+
+/// Data and time quotas, counted from accounting and reset on a schedule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotasConfig {
+    /// Usage counters, written by the server (default: config/quotas.json)
+    #[serde(default = "default_quotas_file")]
+    pub file: PathBuf,
+    
+    /// What happens to a session that uses up its quota (default: disconnect)
+    #[serde(default)]
+    pub on_exhausted: QuotaAction,
+    
+    /// Attributes of the throttled profile, sent in a CoA-Request and in the Accept of a user over quota
+    #[serde(default)]
+    pub throttle: BTreeMap<String, ReplyValue>,
+    
+    /// Plans by name, given to the members of a group with its `quota`
+    #[serde(default)]
+    pub plans: BTreeMap<String, QuotaPlan>,
+    
+    /// Quotas of single users, taking precedence over their groups' plans
+    #[serde(default)]
+    pub users: BTreeMap<String, QuotaPlan>,
+}
+
+impl Default for QuotasConfig {
+    fn default() -> Self {
+        Self {
+            file: default_quotas_file(),
+            on_exhausted: QuotaAction::default(),
+            throttle: BTreeMap::new(),
+            plans: BTreeMap::new(),
+            users: BTreeMap::new(),
+        }
+    }
+}
+
+/// Limits of a quota plan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaPlan {
+    /// Data per period, in and out, e.g. "1G" (default: unlimited)
+    #[serde(default)]
+    pub data: Option<String>,
+    
+    /// Session time per period, e.g. "2h" (default: unlimited)
+    #[serde(default)]
+    pub time: Option<String>,
+    
+    /// When the counters start again (default: daily)
+    #[serde(default)]
+    pub reset: QuotaReset,
+}
+
+/// Schedule on which quota counters reset, in local time
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaReset {
+    /// At midnight
+    #[default]
+    Daily,
+    
+    /// At midnight between Sunday and Monday
+    Weekly,
+    
+    /// At midnight on the first of the month
+    Monthly,
+    
+    /// Never; the quota is a total
+    Never,
+}
+
+/// Action when a session uses up its quota
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaAction {
+    /// End the session with a Disconnect-Request, and reject logins until the reset
+    #[default]
+    Disconnect,
+    
+    /// Move the session to the `throttle` profile with a CoA-Request
+    Throttle,
+}
+
+/// Lockout of users, devices or NASes after repeated failures
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockoutConfig {
+    /// Locks in force, written by the server and `rust-radius lockouts` (default: config/lockouts.json)
+    #[serde(default = "default_lockouts_file")]
+    pub file: PathBuf,
+    
+    /// What to count failures by, and when to lock; no rules disables lockout
+    #[serde(default)]
+    pub rules: Vec<LockoutRule>,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            file: default_lockouts_file(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+/// When failures sharing a key lead to a lock
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LockoutRule {
+    /// What failures are counted by
+    pub key: LockoutKey,
+    
+    /// Failures within the window that lock the key (default: 5)
+    #[serde(default = "default_lockout_failures")]
+    pub max_failures: u32,
+    
+    /// Seconds over which failures are counted (default: 300)
+    #[serde(default = "default_lockout_window")]
+    pub window_secs: u64,
+    
+    /// Seconds a lock lasts (default: 900)
+    #[serde(default = "default_lockout_duration")]
+    pub lockout_secs: u64,
+}
+
+/// Attribute that failures are counted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockoutKey {
+    /// User-Name, without regard to case
+    Username,
+    
+    /// Calling-Station-Id, usually the MAC address of the device
+    CallingStationId,
+    
+    /// NAS-IP-Address, or the address of the client
+    Nas,
+}
+
+/// Policy rules and plugins
+///
+/// The rules file holds `pre-auth`, `post-auth` and `pre-acct` sections; see
+/// the `policy` module for the language. Plugins run after the rules at every
+/// hook, until one of them accepts or rejects.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PolicyConfig {
+    /// Rules file
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+    
+    /// WebAssembly plugins implementing the `policy-hook` world of wit/plugin.wit
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
+}
+
+/// WebAssembly plugin and the limits of each call
+///
+/// Also the options of `wasm` backends.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginConfig {
+    /// Component file
+    pub module: PathBuf,
+    
+    /// Fuel of each call, roughly one unit per instruction (default: 10000000)
+    #[serde(default = "default_plugin_fuel")]
+    pub fuel: u64,
+    
+    /// Linear memory cap in MiB (default: 16)
+    #[serde(default = "default_plugin_memory_mb")]
+    pub memory_mb: usize,
+    
+    /// Deadline of each call in milliseconds (default: 100)
+    #[serde(default = "default_plugin_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+/// TLS settings for TLS-based EAP methods
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EapTlsConfig {
+    /// Server certificate chain (PEM)
+    pub cert_file: PathBuf,
+    
+    /// Server private key (PEM, PKCS#8, PKCS#1 or SEC1)
+    pub key_file: PathBuf,
+    
+    /// CA certificates that client certificates must chain to (PEM)
+    pub ca_file: Option<PathBuf>,
+    
+    /// Certificate revocation list checked for client certificates (PEM or DER)
+    pub crl_file: Option<PathBuf>,
+    
+    /// Maximum TLS data carried in one EAP packet (default: 1024)
+    #[serde(default = "default_eap_fragment_size")]
+    pub fragment_size: usize,
+    
+    /// Minimum TLS version, "1.2" or "1.3" (default: 1.2)
+    #[serde(default = "default_eap_tls_min_version")]
+    pub min_version: String,
+}
+
 /// Deployment template for simplified configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DeploymentTemplate {
@@ -242,7 +759,7 @@ impl Config {
         let content = fs::read_to_string(path)
             .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
         
-        let mut config: Self = toml::from_str(&content)
+        let config: Self = toml::from_str(&content)
             .map_err(|e| format!("Failed to parse config file {}: {}", path.display(), e))?;
         
         // Validate the configuration
@@ -264,8 +781,10 @@ impl Config {
     pub fn from_template(template: DeploymentTemplate, secret: String) -> Self {
         // GOAL: Simplified Deployment and Configuration
         // Create pre-configured templates for common deployment scenarios
-        let mut config = Self::default();
-        config.template = Some(template.clone());
+        let mut config = Self {
+            template: Some(template.clone()),
+            ..Self::default()
+        };
         config.server.secret = secret;
         
         match template {
@@ -274,6 +793,7 @@ impl Config {
                 let mut auth_backend = AuthBackendConfig {
                     backend_type: "local".to_string(),
                     enabled: true,
+                    chain: Default::default(),
                     config: HashMap::new(),
                 };
                 auth_backend.config.insert("users_file".to_string(), 
@@ -285,6 +805,7 @@ impl Config {
                 let mut auth_backend = AuthBackendConfig {
                     backend_type: "mac".to_string(),
                     enabled: true,
+                    chain: Default::default(),
                     config: HashMap::new(),
                 };
                 auth_backend.config.insert("accept_unknown".to_string(), 
@@ -312,6 +833,7 @@ impl Config {
                 let mut auth_backend = AuthBackendConfig {
                     backend_type: "ldap".to_string(),
                     enabled: true,
+                    chain: Default::default(),
                     config: HashMap::new(),
                 };
                 auth_backend.config.insert("server".to_string(), 
@@ -340,6 +862,7 @@ impl Config {
                 let mut auth_backend = AuthBackendConfig {
                     backend_type: "oauth".to_string(),
                     enabled: true,
+                    chain: Default::default(),
                     config: HashMap::new(),
                 };
                 auth_backend.config.insert("provider".to_string(), 
@@ -348,7 +871,11 @@ impl Config {
                     toml::Value::String("your-client-id".to_string()));
                 auth_backend.config.insert("client_secret".to_string(), 
                     toml::Value::String("your-client-secret".to_string()));
-                
+                auth_backend.config.insert("token_url".to_string(), 
+                    toml::Value::String("https://login.microsoftonline.com/your-tenant-id/oauth2/v2.0/token".to_string()));
+                auth_backend.config.insert("scopes".to_string(), 
+                    toml::Value::Array(vec![toml::Value::String("openid".to_string())]));
+
                 config.auth_backends.insert("oauth".to_string(), auth_backend);
                 
                 // Enable captive portal with corporate branding
@@ -380,21 +907,23 @@ impl Config {
         let mut mac_auth = AuthBackendConfig {
             backend_type: "mac".to_string(),
             enabled: true,
+            chain: Default::default(),
             config: HashMap::new(),
         };
         mac_auth.config.insert("accept_unknown".to_string(), 
             toml::Value::Boolean(true));
         config.auth_backends.insert("mac".to_string(), mac_auth);
         
-        // Local user database for vouchers
-        let mut local_auth = AuthBackendConfig {
-            backend_type: "local".to_string(),
+        // Guest vouchers, created with `rust-radius vouchers generate`
+        let mut voucher_auth = AuthBackendConfig {
+            backend_type: "voucher".to_string(),
             enabled: true,
+            chain: Default::default(),
             config: HashMap::new(),
         };
-        local_auth.config.insert("users_file".to_string(), 
+        voucher_auth.config.insert("file".to_string(), 
             toml::Value::String("config/vouchers.json".to_string()));
-        config.auth_backends.insert("local".to_string(), local_auth);
+        config.auth_backends.insert("vouchers".to_string(), voucher_auth);
         
         // Captive portal with venue-specific branding
         let title = format!("{} WiFi Access", venue_type);
@@ -461,6 +990,38 @@ impl Config {
             }
         }
         
+        // EAP-TLS needs a server certificate and a CA to validate clients against
+        let wants_eap_tls = self.security.auth_protocols.iter()
+            .any(|p| p.eq_ignore_ascii_case("eap-tls"));
+        
+        if wants_eap_tls {
+            match &self.eap.tls {
+                Some(tls) if tls.ca_file.is_some() => {},
+                Some(_) => return Err("eap.tls.ca_file must be specified when eap-tls is enabled".into()),
+                None => return Err("eap.tls must be configured when eap-tls is enabled".into()),
+            }
+        }
+        
+        // The admin API can change who gets on the network
+        if let Some(admin) = self.admin.as_ref().filter(|admin| admin.enabled) {
+            if admin.token.len() < 16 {
+                return Err("admin.token must be at least 16 characters long".into());
+            }
+        }
+        
+        // Group profiles can only refer to quota plans that exist
+        for (group, profile) in &self.authorization.groups {
+            if let Some(plan) = profile.quota.as_ref().filter(|plan| !self.quotas.plans.contains_key(*plan)) {
+                return Err(format!("authorization.groups.{}.quota refers to unknown plan {}", group, plan).into());
+            }
+        }
+        if self.lockout.rules.iter().any(|rule| rule.max_failures == 0) {
+            return Err("lockout.rules max_failures must be at least 1".into());
+        }
+        if self.quotas.on_exhausted == QuotaAction::Throttle && self.quotas.throttle.is_empty() {
+            return Err("quotas.throttle must be set when quotas.on_exhausted is throttle".into());
+        }
+        
         // Validate that at least one auth backend is enabled
         let has_enabled_backend = self.auth_backends.values()
             .any(|backend| backend.enabled);
@@ -473,6 +1034,19 @@ impl Config {
     }
 }
 
+impl AuthBackendConfig {
+    /// Deserialize the backend-specific options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an option is missing or has the wrong type
+    pub fn options<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        let table: toml::Table = self.config.clone().into_iter().collect();
+        toml::Value::Table(table).try_into()
+            .map_err(|e| format!("Invalid {} backend options: {}", self.backend_type, e).into())
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         // GOAL: Simplified Deployment and Configuration
@@ -510,6 +1084,16 @@ impl Default for Config {
             },
             auth_backends: HashMap::new(),
             captive_portal: None,
+            admin: None,
+            eap: EapConfig::default(),
+            conversations: ConversationConfig::default(),
+            mfa: None,
+            authorization: AuthorizationConfig::default(),
+            policy: None,
+            sessions: SessionsConfig::default(),
+            dynamic_authorization: DynamicAuthConfig::default(),
+            quotas: QuotasConfig::default(),
+            lockout: LockoutConfig::default(),
             template: None,
         }
     }
@@ -553,6 +1137,46 @@ fn default_radsec_enabled() -> bool {
     cfg!(feature = "radsec")
 }
 
+fn default_group_priority() -> u32 {
+    100
+}
+
+fn default_session_stale_after() -> u64 {
+    7200
+}
+
+fn default_quotas_file() -> PathBuf {
+    PathBuf::from("config/quotas.json")
+}
+
+fn default_lockouts_file() -> PathBuf {
+    PathBuf::from("config/lockouts.json")
+}
+
+fn default_lockout_failures() -> u32 {
+    5
+}
+
+fn default_lockout_window() -> u64 {
+    300
+}
+
+fn default_lockout_duration() -> u64 {
+    900
+}
+
+fn default_dynamic_auth_port() -> u16 {
+    3799
+}
+
+fn default_dynamic_auth_timeout() -> u64 {
+    2000
+}
+
+fn default_dynamic_auth_retries() -> u32 {
+    2
+}
+
 fn default_true() -> bool {
     true
 }
@@ -581,10 +1205,70 @@ fn default_metrics_interval() -> u64 {
     10
 }
 
+fn default_eap_state_timeout() -> u64 {
+    60
+}
+
+fn default_eap_max_conversations() -> usize {
+    4096
+}
+
+fn default_eap_max_rounds() -> u32 {
+    50
+}
+
+fn default_eap_fragment_size() -> usize {
+    1024
+}
+
+fn default_eap_tls_min_version() -> String {
+    "1.2".to_string()
+}
+
+fn default_conversation_timeout() -> u64 {
+    120
+}
+
+fn default_conversation_max_rounds() -> u32 {
+    5
+}
+
+fn default_plugin_fuel() -> u64 {
+    10_000_000
+}
+
+fn default_plugin_memory_mb() -> usize {
+    16
+}
+
+fn default_plugin_timeout_ms() -> u64 {
+    100
+}
+
+fn default_mfa_tokens_file() -> PathBuf {
+    PathBuf::from("config/mfa.json")
+}
+
+fn default_mfa_prompt() -> String {
+    "Enter your one-time code".to_string()
+}
+
+fn default_mfa_totp_drift() -> u64 {
+    1
+}
+
+fn default_mfa_hotp_window() -> u64 {
+    10
+}
+
 fn default_portal_port() -> u16 {
     8080
 }
 
+fn default_admin_port() -> u16 {
+    8081
+}
+
 fn default_portal_title() -> String {
     "WiFi Access Portal".to_string()
 }
@@ -0,0 +1,505 @@
+// conversation.rs - Server-side state of multi-round exchanges
+//
+// EAP, one-time codes and backends that ask for more than a password all need
+// context between an Access-Challenge and the request answering it, but RADIUS
+// only carries an opaque State. Conversations are stored server-side under
+// random State values. Each State is bound to the client, NAS and user it was
+// issued to, expires, and can be answered once. An exchange is capped at a
+// number of rounds so a peer cannot keep one going forever.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use rand::RngCore;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::config::ConversationConfig;
+use crate::protocol::{Attribute, Packet};
+use crate::store;
+use crate::Result;
+
+/// Length of the State values issued
+const STATE_LEN: usize = 16;
+
+/// Who a State was issued to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    /// Address of the RADIUS client
+    pub client: Option<IpAddr>,
+    
+    /// NAS-IP-Address, NAS-IPv6-Address or NAS-Identifier
+    pub nas: Option<String>,
+    
+    /// User-Name
+    pub username: Option<String>,
+}
+
+impl Binding {
+    /// Get the binding of a request
+    pub fn of(request: &Packet) -> Self {
+        let nas = match (request.get_attribute("NAS-IP-Address"), request.get_attribute("NAS-IPv6-Address")) {
+            (Some(Attribute::IpAddr(_, address)), _) => Some(address.to_string()),
+            (_, Some(Attribute::Ipv6Addr(_, address))) => Some(address.to_string()),
+            _ => request.get_text("NAS-Identifier"),
+        };
+        
+        Self {
+            client: request.source().map(|source| source.ip()),
+            nas,
+            username: request.get_text("User-Name"),
+        }
+    }
+}
+
+/// Context of an exchange between two packets
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation<C> {
+    /// Backend or stage that issued the challenge, and gets the context back
+    pub owner: String,
+    
+    /// What the owner needs to continue
+    pub context: C,
+    
+    /// Who the State was issued to
+    pub binding: Binding,
+    
+    /// Challenges issued in this exchange so far
+    pub rounds: u32,
+    
+    /// Expiry as Unix time in milliseconds
+    pub expires: u64,
+}
+
+impl<C> Conversation<C> {
+    /// Check whether the conversation has expired
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires <= now
+    }
+}
+
+/// Storage for conversations awaiting their next packet
+#[async_trait]
+pub trait ConversationStore<C>: Send + Sync {
+    /// Store a conversation under a State
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store is full or cannot be written
+    async fn insert(&self, state: Vec<u8>, conversation: Conversation<C>) -> Result<()>;
+    
+    /// Remove and return the conversation stored under a State, expired or not
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read
+    async fn take(&self, state: &[u8]) -> Result<Option<Conversation<C>>>;
+}
+
+/// Conversations kept in memory; they are lost on restart
+pub struct MemoryConversationStore<C> {
+    /// Conversations by State
+    conversations: Mutex<HashMap<Vec<u8>, Conversation<C>>>,
+    
+    /// Maximum number of stored conversations
+    capacity: usize,
+}
+
+impl<C> MemoryConversationStore<C> {
+    /// Create an empty store
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Maximum number of stored conversations
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            conversations: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+}
+
+#[async_trait]
+impl<C: Send> ConversationStore<C> for MemoryConversationStore<C> {
+    async fn insert(&self, state: Vec<u8>, conversation: Conversation<C>) -> Result<()> {
+        let mut conversations = self.conversations.lock().await;
+        
+        // Drop expired conversations before checking capacity
+        let now = now_millis();
+        conversations.retain(|_, conversation| !conversation.is_expired(now));
+        
+        if conversations.len() >= self.capacity {
+            return Err("Too many concurrent conversations".into());
+        }
+        
+        conversations.insert(state, conversation);
+        Ok(())
+    }
+    
+    async fn take(&self, state: &[u8]) -> Result<Option<Conversation<C>>> {
+        Ok(self.conversations.lock().await.remove(state))
+    }
+}
+
+/// Conversations kept in a JSON file, so they survive a restart
+///
+/// The file is read and rewritten on every change, which suits the modest
+/// number of exchanges a challenge-based login produces.
+pub struct FileConversationStore {
+    /// File holding the conversations by hex-encoded State
+    path: PathBuf,
+    
+    /// Maximum number of stored conversations
+    capacity: usize,
+    
+    /// Serializes access to the file
+    lock: Mutex<()>,
+}
+
+impl FileConversationStore {
+    /// Create a store backed by a file, which is created when needed
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Conversations file
+    /// * `capacity` - Maximum number of stored conversations
+    pub fn new(path: PathBuf, capacity: usize) -> Self {
+        Self {
+            path,
+            capacity,
+            lock: Mutex::new(()),
+        }
+    }
+    
+    /// Read the file, dropping expired conversations
+    async fn load<C: DeserializeOwned>(&self) -> Result<HashMap<String, Conversation<C>>> {
+        let content = match tokio::fs::read_to_string(&self.path).await {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+            Err(e) => return Err(format!("Failed to read conversations {}: {}", self.path.display(), e).into()),
+        };
+        
+        let mut conversations: HashMap<String, Conversation<C>> = serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse conversations {}: {}", self.path.display(), e))?;
+        
+        let now = now_millis();
+        conversations.retain(|_, conversation| !conversation.is_expired(now));
+        Ok(conversations)
+    }
+    
+    /// Replace the file, readable only by its owner on Unix
+    async fn save<C: Serialize>(&self, conversations: &HashMap<String, Conversation<C>>) -> Result<()> {
+        let content = serde_json::to_vec(conversations)?;
+        let path = self.path.clone();
+        
+        tokio::task::spawn_blocking(move || store::replace(&path, &content, true, "conversations"))
+            .await
+            .map_err(|e| format!("Failed to write conversations: {}", e))?
+    }
+}
+
+#[async_trait]
+impl<C: Serialize + DeserializeOwned + Send + Sync + 'static> ConversationStore<C> for FileConversationStore {
+    async fn insert(&self, state: Vec<u8>, conversation: Conversation<C>) -> Result<()> {
+        let _guard = self.lock.lock().await;
+        let mut conversations = self.load().await?;
+        
+        if conversations.len() >= self.capacity {
+            return Err("Too many concurrent conversations".into());
+        }
+        
+        conversations.insert(hex::encode(state), conversation);
+        self.save(&conversations).await
+    }
+    
+    async fn take(&self, state: &[u8]) -> Result<Option<Conversation<C>>> {
+        let _guard = self.lock.lock().await;
+        let mut conversations = self.load().await?;
+        
+        // Written back before the conversation is used, so a State cannot be answered twice
+        let conversation = conversations.remove(&hex::encode(state));
+        if conversation.is_some() {
+            self.save(&conversations).await?;
+        }
+        
+        Ok(conversation)
+    }
+}
+
+/// Result of looking up the State of a request
+#[derive(Debug)]
+pub enum Resumption<C> {
+    /// The request carries no State
+    NoState,
+    
+    /// The request continues this conversation
+    Resumed(Conversation<C>),
+    
+    /// The State is unknown, expired, already answered or was issued to someone else
+    Refused(String),
+}
+
+/// Issues States for conversations and checks them when they come back
+pub struct Conversations<C> {
+    /// Where conversations wait for their next packet
+    store: Arc<dyn ConversationStore<C>>,
+    
+    /// How long a State stays valid
+    timeout: Duration,
+    
+    /// Maximum number of challenges in one exchange
+    max_rounds: u32,
+}
+
+impl<C: Send + 'static> Conversations<C> {
+    /// Create a conversation table
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - Where conversations wait for their next packet
+    /// * `timeout` - How long a State stays valid
+    /// * `max_rounds` - Maximum number of challenges in one exchange
+    pub fn new(store: Arc<dyn ConversationStore<C>>, timeout: Duration, max_rounds: u32) -> Self {
+        Self {
+            store,
+            timeout,
+            max_rounds,
+        }
+    }
+    
+    /// Create a conversation table kept in memory
+    pub fn in_memory(timeout: Duration, capacity: usize, max_rounds: u32) -> Self {
+        Self::new(Arc::new(MemoryConversationStore::new(capacity)), timeout, max_rounds)
+    }
+    
+    /// Continue the conversations of the table this one replaces
+    ///
+    /// Both tables share the store from then on, so peers in the middle of an
+    /// exchange survive a configuration reload.
+    pub fn adopt(&mut self, previous: &Conversations<C>) {
+        self.store = previous.store.clone();
+    }
+    
+    /// Store a conversation under a fresh random State
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Request being challenged, whose sender the State is bound to
+    /// * `owner` - Backend or stage that gets the context back
+    /// * `context` - What the owner needs to continue
+    /// * `rounds` - Challenges issued in this exchange before this one
+    ///
+    /// # Returns
+    ///
+    /// The State, or None if the exchange has reached the maximum number of rounds
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store is full or cannot be written
+    pub async fn issue(&self, request: &Packet, owner: &str, context: C, rounds: u32) -> Result<Option<Vec<u8>>> {
+        if rounds >= self.max_rounds {
+            return Ok(None);
+        }
+        
+        let mut state = vec![0u8; STATE_LEN];
+        rand::thread_rng().fill_bytes(&mut state);
+        
+        let conversation = Conversation {
+            owner: owner.to_string(),
+            context,
+            binding: Binding::of(request),
+            rounds: rounds + 1,
+            expires: now_millis().saturating_add(self.timeout.as_millis() as u64),
+        };
+        self.store.insert(state.clone(), conversation).await?;
+        
+        Ok(Some(state))
+    }
+    
+    /// Find the conversation a request continues
+    ///
+    /// The State is used up whatever the outcome, so it cannot be replayed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read
+    pub async fn resume(&self, request: &Packet) -> Result<Resumption<C>> {
+        let state = match request.get_attribute("State") {
+            Some(Attribute::Binary(_, state)) => state,
+            _ => return Ok(Resumption::NoState),
+        };
+        
+        let conversation = match self.store.take(state).await? {
+            Some(conversation) => conversation,
+            None => return Ok(Resumption::Refused("Unknown or already answered State".to_string())),
+        };
+        
+        if conversation.is_expired(now_millis()) {
+            return Ok(Resumption::Refused("Expired State".to_string()));
+        }
+        if conversation.binding != Binding::of(request) {
+            tracing::warn!(
+                owner = conversation.owner,
+                issued = ?conversation.binding,
+                received = ?Binding::of(request),
+                "State returned by a different client, NAS or user"
+            );
+            return Ok(Resumption::Refused("State was issued to a different client, NAS or user".to_string()));
+        }
+        
+        Ok(Resumption::Resumed(conversation))
+    }
+}
+
+impl<C: Serialize + DeserializeOwned + Send + Sync + 'static> Conversations<C> {
+    /// Create the conversation table described by the configuration
+    pub fn from_config(config: &ConversationConfig) -> Self {
+        let timeout = Duration::from_secs(config.timeout_secs);
+        
+        match &config.file {
+            Some(path) => Self::new(
+                Arc::new(FileConversationStore::new(path.clone(), config.max_conversations)),
+                timeout,
+                config.max_rounds,
+            ),
+            None => Self::in_memory(timeout, config.max_conversations, config.max_rounds),
+        }
+    }
+}
+
+/// Current Unix time in milliseconds
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn request(source: &str, nas: &str, state: Option<&[u8]>) -> Packet {
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.set_source(source.parse().unwrap());
+        request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+        request.add_attribute(Attribute::String("NAS-Identifier".to_string(), nas.to_string()));
+        if let Some(state) = state {
+            request.add_attribute(Attribute::Binary("State".to_string(), state.to_vec()));
+        }
+        request
+    }
+    
+    fn first_with(state: &[u8]) -> Packet {
+        request("192.0.2.1:1645", "ap1", Some(state))
+    }
+    
+    async fn exchange(conversations: &Conversations<Vec<u8>>) {
+        let first = request("192.0.2.1:1645", "ap1", None);
+        assert!(matches!(conversations.resume(&first).await.unwrap(), Resumption::NoState));
+        
+        // The context comes back once, to the owner, from the same client and NAS
+        let state = conversations.issue(&first, "otp", b"context".to_vec(), 0).await.unwrap().unwrap();
+        match conversations.resume(&request("192.0.2.1:1812", "ap1", Some(&state))).await.unwrap() {
+            Resumption::Resumed(conversation) => {
+                assert_eq!((conversation.owner.as_str(), conversation.context.as_slice(), conversation.rounds), ("otp", &b"context"[..], 1));
+            },
+            other => panic!("{:?}", other),
+        }
+        assert!(matches!(conversations.resume(&request("192.0.2.1:1812", "ap1", Some(&state))).await.unwrap(), Resumption::Refused(_)));
+        
+        // Another client or NAS cannot use the State, and burns it trying
+        let state = conversations.issue(&first, "otp", Vec::new(), 0).await.unwrap().unwrap();
+        assert!(matches!(conversations.resume(&request("192.0.2.2:1645", "ap1", Some(&state))).await.unwrap(), Resumption::Refused(_)));
+        assert!(matches!(conversations.resume(&first_with(&state)).await.unwrap(), Resumption::Refused(_)));
+        let state = conversations.issue(&first, "otp", Vec::new(), 0).await.unwrap().unwrap();
+        assert!(matches!(conversations.resume(&request("192.0.2.1:1645", "ap2", Some(&state))).await.unwrap(), Resumption::Refused(_)));
+        
+        // Rounds are capped
+        assert!(conversations.issue(&first, "otp", Vec::new(), 2).await.unwrap().is_some());
+        assert!(conversations.issue(&first, "otp", Vec::new(), 3).await.unwrap().is_none());
+    }
+    
+    #[tokio::test]
+    async fn bound_single_use_states() {
+        exchange(&Conversations::in_memory(Duration::from_secs(60), 16, 3)).await;
+        
+        // The file store behaves the same, and keeps conversations across instances
+        let path = std::env::temp_dir().join(format!("rust-radius-conversations-{}.json", std::process::id()));
+        let config = ConversationConfig {
+            file: Some(path.clone()),
+            timeout_secs: 60,
+            max_conversations: 16,
+            max_rounds: 3,
+        };
+        exchange(&Conversations::from_config(&config)).await;
+        
+        let first = request("192.0.2.1:1645", "ap1", None);
+        let state = Conversations::<Vec<u8>>::from_config(&config).issue(&first, "otp", vec![1], 0).await.unwrap().unwrap();
+        assert!(matches!(Conversations::<Vec<u8>>::from_config(&config).resume(&first_with(&state)).await.unwrap(), Resumption::Resumed(_)));
+        
+        // Expired States are refused
+        let expiring = Conversations::<Vec<u8>>::in_memory(Duration::ZERO, 16, 3);
+        let state = expiring.issue(&first, "otp", Vec::new(), 0).await.unwrap().unwrap();
+        assert!(matches!(expiring.resume(&first_with(&state)).await.unwrap(), Resumption::Refused(_)));
+        
+        std::fs::remove_file(&path).unwrap();
+    }
+    
+    fn conversation(expires: u64) -> Conversation<Vec<u8>> {
+        Conversation {
+            owner: "otp".to_string(),
+            context: Vec::new(),
+            binding: Binding::of(&request("192.0.2.1:1645", "ap1", None)),
+            rounds: 1,
+            expires,
+        }
+    }
+    
+    async fn limits(store: &dyn ConversationStore<Vec<u8>>) {
+        // Expired conversations make room for new ones
+        let now = now_millis();
+        store.insert(vec![1], conversation(now - 1)).await.unwrap();
+        store.insert(vec![2], conversation(now + 60_000)).await.unwrap();
+        store.insert(vec![3], conversation(now + 60_000)).await.unwrap();
+        assert!(store.take(&[1]).await.unwrap().is_none());
+        
+        let error = store.insert(vec![4], conversation(now + 60_000)).await.unwrap_err();
+        assert_eq!(error.to_string(), "Too many concurrent conversations");
+        
+        // Answering one frees its place
+        assert!(store.take(&[2]).await.unwrap().is_some());
+        assert!(store.take(&[2]).await.unwrap().is_none());
+        store.insert(vec![4], conversation(now + 60_000)).await.unwrap();
+    }
+    
+    #[tokio::test]
+    async fn capacity_and_expiry() {
+        limits(&MemoryConversationStore::new(2)).await;
+        
+        let path = std::env::temp_dir().join(format!("rust-radius-conversation-limits-{}.json", std::process::id()));
+        limits(&FileConversationStore::new(path.clone(), 2)).await;
+        
+        // A damaged file is reported rather than taken as empty
+        std::fs::write(&path, "{").unwrap();
+        let store = FileConversationStore::new(path.clone(), 2);
+        let error = ConversationStore::<Vec<u8>>::take(&store, &[4]).await.unwrap_err();
+        assert!(error.to_string().starts_with("Failed to parse conversations"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+        
+        // Each way a State is refused says why
+        let conversations = Conversations::<Vec<u8>>::in_memory(Duration::ZERO, 16, 3);
+        let first = request("192.0.2.1:1645", "ap1", None);
+        let state = conversations.issue(&first, "otp", Vec::new(), 0).await.unwrap().unwrap();
+        for reason in ["Expired State", "Unknown or already answered State"] {
+            match conversations.resume(&first_with(&state)).await.unwrap() {
+                Resumption::Refused(refused) => assert_eq!(refused, reason),
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+}
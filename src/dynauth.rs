@@ -0,0 +1,153 @@
+// dynauth.rs - Dynamic authorization client for rust-radius
+//
+// RFC 5176 lets the server act on a session after its Access-Accept: a
+// Disconnect-Request ends the session and a CoA-Request changes it. The NAS
+// answers with an ACK, or a NAK carrying an Error-Cause. Requests are sent
+// from an ephemeral port, signed with the shared secret, and retransmitted
+// with the same identifier until the NAS answers or the retries run out.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rand::Rng;
+use tokio::net::UdpSocket;
+
+use crate::config::Config;
+use crate::protocol::{self, Attribute, Packet, PacketCode, PacketProcessor};
+use crate::Result;
+
+/// Error-Cause of a NAK for a session the NAS does not have (RFC 5176, section 3.5)
+pub const SESSION_NOT_FOUND: u32 = 503;
+
+/// What the NAS said
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynAuthReply {
+    /// The request was carried out
+    Ack,
+    
+    /// The request was refused, with the Error-Cause if the NAS gave one
+    Nak(Option<u32>),
+    
+    /// No valid answer before the last retry timed out
+    NoAnswer,
+}
+
+/// Sends Disconnect and CoA requests to NASes
+pub struct DynAuthClient {
+    /// Encodes requests and parses answers
+    processor: PacketProcessor,
+    
+    /// Shared secret
+    secret: String,
+    
+    /// Port of the NASes
+    port: u16,
+    
+    /// Wait for each answer
+    timeout: Duration,
+    
+    /// Retransmissions after a timeout
+    retries: u32,
+}
+
+impl DynAuthClient {
+    /// Create a client from the server configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration, for the shared secret and `dynamic_authorization`
+    pub fn new(config: Arc<Config>) -> Self {
+        Self {
+            secret: config.server.secret.clone(),
+            port: config.dynamic_authorization.port,
+            timeout: Duration::from_millis(config.dynamic_authorization.timeout_ms),
+            retries: config.dynamic_authorization.retries,
+            processor: PacketProcessor::new(config),
+        }
+    }
+    
+    /// Ask a NAS to end a session
+    ///
+    /// # Arguments
+    ///
+    /// * `nas` - Address of the NAS
+    /// * `identity` - Attributes identifying the session (User-Name, Acct-Session-Id, ...)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request cannot be encoded or sent
+    pub async fn disconnect(&self, nas: IpAddr, identity: &[Attribute]) -> Result<DynAuthReply> {
+        self.send(Packet::DISCONNECT_REQUEST, nas, identity.to_vec()).await
+    }
+    
+    /// Ask a NAS to change a session
+    ///
+    /// A request with no attributes besides the identity changes nothing, and
+    /// tells whether the NAS still has the session.
+    ///
+    /// # Arguments
+    ///
+    /// * `nas` - Address of the NAS
+    /// * `identity` - Attributes identifying the session
+    /// * `attributes` - Attributes to apply to the session
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request cannot be encoded or sent
+    pub async fn change(&self, nas: IpAddr, identity: &[Attribute], attributes: Vec<Attribute>) -> Result<DynAuthReply> {
+        let mut all = identity.to_vec();
+        all.extend(attributes);
+        self.send(Packet::COA_REQUEST, nas, all).await
+    }
+    
+    /// Send a request and wait for its answer
+    async fn send(&self, code: PacketCode, nas: IpAddr, attributes: Vec<Attribute>) -> Result<DynAuthReply> {
+        let (ack, nak) = match code {
+            PacketCode::DisconnectRequest => (Packet::DISCONNECT_ACK, Packet::DISCONNECT_NAK),
+            _ => (Packet::COA_ACK, Packet::COA_NAK),
+        };
+        
+        let identifier: u8 = rand::thread_rng().gen();
+        let mut request = Packet::new(code, identifier, [0u8; 16]);
+        for attribute in attributes {
+            request.add_attribute(attribute);
+        }
+        let mut data = self.processor.encode(&request)?;
+        protocol::sign_request(self.secret.as_bytes(), &mut data);
+        let mut authenticator = [0u8; 16];
+        authenticator.copy_from_slice(&data[4..20]);
+        
+        let local: IpAddr = match nas {
+            IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+            IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(local, 0)).await?;
+        let target = SocketAddr::new(nas, self.port);
+        socket.connect(target).await?;
+        
+        let mut buffer = [0u8; 4096];
+        for _ in 0..=self.retries {
+            socket.send(&data).await?;
+            
+            let deadline = tokio::time::Instant::now() + self.timeout;
+            // Answers that do not match the request, e.g. to an earlier retry with a bad secret, are skipped
+            while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buffer)).await {
+                let answer = &buffer[..received?];
+                if answer.len() < 20 || answer[1] != identifier || !protocol::verify_response(self.secret.as_bytes(), &authenticator, answer) {
+                    tracing::debug!(nas = %nas, "Ignoring unexpected dynamic authorization answer");
+                    continue;
+                }
+                
+                let reply = self.processor.parse(answer, target)?;
+                return Ok(match reply.code() {
+                    code if code == ack => DynAuthReply::Ack,
+                    code if code == nak => DynAuthReply::Nak(reply.get_integer("Error-Cause")),
+                    code => return Err(format!("Unexpected answer {:?} from {}", code, nas).into()),
+                });
+            }
+        }
+        
+        Ok(DynAuthReply::NoAnswer)
+    }
+}
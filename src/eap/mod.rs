@@ -0,0 +1,886 @@
+// eap/mod.rs - Extensible Authentication Protocol support for rust-radius
+//
+// This module implements EAP over RADIUS (RFC 3579): reassembly and fragmentation
+// of EAP-Message attributes, per-conversation state keyed by the State attribute,
+// method negotiation via NAK, and the `EapMethod` trait that individual methods
+// implement. It supports the "Modern Public WiFi Features" goal (WPA2/WPA3 Enterprise).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use crate::auth::AuthResult;
+use crate::config::Config;
+use crate::conversation::{Conversations, Resumption};
+use crate::protocol::{Attribute, Packet, PacketCode};
+use crate::Result;
+
+#[cfg(feature = "eap-tls")]
+pub mod tls;
+
+#[cfg(feature = "eap-tls")]
+pub mod peap;
+
+#[cfg(feature = "eap-tls")]
+pub mod ttls;
+
+/// Maximum length of a single EAP-Message attribute value
+const MAX_EAP_MESSAGE_LEN: usize = 253;
+
+/// Owner recorded for EAP conversations
+const EAP_OWNER: &str = "eap";
+
+/// EAP packet codes (RFC 3748)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EapCode {
+    /// Request (1)
+    Request = 1,
+    
+    /// Response (2)
+    Response = 2,
+    
+    /// Success (3)
+    Success = 3,
+    
+    /// Failure (4)
+    Failure = 4,
+}
+
+impl EapCode {
+    /// Convert a u8 to an EapCode
+    pub fn from_u8(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Self::Request),
+            2 => Some(Self::Response),
+            3 => Some(Self::Success),
+            4 => Some(Self::Failure),
+            _ => None,
+        }
+    }
+}
+
+/// EAP method types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EapType {
+    /// Identity (1)
+    Identity,
+    
+    /// Notification (2)
+    Notification,
+    
+    /// Legacy Nak (3)
+    Nak,
+    
+    /// MD5-Challenge (4)
+    Md5Challenge,
+    
+    /// EAP-TLS (13)
+    Tls,
+    
+    /// EAP-TTLS (21)
+    Ttls,
+    
+    /// PEAP (25)
+    Peap,
+    
+    /// EAP-MSCHAPv2 (26)
+    MsChapV2,
+    
+    /// EAP-TLV, used inside PEAP (33)
+    Tlv,
+    
+    /// Any other method type
+    Unknown(u8),
+}
+
+impl EapType {
+    /// Convert a u8 to an EapType
+    pub fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Identity,
+            2 => Self::Notification,
+            3 => Self::Nak,
+            4 => Self::Md5Challenge,
+            13 => Self::Tls,
+            21 => Self::Ttls,
+            25 => Self::Peap,
+            26 => Self::MsChapV2,
+            33 => Self::Tlv,
+            other => Self::Unknown(other),
+        }
+    }
+    
+    /// Convert the EapType to its wire value
+    pub fn to_u8(self) -> u8 {
+        match self {
+            Self::Identity => 1,
+            Self::Notification => 2,
+            Self::Nak => 3,
+            Self::Md5Challenge => 4,
+            Self::Tls => 13,
+            Self::Ttls => 21,
+            Self::Peap => 25,
+            Self::MsChapV2 => 26,
+            Self::Tlv => 33,
+            Self::Unknown(value) => value,
+        }
+    }
+    
+    /// Look up an EAP method by its name in `security.auth_protocols`
+    ///
+    /// Returns None for names that are not EAP methods (e.g. "pap" or "chap").
+    pub fn from_protocol_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "eap-md5" | "md5" => Some(Self::Md5Challenge),
+            "eap-tls" | "tls" => Some(Self::Tls),
+            "eap-ttls" | "ttls" => Some(Self::Ttls),
+            "peap" => Some(Self::Peap),
+            "eap-mschapv2" => Some(Self::MsChapV2),
+            _ => None,
+        }
+    }
+}
+
+/// EAP packet
+#[derive(Debug, Clone, PartialEq)]
+pub struct EapPacket {
+    /// Packet code
+    pub code: EapCode,
+    
+    /// Packet identifier
+    pub identifier: u8,
+    
+    /// Method type (Request and Response packets only)
+    pub eap_type: Option<EapType>,
+    
+    /// Type-Data following the method type
+    pub data: Vec<u8>,
+}
+
+impl EapPacket {
+    /// Create an EAP-Request
+    pub fn request(identifier: u8, eap_type: EapType, data: Vec<u8>) -> Self {
+        Self {
+            code: EapCode::Request,
+            identifier,
+            eap_type: Some(eap_type),
+            data,
+        }
+    }
+    
+    /// Create an EAP-Success
+    pub fn success(identifier: u8) -> Self {
+        Self {
+            code: EapCode::Success,
+            identifier,
+            eap_type: None,
+            data: Vec::new(),
+        }
+    }
+    
+    /// Create an EAP-Failure
+    pub fn failure(identifier: u8) -> Self {
+        Self {
+            code: EapCode::Failure,
+            identifier,
+            eap_type: None,
+            data: Vec::new(),
+        }
+    }
+    
+    /// Parse an EAP packet from raw bytes
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the packet is truncated or has an invalid code
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            return Err("EAP packet too short".into());
+        }
+        
+        let code = EapCode::from_u8(data[0])
+            .ok_or_else(|| format!("Invalid EAP code: {}", data[0]))?;
+        let identifier = data[1];
+        let length = u16::from_be_bytes([data[2], data[3]]) as usize;
+        
+        if length < 4 || length > data.len() {
+            return Err(format!("Invalid EAP packet length: {}", length).into());
+        }
+        
+        let (eap_type, payload) = match code {
+            EapCode::Request | EapCode::Response => {
+                if length < 5 {
+                    return Err("EAP Request/Response without a type".into());
+                }
+                (Some(EapType::from_u8(data[4])), data[5..length].to_vec())
+            },
+            EapCode::Success | EapCode::Failure => (None, Vec::new()),
+        };
+        
+        Ok(Self {
+            code,
+            identifier,
+            eap_type,
+            data: payload,
+        })
+    }
+    
+    /// Encode the EAP packet to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let type_len = if self.eap_type.is_some() { 1 } else { 0 };
+        let length = 4 + type_len + self.data.len();
+        
+        let mut buffer = Vec::with_capacity(length);
+        buffer.push(self.code as u8);
+        buffer.push(self.identifier);
+        buffer.extend_from_slice(&(length as u16).to_be_bytes());
+        
+        if let Some(eap_type) = self.eap_type {
+            buffer.push(eap_type.to_u8());
+            buffer.extend_from_slice(&self.data);
+        }
+        
+        buffer
+    }
+}
+
+/// Reassemble the EAP packet carried in one or more EAP-Message attributes
+///
+/// # Returns
+///
+/// Concatenated EAP-Message values, or None if the packet carries no EAP-Message
+pub fn reassemble(packet: &Packet) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut found = false;
+    
+    for attr in packet.get_attributes("EAP-Message") {
+        if let Attribute::Binary(_, value) = attr {
+            data.extend_from_slice(value);
+            found = true;
+        }
+    }
+    
+    found.then_some(data)
+}
+
+/// Split an EAP packet into EAP-Message attributes of at most 253 bytes each
+pub fn fragment(eap: &[u8]) -> Vec<Attribute> {
+    eap.chunks(MAX_EAP_MESSAGE_LEN)
+        .map(|chunk| Attribute::Binary("EAP-Message".to_string(), chunk.to_vec()))
+        .collect()
+}
+
+/// Keying material exported by a successful EAP method
+#[derive(Clone)]
+pub struct EapKeys {
+    /// Master Session Key (at least 64 bytes)
+    pub msk: Vec<u8>,
+    
+    /// Extended Master Session Key
+    pub emsk: Vec<u8>,
+}
+
+impl fmt::Debug for EapKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never log key material
+        f.debug_struct("EapKeys")
+            .field("msk", &format_args!("<{} bytes>", self.msk.len()))
+            .field("emsk", &format_args!("<{} bytes>", self.emsk.len()))
+            .finish()
+    }
+}
+
+/// Result of a successful EAP method
+#[derive(Debug, Default)]
+pub struct EapSuccess {
+    /// Keying material, if the method derives any
+    pub keys: Option<EapKeys>,
+    
+    /// Additional attributes to include in the Access-Accept
+    pub attributes: Vec<Attribute>,
+    
+    /// Client certificate presented by the peer, if any
+    pub certificate: Option<CertificateIdentity>,
+    
+    /// Identity authenticated inside a tunnel (PEAP, EAP-TTLS), as opposed to
+    /// the possibly anonymous outer identity
+    pub inner_identity: Option<String>,
+    
+    /// Groups the inner backend found with the credential
+    pub groups: Vec<String>,
+}
+
+/// Identity information from a client certificate
+///
+/// Exposed to backends (see `AuthBackend::authorize_certificate`) so they can
+/// authorize certificate-authenticated peers.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CertificateIdentity {
+    /// Subject distinguished name
+    pub subject: String,
+    
+    /// Issuer distinguished name
+    pub issuer: String,
+    
+    /// Serial number (hex)
+    pub serial: String,
+    
+    /// Subject common name
+    pub common_name: Option<String>,
+    
+    /// DNS names from the subjectAltName extension
+    pub dns_names: Vec<String>,
+    
+    /// E-mail addresses from the subjectAltName extension
+    pub emails: Vec<String>,
+    
+    /// Microsoft User Principal Names from the subjectAltName extension
+    pub upns: Vec<String>,
+}
+
+impl CertificateIdentity {
+    /// Check whether the certificate names an identity
+    ///
+    /// The identity is compared case-insensitively against the UPNs, e-mail
+    /// addresses, DNS names and common name of the certificate.
+    pub fn matches(&self, identity: &str) -> bool {
+        self.upns.iter()
+            .chain(self.emails.iter())
+            .chain(self.dns_names.iter())
+            .chain(self.common_name.iter())
+            .any(|name| name.eq_ignore_ascii_case(identity))
+    }
+}
+
+/// Result of one step of an EAP method
+#[derive(Debug)]
+pub enum EapStep {
+    /// Send another request of the same method carrying this Type-Data
+    Continue(Vec<u8>),
+    
+    /// The method completed successfully
+    Success(Box<EapSuccess>),
+    
+    /// The method failed
+    Failure {
+        /// Reason for the failure
+        reason: String,
+    },
+}
+
+/// Request context passed to EAP methods
+pub struct EapContext<'a> {
+    /// The Access-Request carrying the current EAP-Response
+    pub request: &'a Packet,
+    
+    /// Identity from the EAP-Response/Identity
+    pub identity: &'a str,
+    
+    /// Verifies credentials carried inside tunneled methods
+    pub inner: &'a dyn InnerAuthenticator,
+}
+
+/// Verifies the inner credentials of tunneled methods (PEAP, EAP-TTLS)
+///
+/// Implemented by the `AuthManager`, which runs the request through its backends.
+#[async_trait]
+pub trait InnerAuthenticator: Send + Sync {
+    /// Authenticate a request built with `inner_request`
+    async fn authenticate_inner(&self, request: &Packet) -> Result<AuthResult>;
+}
+
+/// Build the Access-Request handed to the backends for tunneled credentials
+///
+/// The request carries the NAS attributes of the outer request, the inner
+/// identity as User-Name and the given credential attributes.
+///
+/// # Arguments
+///
+/// * `outer` - Access-Request carrying the EAP conversation
+/// * `identity` - Identity from inside the tunnel
+/// * `credentials` - Credential attributes (User-Password, MS-CHAP attributes, ...)
+pub fn inner_request(outer: &Packet, identity: &str, credentials: Vec<Attribute>) -> Packet {
+    let mut request = Packet::new(PacketCode::AccessRequest, outer.identifier(), *outer.authenticator());
+    
+    for attr in outer.attributes() {
+        match attr.name() {
+            "User-Name" | "User-Password" | "EAP-Message" | "State" | "Message-Authenticator" => {},
+            _ => request.add_attribute(attr.clone()),
+        }
+    }
+    
+    request.add_attribute(Attribute::String("User-Name".to_string(), identity.to_string()));
+    for attr in credentials {
+        request.add_attribute(attr);
+    }
+    
+    request
+}
+
+/// EAP method trait
+///
+/// Each EAP method (EAP-TLS, PEAP, ...) implements this trait and is registered
+/// with the `EapServer`. The method creates one `EapSession` per conversation.
+pub trait EapMethod: Send + Sync {
+    /// Get the method type
+    fn method_type(&self) -> EapType;
+    
+    /// Begin a new conversation with a peer
+    ///
+    /// # Arguments
+    ///
+    /// * `identity` - Identity from the EAP-Response/Identity
+    fn start(&self, identity: &str) -> Result<Box<dyn EapSession>>;
+}
+
+/// Per-conversation state of an EAP method
+#[async_trait]
+pub trait EapSession: Send {
+    /// Produce the first request of the method
+    async fn initiate(&mut self, ctx: &EapContext<'_>) -> Result<EapStep>;
+    
+    /// Process the Type-Data of a response from the peer
+    async fn process(&mut self, ctx: &EapContext<'_>, data: &[u8]) -> Result<EapStep>;
+}
+
+/// Outcome of handling an EAP-Message
+#[derive(Debug)]
+pub enum EapOutcome {
+    /// Send an Access-Challenge carrying the EAP-Request
+    Challenge {
+        /// Encoded EAP-Request
+        eap: Vec<u8>,
+        
+        /// State identifying the conversation
+        state: Vec<u8>,
+    },
+    
+    /// Send an Access-Accept carrying EAP-Success
+    Accept {
+        /// Encoded EAP-Success
+        eap: Vec<u8>,
+        
+        /// Identity the peer authenticated as
+        identity: String,
+        
+        /// Method result (keys, attributes and certificate identity)
+        success: Box<EapSuccess>,
+    },
+    
+    /// Send an Access-Reject carrying EAP-Failure
+    Reject {
+        /// Encoded EAP-Failure
+        eap: Vec<u8>,
+        
+        /// Reason for rejection
+        reason: String,
+    },
+}
+
+/// State of one EAP conversation between challenges
+struct EapConversation {
+    /// Identity from the EAP-Response/Identity
+    identity: String,
+    
+    /// Method currently in progress
+    method: Option<EapType>,
+    
+    /// Method session
+    session: Option<Box<dyn EapSession>>,
+    
+    /// Identifier of the last EAP-Request sent
+    identifier: u8,
+}
+
+/// EAP server
+///
+/// Drives EAP conversations on behalf of the `AuthManager`: it tracks each
+/// conversation by State, negotiates the method and hands responses to the
+/// method's session.
+pub struct EapServer {
+    /// Methods offered to peers, in order of preference
+    preference: Vec<EapType>,
+    
+    /// Registered method implementations
+    methods: HashMap<EapType, Arc<dyn EapMethod>>,
+    
+    /// Conversations awaiting a response
+    conversations: Conversations<EapConversation>,
+}
+
+impl EapServer {
+    /// Create a new EAP server
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration
+    ///
+    /// # Returns
+    ///
+    /// New EAP server with no methods registered
+    pub fn new(config: &Config) -> Self {
+        let preference = config.security.auth_protocols.iter()
+            .filter_map(|name| EapType::from_protocol_name(name))
+            .collect();
+        
+        Self {
+            preference,
+            methods: HashMap::new(),
+            conversations: Conversations::in_memory(
+                Duration::from_secs(config.eap.state_timeout_secs),
+                config.eap.max_conversations,
+                config.eap.max_rounds,
+            ),
+        }
+    }
+    
+    /// Register an EAP method implementation
+    ///
+    /// The method is only offered to peers if it is listed in `security.auth_protocols`.
+    pub fn register_method(&mut self, method: Arc<dyn EapMethod>) {
+        self.methods.insert(method.method_type(), method);
+    }
+    
+    /// Continue the conversations of the server this one replaces
+    ///
+    /// Both servers share the conversation table from then on, so peers in
+    /// the middle of an exchange survive a configuration reload.
+    pub fn adopt_conversations(&mut self, previous: &EapServer) {
+        self.conversations.adopt(&previous.conversations);
+    }
+    
+    /// Check whether a method is listed in `security.auth_protocols`
+    pub fn is_configured(&self, method: EapType) -> bool {
+        self.preference.contains(&method)
+    }
+    
+    /// Get the methods offered to peers, in order of preference
+    pub fn offered_methods(&self) -> Vec<EapType> {
+        self.preference.iter()
+            .copied()
+            .filter(|t| self.methods.contains_key(t))
+            .collect()
+    }
+    
+    /// Handle the EAP-Message carried by an Access-Request
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - RADIUS request packet
+    /// * `inner` - Verifies credentials carried inside tunneled methods
+    ///
+    /// # Returns
+    ///
+    /// Outcome to send back to the NAS
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the request carries no EAP-Message, or if a method fails internally
+    pub async fn handle(&self, request: &Packet, inner: &dyn InnerAuthenticator) -> Result<EapOutcome> {
+        let data = reassemble(request).ok_or("Request has no EAP-Message")?;
+        
+        // An empty EAP-Message is an EAP-Start from the NAS (RFC 3579, section 2.1)
+        if data.len() < 4 {
+            let conversation = EapConversation {
+                identity: String::new(),
+                method: None,
+                session: None,
+                identifier: 0,
+            };
+            
+            return self.challenge(request, conversation, 0, EapType::Identity, Vec::new()).await;
+        }
+        
+        let eap = match EapPacket::parse(&data) {
+            Ok(eap) if eap.code == EapCode::Response => eap,
+            Ok(eap) => return Ok(Self::reject(eap.identifier, format!("Unexpected EAP code {:?}", eap.code))),
+            Err(e) => return Ok(Self::reject(0, format!("Malformed EAP-Message: {}", e))),
+        };
+        
+        // Resume the conversation identified by State, or start a new one
+        let (mut conversation, rounds) = match self.conversations.resume(request).await? {
+            Resumption::Resumed(conversation) => (conversation.context, conversation.rounds),
+            Resumption::Refused(reason) => return Ok(Self::reject(eap.identifier, reason)),
+            Resumption::NoState => (EapConversation {
+                identity: String::new(),
+                method: None,
+                session: None,
+                identifier: eap.identifier,
+            }, 0),
+        };
+        
+        if eap.identifier != conversation.identifier {
+            return Ok(Self::reject(eap.identifier, "EAP identifier mismatch".to_string()));
+        }
+        
+        let step = match eap.eap_type {
+            Some(EapType::Identity) => {
+                conversation.identity = String::from_utf8_lossy(&eap.data).to_string();
+                
+                match self.offered_methods().first() {
+                    Some(method) => self.start_method(&mut conversation, *method, request, inner).await?,
+                    None => EapStep::Failure { reason: "No EAP methods are enabled".to_string() },
+                }
+            },
+            Some(EapType::Nak) => {
+                // The peer refused the proposed method and lists the ones it wants
+                let current = match conversation.method {
+                    Some(method) => method,
+                    None => return Ok(Self::reject(eap.identifier, "Unexpected EAP Nak".to_string())),
+                };
+                
+                let desired: Vec<EapType> = eap.data.iter().map(|t| EapType::from_u8(*t)).collect();
+                let selected = self.offered_methods().into_iter()
+                    .find(|method| *method != current && desired.contains(method));
+                
+                tracing::debug!(
+                    identity = conversation.identity,
+                    refused = ?current,
+                    desired = ?desired,
+                    selected = ?selected,
+                    "EAP method negotiation"
+                );
+                
+                match selected {
+                    Some(method) => self.start_method(&mut conversation, method, request, inner).await?,
+                    None => EapStep::Failure { reason: "No mutually acceptable EAP method".to_string() },
+                }
+            },
+            Some(eap_type) if Some(eap_type) == conversation.method => {
+                let ctx = EapContext {
+                    request,
+                    identity: &conversation.identity,
+                    inner,
+                };
+                
+                match conversation.session.as_mut() {
+                    Some(session) => session.process(&ctx, &eap.data).await?,
+                    None => EapStep::Failure { reason: "EAP method has no session".to_string() },
+                }
+            },
+            other => EapStep::Failure { reason: format!("Unexpected EAP type {:?}", other) },
+        };
+        
+        match step {
+            EapStep::Continue(data) => {
+                let method = conversation.method.unwrap_or(EapType::Identity);
+                self.challenge(request, conversation, rounds, method, data).await
+            },
+            EapStep::Success(success) => Ok(EapOutcome::Accept {
+                eap: EapPacket::success(eap.identifier).to_bytes(),
+                identity: conversation.identity,
+                success,
+            }),
+            EapStep::Failure { reason } => Ok(Self::reject(eap.identifier, reason)),
+        }
+    }
+    
+    /// Start a method for a conversation and produce its first request
+    async fn start_method(
+        &self,
+        conversation: &mut EapConversation,
+        method: EapType,
+        request: &Packet,
+        inner: &dyn InnerAuthenticator,
+    ) -> Result<EapStep> {
+        let implementation = self.methods.get(&method)
+            .ok_or_else(|| format!("EAP method {:?} is not registered", method))?;
+        
+        let mut session = implementation.start(&conversation.identity)?;
+        let ctx = EapContext {
+            request,
+            identity: &conversation.identity,
+            inner,
+        };
+        let step = session.initiate(&ctx).await?;
+        
+        conversation.method = Some(method);
+        conversation.session = Some(session);
+        
+        Ok(step)
+    }
+    
+    /// Store the conversation and build a challenge carrying the next EAP-Request
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request being answered
+    /// * `conversation` - Conversation to continue with the peer's next response
+    /// * `rounds` - Challenges already sent in this conversation
+    /// * `method` - Type of the EAP-Request
+    /// * `data` - Type-Data of the EAP-Request
+    async fn challenge(
+        &self,
+        request: &Packet,
+        mut conversation: EapConversation,
+        rounds: u32,
+        method: EapType,
+        data: Vec<u8>,
+    ) -> Result<EapOutcome> {
+        let identifier = conversation.identifier;
+        conversation.identifier = identifier.wrapping_add(1);
+        let eap = EapPacket::request(conversation.identifier, method, data).to_bytes();
+        
+        match self.conversations.issue(request, EAP_OWNER, conversation, rounds).await? {
+            Some(state) => Ok(EapOutcome::Challenge { eap, state }),
+            None => Ok(Self::reject(identifier, "Too many EAP rounds".to_string())),
+        }
+    }
+    
+    /// Build a rejection carrying EAP-Failure
+    fn reject(identifier: u8, reason: String) -> EapOutcome {
+        EapOutcome::Reject {
+            eap: EapPacket::failure(identifier).to_bytes(),
+            reason,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// Inner authenticator for conversations without tunneled credentials
+    struct NoInner;
+    
+    #[async_trait]
+    impl InnerAuthenticator for NoInner {
+        async fn authenticate_inner(&self, _request: &Packet) -> Result<AuthResult> {
+            Err("unexpected inner authentication".into())
+        }
+    }
+    
+    /// Test method that succeeds after one round trip if the peer echoes "ok"
+    struct EchoMethod(EapType);
+    
+    struct EchoSession;
+    
+    impl EapMethod for EchoMethod {
+        fn method_type(&self) -> EapType {
+            self.0
+        }
+        
+        fn start(&self, _identity: &str) -> Result<Box<dyn EapSession>> {
+            Ok(Box::new(EchoSession))
+        }
+    }
+    
+    #[async_trait]
+    impl EapSession for EchoSession {
+        async fn initiate(&mut self, _ctx: &EapContext<'_>) -> Result<EapStep> {
+            Ok(EapStep::Continue(b"say ok".to_vec()))
+        }
+        
+        async fn process(&mut self, _ctx: &EapContext<'_>, data: &[u8]) -> Result<EapStep> {
+            if data == b"ok" {
+                Ok(EapStep::Success(Box::new(EapSuccess {
+                    keys: Some(EapKeys { msk: vec![1; 64], emsk: vec![2; 64] }),
+                    ..Default::default()
+                })))
+            } else {
+                Ok(EapStep::Failure { reason: "wrong answer".to_string() })
+            }
+        }
+    }
+    
+    fn server() -> EapServer {
+        let mut config = Config::default();
+        config.security.auth_protocols = vec!["pap".to_string(), "eap-tls".to_string(), "eap-md5".to_string()];
+        
+        let mut server = EapServer::new(&config);
+        server.register_method(Arc::new(EchoMethod(EapType::Tls)));
+        server.register_method(Arc::new(EchoMethod(EapType::Md5Challenge)));
+        server
+    }
+    
+    fn request(eap: &EapPacket, state: Option<&[u8]>) -> Packet {
+        let mut packet = Packet::new(PacketCode::AccessRequest, 1, [0; 16]);
+        for attr in fragment(&eap.to_bytes()) {
+            packet.add_attribute(attr);
+        }
+        if let Some(state) = state {
+            packet.add_attribute(Attribute::Binary("State".to_string(), state.to_vec()));
+        }
+        packet
+    }
+    
+    fn response(identifier: u8, eap_type: EapType, data: &[u8]) -> EapPacket {
+        EapPacket {
+            code: EapCode::Response,
+            identifier,
+            eap_type: Some(eap_type),
+            data: data.to_vec(),
+        }
+    }
+    
+    fn expect_challenge(outcome: EapOutcome) -> (EapPacket, Vec<u8>) {
+        match outcome {
+            EapOutcome::Challenge { eap, state } => (EapPacket::parse(&eap).unwrap(), state),
+            other => panic!("expected challenge, got {:?}", other),
+        }
+    }
+    
+    #[test]
+    fn fragments_and_reassembles_large_messages() {
+        let eap = EapPacket::request(7, EapType::Tls, vec![0xab; 1000]);
+        let attrs = fragment(&eap.to_bytes());
+        assert_eq!(attrs.len(), 4);
+        
+        let mut packet = Packet::new(PacketCode::AccessRequest, 1, [0; 16]);
+        for attr in attrs {
+            packet.add_attribute(attr);
+        }
+        
+        let data = reassemble(&packet).unwrap();
+        assert_eq!(EapPacket::parse(&data).unwrap(), eap);
+    }
+    
+    #[tokio::test]
+    async fn negotiates_method_with_nak_and_succeeds() {
+        let server = server();
+        
+        let outcome = server.handle(&request(&response(5, EapType::Identity, b"alice"), None), &NoInner).await.unwrap();
+        let (eap, state) = expect_challenge(outcome);
+        assert_eq!(eap.eap_type, Some(EapType::Tls));
+        
+        // Peer refuses EAP-TLS and asks for MD5-Challenge
+        let nak = response(eap.identifier, EapType::Nak, &[4]);
+        let (eap, state) = expect_challenge(server.handle(&request(&nak, Some(&state)), &NoInner).await.unwrap());
+        assert_eq!(eap.eap_type, Some(EapType::Md5Challenge));
+        
+        let answer = response(eap.identifier, EapType::Md5Challenge, b"ok");
+        match server.handle(&request(&answer, Some(&state)), &NoInner).await.unwrap() {
+            EapOutcome::Accept { eap, identity, success } => {
+                assert_eq!(EapPacket::parse(&eap).unwrap().code, EapCode::Success);
+                assert_eq!(identity, "alice");
+                assert_eq!(success.keys.unwrap().msk.len(), 64);
+            },
+            other => panic!("expected accept, got {:?}", other),
+        }
+        
+        // The State cannot be replayed
+        let replay = server.handle(&request(&answer, Some(&state)), &NoInner).await.unwrap();
+        assert!(matches!(replay, EapOutcome::Reject { .. }));
+    }
+    
+    #[tokio::test]
+    async fn expired_state_is_rejected() {
+        let mut config = Config::default();
+        config.security.auth_protocols = vec!["eap-tls".to_string()];
+        config.eap.state_timeout_secs = 0;
+        
+        let mut server = EapServer::new(&config);
+        server.register_method(Arc::new(EchoMethod(EapType::Tls)));
+        
+        let (eap, state) = expect_challenge(
+            server.handle(&request(&response(1, EapType::Identity, b"bob"), None), &NoInner).await.unwrap()
+        );
+        
+        let answer = response(eap.identifier, EapType::Tls, b"ok");
+        let outcome = server.handle(&request(&answer, Some(&state)), &NoInner).await.unwrap();
+        assert!(matches!(outcome, EapOutcome::Reject { .. }));
+    }
+}
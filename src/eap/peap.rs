@@ -0,0 +1,688 @@
+// eap/peap.rs - PEAPv0 support for rust-radius
+//
+// This module implements PEAPv0 ([MS-PEAP]) with EAP-MSCHAPv2 as the inner
+// method. The inner identity and MS-CHAPv2 response are handed to the
+// `AuthManager` backends; the outer identity is only used for routing.
+// Crypto binding ties the inner MS-CHAPv2 keys to the TLS tunnel.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use rustls::ServerConfig;
+use sha1::Sha1;
+
+use crate::auth::AuthResult;
+use crate::config::EapTlsConfig;
+use crate::eap::tls::{self, TlsProgress, TlsTunnel};
+use crate::eap::{
+    inner_request, EapCode, EapContext, EapKeys, EapMethod, EapPacket, EapSession, EapStep, EapSuccess, EapType,
+};
+use crate::mschap;
+use crate::protocol::Attribute;
+use crate::Result;
+
+/// PEAP version carried in the flags octet
+const PEAP_VERSION: u8 = 0;
+
+/// TLS 1.2 key derivation label (same as EAP-TLS)
+const PEAP_KEY_LABEL: &[u8] = b"client EAP encryption";
+
+/// Mandatory bit of an EAP-TLV type
+const TLV_MANDATORY: u16 = 0x8000;
+
+/// Result TLV
+const TLV_RESULT: u16 = 3;
+
+/// Crypto-Binding TLV
+const TLV_CRYPTO_BINDING: u16 = 12;
+
+/// Result TLV status values
+const RESULT_SUCCESS: u16 = 1;
+const RESULT_FAILURE: u16 = 2;
+
+/// Length of the Crypto-Binding TLV value
+const CRYPTO_BINDING_LEN: usize = 56;
+
+/// Crypto-Binding TLV subtypes
+const BINDING_REQUEST: u8 = 0;
+const BINDING_RESPONSE: u8 = 1;
+
+/// Label for the IPMK and CMK derivation ([MS-PEAP] 3.1.5.5.2.1)
+const IPMK_LABEL: &[u8] = b"Inner Methods Compound Keys";
+
+/// Label for the compound session key derivation ([MS-PEAP] 3.1.5.5.2.2)
+const CSK_LABEL: &[u8] = b"Session Key Generating Function";
+
+/// EAP-MSCHAPv2 op-codes
+const MSCHAPV2_CHALLENGE: u8 = 1;
+const MSCHAPV2_RESPONSE: u8 = 2;
+const MSCHAPV2_SUCCESS: u8 = 3;
+const MSCHAPV2_FAILURE: u8 = 4;
+
+/// Name sent in EAP-MSCHAPv2 challenges
+const SERVER_NAME: &[u8] = b"rust-radius";
+
+type HmacSha1 = Hmac<Sha1>;
+
+/// PEAPv0 method
+pub struct PeapMethod {
+    /// rustls server configuration
+    tls_config: Arc<ServerConfig>,
+    
+    /// Maximum TLS data per EAP packet
+    fragment_size: usize,
+}
+
+impl PeapMethod {
+    /// Create a new PEAP method
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - EAP TLS configuration (client certificates are not required)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server certificate or key cannot be loaded
+    pub fn new(config: &EapTlsConfig) -> Result<Self> {
+        Ok(Self {
+            tls_config: tls::server_config(config, false)?,
+            fragment_size: config.fragment_size,
+        })
+    }
+}
+
+impl EapMethod for PeapMethod {
+    fn method_type(&self) -> EapType {
+        EapType::Peap
+    }
+    
+    fn start(&self, _identity: &str) -> Result<Box<dyn EapSession>> {
+        Ok(Box::new(PeapSession {
+            tunnel: TlsTunnel::new(self.tls_config.clone(), self.fragment_size, PEAP_VERSION)?,
+            phase: Phase::Handshake,
+            inner_identity: String::new(),
+            tlv_identifier: 0,
+        }))
+    }
+}
+
+/// Result of the inner method, kept until the peer confirms it
+struct InnerSuccess {
+    /// Inner session key (MS-CHAPv2 send key followed by receive key)
+    isk: [u8; 32],
+    
+    /// Reply attributes from the backend
+    attributes: Vec<Attribute>,
+    
+    /// Groups the backend found with the credential
+    groups: Vec<String>,
+}
+
+/// Progress of a PEAP conversation
+enum Phase {
+    /// TLS handshake in progress
+    Handshake,
+    
+    /// Sent the inner EAP-Request/Identity
+    Identity,
+    
+    /// Sent an EAP-MSCHAPv2 Challenge
+    Challenge {
+        /// MS-CHAPv2-ID of the challenge
+        ms_id: u8,
+        
+        /// Authenticator challenge
+        challenge: [u8; mschap::CHALLENGE_LEN],
+    },
+    
+    /// Sent an EAP-MSCHAPv2 Success request
+    MsChapSuccess(Box<InnerSuccess>),
+    
+    /// Sent an EAP-MSCHAPv2 Failure request
+    MsChapFailure,
+    
+    /// Sent the Result TLV (and Crypto-Binding TLV on success)
+    Result {
+        /// Inner method result, None if the inner method failed
+        success: Option<Box<InnerSuccess>>,
+        
+        /// Crypto-Binding nonce sent to the peer
+        nonce: [u8; 32],
+        
+        /// Compound MAC key
+        cmk: [u8; 20],
+        
+        /// Intermediate PEAP MAC key
+        ipmk: [u8; 40],
+    },
+}
+
+/// Per-conversation PEAP state
+struct PeapSession {
+    /// TLS tunnel
+    tunnel: TlsTunnel,
+    
+    /// Conversation progress
+    phase: Phase,
+    
+    /// Identity from the inner EAP-Response/Identity
+    inner_identity: String,
+    
+    /// Identifier of the last EAP-TLV request
+    tlv_identifier: u8,
+}
+
+#[async_trait]
+impl EapSession for PeapSession {
+    async fn initiate(&mut self, _ctx: &EapContext<'_>) -> Result<EapStep> {
+        Ok(EapStep::Continue(self.tunnel.start()))
+    }
+    
+    async fn process(&mut self, ctx: &EapContext<'_>, data: &[u8]) -> Result<EapStep> {
+        let progress = match self.tunnel.receive(data) {
+            Ok(progress) => progress,
+            Err(e) => {
+                return Ok(EapStep::Failure { reason: e.to_string() });
+            }
+        };
+        
+        if let TlsProgress::Send(data) = progress {
+            return Ok(EapStep::Continue(data));
+        }
+        
+        if self.tunnel.is_handshaking() {
+            return Ok(EapStep::Failure {
+                reason: "Unexpected acknowledgement during TLS handshake".to_string(),
+            });
+        }
+        
+        // Phase 2 starts by asking for the inner identity
+        if matches!(self.phase, Phase::Handshake) {
+            self.phase = Phase::Identity;
+            return self.send_inner(&[EapType::Identity.to_u8()]);
+        }
+        
+        let inner = self.tunnel.read_plaintext()?;
+        if inner.is_empty() {
+            return Ok(EapStep::Failure {
+                reason: "Missing tunneled EAP response".to_string(),
+            });
+        }
+        
+        self.process_inner(ctx, &inner).await
+    }
+}
+
+impl PeapSession {
+    /// Send tunneled data and return the first fragment
+    fn send_inner(&mut self, data: &[u8]) -> Result<EapStep> {
+        self.tunnel.write_plaintext(data)?;
+        
+        Ok(match self.tunnel.pending() {
+            TlsProgress::Send(data) => EapStep::Continue(data),
+            TlsProgress::Idle => EapStep::Failure {
+                reason: "Failed to send tunneled data".to_string(),
+            },
+        })
+    }
+    
+    /// Handle a tunneled EAP response
+    ///
+    /// PEAPv0 sends inner EAP packets without their header (type and data
+    /// only), except for EAP-TLV packets which are sent whole.
+    async fn process_inner(&mut self, ctx: &EapContext<'_>, inner: &[u8]) -> Result<EapStep> {
+        match std::mem::replace(&mut self.phase, Phase::Handshake) {
+            Phase::Handshake => Ok(EapStep::Failure {
+                reason: "Unexpected tunneled data".to_string(),
+            }),
+            Phase::Identity => {
+                if inner[0] != EapType::Identity.to_u8() {
+                    return Ok(EapStep::Failure {
+                        reason: "Expected inner EAP-Response/Identity".to_string(),
+                    });
+                }
+                
+                self.inner_identity = String::from_utf8_lossy(&inner[1..]).to_string();
+                
+                let mut challenge = [0u8; mschap::CHALLENGE_LEN];
+                rand::thread_rng().fill_bytes(&mut challenge);
+                let ms_id = rand::random::<u8>();
+                
+                let mut value = vec![mschap::CHALLENGE_LEN as u8];
+                value.extend_from_slice(&challenge);
+                value.extend_from_slice(SERVER_NAME);
+                
+                self.phase = Phase::Challenge { ms_id, challenge };
+                self.send_inner(&mschapv2_packet(MSCHAPV2_CHALLENGE, ms_id, &value))
+            },
+            Phase::Challenge { ms_id, challenge } => {
+                self.verify_response(ctx, inner, ms_id, &challenge).await
+            },
+            Phase::MsChapSuccess(success) => {
+                if inner != [EapType::MsChapV2.to_u8(), MSCHAPV2_SUCCESS] {
+                    return Ok(EapStep::Failure {
+                        reason: "Peer did not acknowledge EAP-MSCHAPv2 Success".to_string(),
+                    });
+                }
+                
+                self.send_result(Some(success))
+            },
+            Phase::MsChapFailure => self.send_result(None),
+            Phase::Result { success, nonce, cmk, ipmk } => {
+                self.finish(ctx, inner, success, &nonce, &cmk, &ipmk)
+            },
+        }
+    }
+    
+    /// Verify an EAP-MSCHAPv2 Response through the backends
+    async fn verify_response(&mut self, ctx: &EapContext<'_>, inner: &[u8], ms_id: u8, challenge: &[u8]) -> Result<EapStep> {
+        if inner[0] == EapType::Nak.to_u8() {
+            return Ok(EapStep::Failure {
+                reason: "Peer refused inner EAP-MSCHAPv2".to_string(),
+            });
+        }
+        
+        // Type, OpCode, MS-CHAPv2-ID, MS-Length(2), Value-Size, Value(49), Name
+        if inner.len() < 55 || inner[0] != EapType::MsChapV2.to_u8()
+            || inner[1] != MSCHAPV2_RESPONSE || inner[2] != ms_id || inner[5] != 49 {
+            return Ok(EapStep::Failure {
+                reason: "Malformed EAP-MSCHAPv2 Response".to_string(),
+            });
+        }
+        
+        let value = &inner[6..55];
+        let request = inner_request(
+            ctx.request,
+            &self.inner_identity,
+            mschap::v2_request_attributes(ms_id, challenge, &value[..16], &value[24..48]),
+        );
+        
+        let result = match ctx.inner.authenticate_inner(&request).await {
+            Ok(result) => result,
+            Err(e) => {
+                tracing::error!(
+                    outer_identity = ctx.identity,
+                    inner_identity = self.inner_identity,
+                    error = ?e,
+                    "PEAP inner authentication error"
+                );
+                
+                AuthResult::Reject {
+                    reason: "Inner authentication error".to_string(),
+                    attributes: vec![],
+                }
+            }
+        };
+        
+        let (mut attributes, groups) = match result {
+            AuthResult::Accept { attributes, groups } => (attributes, groups),
+            other => {
+                tracing::info!(
+                    outer_identity = ctx.identity,
+                    inner_identity = self.inner_identity,
+                    result = ?other,
+                    "PEAP inner authentication rejected"
+                );
+                
+                let hex: String = challenge.iter().map(|b| format!("{:02X}", b)).collect();
+                let message = format!("E=691 R=0 C={} V=3 M=Authentication failed", hex);
+                
+                self.phase = Phase::MsChapFailure;
+                return self.send_inner(&mschapv2_packet(MSCHAPV2_FAILURE, ms_id, message.as_bytes()));
+            }
+        };
+        
+        let reply = match mschap::take_v2_reply(&mut attributes) {
+            Some(reply) if reply.send_key.len() == 16 && reply.recv_key.len() == 16 => reply,
+            _ => {
+                return Ok(EapStep::Failure {
+                    reason: "Backend accepted without MS-CHAPv2 keys".to_string(),
+                });
+            }
+        };
+        
+        let mut isk = [0u8; 32];
+        isk[..16].copy_from_slice(&reply.send_key);
+        isk[16..].copy_from_slice(&reply.recv_key);
+        
+        let message = format!("{} M=OK", reply.authenticator_response);
+        
+        self.phase = Phase::MsChapSuccess(Box::new(InnerSuccess { isk, attributes, groups }));
+        self.send_inner(&mschapv2_packet(MSCHAPV2_SUCCESS, ms_id, message.as_bytes()))
+    }
+    
+    /// Send the Result TLV, with a Crypto-Binding TLV if the inner method succeeded
+    fn send_result(&mut self, success: Option<Box<InnerSuccess>>) -> Result<EapStep> {
+        let mut nonce = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        // The peer answers with the nonce + 1, so ours must be even
+        nonce[31] &= 0xfe;
+        
+        let status = if success.is_some() { RESULT_SUCCESS } else { RESULT_FAILURE };
+        let mut tlvs = tlv(TLV_MANDATORY | TLV_RESULT, &status.to_be_bytes());
+        
+        let mut cmk = [0u8; 20];
+        let mut ipmk = [0u8; 40];
+        
+        if let Some(success) = &success {
+            // TK is the start of the same key material the MSK would come from
+            let tk = self.tunnel.export_key_material(PEAP_KEY_LABEL, EapType::Peap, 128)?;
+            let imck = prf_plus(&tk[..40], IPMK_LABEL, &success.isk, 60);
+            ipmk.copy_from_slice(&imck[..40]);
+            cmk.copy_from_slice(&imck[40..]);
+            
+            tlvs.extend(crypto_binding(BINDING_REQUEST, &nonce, &cmk));
+        }
+        
+        self.tlv_identifier = self.tlv_identifier.wrapping_add(1);
+        let packet = EapPacket::request(self.tlv_identifier, EapType::Tlv, tlvs).to_bytes();
+        
+        self.phase = Phase::Result { success, nonce, cmk, ipmk };
+        self.send_inner(&packet)
+    }
+    
+    /// Check the peer's EAP-TLV response and complete the conversation
+    fn finish(
+        &mut self,
+        ctx: &EapContext<'_>,
+        inner: &[u8],
+        success: Option<Box<InnerSuccess>>,
+        nonce: &[u8; 32],
+        cmk: &[u8; 20],
+        ipmk: &[u8; 40],
+    ) -> Result<EapStep> {
+        let success = match success {
+            Some(success) => success,
+            None => return Ok(EapStep::Failure {
+                reason: "Inner authentication failed".to_string(),
+            }),
+        };
+        
+        let packet = match EapPacket::parse(inner) {
+            Ok(packet) if packet.code == EapCode::Response && packet.eap_type == Some(EapType::Tlv) => packet,
+            _ => return Ok(EapStep::Failure {
+                reason: "Expected EAP-TLV response".to_string(),
+            }),
+        };
+        
+        let tlvs = match parse_tlvs(&packet.data) {
+            Some(tlvs) => tlvs,
+            None => return Ok(EapStep::Failure {
+                reason: "Malformed EAP-TLV response".to_string(),
+            }),
+        };
+        
+        let result = tlvs.iter()
+            .find(|tlv| tlv.tlv_type == TLV_RESULT)
+            .map(|tlv| tlv.value);
+        if result != Some(&RESULT_SUCCESS.to_be_bytes()[..]) {
+            return Ok(EapStep::Failure {
+                reason: "Peer did not confirm the inner result".to_string(),
+            });
+        }
+        
+        // Peers that support crypto binding prove they derived the same keys
+        let binding = tlvs.iter().find(|tlv| tlv.tlv_type == TLV_CRYPTO_BINDING);
+        let keys = match binding {
+            Some(tlv) => {
+                if !verify_crypto_binding(tlv.value, tlv.raw, nonce, cmk) {
+                    return Ok(EapStep::Failure {
+                        reason: "PEAP crypto binding verification failed".to_string(),
+                    });
+                }
+                
+                let mut csk = prf_plus(ipmk, CSK_LABEL, &[0], 128);
+                let emsk = csk.split_off(64);
+                EapKeys { msk: csk, emsk }
+            },
+            None => self.tunnel.export_keys(PEAP_KEY_LABEL, EapType::Peap)?,
+        };
+        
+        tracing::debug!(
+            outer_identity = ctx.identity,
+            inner_identity = self.inner_identity,
+            crypto_binding = binding.is_some(),
+            "PEAP authentication complete"
+        );
+        
+        let success = *success;
+        Ok(EapStep::Success(Box::new(EapSuccess {
+            keys: Some(keys),
+            attributes: success.attributes,
+            inner_identity: Some(self.inner_identity.clone()),
+            groups: success.groups,
+            ..Default::default()
+        })))
+    }
+}
+
+/// Build a header-less EAP-MSCHAPv2 packet
+fn mschapv2_packet(op_code: u8, ms_id: u8, value: &[u8]) -> Vec<u8> {
+    // MS-Length covers everything from the OpCode
+    let ms_length = (4 + value.len()) as u16;
+    
+    let mut packet = vec![EapType::MsChapV2.to_u8(), op_code, ms_id];
+    packet.extend_from_slice(&ms_length.to_be_bytes());
+    packet.extend_from_slice(value);
+    packet
+}
+
+/// Encode a single EAP-TLV
+fn tlv(tlv_type: u16, value: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(4 + value.len());
+    data.extend_from_slice(&tlv_type.to_be_bytes());
+    data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    data.extend_from_slice(value);
+    data
+}
+
+/// A parsed EAP-TLV
+struct Tlv<'a> {
+    /// TLV type without the mandatory and reserved bits
+    tlv_type: u16,
+    
+    /// TLV value
+    value: &'a [u8],
+    
+    /// The whole TLV including its header
+    raw: &'a [u8],
+}
+
+/// Parse a sequence of EAP-TLVs
+fn parse_tlvs(data: &[u8]) -> Option<Vec<Tlv<'_>>> {
+    let mut tlvs = Vec::new();
+    let mut offset = 0;
+    
+    while offset < data.len() {
+        let header = data.get(offset..offset + 4)?;
+        let tlv_type = u16::from_be_bytes([header[0], header[1]]) & 0x3fff;
+        let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+        
+        let raw = data.get(offset..offset + 4 + length)?;
+        tlvs.push(Tlv { tlv_type, value: &raw[4..], raw });
+        offset += 4 + length;
+    }
+    
+    Some(tlvs)
+}
+
+/// Build a Crypto-Binding TLV ([MS-PEAP] 2.2.8.1.2)
+fn crypto_binding(sub_type: u8, nonce: &[u8; 32], cmk: &[u8; 20]) -> Vec<u8> {
+    // Reserved, Version, RecvVersion, SubType, Nonce, Compound MAC (zero while signing)
+    let mut value = vec![0, PEAP_VERSION, PEAP_VERSION, sub_type];
+    value.extend_from_slice(nonce);
+    value.extend_from_slice(&[0u8; 20]);
+    
+    let mut binding = tlv(TLV_CRYPTO_BINDING, &value);
+    let mac = compound_mac(cmk, &binding);
+    binding[40..].copy_from_slice(&mac);
+    binding
+}
+
+/// Compound MAC over a Crypto-Binding TLV whose MAC field is zero
+fn compound_mac(cmk: &[u8; 20], binding: &[u8]) -> [u8; 20] {
+    let mut mac = HmacSha1::new_from_slice(cmk).expect("HMAC accepts any key length");
+    mac.update(binding);
+    mac.update(&[EapType::Peap.to_u8()]);
+    mac.finalize().into_bytes().into()
+}
+
+/// Verify the peer's Crypto-Binding TLV
+fn verify_crypto_binding(value: &[u8], raw: &[u8], nonce: &[u8; 32], cmk: &[u8; 20]) -> bool {
+    if value.len() != CRYPTO_BINDING_LEN || value[3] != BINDING_RESPONSE {
+        return false;
+    }
+    
+    // The peer's nonce is ours with the least significant bit set
+    let peer_nonce = &value[4..36];
+    if peer_nonce[..31] != nonce[..31] || peer_nonce[31] != nonce[31] | 1 {
+        return false;
+    }
+    
+    let mut unsigned = raw.to_vec();
+    unsigned[40..].fill(0);
+    let expected = compound_mac(cmk, &unsigned);
+    
+    let difference = expected.iter()
+        .zip(&value[36..])
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    difference == 0
+}
+
+/// PRF+ from [MS-PEAP] 3.1.5.5.2.1 (HMAC-SHA1 based)
+fn prf_plus(key: &[u8], label: &[u8], seed: &[u8], length: usize) -> Vec<u8> {
+    let mut output = Vec::with_capacity(length + 20);
+    let mut previous = Vec::new();
+    let mut counter = 1u8;
+    
+    while output.len() < length {
+        let mut mac = HmacSha1::new_from_slice(key).expect("HMAC accepts any key length");
+        mac.update(&previous);
+        mac.update(label);
+        mac.update(seed);
+        mac.update(&[counter, 0, 0]);
+        
+        previous = mac.finalize().into_bytes().to_vec();
+        output.extend_from_slice(&previous);
+        counter = counter.wrapping_add(1);
+    }
+    
+    output.truncate(length);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eap::tls::tests::{converse, Pki, TestUsers};
+    use crate::protocol::{Packet, PacketCode};
+    use rustls::ClientConnection;
+    
+    /// Keys the peer derives while answering the inner method
+    #[derive(Default)]
+    struct PeerKeys {
+        isk: Vec<u8>,
+        msk: Vec<u8>,
+    }
+    
+    /// Answer one tunneled PEAP request as the peer
+    fn respond(client: &ClientConnection, data: &[u8], password: &str, keys: &mut PeerKeys) -> Vec<u8> {
+        if data.is_empty() {
+            return vec![];
+        }
+        
+        // Inner Identity and EAP-MSCHAPv2 requests are sent without an EAP header
+        if data == [EapType::Identity.to_u8()] {
+            return [&[EapType::Identity.to_u8()], &b"alice"[..]].concat();
+        }
+        
+        if data[0] == EapType::MsChapV2.to_u8() {
+            return match data[1] {
+                MSCHAPV2_CHALLENGE => {
+                    let challenge = &data[6..22];
+                    let peer_challenge = [7u8; 16];
+                    let password_hash = mschap::nt_password_hash(password);
+                    let nt_response = mschap::generate_nt_response(challenge, &peer_challenge, "alice", &password_hash);
+                    
+                    let master = mschap::master_key(&password_hash, &nt_response);
+                    keys.isk = [mschap::server_start_key(&master, true), mschap::server_start_key(&master, false)].concat();
+                    
+                    let mut value = vec![49];
+                    value.extend_from_slice(&peer_challenge);
+                    value.extend_from_slice(&[0u8; 8]);
+                    value.extend_from_slice(&nt_response);
+                    value.push(0);
+                    value.extend_from_slice(b"alice");
+                    mschapv2_packet(MSCHAPV2_RESPONSE, data[2], &value)
+                },
+                op_code => vec![EapType::MsChapV2.to_u8(), op_code],
+            };
+        }
+        
+        // EAP-TLV request: confirm the result and answer the crypto binding
+        let request = EapPacket::parse(data).unwrap();
+        let tlvs = parse_tlvs(&request.data).unwrap();
+        let mut response = tlv(TLV_MANDATORY | TLV_RESULT, &RESULT_SUCCESS.to_be_bytes());
+        
+        if let Some(binding) = tlvs.iter().find(|tlv| tlv.tlv_type == TLV_CRYPTO_BINDING) {
+            let (value, raw) = (binding.value, binding.raw);
+            let tk = client.export_keying_material(vec![0u8; 64], PEAP_KEY_LABEL, None).unwrap();
+            let imck = prf_plus(&tk[..40], IPMK_LABEL, &keys.isk, 60);
+            let cmk: [u8; 20] = imck[40..].try_into().unwrap();
+            
+            let mut unsigned = raw.to_vec();
+            unsigned[40..].fill(0);
+            assert_eq!(compound_mac(&cmk, &unsigned)[..], value[36..], "server compound MAC");
+            
+            let mut nonce: [u8; 32] = value[4..36].try_into().unwrap();
+            nonce[31] |= 1;
+            response.extend(crypto_binding(BINDING_RESPONSE, &nonce, &cmk));
+            
+            keys.msk = prf_plus(&imck[..40], CSK_LABEL, &[0], 64);
+        }
+        
+        EapPacket {
+            code: EapCode::Response,
+            identifier: request.identifier,
+            eap_type: Some(EapType::Tlv),
+            data: response,
+        }.to_bytes()
+    }
+    
+    async fn run(pki: &Pki, password: &str) -> (EapStep, PeerKeys) {
+        let request = Packet::new(PacketCode::AccessRequest, 1, [0; 16]);
+        let ctx = EapContext { request: &request, identity: "anonymous", inner: &TestUsers };
+        let mut session = PeapMethod::new(&pki.config("1.2", 400)).unwrap().start("anonymous").unwrap();
+        let mut client = pki.client(&[&rustls::version::TLS12]);
+        
+        let mut keys = PeerKeys::default();
+        let (step, _) = converse(session.as_mut(), &ctx, &mut client, |client, data| {
+            respond(client, data, password, &mut keys)
+        }).await;
+        
+        (step, keys)
+    }
+    
+    #[tokio::test]
+    async fn mschapv2_with_crypto_binding() {
+        let pki = Pki::new("peap");
+        let (step, keys) = run(&pki, "secret").await;
+        
+        let EapStep::Success(success) = step else {
+            panic!("expected success, got {:?}", step);
+        };
+        assert_eq!(success.inner_identity.as_deref(), Some("alice"));
+        assert_eq!(success.keys.as_ref().unwrap().msk, keys.msk);
+        assert_eq!(success.attributes, vec![Attribute::String("Reply-Message".to_string(), "hello".to_string())]);
+    }
+    
+    #[tokio::test]
+    async fn wrong_password_rejected() {
+        let pki = Pki::new("peap-reject");
+        let (step, _) = run(&pki, "wrong").await;
+        
+        assert!(matches!(step, EapStep::Failure { .. }));
+    }
+}
@@ -0,0 +1,820 @@
+// eap/tls.rs - EAP-TLS for rust-radius
+//
+// This module implements EAP-TLS (RFC 5216, and RFC 9190 for TLS 1.3) on top of
+// rustls. The `TlsTunnel` handles the EAP-TLS framing (flags, fragmentation and
+// acknowledgements) and drives an in-memory rustls server connection, so that it
+// can be shared by the tunneled methods.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rustls::server::{AllowAnyAuthenticatedClient, UnparsedCertRevocationList};
+use rustls::{Certificate, PrivateKey, ProtocolVersion, RootCertStore, ServerConfig, ServerConnection};
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use crate::config::EapTlsConfig;
+use crate::eap::{CertificateIdentity, EapContext, EapKeys, EapMethod, EapSession, EapStep, EapSuccess, EapType};
+use crate::Result;
+
+/// Length-included flag
+const FLAG_LENGTH: u8 = 0x80;
+
+/// More-fragments flag
+const FLAG_MORE: u8 = 0x40;
+
+/// Start flag
+const FLAG_START: u8 = 0x20;
+
+/// Largest TLS message we are willing to reassemble
+const MAX_TLS_MESSAGE_LEN: usize = 64 * 1024;
+
+/// Microsoft User Principal Name (otherName in subjectAltName)
+const OID_UPN: &str = "1.3.6.1.4.1.311.20.2.3";
+
+/// TLS 1.2 key derivation label for EAP-TLS (RFC 5216, section 2.3)
+const EAP_TLS_KEY_LABEL: &[u8] = b"client EAP encryption";
+
+/// TLS 1.3 key derivation label (RFC 9190, section 2.3)
+const EAP_TLS13_KEY_LABEL: &[u8] = b"EXPORTER_EAP_TLS_Key_Material";
+
+/// Build a rustls server configuration for TLS-based EAP methods
+///
+/// # Arguments
+///
+/// * `config` - EAP TLS configuration
+/// * `require_client_cert` - Whether peers must present a certificate issued by `ca_file`
+///
+/// # Errors
+///
+/// Returns an error if a certificate, key or CRL cannot be loaded
+pub fn server_config(config: &EapTlsConfig, require_client_cert: bool) -> Result<Arc<ServerConfig>> {
+    let certs = load_certificates(&config.cert_file)?;
+    let key = load_private_key(&config.key_file)?;
+    
+    let versions: &[&rustls::SupportedProtocolVersion] = match config.min_version.as_str() {
+        "1.2" => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        "1.3" => &[&rustls::version::TLS13],
+        other => return Err(format!("Unsupported TLS version: {}", other).into()),
+    };
+    
+    let builder = ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(versions)
+        .map_err(|e| format!("Invalid TLS configuration: {}", e))?;
+    
+    let builder = if require_client_cert {
+        let ca_file = config.ca_file.as_ref()
+            .ok_or("eap.tls.ca_file is required to validate client certificates")?;
+        
+        let mut roots = RootCertStore::empty();
+        for cert in load_certificates(ca_file)? {
+            roots.add(&cert)
+                .map_err(|e| format!("Invalid CA certificate in {}: {}", ca_file.display(), e))?;
+        }
+        
+        let crls = match &config.crl_file {
+            Some(path) => load_crls(path)?,
+            None => Vec::new(),
+        };
+        
+        let verifier = AllowAnyAuthenticatedClient::new(roots)
+            .with_crls(crls)
+            .map_err(|e| format!("Invalid CRL: {:?}", e))?;
+        
+        builder.with_client_cert_verifier(verifier.boxed())
+    } else {
+        builder.with_no_client_auth()
+    };
+    
+    let mut server_config = builder.with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid server certificate or key: {}", e))?;
+    
+    // Session resumption is not supported, so don't hand out tickets
+    server_config.send_tls13_tickets = 0;
+    
+    Ok(Arc::new(server_config))
+}
+
+/// Load a PEM certificate chain
+fn load_certificates(path: &Path) -> Result<Vec<Certificate>> {
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read certificate file {}: {}", path.display(), e))?;
+    
+    let certs = rustls_pemfile::certs(&mut content.as_slice())
+        .map_err(|e| format!("Failed to parse certificate file {}: {}", path.display(), e))?;
+    
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path.display()).into());
+    }
+    
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// Load a PEM private key (PKCS#8, PKCS#1 or SEC1)
+fn load_private_key(path: &Path) -> Result<PrivateKey> {
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read key file {}: {}", path.display(), e))?;
+    
+    let items = rustls_pemfile::read_all(&mut content.as_slice())
+        .map_err(|e| format!("Failed to parse key file {}: {}", path.display(), e))?;
+    
+    items.into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", path.display()).into())
+}
+
+/// Load certificate revocation lists (PEM or DER)
+fn load_crls(path: &Path) -> Result<Vec<UnparsedCertRevocationList>> {
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read CRL file {}: {}", path.display(), e))?;
+    
+    let crls = rustls_pemfile::crls(&mut content.as_slice())
+        .map_err(|e| format!("Failed to parse CRL file {}: {}", path.display(), e))?;
+    
+    // Files without PEM armour are treated as a single DER-encoded CRL
+    if crls.is_empty() {
+        return Ok(vec![UnparsedCertRevocationList(content)]);
+    }
+    
+    Ok(crls.into_iter().map(UnparsedCertRevocationList).collect())
+}
+
+/// Extract the identity of a DER-encoded certificate
+///
+/// # Errors
+///
+/// Returns an error if the certificate cannot be parsed
+pub fn certificate_identity(der: &[u8]) -> Result<CertificateIdentity> {
+    let (_, cert) = X509Certificate::from_der(der)
+        .map_err(|e| format!("Failed to parse certificate: {}", e))?;
+    
+    let mut identity = CertificateIdentity {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        serial: cert.raw_serial_as_string(),
+        common_name: cert.subject().iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(|cn| cn.to_string()),
+        ..Default::default()
+    };
+    
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            match name {
+                GeneralName::DNSName(dns) => identity.dns_names.push(dns.to_string()),
+                GeneralName::RFC822Name(email) => identity.emails.push(email.to_string()),
+                GeneralName::OtherName(oid, value) if oid.to_id_string() == OID_UPN => {
+                    if let Some(upn) = parse_upn(value) {
+                        identity.upns.push(upn);
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+    
+    Ok(identity)
+}
+
+/// Parse the `[0] EXPLICIT UTF8String` value of a UPN otherName
+fn parse_upn(value: &[u8]) -> Option<String> {
+    use x509_parser::der_parser::asn1_rs::Any;
+    
+    let (_, explicit) = Any::from_der(value).ok()?;
+    let (_, inner) = Any::from_der(explicit.data).ok()?;
+    
+    std::str::from_utf8(inner.data).ok().map(|s| s.to_string())
+}
+
+/// Progress of the TLS exchange after a peer response
+#[derive(Debug)]
+pub enum TlsProgress {
+    /// Send this Type-Data to the peer
+    Send(Vec<u8>),
+    
+    /// The peer acknowledged our last fragment and nothing is pending
+    Idle,
+}
+
+/// TLS connection carried over EAP
+///
+/// Implements the EAP-TLS record framing shared by EAP-TLS, PEAP and TTLS: the
+/// L/M/S flags, fragmentation of outgoing data, reassembly of incoming
+/// fragments and acknowledgements.
+pub struct TlsTunnel {
+    /// In-memory rustls server connection
+    conn: ServerConnection,
+    
+    /// Maximum TLS data per EAP packet
+    fragment_size: usize,
+    
+    /// Version bits carried in the flags octet (PEAP/TTLS version)
+    version: u8,
+    
+    /// Incoming fragments awaiting reassembly
+    incoming: Vec<u8>,
+    
+    /// Outgoing TLS data not yet sent
+    outgoing: Vec<u8>,
+    
+    /// Whether the first fragment of `outgoing` has been sent
+    outgoing_started: bool,
+}
+
+impl TlsTunnel {
+    /// Create a new TLS tunnel
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - rustls server configuration
+    /// * `fragment_size` - Maximum TLS data per EAP packet
+    /// * `version` - Method version carried in the flags octet (0 for EAP-TLS)
+    pub fn new(config: Arc<ServerConfig>, fragment_size: usize, version: u8) -> Result<Self> {
+        let conn = ServerConnection::new(config)
+            .map_err(|e| format!("Failed to create TLS connection: {}", e))?;
+        
+        Ok(Self {
+            conn,
+            fragment_size: fragment_size.max(64),
+            version,
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            outgoing_started: false,
+        })
+    }
+    
+    /// Type-Data of the initial EAP-TLS Start request
+    pub fn start(&self) -> Vec<u8> {
+        vec![FLAG_START | self.version]
+    }
+    
+    /// Process the Type-Data of a peer response
+    ///
+    /// # Errors
+    ///
+    /// Returns an error on malformed framing or if the TLS handshake fails
+    pub fn receive(&mut self, data: &[u8]) -> Result<TlsProgress> {
+        let flags = *data.first().ok_or("Empty EAP-TLS response")?;
+        let mut payload = &data[1..];
+        
+        if flags & FLAG_LENGTH != 0 {
+            if payload.len() < 4 {
+                return Err("EAP-TLS length field truncated".into());
+            }
+            
+            let total = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize;
+            if total > MAX_TLS_MESSAGE_LEN {
+                return Err(format!("EAP-TLS message too large: {} bytes", total).into());
+            }
+            
+            payload = &payload[4..];
+        }
+        
+        // An empty response acknowledges our previous fragment
+        if payload.is_empty() && flags & FLAG_MORE == 0 {
+            return Ok(if self.outgoing.is_empty() {
+                TlsProgress::Idle
+            } else {
+                TlsProgress::Send(self.next_fragment())
+            });
+        }
+        
+        if self.incoming.len() + payload.len() > MAX_TLS_MESSAGE_LEN {
+            return Err("EAP-TLS message too large".into());
+        }
+        self.incoming.extend_from_slice(payload);
+        
+        // Acknowledge fragments until the last one arrives
+        if flags & FLAG_MORE != 0 {
+            return Ok(TlsProgress::Send(vec![self.version]));
+        }
+        
+        let records = std::mem::take(&mut self.incoming);
+        self.process_records(&records)?;
+        
+        Ok(self.pending())
+    }
+    
+    /// Feed complete TLS records to rustls and collect its output
+    fn process_records(&mut self, records: &[u8]) -> Result<()> {
+        let mut reader = records;
+        
+        while !reader.is_empty() {
+            self.conn.read_tls(&mut reader)
+                .map_err(|e| format!("Failed to read TLS data: {}", e))?;
+            
+            if let Err(e) = self.conn.process_new_packets() {
+                return Err(format!("TLS handshake failed: {}", e).into());
+            }
+        }
+        
+        self.flush()
+    }
+    
+    /// Move any TLS data rustls wants to send into the outgoing buffer
+    fn flush(&mut self) -> Result<()> {
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.outgoing)
+                .map_err(|e| format!("Failed to write TLS data: {}", e))?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Next Type-Data to send: a fragment of pending output, or Idle
+    pub fn pending(&mut self) -> TlsProgress {
+        if self.outgoing.is_empty() {
+            TlsProgress::Idle
+        } else {
+            TlsProgress::Send(self.next_fragment())
+        }
+    }
+    
+    /// Take the next fragment of outgoing TLS data
+    fn next_fragment(&mut self) -> Vec<u8> {
+        let length = self.outgoing.len().min(self.fragment_size);
+        let more = length < self.outgoing.len();
+        
+        let mut flags = self.version;
+        if more {
+            flags |= FLAG_MORE;
+        }
+        
+        let mut data = Vec::with_capacity(length + 5);
+        
+        // The first fragment of a fragmented message carries the total length
+        if more && !self.outgoing_started {
+            data.push(flags | FLAG_LENGTH);
+            data.extend_from_slice(&(self.outgoing.len() as u32).to_be_bytes());
+        } else {
+            data.push(flags);
+        }
+        
+        data.extend(self.outgoing.drain(..length));
+        self.outgoing_started = more;
+        
+        data
+    }
+    
+    /// Whether the TLS handshake is still in progress
+    pub fn is_handshaking(&self) -> bool {
+        self.conn.is_handshaking()
+    }
+    
+    /// Negotiated TLS version
+    pub fn protocol_version(&self) -> Option<ProtocolVersion> {
+        self.conn.protocol_version()
+    }
+    
+    /// Whether TLS 1.3 was negotiated
+    pub fn is_tls13(&self) -> bool {
+        self.conn.protocol_version() == Some(ProtocolVersion::TLSv1_3)
+    }
+    
+    /// Identity of the certificate presented by the peer, if any
+    pub fn peer_identity(&self) -> Option<CertificateIdentity> {
+        let cert = self.conn.peer_certificates()?.first()?;
+        
+        match certificate_identity(&cert.0) {
+            Ok(identity) => Some(identity),
+            Err(e) => {
+                tracing::warn!(error = ?e, "Failed to parse peer certificate");
+                None
+            }
+        }
+    }
+    
+    /// Export keying material from the TLS session
+    ///
+    /// TLS 1.2 uses the method's PRF label with the client and server randoms;
+    /// TLS 1.3 uses the RFC 9190 exporter with the method's type code as context.
+    pub fn export_key_material(&self, tls12_label: &[u8], method: EapType, length: usize) -> Result<Vec<u8>> {
+        if self.is_tls13() {
+            self.export(EAP_TLS13_KEY_LABEL, Some(&[method.to_u8()]), length)
+        } else {
+            self.export(tls12_label, None, length)
+        }
+    }
+    
+    /// Run the TLS exporter with an arbitrary label and context
+    pub fn export(&self, label: &[u8], context: Option<&[u8]>, length: usize) -> Result<Vec<u8>> {
+        self.conn.export_keying_material(vec![0u8; length], label, context)
+            .map_err(|e| format!("Failed to export TLS keying material: {}", e).into())
+    }
+    
+    /// Export the MSK and EMSK (64 bytes each)
+    pub fn export_keys(&self, tls12_label: &[u8], method: EapType) -> Result<EapKeys> {
+        let mut material = self.export_key_material(tls12_label, method, 128)?;
+        let emsk = material.split_off(64);
+        
+        Ok(EapKeys { msk: material, emsk })
+    }
+    
+    /// Queue application data to send through the tunnel
+    pub fn write_plaintext(&mut self, data: &[u8]) -> Result<()> {
+        self.conn.writer().write_all(data)
+            .map_err(|e| format!("Failed to write tunneled data: {}", e))?;
+        self.flush()
+    }
+    
+    /// Read application data received through the tunnel
+    pub fn read_plaintext(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        
+        match self.conn.reader().read_to_end(&mut data) {
+            Ok(_) => {},
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {},
+            Err(e) => return Err(format!("Failed to read tunneled data: {}", e).into()),
+        }
+        
+        Ok(data)
+    }
+}
+
+/// EAP-TLS method
+pub struct EapTlsMethod {
+    /// rustls server configuration (client certificates required)
+    tls_config: Arc<ServerConfig>,
+    
+    /// Maximum TLS data per EAP packet
+    fragment_size: usize,
+}
+
+impl EapTlsMethod {
+    /// Create a new EAP-TLS method
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - EAP TLS configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the certificates, key or CRL cannot be loaded
+    pub fn new(config: &EapTlsConfig) -> Result<Self> {
+        Ok(Self {
+            tls_config: server_config(config, true)?,
+            fragment_size: config.fragment_size,
+        })
+    }
+}
+
+impl EapMethod for EapTlsMethod {
+    fn method_type(&self) -> EapType {
+        EapType::Tls
+    }
+    
+    fn start(&self, _identity: &str) -> Result<Box<dyn EapSession>> {
+        Ok(Box::new(EapTlsSession {
+            tunnel: TlsTunnel::new(self.tls_config.clone(), self.fragment_size, 0)?,
+            commitment_sent: false,
+        }))
+    }
+}
+
+/// Per-conversation EAP-TLS state
+struct EapTlsSession {
+    /// TLS connection
+    tunnel: TlsTunnel,
+    
+    /// Whether the TLS 1.3 commitment message has been queued
+    commitment_sent: bool,
+}
+
+#[async_trait]
+impl EapSession for EapTlsSession {
+    async fn initiate(&mut self, _ctx: &EapContext<'_>) -> Result<EapStep> {
+        Ok(EapStep::Continue(self.tunnel.start()))
+    }
+    
+    async fn process(&mut self, ctx: &EapContext<'_>, data: &[u8]) -> Result<EapStep> {
+        let progress = match self.tunnel.receive(data) {
+            Ok(progress) => progress,
+            Err(e) => {
+                return Ok(EapStep::Failure { reason: e.to_string() });
+            }
+        };
+        
+        match progress {
+            TlsProgress::Send(data) => Ok(EapStep::Continue(data)),
+            TlsProgress::Idle if self.tunnel.is_handshaking() => Ok(EapStep::Failure {
+                reason: "Unexpected acknowledgement during TLS handshake".to_string(),
+            }),
+            TlsProgress::Idle if self.tunnel.is_tls13() && !self.commitment_sent => {
+                // With TLS 1.3 the server signals the end of the handshake with a
+                // single 0x00 byte of application data (RFC 9190, section 2.5)
+                self.commitment_sent = true;
+                self.tunnel.write_plaintext(&[0])?;
+                
+                Ok(match self.tunnel.pending() {
+                    TlsProgress::Send(data) => EapStep::Continue(data),
+                    TlsProgress::Idle => EapStep::Failure {
+                        reason: "Failed to send TLS commitment message".to_string(),
+                    },
+                })
+            },
+            TlsProgress::Idle => {
+                let certificate = self.tunnel.peer_identity();
+                let keys = self.tunnel.export_keys(EAP_TLS_KEY_LABEL, EapType::Tls)?;
+                
+                tracing::debug!(
+                    identity = ctx.identity,
+                    version = ?self.tunnel.protocol_version(),
+                    subject = ?certificate.as_ref().map(|c| &c.subject),
+                    "EAP-TLS handshake complete"
+                );
+                
+                Ok(EapStep::Success(Box::new(EapSuccess {
+                    keys: Some(keys),
+                    certificate,
+                    ..Default::default()
+                })))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::protocol::{Packet, PacketCode};
+    use rcgen::{
+        BasicConstraints, CertificateParams, CertificateRevocationList, CertificateRevocationListParams,
+        DnType, IsCa, KeyIdMethod, RevokedCertParams, SerialNumber, SanType,
+    };
+    use crate::auth::AuthResult;
+    use crate::eap::InnerAuthenticator;
+    use crate::mschap;
+    use crate::protocol::Attribute;
+    use rustls::{ClientConfig, ClientConnection};
+    use std::path::PathBuf;
+    
+    /// EAP-TLS never consults the inner authenticator
+    struct NoInner;
+    
+    #[async_trait]
+    impl InnerAuthenticator for NoInner {
+        async fn authenticate_inner(&self, _request: &Packet) -> Result<AuthResult> {
+            Err("unexpected inner authentication".into())
+        }
+    }
+    
+    /// Inner authenticator knowing a single user, "alice" with password "secret"
+    pub(crate) struct TestUsers;
+    
+    #[async_trait]
+    impl InnerAuthenticator for TestUsers {
+        async fn authenticate_inner(&self, request: &Packet) -> Result<AuthResult> {
+            let reject = AuthResult::Reject { reason: "rejected".to_string(), attributes: vec![] };
+            
+            if request.get_attribute("User-Name") != Some(&Attribute::String("User-Name".to_string(), "alice".to_string())) {
+                return Ok(reject);
+            }
+            
+            let mut attributes = vec![Attribute::String("Reply-Message".to_string(), "hello".to_string())];
+            match request.get_attribute("User-Password") {
+                Some(Attribute::String(_, password)) if password == "secret" => {},
+                Some(_) => return Ok(reject),
+                None => match mschap::verify_v2_request(request, "alice", &mschap::nt_password_hash("secret")) {
+                    mschap::MsChapResult::Accepted(reply) => attributes.extend(reply),
+                    _ => return Ok(reject),
+                },
+            }
+            
+            Ok(AuthResult::Accept { attributes, groups: vec![] })
+        }
+    }
+    
+    /// Test PKI: a CA, a server certificate and a client certificate
+    pub(crate) struct Pki {
+        dir: PathBuf,
+        ca: rcgen::Certificate,
+        client: rcgen::Certificate,
+    }
+    
+    impl Pki {
+        pub(crate) fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("radius-eap-tls-{}-{}", std::process::id(), name));
+            fs::create_dir_all(&dir).unwrap();
+            
+            let mut ca_params = CertificateParams::new(vec![]);
+            ca_params.distinguished_name.push(DnType::CommonName, "Test CA");
+            ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+            
+            let server = rcgen::Certificate::from_params(CertificateParams::new(vec!["radius.example.com".to_string()])).unwrap();
+            
+            let mut client_params = CertificateParams::new(vec![]);
+            client_params.distinguished_name.push(DnType::CommonName, "alice");
+            client_params.subject_alt_names = vec![SanType::Rfc822Name("alice@example.com".to_string())];
+            client_params.serial_number = Some(SerialNumber::from(vec![0x42]));
+            let client = rcgen::Certificate::from_params(client_params).unwrap();
+            
+            fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+            fs::write(dir.join("server.pem"), server.serialize_pem_with_signer(&ca).unwrap()).unwrap();
+            fs::write(dir.join("server.key"), server.serialize_private_key_pem()).unwrap();
+            
+            Self { dir, ca, client }
+        }
+        
+        pub(crate) fn config(&self, min_version: &str, fragment_size: usize) -> EapTlsConfig {
+            EapTlsConfig {
+                cert_file: self.dir.join("server.pem"),
+                key_file: self.dir.join("server.key"),
+                ca_file: Some(self.dir.join("ca.pem")),
+                crl_file: None,
+                fragment_size,
+                min_version: min_version.to_string(),
+            }
+        }
+        
+        fn revoke_client(&self, config: &mut EapTlsConfig) {
+            let now = rcgen::date_time_ymd(2024, 1, 1);
+            let crl = CertificateRevocationList::from_params(CertificateRevocationListParams {
+                this_update: now,
+                next_update: rcgen::date_time_ymd(2099, 1, 1),
+                crl_number: SerialNumber::from(vec![1]),
+                issuing_distribution_point: None,
+                revoked_certs: vec![RevokedCertParams {
+                    serial_number: SerialNumber::from(vec![0x42]),
+                    revocation_time: now,
+                    reason_code: None,
+                    invalidity_date: None,
+                }],
+                alg: &rcgen::PKCS_ECDSA_P256_SHA256,
+                key_identifier_method: KeyIdMethod::Sha256,
+            }).unwrap();
+            
+            let path = self.dir.join("ca.crl");
+            fs::write(&path, crl.serialize_pem_with_signer(&self.ca).unwrap()).unwrap();
+            config.crl_file = Some(path);
+        }
+        
+        pub(crate) fn client(&self, versions: &[&'static rustls::SupportedProtocolVersion]) -> ClientConnection {
+            let mut roots = RootCertStore::empty();
+            roots.add(&Certificate(self.ca.serialize_der().unwrap())).unwrap();
+            
+            let config = ClientConfig::builder()
+                .with_safe_default_cipher_suites()
+                .with_safe_default_kx_groups()
+                .with_protocol_versions(versions)
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_client_auth_cert(
+                    vec![Certificate(self.client.serialize_der_with_signer(&self.ca).unwrap())],
+                    PrivateKey(self.client.serialize_private_key_der()),
+                )
+                .unwrap();
+            
+            ClientConnection::new(Arc::new(config), "radius.example.com".try_into().unwrap()).unwrap()
+        }
+    }
+    
+    impl Drop for Pki {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+    
+    /// Run an EAP-TLS conversation, acting as the peer with `client`
+    ///
+    /// Returns the final step, the number of round trips and the tunneled data
+    /// the peer received.
+    async fn run(config: &EapTlsConfig, client: &mut ClientConnection) -> (EapStep, usize, Vec<u8>) {
+        let request = Packet::new(PacketCode::AccessRequest, 1, [0; 16]);
+        let ctx = EapContext { request: &request, identity: "alice", inner: &NoInner };
+        let mut session = EapTlsMethod::new(config).unwrap().start("alice").unwrap();
+        
+        let mut received = Vec::new();
+        let (step, rounds) = converse(session.as_mut(), &ctx, client, |_, data| {
+            received.extend_from_slice(data);
+            vec![]
+        }).await;
+        
+        (step, rounds, received)
+    }
+    
+    /// Drive a TLS-based conversation, acting as the peer with `client`
+    ///
+    /// Once the peer's handshake is complete, `respond` is called after each
+    /// complete server message with the tunneled data received, and returns
+    /// the data to send back through the tunnel.
+    pub(crate) async fn converse(
+        session: &mut dyn EapSession,
+        ctx: &EapContext<'_>,
+        client: &mut ClientConnection,
+        mut respond: impl FnMut(&ClientConnection, &[u8]) -> Vec<u8>,
+    ) -> (EapStep, usize) {
+        let mut step = session.initiate(ctx).await.unwrap();
+        let mut incoming = Vec::new();
+        let mut outgoing = Vec::new();
+        let mut rounds = 0;
+        
+        while let EapStep::Continue(data) = step {
+            rounds += 1;
+            assert!(rounds < 100, "conversation did not terminate");
+            
+            let flags = data[0];
+            let payload = if flags & FLAG_LENGTH != 0 { &data[5..] } else { &data[1..] };
+            incoming.extend_from_slice(payload);
+            
+            // Peer output is fragmented at 200 bytes to exercise reassembly
+            let response = if flags & FLAG_MORE != 0 {
+                vec![0]
+            } else {
+                let reader = std::mem::take(&mut incoming);
+                let mut reader = reader.as_slice();
+                while !reader.is_empty() {
+                    client.read_tls(&mut reader).unwrap();
+                }
+                if client.process_new_packets().is_err() {
+                    return (EapStep::Failure { reason: "client rejected server".to_string() }, rounds);
+                }
+                
+                if !client.is_handshaking() {
+                    let mut tunneled = Vec::new();
+                    let _ = client.reader().read_to_end(&mut tunneled);
+                    
+                    let reply = respond(client, &tunneled);
+                    if !reply.is_empty() {
+                        client.writer().write_all(&reply).unwrap();
+                    }
+                }
+                
+                if outgoing.is_empty() {
+                    while client.wants_write() {
+                        client.write_tls(&mut outgoing).unwrap();
+                    }
+                }
+                
+                let length = outgoing.len().min(200);
+                let mut response = vec![if length < outgoing.len() { FLAG_MORE } else { 0 }];
+                response.extend(outgoing.drain(..length));
+                response
+            };
+            
+            step = session.process(ctx, &response).await.unwrap();
+        }
+        
+        (step, rounds)
+    }
+    
+    fn assert_success(step: &EapStep, client: &ClientConnection, label: &[u8], context: Option<&[u8]>) {
+        let EapStep::Success(success) = step else {
+            panic!("expected success, got {:?}", step);
+        };
+        
+        let expected = client.export_keying_material(vec![0u8; 128], label, context).unwrap();
+        let keys = success.keys.as_ref().unwrap();
+        assert_eq!(keys.msk, expected[..64]);
+        assert_eq!(keys.emsk, expected[64..]);
+        
+        let certificate = success.certificate.as_ref().unwrap();
+        assert_eq!(certificate.common_name.as_deref(), Some("alice"));
+        assert!(certificate.matches("alice@example.com"));
+    }
+    
+    #[tokio::test]
+    async fn tls12_handshake_with_fragmentation() {
+        let pki = Pki::new("tls12");
+        let config = pki.config("1.2", 300);
+        let mut client = pki.client(&[&rustls::version::TLS12]);
+        
+        let (step, rounds, _) = run(&config, &mut client).await;
+        assert!(rounds > 4, "expected fragmented exchange");
+        assert_success(&step, &client, EAP_TLS_KEY_LABEL, None);
+    }
+    
+    #[tokio::test]
+    async fn tls13_handshake_with_commitment() {
+        let pki = Pki::new("tls13");
+        let config = pki.config("1.3", 1024);
+        let mut client = pki.client(&[&rustls::version::TLS13]);
+        
+        let (step, _, received) = run(&config, &mut client).await;
+        assert_success(&step, &client, EAP_TLS13_KEY_LABEL, Some(&[EapType::Tls.to_u8()]));
+        
+        // The peer must have received the 0x00 commitment message
+        assert_eq!(received, vec![0]);
+    }
+    
+    #[tokio::test]
+    async fn revoked_client_certificate_rejected() {
+        let pki = Pki::new("revoked");
+        let mut config = pki.config("1.2", 1024);
+        pki.revoke_client(&mut config);
+        let mut client = pki.client(&[&rustls::version::TLS12]);
+        
+        let (step, _, _) = run(&config, &mut client).await;
+        assert!(matches!(step, EapStep::Failure { .. }));
+    }
+}
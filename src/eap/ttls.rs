@@ -0,0 +1,430 @@
+// eap/ttls.rs - EAP-TTLS support for rust-radius
+//
+// This module implements EAP-TTLSv0 (RFC 5281) with PAP and MS-CHAPv2 as
+// inner methods. Inner credentials arrive as Diameter AVPs and are handed to
+// the `AuthManager` backends as RADIUS attributes.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rustls::ServerConfig;
+
+use crate::auth::AuthResult;
+use crate::config::EapTlsConfig;
+use crate::eap::tls::{self, TlsProgress, TlsTunnel};
+use crate::eap::{inner_request, EapContext, EapMethod, EapSession, EapStep, EapSuccess, EapType};
+use crate::mschap;
+use crate::protocol::{Attribute, VENDOR_MICROSOFT};
+use crate::Result;
+
+/// EAP-TTLS version carried in the flags octet
+const TTLS_VERSION: u8 = 0;
+
+/// TLS 1.2 key derivation label (RFC 5281, section 8)
+const TTLS_KEY_LABEL: &[u8] = b"ttls keying material";
+
+/// Label for the implicit MS-CHAP challenge (RFC 5281, section 11.2.4)
+const TTLS_CHALLENGE_LABEL: &[u8] = b"ttls challenge";
+
+/// AVP flags
+const AVP_FLAG_VENDOR: u8 = 0x80;
+const AVP_FLAG_MANDATORY: u8 = 0x40;
+
+/// RADIUS attribute AVP codes
+const AVP_USER_NAME: u32 = 1;
+const AVP_USER_PASSWORD: u32 = 2;
+const AVP_EAP_MESSAGE: u32 = 79;
+
+/// Microsoft vendor AVP codes (RFC 2548)
+const AVP_MS_CHAP_CHALLENGE: u32 = 11;
+const AVP_MS_CHAP2_RESPONSE: u32 = 25;
+const AVP_MS_CHAP2_SUCCESS: u32 = 26;
+
+/// EAP-TTLS method
+pub struct TtlsMethod {
+    /// rustls server configuration
+    tls_config: Arc<ServerConfig>,
+    
+    /// Maximum TLS data per EAP packet
+    fragment_size: usize,
+}
+
+impl TtlsMethod {
+    /// Create a new EAP-TTLS method
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - EAP TLS configuration (client certificates are not required)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server certificate or key cannot be loaded
+    pub fn new(config: &EapTlsConfig) -> Result<Self> {
+        Ok(Self {
+            tls_config: tls::server_config(config, false)?,
+            fragment_size: config.fragment_size,
+        })
+    }
+}
+
+impl EapMethod for TtlsMethod {
+    fn method_type(&self) -> EapType {
+        EapType::Ttls
+    }
+    
+    fn start(&self, _identity: &str) -> Result<Box<dyn EapSession>> {
+        Ok(Box::new(TtlsSession {
+            tunnel: TlsTunnel::new(self.tls_config.clone(), self.fragment_size, TTLS_VERSION)?,
+            phase: Phase::Handshake,
+        }))
+    }
+}
+
+/// Progress of an EAP-TTLS conversation
+enum Phase {
+    /// TLS handshake in progress
+    Handshake,
+    
+    /// Waiting for the inner credentials
+    Credentials,
+    
+    /// Sent MS-CHAP2-Success, waiting for the peer's acknowledgement
+    MsChapSuccess(Box<EapSuccess>),
+}
+
+/// A Diameter AVP carried in the tunnel
+struct Avp {
+    /// AVP code
+    code: u32,
+    
+    /// Vendor-ID, if the V flag is set
+    vendor: Option<u32>,
+    
+    /// AVP data
+    data: Vec<u8>,
+}
+
+/// Per-conversation EAP-TTLS state
+struct TtlsSession {
+    /// TLS tunnel
+    tunnel: TlsTunnel,
+    
+    /// Conversation progress
+    phase: Phase,
+}
+
+#[async_trait]
+impl EapSession for TtlsSession {
+    async fn initiate(&mut self, _ctx: &EapContext<'_>) -> Result<EapStep> {
+        Ok(EapStep::Continue(self.tunnel.start()))
+    }
+    
+    async fn process(&mut self, ctx: &EapContext<'_>, data: &[u8]) -> Result<EapStep> {
+        let progress = match self.tunnel.receive(data) {
+            Ok(progress) => progress,
+            Err(e) => {
+                return Ok(EapStep::Failure { reason: e.to_string() });
+            }
+        };
+        
+        if let TlsProgress::Send(data) = progress {
+            return Ok(EapStep::Continue(data));
+        }
+        
+        if self.tunnel.is_handshaking() {
+            return Ok(EapStep::Failure {
+                reason: "Unexpected acknowledgement during TLS handshake".to_string(),
+            });
+        }
+        
+        // Peers may send their AVPs together with the end of the handshake
+        let inner = self.tunnel.read_plaintext()?;
+        
+        match std::mem::replace(&mut self.phase, Phase::Credentials) {
+            Phase::Handshake if inner.is_empty() => {
+                // Ask the peer for its credentials
+                Ok(EapStep::Continue(vec![TTLS_VERSION]))
+            },
+            Phase::Handshake | Phase::Credentials if !inner.is_empty() => {
+                self.authenticate(ctx, &inner).await
+            },
+            Phase::MsChapSuccess(success) if inner.is_empty() => Ok(EapStep::Success(success)),
+            _ => Ok(EapStep::Failure {
+                reason: "Unexpected EAP-TTLS response".to_string(),
+            }),
+        }
+    }
+}
+
+impl TtlsSession {
+    /// Authenticate the AVPs sent by the peer
+    async fn authenticate(&mut self, ctx: &EapContext<'_>, inner: &[u8]) -> Result<EapStep> {
+        let avps = match parse_avps(inner) {
+            Some(avps) => avps,
+            None => return Ok(EapStep::Failure {
+                reason: "Malformed EAP-TTLS AVPs".to_string(),
+            }),
+        };
+        
+        let find = |code: u32, vendor: Option<u32>| {
+            avps.iter()
+                .find(|avp| avp.code == code && avp.vendor == vendor)
+                .map(|avp| avp.data.as_slice())
+        };
+        
+        let identity = match find(AVP_USER_NAME, None) {
+            Some(name) => String::from_utf8_lossy(name).to_string(),
+            None => return Ok(EapStep::Failure {
+                reason: "Missing tunneled User-Name".to_string(),
+            }),
+        };
+        
+        let password = find(AVP_USER_PASSWORD, None);
+        let challenge = find(AVP_MS_CHAP_CHALLENGE, Some(VENDOR_MICROSOFT));
+        let response = find(AVP_MS_CHAP2_RESPONSE, Some(VENDOR_MICROSOFT));
+        
+        let (method, credentials) = match (password, challenge, response) {
+            (Some(password), _, _) => {
+                // The password is padded with nulls to a multiple of 16 octets
+                let end = password.iter().rposition(|b| *b != 0).map_or(0, |i| i + 1);
+                let password = String::from_utf8_lossy(&password[..end]).to_string();
+                
+                ("pap", vec![Attribute::String("User-Password".to_string(), password)])
+            },
+            (None, Some(challenge), Some(response)) => {
+                // The challenge and ident are derived from the tunnel, not chosen by the peer
+                let expected = self.tunnel.export(TTLS_CHALLENGE_LABEL, None, mschap::CHALLENGE_LEN + 1)?;
+                if response.len() != 50 || challenge != &expected[..mschap::CHALLENGE_LEN]
+                    || response[0] != expected[mschap::CHALLENGE_LEN] {
+                    return Ok(EapStep::Failure {
+                        reason: "Invalid tunneled MS-CHAP challenge".to_string(),
+                    });
+                }
+                
+                ("mschapv2", mschap::v2_request_attributes(response[0], challenge, &response[2..18], &response[26..50]))
+            },
+            _ if find(AVP_EAP_MESSAGE, None).is_some() => return Ok(EapStep::Failure {
+                reason: "Tunneled EAP is not supported in EAP-TTLS".to_string(),
+            }),
+            _ => return Ok(EapStep::Failure {
+                reason: "No supported tunneled credentials".to_string(),
+            }),
+        };
+        
+        let request = inner_request(ctx.request, &identity, credentials);
+        let (mut attributes, groups) = match ctx.inner.authenticate_inner(&request).await {
+            Ok(AuthResult::Accept { attributes, groups }) => (attributes, groups),
+            Ok(other) => {
+                tracing::info!(
+                    outer_identity = ctx.identity,
+                    inner_identity = identity,
+                    method = method,
+                    result = ?other,
+                    "EAP-TTLS inner authentication rejected"
+                );
+                
+                return Ok(EapStep::Failure {
+                    reason: "Inner authentication failed".to_string(),
+                });
+            },
+            Err(e) => {
+                tracing::error!(
+                    outer_identity = ctx.identity,
+                    inner_identity = identity,
+                    error = ?e,
+                    "EAP-TTLS inner authentication error"
+                );
+                
+                return Ok(EapStep::Failure {
+                    reason: "Inner authentication error".to_string(),
+                });
+            },
+        };
+        
+        let reply = mschap::take_v2_reply(&mut attributes);
+        
+        tracing::debug!(
+            outer_identity = ctx.identity,
+            inner_identity = identity,
+            method = method,
+            "EAP-TTLS authentication complete"
+        );
+        
+        let success = Box::new(EapSuccess {
+            keys: Some(self.tunnel.export_keys(TTLS_KEY_LABEL, EapType::Ttls)?),
+            attributes,
+            inner_identity: Some(identity),
+            groups,
+            ..Default::default()
+        });
+        
+        // MS-CHAPv2 peers must see the authenticator response before the EAP-Success
+        match (method, reply) {
+            ("mschapv2", Some(reply)) => {
+                let mut value = vec![response.map_or(0, |r| r[0])];
+                value.extend_from_slice(reply.authenticator_response.as_bytes());
+                
+                self.tunnel.write_plaintext(&encode_avp(AVP_MS_CHAP2_SUCCESS, Some(VENDOR_MICROSOFT), &value))?;
+                self.phase = Phase::MsChapSuccess(success);
+                
+                Ok(match self.tunnel.pending() {
+                    TlsProgress::Send(data) => EapStep::Continue(data),
+                    TlsProgress::Idle => EapStep::Failure {
+                        reason: "Failed to send tunneled data".to_string(),
+                    },
+                })
+            },
+            ("mschapv2", None) => Ok(EapStep::Failure {
+                reason: "Backend accepted without MS-CHAP2-Success".to_string(),
+            }),
+            _ => Ok(EapStep::Success(success)),
+        }
+    }
+}
+
+/// Parse Diameter AVPs (RFC 5281, section 10.1)
+fn parse_avps(data: &[u8]) -> Option<Vec<Avp>> {
+    let mut avps = Vec::new();
+    let mut offset = 0;
+    
+    while offset < data.len() {
+        let header = data.get(offset..offset + 8)?;
+        let code = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let flags = header[4];
+        let length = u32::from_be_bytes([0, header[5], header[6], header[7]]) as usize;
+        
+        let header_len = if flags & AVP_FLAG_VENDOR != 0 { 12 } else { 8 };
+        if length < header_len {
+            return None;
+        }
+        
+        let avp = data.get(offset..offset + length)?;
+        let vendor = if flags & AVP_FLAG_VENDOR != 0 {
+            Some(u32::from_be_bytes([avp[8], avp[9], avp[10], avp[11]]))
+        } else {
+            None
+        };
+        
+        avps.push(Avp {
+            code,
+            vendor,
+            data: avp[header_len..].to_vec(),
+        });
+        
+        // AVPs are padded to a multiple of four octets
+        offset += (length + 3) & !3;
+    }
+    
+    Some(avps)
+}
+
+/// Encode a Diameter AVP with the mandatory flag set
+fn encode_avp(code: u32, vendor: Option<u32>, data: &[u8]) -> Vec<u8> {
+    let header_len = if vendor.is_some() { 12 } else { 8 };
+    let length = header_len + data.len();
+    
+    let mut flags = AVP_FLAG_MANDATORY;
+    if vendor.is_some() {
+        flags |= AVP_FLAG_VENDOR;
+    }
+    
+    let mut avp = Vec::with_capacity(length + 3);
+    avp.extend_from_slice(&code.to_be_bytes());
+    avp.push(flags);
+    avp.extend_from_slice(&(length as u32).to_be_bytes()[1..]);
+    if let Some(vendor) = vendor {
+        avp.extend_from_slice(&vendor.to_be_bytes());
+    }
+    avp.extend_from_slice(data);
+    
+    while avp.len() % 4 != 0 {
+        avp.push(0);
+    }
+    
+    avp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eap::tls::tests::{converse, Pki, TestUsers};
+    use crate::protocol::{Packet, PacketCode};
+    
+    async fn run(pki: &Pki, version: &'static rustls::SupportedProtocolVersion, mschapv2: bool) -> (EapStep, Vec<u8>, Vec<u8>) {
+        let request = Packet::new(PacketCode::AccessRequest, 1, [0; 16]);
+        let ctx = EapContext { request: &request, identity: "anonymous", inner: &TestUsers };
+        let mut session = TtlsMethod::new(&pki.config("1.2", 1024)).unwrap().start("anonymous").unwrap();
+        let mut client = pki.client(&[version]);
+        
+        let mut sent = false;
+        let mut received = Vec::new();
+        let (step, _) = converse(session.as_mut(), &ctx, &mut client, |client, data| {
+            received.extend_from_slice(data);
+            if sent {
+                return vec![];
+            }
+            sent = true;
+            
+            let mut avps = encode_avp(AVP_USER_NAME, None, b"alice");
+            if mschapv2 {
+                let material = client.export_keying_material(vec![0u8; 17], TTLS_CHALLENGE_LABEL, None).unwrap();
+                let peer_challenge = [9u8; 16];
+                let nt_response = mschap::generate_nt_response(
+                    &material[..16], &peer_challenge, "alice", &mschap::nt_password_hash("secret"),
+                );
+                
+                let mut response = vec![material[16], 0];
+                response.extend_from_slice(&peer_challenge);
+                response.extend_from_slice(&[0u8; 8]);
+                response.extend_from_slice(&nt_response);
+                
+                avps.extend(encode_avp(AVP_MS_CHAP_CHALLENGE, Some(VENDOR_MICROSOFT), &material[..16]));
+                avps.extend(encode_avp(AVP_MS_CHAP2_RESPONSE, Some(VENDOR_MICROSOFT), &response));
+            } else {
+                avps.extend(encode_avp(AVP_USER_PASSWORD, None, b"secret\0\0\0\0\0\0\0\0\0\0"));
+            }
+            avps
+        }).await;
+        
+        let label: &[u8] = if client.protocol_version() == Some(rustls::ProtocolVersion::TLSv1_3) {
+            b"EXPORTER_EAP_TLS_Key_Material"
+        } else {
+            TTLS_KEY_LABEL
+        };
+        let context = [EapType::Ttls.to_u8()];
+        let context = (label != TTLS_KEY_LABEL).then_some(&context[..]);
+        let material = client.export_keying_material(vec![0u8; 128], label, context).unwrap();
+        
+        (step, material[..64].to_vec(), received)
+    }
+    
+    #[tokio::test]
+    async fn pap_over_tls13() {
+        let pki = Pki::new("ttls-pap");
+        let (step, msk, _) = run(&pki, &rustls::version::TLS13, false).await;
+        
+        let EapStep::Success(success) = step else {
+            panic!("expected success, got {:?}", step);
+        };
+        assert_eq!(success.inner_identity.as_deref(), Some("alice"));
+        assert_eq!(success.keys.as_ref().unwrap().msk, msk);
+    }
+    
+    #[tokio::test]
+    async fn mschapv2_over_tls12() {
+        let pki = Pki::new("ttls-mschapv2");
+        let (step, msk, received) = run(&pki, &rustls::version::TLS12, true).await;
+        
+        let EapStep::Success(success) = step else {
+            panic!("expected success, got {:?}", step);
+        };
+        assert_eq!(success.keys.as_ref().unwrap().msk, msk);
+        
+        // The peer received MS-CHAP2-Success and no MPPE keys leak into the reply
+        let avps = parse_avps(&received).unwrap();
+        assert_eq!(avps.len(), 1);
+        assert_eq!((avps[0].code, avps[0].vendor), (AVP_MS_CHAP2_SUCCESS, Some(VENDOR_MICROSOFT)));
+        assert!(avps[0].data[1..].starts_with(b"S="));
+        assert_eq!(success.attributes, vec![Attribute::String("Reply-Message".to_string(), "hello".to_string())]);
+    }
+}
@@ -0,0 +1,702 @@
+// ldap.rs - LDAP directory authentication for rust-radius
+//
+// Users are authenticated with search-then-bind: a service account looks up
+// the user's entry (and group memberships), then the user's DN is bound with
+// the password from the request. Connections are pooled and kept bound as the
+// service account between requests, so a request normally costs one search
+// and one bind.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ldap3::{ldap_escape, Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use rustls::{Certificate, ClientConfig, RootCertStore};
+use serde::Deserialize;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::protocol::Attribute;
+use crate::users::{self, ReplyValue};
+use crate::Result;
+
+/// LDAP result code for a failed bind (RFC 4511)
+const INVALID_CREDENTIALS: u32 = 49;
+
+/// Options of an LDAP backend
+#[derive(Debug, Clone, Deserialize)]
+pub struct LdapSettings {
+    /// Server URL, `ldap://host[:port]` or `ldaps://host[:port]`
+    pub server: String,
+    
+    /// Upgrade `ldap://` connections with StartTLS
+    #[serde(default)]
+    pub starttls: bool,
+    
+    /// PEM file with the CA certificates to trust, instead of the system store
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    
+    /// Service account DN used for searches; searches are anonymous without it
+    #[serde(default)]
+    pub bind_dn: Option<String>,
+    
+    /// Service account password
+    #[serde(default)]
+    pub bind_password: Option<String>,
+    
+    /// Base DN of user entries
+    pub user_base_dn: String,
+    
+    /// Filter selecting a user's entry; `{username}` is replaced by the escaped User-Name
+    #[serde(default = "default_user_filter")]
+    pub user_filter: String,
+    
+    /// User attribute listing the DNs of the user's groups; `""` disables it
+    #[serde(default = "default_group_attribute")]
+    pub group_attribute: String,
+    
+    /// Base DN of group entries; groups are only searched if this is set
+    #[serde(default)]
+    pub group_base_dn: Option<String>,
+    
+    /// Filter selecting a user's groups; `{dn}` and `{username}` are replaced
+    #[serde(default = "default_group_filter")]
+    pub group_filter: String,
+    
+    /// Reply attributes per group, keyed by group DN or CN
+    #[serde(default)]
+    pub groups: BTreeMap<String, BTreeMap<String, ReplyValue>>,
+    
+    /// Maximum number of connections to the server
+    #[serde(default = "default_pool_size")]
+    pub pool_size: usize,
+    
+    /// Connect and operation timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_user_filter() -> String {
+    "(uid={username})".to_string()
+}
+
+fn default_group_attribute() -> String {
+    "memberOf".to_string()
+}
+
+fn default_group_filter() -> String {
+    "(|(member={dn})(uniqueMember={dn})(memberUid={username}))".to_string()
+}
+
+fn default_pool_size() -> usize {
+    4
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Directory entry of an authenticated user
+#[derive(Debug, Clone, PartialEq)]
+pub struct LdapUser {
+    /// DN of the user's entry
+    pub dn: String,
+    
+    /// DNs of the user's groups
+    pub groups: Vec<String>,
+}
+
+/// Outcome of an LDAP authentication
+#[derive(Debug, Clone, PartialEq)]
+pub enum LdapOutcome {
+    /// The password is valid
+    Authenticated(LdapUser),
+    
+    /// No entry matches the username
+    NotFound,
+    
+    /// The password is wrong, or the username is ambiguous
+    Rejected(String),
+}
+
+/// Pooled connection
+struct Connection {
+    /// Connection handle
+    ldap: Ldap,
+    
+    /// Whether the connection is bound as the service account
+    service: bool,
+}
+
+/// Connection borrowed from the pool; returned to it when dropped
+struct Checkout<'a> {
+    /// Idle connections of the pool
+    idle: &'a Mutex<Vec<Connection>>,
+    
+    /// The connection, until it is returned
+    connection: Option<Connection>,
+    
+    /// Pool slot held by the connection
+    _permit: SemaphorePermit<'a>,
+}
+
+impl Checkout<'_> {
+    fn connection(&mut self) -> &mut Connection {
+        self.connection.as_mut().expect("connection is only taken on drop")
+    }
+}
+
+impl Drop for Checkout<'_> {
+    fn drop(&mut self) {
+        if let Some(mut connection) = self.connection.take() {
+            if !connection.ldap.is_closed() {
+                self.idle.lock().unwrap().push(connection);
+            }
+        }
+    }
+}
+
+/// LDAP directory with a connection pool
+pub struct LdapDirectory {
+    /// Backend options
+    settings: LdapSettings,
+    
+    /// TLS configuration, if a CA file is configured
+    tls: Option<Arc<ClientConfig>>,
+    
+    /// Idle connections
+    idle: Mutex<Vec<Connection>>,
+    
+    /// Limits the number of open connections
+    permits: Semaphore,
+}
+
+impl LdapDirectory {
+    /// Create a directory client
+    ///
+    /// No connection is made until the first request.
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - Backend options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the URL or the CA file is invalid
+    pub fn new(settings: LdapSettings) -> Result<Self> {
+        let server = settings.server.to_ascii_lowercase();
+        let ldaps = server.starts_with("ldaps://");
+        if !ldaps && !server.starts_with("ldap://") {
+            return Err(format!("LDAP server must be an ldap:// or ldaps:// URL: {}", settings.server).into());
+        }
+        
+        if ldaps && settings.starttls {
+            return Err("starttls cannot be used with an ldaps:// server".into());
+        }
+        
+        if !ldaps && !settings.starttls {
+            tracing::warn!(server = settings.server, "LDAP passwords are sent unencrypted; use ldaps:// or starttls");
+        }
+        
+        if settings.pool_size == 0 {
+            return Err("LDAP pool_size must be at least 1".into());
+        }
+        
+        let tls = match &settings.ca_file {
+            Some(path) => Some(tls_config(path)?),
+            None => None,
+        };
+        
+        Ok(Self {
+            permits: Semaphore::new(settings.pool_size),
+            idle: Mutex::new(Vec::new()),
+            tls,
+            settings,
+        })
+    }
+    
+    /// Authenticate a user with search-then-bind
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - User-Name of the request
+    /// * `password` - Password to bind with
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be reached or a search fails
+    pub async fn authenticate(&self, username: &str, password: &str) -> Result<LdapOutcome> {
+        // An empty password is an unauthenticated bind, which servers accept
+        if password.is_empty() {
+            return Ok(LdapOutcome::Rejected("Empty password".to_string()));
+        }
+        
+        let timeout = Duration::from_secs(self.settings.timeout);
+        let mut checkout = self.checkout().await?;
+        let connection = checkout.connection();
+        
+        let user = match self.find(connection, username).await? {
+            Ok(user) => user,
+            Err(outcome) => return Ok(outcome),
+        };
+        
+        // Bind as the user; the connection is rebound as the service account on its next use
+        connection.service = false;
+        let result = connection.ldap.with_timeout(timeout).simple_bind(&user.dn, password).await?;
+        
+        match result.rc {
+            0 => Ok(LdapOutcome::Authenticated(user)),
+            INVALID_CREDENTIALS => Ok(LdapOutcome::Rejected("Invalid password".to_string())),
+            code => Err(format!("LDAP bind as {} failed with code {}: {}", user.dn, code, result.text).into()),
+        }
+    }
+    
+    /// Names of a user's groups (the first RDN value of each group DN)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be reached or a search fails
+    pub async fn groups(&self, username: &str) -> Result<Vec<String>> {
+        let mut checkout = self.checkout().await?;
+        
+        Ok(match self.find(checkout.connection(), username).await? {
+            Ok(user) => user.groups.iter().map(|dn| group_name(dn).to_string()).collect(),
+            Err(_) => Vec::new(),
+        })
+    }
+    
+    /// Search for a user's entry and groups as the service account
+    ///
+    /// # Returns
+    ///
+    /// The user, or the outcome if no single entry matches
+    async fn find(&self, connection: &mut Connection, username: &str) -> Result<std::result::Result<LdapUser, LdapOutcome>> {
+        let timeout = Duration::from_secs(self.settings.timeout);
+        
+        // Find the user's entry
+        let filter = self.settings.user_filter.replace("{username}", &ldap_escape(username));
+        let attributes = match self.settings.group_attribute.as_str() {
+            "" => vec!["1.1"],
+            attribute => vec![attribute],
+        };
+        let (entries, _) = connection.ldap.with_timeout(timeout)
+            .search(&self.settings.user_base_dn, Scope::Subtree, &filter, attributes).await?
+            .success()?;
+        
+        let entry = match entries.len() {
+            0 => return Ok(Err(LdapOutcome::NotFound)),
+            1 => SearchEntry::construct(entries.into_iter().next().unwrap()),
+            count => return Ok(Err(LdapOutcome::Rejected(format!("User {} matches {} entries", username, count)))),
+        };
+        
+        // Collect group memberships while still bound as the service account
+        let mut groups: Vec<String> = entry.attrs.iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case(&self.settings.group_attribute))
+            .flat_map(|(_, values)| values.iter().cloned())
+            .collect();
+        
+        if let Some(base) = &self.settings.group_base_dn {
+            let filter = self.settings.group_filter
+                .replace("{dn}", &ldap_escape(entry.dn.as_str()))
+                .replace("{username}", &ldap_escape(username));
+            let (entries, _) = connection.ldap.with_timeout(timeout)
+                .search(base, Scope::Subtree, &filter, vec!["1.1"]).await?
+                .success()?;
+            
+            for entry in entries {
+                let dn = SearchEntry::construct(entry).dn;
+                if !groups.iter().any(|group| group.eq_ignore_ascii_case(&dn)) {
+                    groups.push(dn);
+                }
+            }
+        }
+        
+        Ok(Ok(LdapUser { dn: entry.dn, groups }))
+    }
+    
+    /// Reply attributes for a user's groups
+    ///
+    /// # Arguments
+    ///
+    /// * `groups` - DNs of the user's groups
+    pub fn reply_attributes(&self, groups: &[String]) -> Vec<Attribute> {
+        let mut attributes = Vec::new();
+        
+        for (group, reply) in &self.settings.groups {
+            let member = groups.iter().any(|dn| dn.eq_ignore_ascii_case(group) || group_name(dn).eq_ignore_ascii_case(group));
+            if member {
+                attributes.extend(users::reply_attributes(reply));
+            }
+        }
+        
+        attributes
+    }
+    
+    /// Borrow a connection bound as the service account
+    async fn checkout(&self) -> Result<Checkout<'_>> {
+        let permit = self.permits.acquire().await?;
+        
+        let idle = {
+            let mut idle = self.idle.lock().unwrap();
+            let mut open = None;
+            while let Some(mut connection) = idle.pop() {
+                if !connection.ldap.is_closed() {
+                    open = Some(connection);
+                    break;
+                }
+            }
+            open
+        };
+        
+        let mut connection = match idle {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+        
+        if !connection.service {
+            // An idle connection may have been dropped by the server; retry once on a new one
+            if let Err(e) = self.bind_service(&mut connection.ldap).await {
+                tracing::debug!(error = %e, "Rebinding pooled LDAP connection failed; reconnecting");
+                connection = self.connect().await?;
+                self.bind_service(&mut connection.ldap).await?;
+            }
+            connection.service = true;
+        }
+        
+        Ok(Checkout {
+            idle: &self.idle,
+            connection: Some(connection),
+            _permit: permit,
+        })
+    }
+    
+    /// Open a new connection
+    async fn connect(&self) -> Result<Connection> {
+        let mut settings = LdapConnSettings::new()
+            .set_conn_timeout(Duration::from_secs(self.settings.timeout))
+            .set_starttls(self.settings.starttls);
+        if let Some(tls) = &self.tls {
+            settings = settings.set_config(tls.clone());
+        }
+        
+        let (driver, ldap) = LdapConnAsync::with_settings(settings, &self.settings.server).await
+            .map_err(|e| format!("Failed to connect to LDAP server {}: {}", self.settings.server, e))?;
+        
+        tokio::spawn(async move {
+            if let Err(e) = driver.drive().await {
+                tracing::debug!(error = %e, "LDAP connection closed");
+            }
+        });
+        
+        Ok(Connection { ldap, service: false })
+    }
+    
+    /// Bind as the service account, or anonymously if none is configured
+    async fn bind_service(&self, ldap: &mut Ldap) -> Result<()> {
+        let (dn, password) = match &self.settings.bind_dn {
+            Some(dn) => (dn.as_str(), self.settings.bind_password.as_deref().unwrap_or("")),
+            None => ("", ""),
+        };
+        
+        ldap.with_timeout(Duration::from_secs(self.settings.timeout))
+            .simple_bind(dn, password).await?
+            .success()
+            .map_err(|e| format!("LDAP service bind as {} failed: {}", dn, e))?;
+        
+        Ok(())
+    }
+}
+
+/// Value of the first RDN of a DN (the CN of most groups)
+fn group_name(dn: &str) -> &str {
+    dn.split(',').next()
+        .and_then(|rdn| rdn.split_once('='))
+        .map(|(_, value)| value.trim())
+        .unwrap_or(dn)
+}
+
+/// Build a TLS configuration trusting the certificates in a PEM file
+fn tls_config(path: &Path) -> Result<Arc<ClientConfig>> {
+    let content = fs::read(path)
+        .map_err(|e| format!("Failed to read CA file {}: {}", path.display(), e))?;
+    let certs = rustls_pemfile::certs(&mut content.as_slice())
+        .map_err(|e| format!("Failed to parse CA file {}: {}", path.display(), e))?;
+    
+    if certs.is_empty() {
+        return Err(format!("No certificates found in {}", path.display()).into());
+    }
+    
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots.add(&Certificate(cert))
+            .map_err(|e| format!("Invalid CA certificate in {}: {}", path.display(), e))?;
+    }
+    
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+    
+    /// Entries of the stub directory: DN, password and attributes
+    type Directory = Vec<(&'static str, Option<&'static str>, Vec<(&'static str, &'static str)>)>;
+    
+    trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+    impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+    
+    /// Encode a BER element
+    fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        match content.len() {
+            len if len < 0x80 => out.push(len as u8),
+            len if len < 0x100 => out.extend([0x81, len as u8]),
+            len => out.extend([0x82, (len >> 8) as u8, len as u8]),
+        }
+        out.extend_from_slice(content);
+        out
+    }
+    
+    /// Split constructed content into its elements
+    fn elements(mut data: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut out = Vec::new();
+        while data.len() >= 2 {
+            let (len, header) = match data[1] {
+                len if len < 0x80 => (len as usize, 2),
+                0x81 => (data[2] as usize, 3),
+                _ => ((data[2] as usize) << 8 | data[3] as usize, 4),
+            };
+            out.push((data[0], &data[header..header + len]));
+            data = &data[header + len..];
+        }
+        out
+    }
+    
+    fn text(value: &[u8]) -> String {
+        String::from_utf8_lossy(value).to_string()
+    }
+    
+    /// Evaluate the subset of search filters the backend sends
+    fn matches(filter: (u8, &[u8]), attributes: &[(&str, &str)]) -> bool {
+        let (tag, content) = filter;
+        match tag {
+            0xa0 => elements(content).into_iter().all(|f| matches(f, attributes)),
+            0xa1 => elements(content).into_iter().any(|f| matches(f, attributes)),
+            0xa3 => {
+                let parts = elements(content);
+                let (name, value) = (text(parts[0].1), text(parts[1].1));
+                attributes.iter().any(|(n, v)| n.eq_ignore_ascii_case(&name) && v.eq_ignore_ascii_case(&value))
+            },
+            0x87 => text(content).eq_ignore_ascii_case("objectClass"),
+            _ => false,
+        }
+    }
+    
+    fn result(tag: u8, code: u8) -> Vec<u8> {
+        tlv(tag, &[tlv(0x0a, &[code]), tlv(0x04, b""), tlv(0x04, b"")].concat())
+    }
+    
+    /// Serve one client connection of the stub directory
+    async fn session(mut stream: Box<dyn Stream>, directory: Arc<Directory>, tls: TlsAcceptor) {
+        loop {
+            // Read one LDAPMessage
+            let mut header = [0u8; 2];
+            if stream.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            let len = match header[1] {
+                len if len < 0x80 => len as usize,
+                len => {
+                    let mut bytes = vec![0u8; (len & 0x7f) as usize];
+                    stream.read_exact(&mut bytes).await.unwrap();
+                    bytes.iter().fold(0, |acc, b| acc << 8 | *b as usize)
+                },
+            };
+            let mut body = vec![0u8; len];
+            stream.read_exact(&mut body).await.unwrap();
+            
+            let message = elements(&body);
+            let id = tlv(0x02, message[0].1);
+            let (op, content) = message[1];
+            let reply = |op: Vec<u8>| tlv(0x30, &[id.clone(), op].concat());
+            
+            match op {
+                // BindRequest; an empty password is an unauthenticated bind and succeeds
+                0x60 => {
+                    let parts = elements(content);
+                    let (dn, password) = (text(parts[1].1), text(parts[2].1));
+                    let valid = password.is_empty() || directory.iter()
+                        .any(|(entry, secret, _)| entry.eq_ignore_ascii_case(&dn) && *secret == Some(password.as_str()));
+                    stream.write_all(&reply(result(0x61, if valid { 0 } else { 49 }))).await.unwrap();
+                },
+                // SearchRequest
+                0x63 => {
+                    let parts = elements(content);
+                    let base = text(parts[0].1).to_ascii_lowercase();
+                    for (dn, _, attributes) in directory.iter() {
+                        if !dn.to_ascii_lowercase().ends_with(&base) || !matches(parts[6], attributes) {
+                            continue;
+                        }
+                        let attributes: Vec<u8> = attributes.iter()
+                            .map(|(name, value)| tlv(0x30, &[tlv(0x04, name.as_bytes()), tlv(0x31, &tlv(0x04, value.as_bytes()))].concat()))
+                            .collect::<Vec<_>>()
+                            .concat();
+                        let entry = tlv(0x64, &[tlv(0x04, dn.as_bytes()), tlv(0x30, &attributes)].concat());
+                        stream.write_all(&reply(entry)).await.unwrap();
+                    }
+                    stream.write_all(&reply(result(0x65, 0))).await.unwrap();
+                },
+                // StartTLS
+                0x77 => {
+                    stream.write_all(&reply(result(0x78, 0))).await.unwrap();
+                    stream = match tls.accept(stream).await {
+                        Ok(stream) => Box::new(stream),
+                        Err(_) => return,
+                    };
+                },
+                // UnbindRequest
+                0x42 => return,
+                _ => {},
+            }
+        }
+    }
+    
+    /// Start the stub directory, returning its port and the CA file clients should trust
+    async fn stub_server(name: &str) -> (u16, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("radius-ldap-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        
+        let mut ca_params = CertificateParams::new(vec![]);
+        ca_params.distinguished_name.push(DnType::CommonName, "Test CA");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let server = rcgen::Certificate::from_params(CertificateParams::new(vec!["localhost".to_string()])).unwrap();
+        fs::write(dir.join("ca.pem"), ca.serialize_pem().unwrap()).unwrap();
+        
+        let tls = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(server.serialize_der_with_signer(&ca).unwrap())],
+                rustls::PrivateKey(server.serialize_private_key_der()),
+            )
+            .unwrap();
+        let tls = TlsAcceptor::from(Arc::new(tls));
+        
+        let directory: Arc<Directory> = Arc::new(vec![
+            ("cn=radius,dc=example,dc=com", Some("service"), vec![]),
+            ("uid=alice,ou=users,dc=example,dc=com", Some("secret"), vec![
+                ("uid", "alice"),
+                ("memberOf", "cn=staff,ou=groups,dc=example,dc=com"),
+            ]),
+            ("uid=bob,ou=users,dc=example,dc=com", Some("hunter2"), vec![("uid", "bob")]),
+            ("cn=admins,ou=groups,dc=example,dc=com", None, vec![("member", "uid=bob,ou=users,dc=example,dc=com")]),
+        ]);
+        
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(session(Box::new(stream), directory.clone(), tls.clone()));
+            }
+        });
+        
+        (port, dir.join("ca.pem"))
+    }
+    
+    fn settings(options: &str) -> LdapSettings {
+        let options = format!(r#"
+            bind_dn = "cn=radius,dc=example,dc=com"
+            bind_password = "service"
+            user_base_dn = "ou=users,dc=example,dc=com"
+            group_base_dn = "ou=groups,dc=example,dc=com"
+            pool_size = 1
+            {}
+            [groups.staff]
+            Filter-Id = "staff"
+            [groups."cn=admins,ou=groups,dc=example,dc=com"]
+            Tunnel-Private-Group-Id = "10"
+        "#, options);
+        toml::from_str(&options).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn search_then_bind() {
+        let (port, _) = stub_server("plain").await;
+        let directory = LdapDirectory::new(settings(&format!("server = \"ldap://127.0.0.1:{}\"", port))).unwrap();
+        
+        // Group from memberOf
+        let alice = match directory.authenticate("alice", "secret").await.unwrap() {
+            LdapOutcome::Authenticated(user) => user,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(alice.dn, "uid=alice,ou=users,dc=example,dc=com");
+        assert_eq!(directory.reply_attributes(&alice.groups), vec![Attribute::String("Filter-Id".to_string(), "staff".to_string())]);
+        
+        // Group from a group search, on the pooled connection that was bound as alice
+        let bob = match directory.authenticate("bob", "hunter2").await.unwrap() {
+            LdapOutcome::Authenticated(user) => user,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(directory.reply_attributes(&bob.groups), vec![Attribute::String("Tunnel-Private-Group-Id".to_string(), "10".to_string())]);
+        
+        for (username, password) in [("alice", "wrong"), ("alice", "")] {
+            assert!(matches!(directory.authenticate(username, password).await.unwrap(), LdapOutcome::Rejected(_)), "{}", password);
+        }
+        for username in ["carol", "*", "alice)(uid=*"] {
+            assert!(matches!(directory.authenticate(username, "secret").await.unwrap(), LdapOutcome::NotFound), "{}", username);
+        }
+    }
+    
+    #[tokio::test]
+    async fn starttls() {
+        let (port, ca_file) = stub_server("starttls").await;
+        let options = format!("server = \"ldap://localhost:{}\"\nstarttls = true\nca_file = \"{}\"", port, ca_file.display());
+        let directory = LdapDirectory::new(settings(&options)).unwrap();
+        
+        assert!(matches!(directory.authenticate("alice", "secret").await.unwrap(), LdapOutcome::Authenticated(_)));
+        
+        // A server whose certificate is not trusted is an error, not a reject
+        let options = format!("server = \"ldap://localhost:{}\"\nstarttls = true", port);
+        let directory = LdapDirectory::new(settings(&options)).unwrap();
+        assert!(directory.authenticate("alice", "secret").await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn unreachable_server() {
+        // Nothing listens on a port that was just released
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let directory = LdapDirectory::new(settings(&format!("server = \"ldap://127.0.0.1:{}\"", closed))).unwrap();
+        assert!(directory.authenticate("alice", "secret").await.is_err());
+        
+        // A server that accepts but never answers times out
+        let silent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = silent.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = silent.accept().await {
+                held.push(stream);
+            }
+        });
+        let directory = LdapDirectory::new(settings(&format!("server = \"ldap://127.0.0.1:{}\"\ntimeout = 1", port))).unwrap();
+        assert!(directory.authenticate("alice", "secret").await.is_err());
+        
+        // A service account the directory refuses is an error for every user, not a reject
+        let (port, _) = stub_server("service").await;
+        let mut refused = settings(&format!("server = \"ldap://127.0.0.1:{}\"", port));
+        refused.bind_password = Some("wrong".to_string());
+        let directory = LdapDirectory::new(refused).unwrap();
+        assert!(directory.authenticate("alice", "secret").await.is_err());
+    }
+}
@@ -5,14 +5,44 @@
 // and public API for the rust-radius crate.
 
 // === Re-exports for public API ===
+#[cfg(feature = "admin-api")]
+pub mod admin;
 pub mod auth;
+pub mod authorize;
 pub mod config;
+pub mod conversation;
+pub mod dynauth;
 pub mod captive_portal;
-// pub mod metrics; // Temporarily disabled due to compilation issues
-// pub mod plugins; // Temporarily disabled - module not implemented yet
+pub mod eap;
+#[cfg(feature = "ldap-auth")]
+pub mod ldap;
+pub mod lockout;
+pub mod mac;
+pub mod mac_registry;
+pub mod mfa;
+pub mod mschap;
+#[cfg(feature = "oauth-auth")]
+pub mod oauth;
+pub mod password;
+pub mod metrics;
+#[cfg(feature = "wasm-plugins")]
+pub mod plugins;
+pub mod policy;
 pub mod protocol;
+pub mod quotas;
 // pub mod radsec; // Temporarily disabled - module not implemented yet
-// pub mod server; // Temporarily disabled due to compilation issues
+pub mod redirect;
+pub mod reload;
+#[cfg(feature = "rest-auth")]
+pub mod rest;
+pub mod server;
+pub mod sessions;
+#[cfg(feature = "sql-auth")]
+pub mod sql;
+pub mod store;
+pub mod units;
+pub mod users;
+pub mod vouchers;
 // pub mod utils; // Temporarily disabled - module not implemented yet
 
 use std::error::Error;
@@ -21,7 +51,7 @@ use std::error::Error;
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // Simplified version for development purposes
-pub fn start_server() -> std::result::Result<(), Box<dyn Error>> {
+pub fn start_server() -> Result<()> {
     println!("Simplified RADIUS server version {}", VERSION);
     println!("This is a minimal implementation for development purposes.");
     Ok(())
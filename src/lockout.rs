@@ -0,0 +1,493 @@
+// lockout.rs - Lockout after repeated failures for rust-radius
+//
+// Every Access-Reject counts as a failure against the keys of the request:
+// its User-Name, its Calling-Station-Id and its NAS, as the lockout rules
+// choose. When a key reaches a rule's number of failures within its window,
+// it is locked and later attempts are rejected before any backend sees them,
+// until the lock runs out. This keeps a misconfigured phone from locking its
+// user out of the directory behind the server.
+//
+// The User-Name of an EAP request is only the outer identity, often
+// `anonymous`, so for PEAP and EAP-TTLS the user is locked by the identity
+// inside the tunnel, when its credentials reach the backends.
+//
+// Failures and locks are kept in memory. Locks are also written to a JSON
+// file in the background so they survive restarts and `rust-radius lockouts
+// unlock` can lift them; the server's file watcher takes in such changes.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{LockoutConfig, LockoutKey, LockoutRule};
+use crate::mac::MacAddr;
+use crate::protocol::Packet;
+use crate::sessions;
+use crate::store::JsonFile;
+use crate::Result;
+
+/// Failure histories kept before old ones are pruned
+const PRUNE_AFTER: usize = 1024;
+
+/// A lock in force
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lock {
+    /// When the key was locked
+    pub since: DateTime<Utc>,
+    
+    /// When attempts are allowed again
+    pub until: DateTime<Utc>,
+    
+    /// Failures that led to the lock
+    pub failures: u32,
+}
+
+/// Locks by key, as stored in the lockouts file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Lockouts {
+    /// Lock of each key, e.g. `username:alice` or `calling-station-id:aa:bb:cc:dd:ee:ff`
+    pub locks: BTreeMap<String, Lock>,
+}
+
+impl JsonFile for Lockouts {
+    const WHAT: &'static str = "lockouts";
+}
+
+impl Lockouts {
+    /// Forget locks that have run out
+    pub fn expire(&mut self, now: DateTime<Utc>) {
+        self.locks.retain(|_, lock| lock.until > now);
+    }
+    
+    /// Lift the lock of a key
+    ///
+    /// A bare user name is taken to mean `username:<name>`.
+    ///
+    /// # Returns
+    ///
+    /// Whether the key was locked
+    pub fn unlock(&mut self, key: &str) -> bool {
+        self.locks.remove(key).is_some()
+            || self.locks.remove(&format!("username:{}", key.to_lowercase())).is_some()
+    }
+}
+
+/// Which keys of a request a check or a count covers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keys {
+    /// Every rule's key
+    All,
+    
+    /// The device and NAS keys, for an EAP request whose User-Name is the outer identity
+    Outer,
+    
+    /// The user name key, for the credentials from inside an EAP tunnel
+    Inner,
+}
+
+impl Keys {
+    /// Check whether a rule's key is covered
+    fn covers(self, kind: LockoutKey) -> bool {
+        match self {
+            Keys::All => true,
+            Keys::Outer => kind != LockoutKey::Username,
+            Keys::Inner => kind == LockoutKey::Username,
+        }
+    }
+}
+
+/// Get the key of a request under a rule, None if the request lacks the attribute
+pub fn key(kind: LockoutKey, request: &Packet) -> Option<String> {
+    match kind {
+        LockoutKey::Username => request.get_text("User-Name")
+            .map(|username| format!("username:{}", username.to_lowercase())),
+        LockoutKey::CallingStationId => request.get_text("Calling-Station-Id")
+            .map(|station| match station.parse::<MacAddr>() {
+                Ok(mac) => format!("calling-station-id:{}", mac),
+                Err(_) => format!("calling-station-id:{}", station.trim()),
+            }),
+        LockoutKey::Nas => Some(format!("nas:{}", sessions::nas_address(request))),
+    }
+}
+
+/// Failures and locks as the server sees them
+#[derive(Debug, Default)]
+struct LockoutState {
+    /// Recent failures of each key
+    failures: HashMap<String, VecDeque<Instant>>,
+    
+    /// Locks in force
+    lockouts: Lockouts,
+    
+    /// Locks as last read from or written to the lockouts file
+    written: Lockouts,
+    
+    /// Locks taken so far
+    taken: u64,
+    
+    /// Locks taken when the file was last written
+    saved: u64,
+}
+
+/// Lockout state shared by the guards of successive configurations
+#[derive(Debug)]
+struct Shared {
+    /// Lockouts file
+    file: PathBuf,
+    
+    /// Failures and locks
+    state: Mutex<LockoutState>,
+    
+    /// Serializes reads and writes of the lockouts file
+    io: Mutex<()>,
+}
+
+impl Shared {
+    /// Write the locks if some were taken since the last write, forgetting those that have run out
+    fn write(&self) -> Result<()> {
+        let _io = self.io.lock().unwrap();
+        {
+            let state = self.state.lock().unwrap();
+            if state.taken == state.saved {
+                return Ok(());
+            }
+        }
+        
+        // Reread first so a lock lifted by hand since the last read does not come back
+        let current = Lockouts::load_or_default(&self.file)?;
+        let (lockouts, taken) = {
+            let mut state = self.state.lock().unwrap();
+            merge(&mut state, current);
+            state.lockouts.expire(Utc::now());
+            (state.lockouts.clone(), state.taken)
+        };
+        
+        lockouts.save(&self.file)?;
+        let mut state = self.state.lock().unwrap();
+        state.written = lockouts;
+        state.saved = taken;
+        
+        Ok(())
+    }
+    
+    /// Take in locks lifted or added by hand in the lockouts file
+    fn read(&self) -> Result<()> {
+        let _io = self.io.lock().unwrap();
+        let lockouts = Lockouts::load_or_default(&self.file)?;
+        merge(&mut self.state.lock().unwrap(), lockouts);
+        
+        Ok(())
+    }
+}
+
+/// Take in the locks of the lockouts file, keeping those taken since it was last written
+fn merge(state: &mut LockoutState, lockouts: Lockouts) {
+    if lockouts == state.written {
+        return;
+    }
+    
+    let taken: Vec<(String, Lock)> = state.lockouts.locks.iter()
+        .filter(|(key, lock)| state.written.locks.get(*key) != Some(*lock))
+        .map(|(key, lock)| (key.clone(), lock.clone()))
+        .collect();
+    state.lockouts = lockouts.clone();
+    state.lockouts.locks.extend(taken);
+    state.written = lockouts;
+}
+
+/// Counts failures and rejects locked-out requests
+pub struct LockoutGuard {
+    /// Lockout rules
+    rules: Vec<LockoutRule>,
+    
+    /// Failures and locks, shared with the guard this one replaces
+    shared: Arc<Shared>,
+}
+
+impl LockoutGuard {
+    /// Create a guard from the lockout configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the lockouts file exists but cannot be loaded
+    pub fn new(config: &LockoutConfig) -> Result<Self> {
+        let guard = Self {
+            rules: config.rules.clone(),
+            shared: Arc::new(Shared {
+                file: config.file.clone(),
+                state: Mutex::new(LockoutState::default()),
+                io: Mutex::new(()),
+            }),
+        };
+        if !guard.is_empty() {
+            guard.shared.read()?;
+        }
+        
+        Ok(guard)
+    }
+    
+    /// Check whether lockout is disabled
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+    
+    /// Keep the failure counts and locks of the guard this one replaces
+    ///
+    /// Locks are only kept if the lockouts file is unchanged; otherwise those of
+    /// the new file apply.
+    pub fn adopt(&mut self, previous: &LockoutGuard) {
+        if self.shared.file == previous.shared.file {
+            self.shared = previous.shared.clone();
+        } else {
+            let failures = previous.shared.state.lock().unwrap().failures.clone();
+            self.shared.state.lock().unwrap().failures = failures;
+        }
+    }
+    
+    /// Get the lockouts file, whose changes are taken in with `reload`
+    pub fn watched_file(&self) -> Option<&Path> {
+        (!self.is_empty()).then_some(self.shared.file.as_path())
+    }
+    
+    /// Take in changes made to the lockouts file, e.g. by `rust-radius lockouts unlock`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed
+    pub async fn reload(&self) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || shared.read()).await?
+    }
+    
+    /// Write locks not yet in the lockouts file now
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be written
+    pub async fn flush(&self) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || shared.write()).await?
+    }
+    
+    /// Find a lock that applies to a request
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request, or the inner request of an EAP tunnel
+    /// * `keys` - Keys of the request to check
+    ///
+    /// # Returns
+    ///
+    /// The locked key and the time the lock runs out, if any
+    pub fn locked(&self, request: &Packet, keys: Keys) -> Option<(String, DateTime<Utc>)> {
+        if self.is_empty() {
+            return None;
+        }
+        
+        let state = self.shared.state.lock().unwrap();
+        let now = Utc::now();
+        self.rules.iter()
+            .filter(|rule| keys.covers(rule.key))
+            .filter_map(|rule| key(rule.key, request))
+            .find_map(|key| match state.lockouts.locks.get(&key) {
+                Some(lock) if lock.until > now => Some((key, lock.until)),
+                _ => None,
+            })
+    }
+    
+    /// Count the outcome of a request that was not locked out
+    ///
+    /// A reject counts as a failure for every rule's key and may lock it. An
+    /// accept clears the failures of the user and the device; those of the
+    /// NAS, which many users share, are left alone. New locks are written to
+    /// the lockouts file in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request answered, or the inner request of an EAP tunnel
+    /// * `keys` - Keys of the request to count
+    /// * `accepted` - Whether it was accepted
+    pub fn record(&self, request: &Packet, keys: Keys, accepted: bool) {
+        if self.is_empty() {
+            return;
+        }
+        
+        let mut state = self.shared.state.lock().unwrap();
+        let now = Instant::now();
+        
+        if accepted {
+            for rule in self.rules.iter().filter(|rule| rule.key != LockoutKey::Nas && keys.covers(rule.key)) {
+                if let Some(key) = key(rule.key, request) {
+                    state.failures.remove(&key);
+                }
+            }
+            return;
+        }
+        
+        let mut locks = Vec::new();
+        for rule in self.rules.iter().filter(|rule| keys.covers(rule.key)) {
+            let key = match key(rule.key, request) {
+                Some(key) => key,
+                None => continue,
+            };
+            
+            let window = Duration::from_secs(rule.window_secs);
+            let failures = state.failures.entry(key.clone()).or_default();
+            failures.retain(|failure| now.duration_since(*failure) < window);
+            failures.push_back(now);
+            let count = failures.len() as u32;
+            if count < rule.max_failures {
+                continue;
+            }
+            
+            state.failures.remove(&key);
+            let since = Utc::now();
+            let until = since + chrono::Duration::seconds(rule.lockout_secs.min(i64::MAX as u64) as i64);
+            tracing::warn!(key = key, failures = count, window_secs = rule.window_secs, until = %until, "Locked out after repeated failures");
+            locks.push((key, Lock { since, until, failures: count }));
+        }
+        
+        // Keys that have not failed for longer than any window are forgotten
+        if state.failures.len() > PRUNE_AFTER {
+            let longest = Duration::from_secs(self.rules.iter().map(|rule| rule.window_secs).max().unwrap_or(0));
+            state.failures.retain(|_, failures| failures.back().is_some_and(|last| now.duration_since(*last) < longest));
+        }
+        
+        if locks.is_empty() {
+            return;
+        }
+        state.lockouts.locks.extend(locks);
+        state.taken += 1;
+        drop(state);
+        
+        let shared = self.shared.clone();
+        tokio::task::spawn_blocking(move || {
+            if let Err(e) = shared.write() {
+                tracing::error!(error = %e, "Failed to save lockouts");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Attribute;
+    
+    #[tokio::test]
+    async fn lock_and_unlock() {
+        let file = std::env::temp_dir().join(format!("rust-radius-lockouts-{}.json", std::process::id()));
+        let config: LockoutConfig = toml::from_str(&format!(r#"
+            file = "{}"
+            [[rules]]
+            key = "username"
+            max_failures = 3
+            [[rules]]
+            key = "calling-station-id"
+            max_failures = 5
+        "#, file.display())).unwrap();
+        let guard = LockoutGuard::new(&config).unwrap();
+        
+        let login = |username: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), "AA-BB-CC-DD-EE-FF".to_string()));
+            request
+        };
+        
+        // An accept starts the count again
+        guard.record(&login("alice"), Keys::All, false);
+        guard.record(&login("alice"), Keys::All, false);
+        guard.record(&login("alice"), Keys::All, true);
+        guard.record(&login("Alice"), Keys::All, false);
+        guard.record(&login("alice"), Keys::All, false);
+        assert_eq!(guard.locked(&login("alice"), Keys::All), None);
+        
+        // The third failure in a row locks the user, whatever the case of the name
+        guard.record(&login("alice"), Keys::All, false);
+        let (key, _) = guard.locked(&login("ALICE"), Keys::All).unwrap();
+        assert_eq!(key, "username:alice");
+        assert_eq!(guard.locked(&login("bob"), Keys::All), None);
+        
+        // Five failures from the device lock it for every user
+        guard.record(&login("bob"), Keys::All, false);
+        guard.record(&login("bob"), Keys::All, false);
+        assert_eq!(guard.locked(&login("carol"), Keys::All).unwrap().0, "calling-station-id:aa:bb:cc:dd:ee:ff");
+        
+        // Locks are written in the background
+        guard.flush().await.unwrap();
+        let mut lockouts = Lockouts::load(&file).unwrap();
+        assert_eq!(lockouts.locks.len(), 2);
+        
+        // Unlocking in the file, as the CLI does, takes effect once the file is reloaded
+        assert!(lockouts.unlock("Alice"));
+        assert!(lockouts.unlock("calling-station-id:aa:bb:cc:dd:ee:ff"));
+        assert!(!lockouts.unlock("bob"));
+        lockouts.save(&file).unwrap();
+        
+        // A lock taken in the meantime is kept, and the next write does not bring back the lifted ones
+        for _ in 0..3 {
+            guard.record(&login("dave"), Keys::All, false);
+        }
+        guard.reload().await.unwrap();
+        assert_eq!(guard.locked(&login("alice"), Keys::All), None);
+        assert_eq!(guard.locked(&login("dave"), Keys::All).unwrap().0, "username:dave");
+        guard.flush().await.unwrap();
+        assert_eq!(Lockouts::load(&file).unwrap().locks.keys().collect::<Vec<_>>(), ["username:dave"]);
+        
+        std::fs::remove_file(&file).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn tunneled_users_locked_by_inner_identity() {
+        let file = std::env::temp_dir().join(format!("rust-radius-lockouts-inner-{}.json", std::process::id()));
+        let config: LockoutConfig = toml::from_str(&format!(r#"
+            file = "{}"
+            [[rules]]
+            key = "username"
+            max_failures = 2
+            [[rules]]
+            key = "calling-station-id"
+            max_failures = 2
+        "#, file.display())).unwrap();
+        let guard = LockoutGuard::new(&config).unwrap();
+        
+        let request = |username: &str, station: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), station.to_string()));
+            request
+        };
+        
+        // Failures of the outer requests never lock the shared anonymous identity
+        guard.record(&request("anonymous", "AA-BB-CC-DD-EE-01"), Keys::Outer, false);
+        guard.record(&request("anonymous", "AA-BB-CC-DD-EE-02"), Keys::Outer, false);
+        assert_eq!(guard.locked(&request("anonymous", "AA-BB-CC-DD-EE-03"), Keys::All), None);
+        
+        // The inner identity is locked, and only checked by the inner request
+        guard.record(&request("alice", "AA-BB-CC-DD-EE-01"), Keys::Inner, false);
+        guard.record(&request("alice", "AA-BB-CC-DD-EE-02"), Keys::Inner, false);
+        assert_eq!(guard.locked(&request("alice", "AA-BB-CC-DD-EE-03"), Keys::Inner).unwrap().0, "username:alice");
+        assert_eq!(guard.locked(&request("alice", "AA-BB-CC-DD-EE-03"), Keys::Outer), None);
+        
+        // Inner failures do not count against the device; outer ones do
+        guard.record(&request("anonymous", "AA-BB-CC-DD-EE-01"), Keys::Outer, false);
+        assert_eq!(guard.locked(&request("anonymous", "AA-BB-CC-DD-EE-01"), Keys::Outer).unwrap().0, "calling-station-id:aa:bb:cc:dd:ee:01");
+        
+        guard.flush().await.unwrap();
+        std::fs::remove_file(&file).unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+// mac.rs - MAC addresses for rust-radius
+//
+// NAS vendors format the MAC address of a MAB request differently (Cisco
+// `aabb.ccdd.eeff`, Aruba `AABBCCDDEEFF`, UniFi `aa-bb-cc-dd-ee-ff`, ...).
+// This module parses all of them into one canonical type so lookups do not
+// depend on who sent the request.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::protocol::Packet;
+
+/// Attributes that may carry the MAC address of the device, in order of preference
+const IDENTITY_ATTRIBUTES: [&str; 2] = ["User-Name", "Calling-Station-Id"];
+
+/// IEEE 802 MAC address
+///
+/// Parsed from 12 hex digits, either bare or split into equal groups of 2, 4
+/// or 6 digits by `:`, `-` or `.`. Displayed as lowercase `aa:bb:cc:dd:ee:ff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddr([u8; 6]);
+
+impl MacAddr {
+    /// Create a MAC address from its bytes
+    pub fn new(bytes: [u8; 6]) -> Self {
+        Self(bytes)
+    }
+    
+    /// Get the bytes of the address
+    pub fn bytes(&self) -> [u8; 6] {
+        self.0
+    }
+    
+    /// Get the Organizationally Unique Identifier (the vendor prefix)
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+    
+    /// Find the MAC addresses a request identifies a device by
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - RADIUS request packet
+    ///
+    /// # Returns
+    ///
+    /// The addresses in User-Name and Calling-Station-Id that parse as MACs,
+    /// without duplicates, in that order
+    pub fn from_request(request: &Packet) -> Vec<MacAddr> {
+        let mut macs = Vec::new();
+        
+        for name in IDENTITY_ATTRIBUTES {
+            if let Some(mac) = request.get_text(name).and_then(|value| value.parse().ok()) {
+                if !macs.contains(&mac) {
+                    macs.push(mac);
+                }
+            }
+        }
+        
+        macs
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid MAC address: {}", s);
+        let value = s.trim();
+        
+        // A single kind of separator, splitting the digits into equal groups
+        let separators: Vec<char> = value.chars().filter(|c| matches!(c, ':' | '-' | '.')).collect();
+        let digits: String = match separators.first() {
+            None => value.to_string(),
+            Some(separator) => {
+                if separators.iter().any(|c| c != separator) {
+                    return Err(invalid());
+                }
+                
+                let groups: Vec<&str> = value.split(*separator).collect();
+                let width = groups[0].len();
+                if !matches!(width, 2 | 4 | 6) || groups.iter().any(|group| group.len() != width) {
+                    return Err(invalid());
+                }
+                
+                groups.concat()
+            },
+        };
+        
+        if digits.len() != 12 {
+            return Err(invalid());
+        }
+        
+        let mut bytes = [0u8; 6];
+        hex::decode_to_slice(&digits, &mut bytes).map_err(|_| invalid())?;
+        
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+impl Serialize for MacAddr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MacAddr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Attribute;
+    
+    #[test]
+    fn vendor_formats() {
+        let expected = MacAddr::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]);
+        
+        for value in ["aabb.ccdd.eeff", "AABBCCDDEEFF", "aa-bb-cc-dd-ee-ff", "AA:BB:CC:DD:EE:FF", "aabbcc-ddeeff"] {
+            assert_eq!(value.parse::<MacAddr>(), Ok(expected), "{}", value);
+        }
+        
+        for value in ["aabb.ccdd.eef", "aa:bb-cc:dd:ee:ff", "aab:bcc:dde:eff", "aabbccddeegg", "alice"] {
+            assert!(value.parse::<MacAddr>().is_err(), "{}", value);
+        }
+        
+        assert_eq!(expected.to_string(), "aa:bb:cc:dd:ee:ff");
+        assert_eq!(expected.oui(), [0xaa, 0xbb, 0xcc]);
+        
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), "AABBCCDDEEFF".to_string()));
+        request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), "AA-BB-CC-DD-EE-FF".to_string()));
+        assert_eq!(MacAddr::from_request(&request), vec![expected]);
+    }
+    
+    #[test]
+    fn malformed_addresses() {
+        let malformed = [
+            "",
+            ":::::",
+            "aa:bb:cc:dd:ee:ff:",
+            "aa:bb:cc:dd:ee:ff:00",
+            "aa:bb:cc:dd:ee",
+            "aa:bb:cc::ee:ff",
+            "aabb.ccdd.ee.ff",
+            "aa bb cc dd ee ff",
+            "aabbccddeeé",
+            "+abbccddeeff",
+        ];
+        for value in malformed {
+            assert_eq!(value.parse::<MacAddr>(), Err(format!("Invalid MAC address: {}", value)), "{:?}", value);
+        }
+        
+        // Surrounding whitespace is not part of the address
+        assert_eq!(" aa:bb:cc:dd:ee:ff\n".parse::<MacAddr>(), Ok(MacAddr::new([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff])));
+        
+        assert!(serde_json::from_str::<MacAddr>("\"aa:bb:cc\"").is_err());
+        
+        // Requests whose User-Name is not a MAC carry no address
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+        request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), "0155512345".to_string()));
+        assert!(MacAddr::from_request(&request).is_empty());
+    }
+}
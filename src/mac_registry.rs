@@ -0,0 +1,411 @@
+// mac_registry.rs - Persistent MAC registry for rust-radius
+//
+// This module keeps the devices known to the MAB backend, vendor (OUI) rules
+// and a deny-list in a JSON file, so they survive restarts and can be managed
+// from the CLI, the admin API or by hand. The MAB backend reloads the file
+// when it changes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::mac::MacAddr;
+use crate::store::JsonFile;
+use crate::users::{self, ReplyValue};
+use crate::Result;
+
+/// Top-level keys of a registry file; any other key makes it a legacy MAC list
+const REGISTRY_KEYS: [&str; 3] = ["devices", "vendors", "deny"];
+
+/// Organizationally Unique Identifier, the vendor prefix of a MAC address
+///
+/// Parsed from 6 hex digits, either bare or split into pairs by `:`, `-` or
+/// `.`, optionally followed by a `*` wildcard (`00:1b:a9:*`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Oui([u8; 3]);
+
+impl Oui {
+    /// Check whether a MAC address belongs to this vendor
+    pub fn matches(&self, mac: &MacAddr) -> bool {
+        mac.oui() == self.0
+    }
+}
+
+impl FromStr for Oui {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid OUI: {}", s);
+        let value = s.trim().trim_end_matches('*').trim_end_matches([':', '-', '.']);
+        
+        let digits: String = match value.chars().find(|c| matches!(c, ':' | '-' | '.')) {
+            None => value.to_string(),
+            Some(separator) => {
+                let groups: Vec<&str> = value.split(separator).collect();
+                if groups.iter().any(|group| group.len() != 2) {
+                    return Err(invalid());
+                }
+                groups.concat()
+            },
+        };
+        
+        if digits.len() != 6 {
+            return Err(invalid());
+        }
+        
+        let mut bytes = [0u8; 3];
+        hex::decode_to_slice(&digits, &mut bytes).map_err(|_| invalid())?;
+        
+        Ok(Self(bytes))
+    }
+}
+
+impl fmt::Display for Oui {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}:{:02x}", self.0[0], self.0[1], self.0[2])
+    }
+}
+
+/// Deny-list entry: a single device or every device of a vendor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MacPattern {
+    /// One MAC address
+    Device(MacAddr),
+    
+    /// Every MAC address with this OUI
+    Vendor(Oui),
+}
+
+impl MacPattern {
+    /// Check whether a MAC address matches the pattern
+    pub fn matches(&self, mac: &MacAddr) -> bool {
+        match self {
+            MacPattern::Device(device) => device == mac,
+            MacPattern::Vendor(oui) => oui.matches(mac),
+        }
+    }
+}
+
+impl FromStr for MacPattern {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(mac) = s.parse() {
+            return Ok(MacPattern::Device(mac));
+        }
+        
+        s.parse()
+            .map(MacPattern::Vendor)
+            .map_err(|_| format!("Invalid MAC address or OUI: {}", s))
+    }
+}
+
+impl fmt::Display for MacPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MacPattern::Device(mac) => write!(f, "{}", mac),
+            MacPattern::Vendor(oui) => write!(f, "{}:*", oui),
+        }
+    }
+}
+
+/// Serialize and deserialize a type through its string form
+macro_rules! string_serde {
+    ($type:ty) => {
+        impl Serialize for $type {
+            fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+        
+        impl<'de> Deserialize<'de> for $type {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+                let value = String::deserialize(deserializer)?;
+                value.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+string_serde!(Oui);
+string_serde!(MacPattern);
+
+/// Registered device
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceEntry {
+    /// Person or team responsible for the device
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    
+    /// Expiry date (`2025-12-31`) or RFC 3339 timestamp; the device is unknown afterwards
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    
+    /// Free-form notes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    
+    /// Attributes added to the Access-Accept
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reply: BTreeMap<String, ReplyValue>,
+}
+
+impl DeviceEntry {
+    /// Parse the expiry of the entry
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the expiry is neither a date nor an RFC 3339 timestamp
+    pub fn expiry(&self) -> Result<Option<DateTime<Utc>>> {
+        self.expires.as_deref().map(users::parse_expiry).transpose()
+    }
+}
+
+/// Rule for every device of a vendor, e.g. sending all printers to the IoT VLAN
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VendorRule {
+    /// Free-form notes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+    
+    /// Attributes added to the Access-Accept
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reply: BTreeMap<String, ReplyValue>,
+}
+
+/// Outcome of a registry lookup
+#[derive(Debug, PartialEq)]
+pub enum Lookup<'a> {
+    /// The device matches the deny-list
+    Denied(MacPattern),
+    
+    /// The device is registered and has not expired
+    Device(&'a DeviceEntry),
+    
+    /// The device is covered by a vendor rule
+    Vendor(Oui, &'a VendorRule),
+    
+    /// The registry knows nothing about the device
+    Unknown,
+}
+
+/// MAC registry file
+///
+/// ```json
+/// {
+///     "devices": {
+///         "aa:bb:cc:dd:ee:ff": { "owner": "facilities", "expires": "2025-12-31", "reply": { "Tunnel-Private-Group-Id": "20" } }
+///     },
+///     "vendors": {
+///         "00:1b:a9": { "notes": "Printers", "reply": { "Tunnel-Private-Group-Id": "30" } }
+///     },
+///     "deny": ["11:22:33:44:55:66", "de:ad:be:*"]
+/// }
+/// ```
+///
+/// A flat object of MAC addresses to reply attributes is read as a list of devices.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MacRegistry {
+    /// Registered devices
+    #[serde(default)]
+    pub devices: BTreeMap<MacAddr, DeviceEntry>,
+    
+    /// Vendor rules by OUI
+    #[serde(default)]
+    pub vendors: BTreeMap<Oui, VendorRule>,
+    
+    /// Devices and vendors that are always rejected
+    #[serde(default)]
+    pub deny: BTreeSet<MacPattern>,
+}
+
+impl MacRegistry {
+    /// Parse a registry file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content is not a valid registry or legacy MAC
+    /// list, or if it lists a device or vendor twice in different spellings
+    pub fn parse(content: &str) -> Result<Self> {
+        let value: serde_json::Value = serde_json::from_str(content)?;
+        
+        let is_registry = value.as_object()
+            .map(|object| object.keys().all(|key| REGISTRY_KEYS.contains(&key.as_str())))
+            .unwrap_or(false);
+        
+        if is_registry {
+            unique::<MacAddr>(value.get("devices"), "Device")?;
+            unique::<Oui>(value.get("vendors"), "Vendor")?;
+        } else {
+            unique::<MacAddr>(Some(&value), "Device")?;
+        }
+        
+        let registry = if is_registry {
+            serde_json::from_value(value)?
+        } else {
+            let macs: BTreeMap<MacAddr, BTreeMap<String, ReplyValue>> = serde_json::from_value(value)?;
+            Self {
+                devices: macs.into_iter()
+                    .map(|(mac, reply)| (mac, DeviceEntry { reply, ..DeviceEntry::default() }))
+                    .collect(),
+                ..Self::default()
+            }
+        };
+        
+        registry.validate()?;
+        Ok(registry)
+    }
+    
+    /// Check that every device expiry parses
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first invalid entry
+    pub fn validate(&self) -> Result<()> {
+        for (mac, device) in &self.devices {
+            device.expiry().map_err(|e| format!("Device {}: {}", mac, e))?;
+        }
+        
+        Ok(())
+    }
+    
+    /// Look up a device
+    ///
+    /// The deny-list wins over everything else; a registered device wins over
+    /// its vendor rule until it expires.
+    ///
+    /// # Arguments
+    ///
+    /// * `mac` - MAC address of the device
+    /// * `now` - Current time, for expiry
+    pub fn lookup(&self, mac: &MacAddr, now: DateTime<Utc>) -> Lookup<'_> {
+        if let Some(pattern) = self.deny.iter().find(|pattern| pattern.matches(mac)) {
+            return Lookup::Denied(*pattern);
+        }
+        
+        if let Some(device) = self.devices.get(mac) {
+            match device.expiry() {
+                Ok(Some(expires)) if now >= expires => {
+                    tracing::debug!(mac = %mac, "Registered device has expired");
+                },
+                _ => return Lookup::Device(device),
+            }
+        }
+        
+        let oui = Oui(mac.oui());
+        match self.vendors.get(&oui) {
+            Some(rule) => Lookup::Vendor(oui, rule),
+            None => Lookup::Unknown,
+        }
+    }
+}
+
+impl JsonFile for MacRegistry {
+    const WHAT: &'static str = "MAC registry";
+    
+    // Device lists are not secret, and are often kept in version control
+    const PRIVATE: bool = false;
+    
+    fn from_json(content: &str) -> Result<Self> {
+        Self::parse(content)
+    }
+    
+    fn check(&self) -> Result<()> {
+        self.validate()
+    }
+}
+
+/// Check that no two keys of a JSON object name the same device or vendor
+///
+/// Keys that do not parse are left for deserialization to report.
+///
+/// # Errors
+///
+/// Returns an error naming the first key that repeats an earlier one
+fn unique<K: FromStr + Ord>(object: Option<&serde_json::Value>, what: &str) -> Result<()> {
+    let mut seen = BTreeSet::new();
+    for key in object.and_then(|object| object.as_object()).into_iter().flat_map(|object| object.keys()) {
+        if let Ok(parsed) = key.parse::<K>() {
+            if !seen.insert(parsed) {
+                return Err(format!("{} {} is listed more than once", what, key).into());
+            }
+        }
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn lookup_order() {
+        let registry = MacRegistry::parse(r#"{
+            "devices": {
+                "00-1B-A9-00-00-01": { "owner": "facilities", "reply": { "Tunnel-Private-Group-Id": "20" } },
+                "001b.a900.0002": { "expires": "2020-01-01" },
+                "de:ad:be:00:00:01": {}
+            },
+            "vendors": { "001BA9": { "reply": { "Tunnel-Private-Group-Id": "30" } } },
+            "deny": ["de:ad:be:*", "00:1b:a9:00:00:03"]
+        }"#).unwrap();
+        let now = Utc::now();
+        let mac = |value: &str| value.parse::<MacAddr>().unwrap();
+        
+        assert!(matches!(registry.lookup(&mac("00:1b:a9:00:00:01"), now), Lookup::Device(device) if device.owner.as_deref() == Some("facilities")));
+        assert!(matches!(registry.lookup(&mac("00:1b:a9:00:00:02"), now), Lookup::Vendor(..)));
+        assert!(matches!(registry.lookup(&mac("00:1b:a9:00:00:03"), now), Lookup::Denied(_)));
+        assert!(matches!(registry.lookup(&mac("de:ad:be:00:00:01"), now), Lookup::Denied(MacPattern::Vendor(_))));
+        assert_eq!(registry.lookup(&mac("00:00:00:00:00:01"), now), Lookup::Unknown);
+        
+        // Saved files read back the same
+        let round_trip = MacRegistry::parse(&serde_json::to_string(&registry).unwrap()).unwrap();
+        assert_eq!(round_trip, registry);
+        
+        // Legacy MAC lists are read as devices
+        let legacy = MacRegistry::parse(r#"{ "aa:bb:cc:dd:ee:ff": { "Session-Timeout": 60 } }"#).unwrap();
+        assert_eq!(legacy.devices[&mac("aabbccddeeff")].reply["Session-Timeout"], ReplyValue::Integer(60));
+        
+        assert!(MacRegistry::parse(r#"{ "devices": { "aa:bb:cc:dd:ee:ff": { "expires": "soon" } } }"#).is_err());
+    }
+    
+    #[test]
+    fn duplicate_registrations() {
+        // One device written two ways would leave it to chance which entry applies
+        let error = MacRegistry::parse(r#"{
+            "devices": {
+                "aa:bb:cc:dd:ee:ff": { "owner": "facilities" },
+                "AABB.CCDD.EEFF": { "owner": "guests" }
+            }
+        }"#).unwrap_err();
+        assert!(error.to_string().starts_with("Device "), "{}", error);
+        assert!(error.to_string().ends_with(" is listed more than once"), "{}", error);
+        
+        assert!(MacRegistry::parse(r#"{ "vendors": { "00:1b:a9": {}, "001BA9*": {} } }"#).is_err());
+        assert!(MacRegistry::parse(r#"{ "aa-bb-cc-dd-ee-ff": {}, "aabbccddeeff": {} }"#).is_err());
+        
+        // A device may still appear in the deny-list, which wins
+        let registry = MacRegistry::parse(r#"{
+            "devices": { "aa:bb:cc:dd:ee:ff": {} },
+            "vendors": { "aa:bb:cc": {} },
+            "deny": ["aa:bb:cc:dd:ee:ff", "AABBCCDDEEFF"]
+        }"#).unwrap();
+        assert_eq!(registry.deny.len(), 1);
+        assert!(matches!(registry.lookup(&"aa:bb:cc:dd:ee:ff".parse().unwrap(), Utc::now()), Lookup::Denied(_)));
+        
+        // Registering a known device again replaces its entry
+        let mut registry = MacRegistry::default();
+        let mac: MacAddr = "aa:bb:cc:dd:ee:ff".parse().unwrap();
+        registry.devices.insert(mac, DeviceEntry { owner: Some("facilities".to_string()), ..DeviceEntry::default() });
+        registry.devices.insert("AABBCCDDEEFF".parse().unwrap(), DeviceEntry { owner: Some("guests".to_string()), ..DeviceEntry::default() });
+        assert_eq!(registry.devices.len(), 1);
+        assert_eq!(registry.devices[&mac].owner.as_deref(), Some("guests"));
+    }
+}
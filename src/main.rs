@@ -3,13 +3,25 @@
 //! This is the main entry point for the rust-radius server.
 //! This is a simplified version for development purposes.
 
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-use rust_radius::config::Config;
-use rust_radius::start_server;
+use rust_radius::lockout::Lockouts;
+use rust_radius::mac::MacAddr;
+use rust_radius::mac_registry::{DeviceEntry, MacPattern, MacRegistry, Oui, VendorRule};
+use rust_radius::mfa::{OtpAlgorithm, OtpKind, OtpToken, OtpTokens};
+use rust_radius::password::{Credential, Scheme};
+use rust_radius::policy::{Hook, Policy};
+use rust_radius::protocol::{Attribute, Packet};
+use rust_radius::server::Server;
+use rust_radius::store::JsonFile;
+use rust_radius::units;
+use rust_radius::users::{self, ReplyValue};
+use rust_radius::vouchers::{self, Voucher, Vouchers};
 use rust_radius::Result;
 
 /// Command line arguments
@@ -59,6 +71,361 @@ enum Commands {
         #[arg(short, long, default_value = "config/radius.toml")]
         config: PathBuf,
     },
+    
+    /// Manage the local users file
+    #[command(about = "Manage the local users file")]
+    Users {
+        /// Users subcommand to run
+        #[command(subcommand)]
+        command: UsersCommands,
+    },
+    
+    /// Manage the MAC registry of the MAB backend
+    #[command(about = "Manage the MAC registry of the MAB backend")]
+    Macs {
+        /// MAC registry file
+        #[arg(short, long, default_value = "config/macs.json")]
+        file: PathBuf,
+        
+        /// MAC registry subcommand to run
+        #[command(subcommand)]
+        command: MacsCommands,
+    },
+    
+    /// Manage one-time password tokens
+    #[command(about = "Manage one-time password (MFA) tokens")]
+    Mfa {
+        /// OTP tokens file
+        #[arg(short, long, default_value = "config/mfa.json")]
+        file: PathBuf,
+        
+        /// MFA subcommand to run
+        #[command(subcommand)]
+        command: MfaCommands,
+    },
+    
+    /// Manage guest vouchers
+    #[command(about = "Manage guest access vouchers")]
+    Vouchers {
+        /// Vouchers file
+        #[arg(short, long, default_value = "config/vouchers.json")]
+        file: PathBuf,
+        
+        /// Vouchers subcommand to run
+        #[command(subcommand)]
+        command: VouchersCommands,
+    },
+    
+    /// Check policy rules
+    #[command(about = "Check policy rules against sample packets")]
+    Policy {
+        /// Policy rules file
+        #[arg(short, long, default_value = "config/policy.rules")]
+        file: PathBuf,
+        
+        /// Policy subcommand to run
+        #[command(subcommand)]
+        command: PolicyCommands,
+    },
+    
+    /// Manage lockouts after repeated failures
+    #[command(about = "List and lift lockouts after repeated failures")]
+    Lockouts {
+        /// Lockouts file
+        #[arg(short, long, default_value = "config/lockouts.json")]
+        file: PathBuf,
+        
+        /// Lockouts subcommand to run
+        #[command(subcommand)]
+        command: LockoutsCommands,
+    },
+}
+
+/// Subcommands for the local users file
+#[derive(Subcommand)]
+enum UsersCommands {
+    /// Hash a password into a users file entry
+    #[command(about = "Hash a password into a users file entry")]
+    Hash {
+        /// Scheme: argon2id, bcrypt, sha512-crypt, nt (MS-CHAP) or cleartext (CHAP)
+        #[arg(short, long, default_value = "argon2id")]
+        scheme: Scheme,
+        
+        /// Password to hash (read from standard input if omitted)
+        password: Option<String>,
+    },
+}
+
+/// Subcommands for the MAC registry
+#[derive(Subcommand)]
+enum MacsCommands {
+    /// Print the registry
+    #[command(about = "Print the registry")]
+    List,
+    
+    /// Register or replace a device
+    #[command(about = "Register or replace a device")]
+    Add {
+        /// MAC address, in any common format
+        mac: MacAddr,
+        
+        /// Person or team responsible for the device
+        #[arg(long)]
+        owner: Option<String>,
+        
+        /// Expiry date (2025-12-31) or RFC 3339 timestamp
+        #[arg(long)]
+        expires: Option<String>,
+        
+        /// Free-form notes
+        #[arg(long)]
+        notes: Option<String>,
+        
+        /// Reply attribute as NAME=VALUE; repeat for several
+        #[arg(long, value_parser = parse_reply)]
+        reply: Vec<(String, ReplyValue)>,
+    },
+    
+    /// Forget a device
+    #[command(about = "Forget a device")]
+    Remove {
+        /// MAC address
+        mac: MacAddr,
+    },
+    
+    /// Add or replace a rule for every device of a vendor
+    #[command(about = "Add or replace a rule for every device of a vendor")]
+    Vendor {
+        /// OUI, e.g. 00:1b:a9
+        oui: Oui,
+        
+        /// Free-form notes
+        #[arg(long)]
+        notes: Option<String>,
+        
+        /// Reply attribute as NAME=VALUE; repeat for several
+        #[arg(long, value_parser = parse_reply)]
+        reply: Vec<(String, ReplyValue)>,
+    },
+    
+    /// Remove a vendor rule
+    #[command(about = "Remove a vendor rule")]
+    RemoveVendor {
+        /// OUI
+        oui: Oui,
+    },
+    
+    /// Always reject a device or vendor
+    #[command(about = "Always reject a device (MAC) or vendor (OUI)")]
+    Deny {
+        /// MAC address or OUI
+        pattern: MacPattern,
+    },
+    
+    /// Lift a denial
+    #[command(about = "Lift a denial")]
+    Allow {
+        /// MAC address or OUI
+        pattern: MacPattern,
+    },
+}
+
+/// Subcommands for OTP tokens
+#[derive(Subcommand)]
+enum MfaCommands {
+    /// List enrolled users
+    #[command(about = "List enrolled users")]
+    List,
+    
+    /// Give a user a new token, replacing any previous one
+    #[command(about = "Enroll a user, printing the secret and an otpauth:// URI for authenticator apps")]
+    Enroll {
+        /// User name, as sent in User-Name
+        username: String,
+        
+        /// Counter-based (HOTP) instead of time-based (TOTP)
+        #[arg(long)]
+        hotp: bool,
+        
+        /// Code length
+        #[arg(long, default_value_t = 6)]
+        digits: u32,
+        
+        /// HMAC hash: sha1, sha256 or sha512
+        #[arg(long, default_value = "sha1")]
+        algorithm: OtpAlgorithm,
+        
+        /// Service name shown in authenticator apps
+        #[arg(long, default_value = "rust-radius")]
+        issuer: String,
+    },
+    
+    /// Check a code, e.g. to confirm an enrollment; the code is used up
+    #[command(about = "Check a code of a user's token")]
+    Verify {
+        /// User name
+        username: String,
+        
+        /// One-time code
+        code: String,
+    },
+    
+    /// Remove a user's token
+    #[command(about = "Remove a user's token")]
+    Remove {
+        /// User name
+        username: String,
+    },
+}
+
+/// Subcommands for guest vouchers
+#[derive(Subcommand)]
+enum VouchersCommands {
+    /// Create vouchers and print their codes
+    #[command(about = "Create vouchers, printing their codes or exporting them as CSV")]
+    Generate {
+        /// Number of vouchers
+        #[arg(long, default_value_t = 1)]
+        count: usize,
+        
+        /// Access time from first use, e.g. 30m, 24h or 7d
+        #[arg(long, value_parser = units::parse_duration)]
+        duration: Option<u64>,
+        
+        /// Data budget, e.g. 500M or 2G
+        #[arg(long, value_parser = units::parse_bytes)]
+        data: Option<u64>,
+        
+        /// Devices per voucher; 0 for any number
+        #[arg(long, default_value_t = 1)]
+        devices: u32,
+        
+        /// First date (2025-12-24) or RFC 3339 timestamp the vouchers can be used
+        #[arg(long)]
+        valid_from: Option<String>,
+        
+        /// Last date (2025-12-31) or RFC 3339 timestamp the vouchers can be used
+        #[arg(long)]
+        valid_until: Option<String>,
+        
+        /// Characters per code
+        #[arg(long, default_value_t = 10)]
+        length: usize,
+        
+        /// Write the codes and their limits to this CSV file instead of printing them
+        #[arg(long)]
+        export: Option<PathBuf>,
+    },
+    
+    /// Print every voucher and its usage
+    #[command(about = "Print every voucher and its usage")]
+    List,
+    
+    /// Delete a voucher, ending its use at the next login
+    #[command(about = "Delete a voucher")]
+    Revoke {
+        /// Voucher code
+        code: String,
+    },
+}
+
+/// Subcommands for policy rules
+#[derive(Subcommand)]
+enum PolicyCommands {
+    /// Run a sample packet through the rules of a hook
+    #[command(about = "Run a sample packet through the rules of a hook and print the outcome")]
+    Test {
+        /// Hook: pre-auth, post-auth or pre-acct
+        #[arg(long, default_value = "pre-auth")]
+        hook: Hook,
+        
+        /// Address the request comes from
+        #[arg(long, default_value = "127.0.0.1")]
+        client: IpAddr,
+        
+        /// Group of the user; repeat for several
+        #[arg(long)]
+        group: Vec<String>,
+        
+        /// Reply attribute of the Access-Accept as NAME=VALUE (post-auth); repeat for several
+        #[arg(long, value_parser = parse_reply)]
+        reply: Vec<(String, ReplyValue)>,
+        
+        /// Request attributes as NAME=VALUE
+        #[arg(value_parser = parse_reply)]
+        attributes: Vec<(String, ReplyValue)>,
+    },
+}
+
+/// Subcommands for lockouts
+#[derive(Subcommand)]
+enum LockoutsCommands {
+    /// Print every lock in force
+    #[command(about = "Print every lock in force")]
+    List,
+    
+    /// Lift a lock before it runs out
+    #[command(about = "Lift a lock")]
+    Unlock {
+        /// Locked key as listed, e.g. calling-station-id:aa:bb:cc:dd:ee:ff, or a user name
+        key: String,
+    },
+}
+
+/// Parse a NAME=VALUE reply attribute; numbers become integer attributes
+fn parse_reply(value: &str) -> std::result::Result<(String, ReplyValue), String> {
+    let (name, value) = value.split_once('=')
+        .ok_or_else(|| format!("Expected NAME=VALUE, got {}", value))?;
+    
+    let value = match value.parse() {
+        Ok(number) => ReplyValue::Integer(number),
+        Err(_) => ReplyValue::Text(value.to_string()),
+    };
+    
+    Ok((name.to_string(), value))
+}
+
+/// Build a sample attribute; addresses become address attributes
+fn sample_attribute(name: String, value: ReplyValue) -> Attribute {
+    match value {
+        ReplyValue::Integer(value) => Attribute::Integer(name, value),
+        ReplyValue::Text(value) => match value.parse() {
+            Ok(address) => Attribute::IpAddr(name, address),
+            Err(_) => Attribute::String(name, value),
+        },
+        ReplyValue::List(_) => unreachable!("parse_reply returns single values"),
+    }
+}
+
+/// Print the attributes of a packet, one per line
+fn print_attributes(title: &str, packet: &Packet) {
+    println!("{}:", title);
+    for attribute in packet.attributes() {
+        match attribute {
+            Attribute::String(name, value) => println!("  {} = {:?}", name, value),
+            Attribute::Integer(name, value) => println!("  {} = {}", name, value),
+            Attribute::IpAddr(name, value) => println!("  {} = {}", name, value),
+            other => println!("  {:?}", other),
+        }
+    }
+}
+
+/// Collect reply attributes, repeating names as lists
+fn reply_map(reply: Vec<(String, ReplyValue)>) -> BTreeMap<String, ReplyValue> {
+    let mut map = BTreeMap::new();
+    
+    for (name, value) in reply {
+        match map.remove(&name) {
+            None => { map.insert(name, value); },
+            Some(ReplyValue::List(mut values)) => {
+                values.push(value);
+                map.insert(name, ReplyValue::List(values));
+            },
+            Some(previous) => { map.insert(name, ReplyValue::List(vec![previous, value])); },
+        }
+    }
+    
+    map
 }
 
 #[tokio::main]
@@ -117,16 +484,222 @@ async fn main() -> Result<()> {
             
             tracing::info!("Configuration file exists");
         },
-        Some(Commands::Start { config: _ }) | None => {
-            // Start the simplified RADIUS server
-            tracing::info!("Starting simplified RADIUS server");
+        Some(Commands::Users { command: UsersCommands::Hash { scheme, password } }) => {
+            let password = match password {
+                Some(password) => password,
+                None => {
+                    let mut line = String::new();
+                    std::io::stdin().read_line(&mut line)?;
+                    line.trim_end_matches(['\r', '\n']).to_string()
+                }
+            };
             
-            // Use our simplified server function
-            start_server()?;
+            // Print the entry so it can be pasted into the users file
+            println!("{}", Credential::hash(scheme, &password)?);
+        },
+        Some(Commands::Macs { file, command }) => {
+            let mut registry = MacRegistry::load_or_default(&file)?;
+            
+            let changed = match command {
+                MacsCommands::List => {
+                    println!("{}", serde_json::to_string_pretty(&registry)?);
+                    return Ok(());
+                },
+                MacsCommands::Add { mac, owner, expires, notes, reply } => {
+                    registry.devices.insert(mac, DeviceEntry { owner, expires, notes, reply: reply_map(reply) });
+                    true
+                },
+                MacsCommands::Remove { mac } => registry.devices.remove(&mac).is_some(),
+                MacsCommands::Vendor { oui, notes, reply } => {
+                    registry.vendors.insert(oui, VendorRule { notes, reply: reply_map(reply) });
+                    true
+                },
+                MacsCommands::RemoveVendor { oui } => registry.vendors.remove(&oui).is_some(),
+                MacsCommands::Deny { pattern } => {
+                    registry.deny.insert(pattern);
+                    true
+                },
+                MacsCommands::Allow { pattern } => registry.deny.remove(&pattern),
+            };
+            
+            if !changed {
+                return Err("No such entry in the MAC registry".into());
+            }
+            
+            // A running server reloads the file when it changes
+            registry.save(&file)?;
+            tracing::info!(path = ?file, "MAC registry updated");
+        },
+        Some(Commands::Mfa { file, command }) => {
+            let mut tokens = OtpTokens::load_or_default(&file)?;
+            
+            match command {
+                MfaCommands::List => {
+                    for (username, token) in &tokens.users {
+                        println!("{}\t{:?}\t{:?}\t{} digits", username, token.kind, token.algorithm, token.digits);
+                    }
+                    return Ok(());
+                },
+                MfaCommands::Enroll { username, hotp, digits, algorithm, issuer } => {
+                    let kind = if hotp { OtpKind::Hotp } else { OtpKind::Totp };
+                    let token = OtpToken::generate(kind, digits, algorithm);
+                    
+                    println!("Secret: {}", token.secret);
+                    println!("URI:    {}", token.provisioning_uri(&issuer, &username));
+                    tokens.users.insert(username, token);
+                },
+                MfaCommands::Verify { username, code } => {
+                    let token = tokens.users.get_mut(&username)
+                        .ok_or_else(|| format!("{} has no OTP token", username))?;
+                    let time = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+                    
+                    // Same tolerances as the server's defaults
+                    if !token.verify(&code, time, 1, 10)? {
+                        return Err("Invalid code".into());
+                    }
+                    println!("Code accepted");
+                },
+                MfaCommands::Remove { username } => {
+                    if tokens.users.remove(&username).is_none() {
+                        return Err(format!("{} has no OTP token", username).into());
+                    }
+                },
+            }
+            
+            // A running server reads the file on every login
+            tokens.save(&file)?;
+            tracing::info!(path = ?file, "OTP tokens updated");
+        },
+        Some(Commands::Vouchers { file, command }) => {
+            let mut vouchers = Vouchers::load_or_default(&file)?;
+            
+            match command {
+                VouchersCommands::Generate { count, duration, data, devices, valid_from, valid_until, length, export } => {
+                    if length < 6 {
+                        return Err("Voucher codes need at least 6 characters".into());
+                    }
+                    
+                    let template = Voucher {
+                        valid_from: valid_from.as_deref().map(vouchers::parse_start).transpose()?,
+                        valid_until: valid_until.as_deref().map(users::parse_expiry).transpose()?,
+                        duration,
+                        data_limit: data,
+                        max_devices: devices,
+                        ..Default::default()
+                    };
+                    let codes = vouchers.generate(count, length, &template);
+                    
+                    match export {
+                        Some(path) => {
+                            let mut csv = String::from("code,duration,data_limit,max_devices,valid_from,valid_until\n");
+                            let optional = |value: Option<String>| value.unwrap_or_default();
+                            for code in &codes {
+                                csv.push_str(&format!("{},{},{},{},{},{}\n",
+                                    code,
+                                    optional(duration.map(|seconds| seconds.to_string())),
+                                    optional(data.map(|bytes| bytes.to_string())),
+                                    devices,
+                                    optional(template.valid_from.map(|time| time.to_rfc3339())),
+                                    optional(template.valid_until.map(|time| time.to_rfc3339())),
+                                ));
+                            }
+                            std::fs::write(&path, csv)?;
+                            tracing::info!(path = ?path, count = codes.len(), "Vouchers exported");
+                        },
+                        None => {
+                            for code in &codes {
+                                println!("{}", code);
+                            }
+                        },
+                    }
+                },
+                VouchersCommands::List => {
+                    let now = chrono::Utc::now();
+                    for (code, voucher) in &vouchers.codes {
+                        let status = match voucher.activated {
+                            _ if voucher.is_spent(now) => "spent".to_string(),
+                            Some(activated) => format!("active since {}", activated.to_rfc3339()),
+                            None => "unused".to_string(),
+                        };
+                        let data = match voucher.data_limit {
+                            Some(limit) => format!("{}/{} bytes", voucher.data_used(), limit),
+                            None => format!("{} bytes", voucher.data_used()),
+                        };
+                        println!("{}\t{}\t{}/{} devices\t{}", code, status, voucher.devices.len(), voucher.max_devices, data);
+                    }
+                    return Ok(());
+                },
+                VouchersCommands::Revoke { code } => {
+                    if vouchers.codes.remove(&vouchers::normalize(&code)).is_none() {
+                        return Err(format!("No voucher {}", code).into());
+                    }
+                },
+            }
             
-            // Create and show a mock captive portal HTML page
-            let portal_html = include_str!("../src/captive_portal.rs");
-            println!("\nCaptive Portal would be running if server was fully implemented.");
+            // A running server reads the file on every login
+            vouchers.save(&file)?;
+            tracing::info!(path = ?file, "Vouchers updated");
+        },
+        Some(Commands::Policy { file, command: PolicyCommands::Test { hook, client, group, reply, attributes } }) => {
+            let policy = Policy::load(&file)?;
+            
+            let code = if hook == Hook::PreAcct { Packet::ACCOUNTING_REQUEST } else { Packet::ACCESS_REQUEST };
+            let mut request = Packet::new(code, 0, [0u8; 16]);
+            request.set_source(SocketAddr::new(client, 1812));
+            for (name, value) in attributes {
+                request.add_attribute(sample_attribute(name, value));
+            }
+            
+            let mut accept = request.create_response(Packet::ACCESS_ACCEPT);
+            for (name, value) in reply {
+                accept.add_attribute(sample_attribute(name, value));
+            }
+            
+            let reply = (hook == Hook::PostAuth).then_some(&mut accept);
+            let outcome = policy.run(hook, &mut request, reply, &group, &chrono::Local::now());
+            
+            println!("Verdict: {:?}", outcome.verdict);
+            if let Some(backend) = &outcome.backend {
+                println!("Backend: {}", backend);
+            }
+            if let Some(realm) = &outcome.realm {
+                println!("Realm:   {}", realm);
+            }
+            print_attributes("Request", &request);
+            if hook == Hook::PostAuth {
+                print_attributes("Reply", &accept);
+            }
+        },
+        Some(Commands::Lockouts { file, command }) => {
+            let mut lockouts = Lockouts::load_or_default(&file)?;
+            lockouts.expire(chrono::Utc::now());
+            
+            match command {
+                LockoutsCommands::List => {
+                    for (key, lock) in &lockouts.locks {
+                        println!("{}\tuntil {}\t{} failures since {}", key, lock.until.to_rfc3339(), lock.failures, lock.since.to_rfc3339());
+                    }
+                    return Ok(());
+                },
+                LockoutsCommands::Unlock { key } => {
+                    if !lockouts.unlock(&key) {
+                        return Err(format!("{} is not locked", key).into());
+                    }
+                },
+            }
+            
+            // A running server rereads the file when it changes
+            lockouts.save(&file)?;
+            tracing::info!(path = ?file, "Lockouts updated");
+        },
+        Some(Commands::Start { config }) => {
+            // SIGHUP re-reads the configuration file
+            tracing::info!(config = ?config, "Starting RADIUS server");
+            Server::from_file(&config).await?.run().await?;
+        },
+        None => {
+            tracing::info!(config = ?args.config, "Starting RADIUS server");
+            Server::from_file(&args.config).await?.run().await?;
         }
     }
     
@@ -5,7 +5,7 @@
 
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{Duration, Instant};
+use std::time::Instant;
 
 // We'll use our own simple metrics structures instead of Prometheus for now
 
@@ -87,7 +87,7 @@ impl SimpleHistogram {
 pub struct SimpleCounterVec {
     name: String,
     help: String,
-    counters: std::sync::Mutex<std::collections::HashMap<String, SimpleCounter>>,
+    counters: std::sync::Mutex<std::collections::HashMap<String, Arc<SimpleCounter>>>,
 }
 
 impl SimpleCounterVec {
@@ -99,22 +99,16 @@ impl SimpleCounterVec {
         }
     }
     
-    fn with_label_values(&self, values: &[&str]) -> &SimpleCounter {
+    fn with_label_values(&self, values: &[&str]) -> Arc<SimpleCounter> {
         let key = values.join("_");
         let mut counters = self.counters.lock().unwrap();
         
-        if !counters.contains_key(&key) {
-            let counter = SimpleCounter::new(
+        counters.entry(key)
+            .or_insert_with_key(|key| Arc::new(SimpleCounter::new(
                 &format!("{}{}{}", self.name, "_", key),
                 &self.help
-            );
-            counters.insert(key.clone(), counter);
-        }
-        
-        // This is not ideal, but for simplicity we'll return a reference to the counter
-        // In a real implementation, we would need to handle this differently
-        // to avoid the potential lifetime issues
-        unsafe { std::mem::transmute(counters.get(&key).unwrap()) }
+            )))
+            .clone()
     }
 }
 
@@ -130,8 +124,9 @@ impl SimpleRegistry {
         }
     }
     
-    fn register(&self, _metric: Box<dyn std::any::Any>) -> std::result::Result<(), String> {
-        // In a real implementation, we would store the metric
+    fn register(&mut self, name: &str, help: &str) -> std::result::Result<(), String> {
+        // In a real implementation, we would store the metric itself
+        self.metrics.push(format!("{} - {}", name, help));
         Ok(())
     }
 }
@@ -191,7 +186,7 @@ impl MetricsCollector {
         // Initialize metrics for monitoring and troubleshooting
         
         // Create registry
-        let registry = Registry::new();
+        let mut registry = Registry::new();
         
         // Create metrics
         let auth_requests = SimpleCounter::new(
@@ -226,16 +221,12 @@ impl MetricsCollector {
         );
         
         // Register metrics
-        let _ = registry.register(Box::new(auth_requests));
-        let request_counter = SimpleCounter::new(
-            "radius_requests_total", 
-            "Total number of requests"
-        );
-        let _ = registry.register(Box::new(request_counter));
-        let _ = registry.register(Box::new(acct_requests));
-        let _ = registry.register(Box::new(active_connections));
-        let _ = registry.register(Box::new(request_latency));
-        let _ = registry.register(Box::new(uptime));
+        let _ = registry.register(&auth_requests.name, &auth_requests.help);
+        let _ = registry.register(&auth_results.name, &auth_results.help);
+        let _ = registry.register(&acct_requests.name, &acct_requests.help);
+        let _ = registry.register(&active_connections.name, &active_connections.help);
+        let _ = registry.register(&request_latency.name, &request_latency.help);
+        let _ = registry.register(&uptime.name, &uptime.help);
         
         Self {
             config,
@@ -256,9 +247,12 @@ impl MetricsCollector {
     }
     
     /// Increment authentication responses counter by result
-    pub fn increment_auth_responses(&self) {
-        // In a real implementation, we would track the result (accept, reject, challenge)
-        self.auth_results.with_label_values(&["accept"]).inc();
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - Outcome label (accept, reject or challenge)
+    pub fn increment_auth_responses(&self, result: &str) {
+        self.auth_results.with_label_values(&[result]).inc();
     }
     
     /// Increment accounting requests counter
@@ -323,7 +317,7 @@ impl MetricsCollector {
         }
         
         let addr = format!("{}:{}", self.config.metrics.host, self.config.metrics.port);
-        tracing::info!(addr = addr, "Starting Prometheus metrics server");
+        tracing::info!(addr = addr, metrics = self.registry.metrics.len(), "Starting Prometheus metrics server");
         
         // In a real implementation, we would start an HTTP server here
         // For example, using axum or hyper:
@@ -0,0 +1,490 @@
+// mfa.rs - One-time password second factor for rust-radius
+//
+// When a backend accepts a user who has an OTP token, the AuthManager answers
+// with an Access-Challenge instead and holds the Accept back in a conversation.
+// The client's next Access-Request carries the State and a one-time code as
+// its password. A valid RFC 6238 (TOTP) or RFC 4226 (HOTP) code releases the
+// held-back Accept. Tokens are kept in a JSON file managed with `rust-radius mfa`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+
+use crate::config::MfaConfig;
+use crate::protocol::{Attribute, Packet};
+use crate::store::JsonFile;
+use crate::Result;
+
+/// Kind of one-time password
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpKind {
+    /// Time-based (RFC 6238), as used by authenticator apps
+    Totp,
+    
+    /// Counter-based (RFC 4226), as used by hardware tokens
+    Hotp,
+}
+
+/// HMAC hash function of a token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtpAlgorithm {
+    /// HMAC-SHA-1, the only one most apps support
+    #[default]
+    Sha1,
+    
+    /// HMAC-SHA-256
+    Sha256,
+    
+    /// HMAC-SHA-512
+    Sha512,
+}
+
+impl FromStr for OtpAlgorithm {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().replace('-', "").as_str() {
+            "sha1" => Ok(OtpAlgorithm::Sha1),
+            "sha256" => Ok(OtpAlgorithm::Sha256),
+            "sha512" => Ok(OtpAlgorithm::Sha512),
+            _ => Err(format!("Unknown OTP algorithm: {}", s)),
+        }
+    }
+}
+
+impl OtpAlgorithm {
+    /// Secret length recommended by RFC 4226 / RFC 6238 for the hash
+    fn key_len(self) -> usize {
+        match self {
+            OtpAlgorithm::Sha1 => 20,
+            OtpAlgorithm::Sha256 => 32,
+            OtpAlgorithm::Sha512 => 64,
+        }
+    }
+    
+    /// Name used in otpauth URIs
+    fn uri_name(self) -> &'static str {
+        match self {
+            OtpAlgorithm::Sha1 => "SHA1",
+            OtpAlgorithm::Sha256 => "SHA256",
+            OtpAlgorithm::Sha512 => "SHA512",
+        }
+    }
+}
+
+/// One user's OTP token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OtpToken {
+    /// TOTP or HOTP
+    #[serde(rename = "type")]
+    pub kind: OtpKind,
+    
+    /// Shared secret, base32 without padding
+    pub secret: String,
+    
+    /// Code length, 6 to 8 digits
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    
+    /// HMAC hash function
+    #[serde(default)]
+    pub algorithm: OtpAlgorithm,
+    
+    /// TOTP time step in seconds
+    #[serde(default = "default_period")]
+    pub period: u64,
+    
+    /// Lowest counter (HOTP) or time step (TOTP) still accepted; codes below
+    /// it have been used, which is what prevents replays
+    #[serde(default)]
+    pub counter: u64,
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period() -> u64 {
+    30
+}
+
+impl OtpToken {
+    /// Create a token with a random secret
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - TOTP or HOTP
+    /// * `digits` - Code length
+    /// * `algorithm` - HMAC hash function
+    pub fn generate(kind: OtpKind, digits: u32, algorithm: OtpAlgorithm) -> Self {
+        let mut key = vec![0u8; algorithm.key_len()];
+        rand::thread_rng().fill_bytes(&mut key);
+        
+        Self {
+            kind,
+            secret: BASE32_NOPAD.encode(&key),
+            digits,
+            algorithm,
+            period: default_period(),
+            counter: 0,
+        }
+    }
+    
+    /// Check the secret, code length and period
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first invalid setting
+    pub fn validate(&self) -> Result<()> {
+        self.key()?;
+        
+        if !(6..=8).contains(&self.digits) {
+            return Err(format!("OTP codes must have 6 to 8 digits, not {}", self.digits).into());
+        }
+        if self.period == 0 {
+            return Err("TOTP period must be at least one second".into());
+        }
+        
+        Ok(())
+    }
+    
+    /// Decode the secret; padding, spaces and lower case are tolerated
+    fn key(&self) -> Result<Vec<u8>> {
+        let secret: String = self.secret.chars()
+            .filter(|c| !c.is_whitespace() && *c != '=')
+            .collect::<String>()
+            .to_ascii_uppercase();
+        
+        match BASE32_NOPAD.decode(secret.as_bytes()) {
+            Ok(key) if !key.is_empty() => Ok(key),
+            _ => Err("OTP secret is not valid base32".into()),
+        }
+    }
+    
+    /// Compute the code for a counter or time step (RFC 4226, section 5.3)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret is invalid
+    pub fn code(&self, counter: u64) -> Result<String> {
+        let key = self.key()?;
+        let message = counter.to_be_bytes();
+        
+        let hash = match self.algorithm {
+            OtpAlgorithm::Sha1 => hmac::<Hmac<Sha1>>(&key, &message),
+            OtpAlgorithm::Sha256 => hmac::<Hmac<Sha256>>(&key, &message),
+            OtpAlgorithm::Sha512 => hmac::<Hmac<Sha512>>(&key, &message),
+        };
+        
+        // Dynamic truncation
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+        let code = u64::from(binary) % 10u64.pow(self.digits);
+        
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+    
+    /// Check a code and, if it is valid, burn it and every earlier one
+    ///
+    /// # Arguments
+    ///
+    /// * `code` - Code entered by the user
+    /// * `time` - Current Unix time, for TOTP
+    /// * `drift` - TOTP time steps accepted before and after the current one
+    /// * `window` - HOTP counters accepted ahead of the expected one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the secret is invalid
+    pub fn verify(&mut self, code: &str, time: u64, drift: u64, window: u64) -> Result<bool> {
+        if code.len() != self.digits as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+            return Ok(false);
+        }
+        
+        let candidates = match self.kind {
+            OtpKind::Hotp => self.counter..=self.counter.saturating_add(window),
+            OtpKind::Totp => {
+                let step = time / self.period;
+                step.saturating_sub(drift).max(self.counter)..=step.saturating_add(drift)
+            },
+        };
+        
+        for counter in candidates {
+            if bool::from(self.code(counter)?.as_bytes().ct_eq(code.as_bytes())) {
+                self.counter = counter + 1;
+                return Ok(true);
+            }
+        }
+        
+        Ok(false)
+    }
+    
+    /// Build the `otpauth://` URI that authenticator apps import (usually as a QR code)
+    ///
+    /// # Arguments
+    ///
+    /// * `issuer` - Service name shown in the app
+    /// * `account` - User name shown in the app
+    pub fn provisioning_uri(&self, issuer: &str, account: &str) -> String {
+        let kind = match self.kind {
+            OtpKind::Totp => "totp",
+            OtpKind::Hotp => "hotp",
+        };
+        
+        let mut uri = format!(
+            "otpauth://{}/{}:{}?secret={}&issuer={}&algorithm={}&digits={}",
+            kind, uri_escape(issuer), uri_escape(account), self.secret, uri_escape(issuer),
+            self.algorithm.uri_name(), self.digits,
+        );
+        match self.kind {
+            OtpKind::Totp => { let _ = write!(uri, "&period={}", self.period); },
+            OtpKind::Hotp => { let _ = write!(uri, "&counter={}", self.counter); },
+        }
+        
+        uri
+    }
+}
+
+/// HMAC of a message
+fn hmac<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <M as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encode a URI path segment or query value
+fn uri_escape(value: &str) -> String {
+    let mut escaped = String::new();
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => escaped.push(byte as char),
+            _ => { let _ = write!(escaped, "%{:02X}", byte); },
+        }
+    }
+    escaped
+}
+
+/// OTP tokens by user name, as stored in the tokens file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct OtpTokens {
+    /// Token of each enrolled user
+    pub users: BTreeMap<String, OtpToken>,
+}
+
+impl OtpTokens {
+    /// Check every token
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the first invalid token
+    pub fn validate(&self) -> Result<()> {
+        for (username, token) in &self.users {
+            token.validate().map_err(|e| format!("Invalid OTP token of {}: {}", username, e))?;
+        }
+        Ok(())
+    }
+}
+
+impl JsonFile for OtpTokens {
+    const WHAT: &'static str = "OTP tokens";
+    
+    fn from_json(content: &str) -> Result<Self> {
+        let tokens: Self = serde_json::from_str(content)?;
+        tokens.validate()?;
+        Ok(tokens)
+    }
+    
+    fn check(&self) -> Result<()> {
+        self.validate()
+    }
+}
+
+/// Second authentication stage run after a backend accepts
+pub struct MfaStage {
+    /// Tokens file
+    tokens_file: PathBuf,
+    
+    /// Serializes updates of the tokens file
+    tokens_lock: Mutex<()>,
+    
+    /// Challenge prompt
+    prompt: String,
+    
+    /// TOTP time steps accepted before and after the current one
+    totp_drift: u64,
+    
+    /// HOTP counters accepted ahead of the expected one
+    hotp_window: u64,
+}
+
+impl MfaStage {
+    /// Create the MFA stage
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - MFA configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokens file exists but cannot be loaded
+    pub fn new(config: &MfaConfig) -> Result<Self> {
+        OtpTokens::load_or_default(&config.tokens_file)?;
+        
+        Ok(Self {
+            tokens_file: config.tokens_file.clone(),
+            tokens_lock: Mutex::new(()),
+            prompt: config.prompt.clone(),
+            totp_drift: config.totp_drift,
+            hotp_window: config.hotp_window,
+        })
+    }
+    
+    /// Get the Reply-Message of the challenge
+    pub fn prompt(&self) -> &str {
+        &self.prompt
+    }
+    
+    /// Check whether a user has a token
+    ///
+    /// The file is read on every login, so enrollments take effect at once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokens file cannot be read
+    pub fn is_enrolled(&self, username: &str) -> Result<bool> {
+        Ok(OtpTokens::load_or_default(&self.tokens_file)?.users.contains_key(username))
+    }
+    
+    /// Check the one-time code a request answers a challenge with
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request carrying the code as its password
+    /// * `username` - User the challenge was issued to
+    ///
+    /// # Returns
+    ///
+    /// Ok if the code is valid (it is then used up), otherwise the reason to reject
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the tokens file cannot be read or updated
+    pub async fn verify(&self, request: &Packet, username: &str) -> Result<std::result::Result<(), String>> {
+        let code = match request.get_attribute("User-Password") {
+            Some(Attribute::String(_, code)) => code.trim().to_string(),
+            _ => return Ok(Err("One-time codes require PAP".to_string())),
+        };
+        
+        // Burning the code is written out before the Accept, so a crash cannot allow a replay
+        let _guard = self.tokens_lock.lock().await;
+        let mut tokens = OtpTokens::load_or_default(&self.tokens_file)?;
+        let token = match tokens.users.get_mut(username) {
+            Some(token) => token,
+            None => return Ok(Err("OTP token was removed".to_string())),
+        };
+        
+        let time = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        if !token.verify(&code, time, self.totp_drift, self.hotp_window)? {
+            return Ok(Err("Invalid one-time code".to_string()));
+        }
+        tokens.save(&self.tokens_file)?;
+        
+        Ok(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn token(kind: OtpKind, digits: u32, algorithm: OtpAlgorithm, key: &[u8]) -> OtpToken {
+        OtpToken {
+            kind,
+            secret: BASE32_NOPAD.encode(key),
+            digits,
+            algorithm,
+            period: 30,
+            counter: 0,
+        }
+    }
+    
+    #[test]
+    fn rfc_vectors() {
+        // RFC 4226, appendix D
+        let hotp = token(OtpKind::Hotp, 6, OtpAlgorithm::Sha1, b"12345678901234567890");
+        let codes = ["755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583", "399871", "520489"];
+        for (counter, code) in codes.iter().enumerate() {
+            assert_eq!(hotp.code(counter as u64).unwrap(), *code);
+        }
+        
+        // RFC 6238, appendix B
+        let sha1 = token(OtpKind::Totp, 8, OtpAlgorithm::Sha1, b"12345678901234567890");
+        let sha256 = token(OtpKind::Totp, 8, OtpAlgorithm::Sha256, b"12345678901234567890123456789012");
+        let sha512 = token(OtpKind::Totp, 8, OtpAlgorithm::Sha512, b"1234567890123456789012345678901234567890123456789012345678901234");
+        for (time, codes) in [(59, ["94287082", "46119246", "90693936"]), (1111111109, ["07081804", "68084774", "25091201"]), (20000000000, ["65353130", "77737706", "47863826"])] {
+            for (token, code) in [&sha1, &sha256, &sha512].into_iter().zip(codes) {
+                assert_eq!(token.code(time / 30).unwrap(), code);
+            }
+        }
+        
+        // Codes one step either side are accepted, once
+        let mut totp = sha1.clone();
+        assert!(!totp.verify("94287082", 59 + 90, 1, 0).unwrap());
+        assert!(totp.verify("94287082", 59 + 30, 1, 0).unwrap());
+        assert!(!totp.verify("94287082", 59, 1, 0).unwrap());
+        
+        // HOTP tolerates codes generated but never used, but not going back
+        let mut hotp = hotp;
+        assert!(hotp.verify("338314", 0, 0, 5).unwrap());
+        assert_eq!(hotp.counter, 5);
+        assert!(!hotp.verify("969429", 0, 0, 5).unwrap());
+        assert!(!hotp.verify("520489", 0, 0, 3).unwrap());
+        
+        assert_eq!(
+            sha1.provisioning_uri("Example WiFi", "alice@example.com"),
+            "otpauth://totp/Example%20WiFi:alice%40example.com?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Example%20WiFi&algorithm=SHA1&digits=8&period=30",
+        );
+    }
+    
+    #[tokio::test]
+    async fn codes_are_used_up() {
+        let tokens_file = std::env::temp_dir().join(format!("rust-radius-mfa-{}.json", std::process::id()));
+        let mut tokens = OtpTokens::default();
+        tokens.users.insert("alice".to_string(), token(OtpKind::Hotp, 6, OtpAlgorithm::Sha1, b"12345678901234567890"));
+        tokens.save(&tokens_file).unwrap();
+        
+        let config: MfaConfig = toml::from_str(&format!("enabled = true\ntokens_file = {:?}", tokens_file)).unwrap();
+        let stage = MfaStage::new(&config).unwrap();
+        assert!(stage.is_enrolled("alice").unwrap());
+        assert!(!stage.is_enrolled("bob").unwrap());
+        
+        let answer = |code: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Password".to_string(), code.to_string()));
+            request
+        };
+        
+        // The counter is written back, so the code cannot be used again
+        assert_eq!(stage.verify(&answer("755224"), "alice").await.unwrap(), Ok(()));
+        assert_eq!(OtpTokens::load(&tokens_file).unwrap().users["alice"].counter, 1);
+        assert!(stage.verify(&answer("755224"), "alice").await.unwrap().is_err());
+        assert!(stage.verify(&answer("287082"), "bob").await.unwrap().is_err());
+        
+        std::fs::remove_file(&tokens_file).unwrap();
+    }
+}
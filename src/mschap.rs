@@ -0,0 +1,489 @@
+// mschap.rs - MS-CHAP cryptography for rust-radius
+//
+// This module implements the MS-CHAPv2 primitives from RFC 2759, MS-CHAPv1
+// from RFC 2433 and the MPPE key derivation from RFC 3079. They are shared by
+// RADIUS MS-CHAP verification in the backends and by the tunneled EAP methods
+// (PEAP, EAP-TTLS).
+
+use des::cipher::generic_array::GenericArray;
+use des::cipher::{BlockEncrypt, KeyInit};
+use md4::{Digest, Md4};
+use sha1::Sha1;
+use subtle::ConstantTimeEq;
+
+use crate::protocol::{Attribute, Packet, VENDOR_MICROSOFT};
+
+/// Length of the authenticator and peer challenges
+pub const CHALLENGE_LEN: usize = 16;
+
+/// Length of the NT-Response
+pub const NT_RESPONSE_LEN: usize = 24;
+
+/// Length of the MPPE send and receive keys
+pub const MPPE_KEY_LEN: usize = 16;
+
+/// "Magic server to client signing constant"
+const MAGIC_SERVER_SIGNING: &[u8] = b"Magic server to client signing constant";
+
+/// "Pad to make it do more than one iteration"
+const MAGIC_PAD: &[u8] = b"Pad to make it do more than one iteration";
+
+/// RFC 3079 master key constant
+const MAGIC_MASTER_KEY: &[u8] = b"This is the MPPE Master Key";
+
+/// RFC 3079 client send / server receive key constant
+const MAGIC_CLIENT_SEND: &[u8] = b"On the client side, this is the send key; on the server side, it is the receive key.";
+
+/// RFC 3079 client receive / server send key constant
+const MAGIC_CLIENT_RECEIVE: &[u8] = b"On the client side, this is the receive key; on the server side, it is the send key.";
+
+/// NT password hash (MD4 of the UTF-16LE password)
+pub fn nt_password_hash(password: &str) -> [u8; 16] {
+    let unicode: Vec<u8> = password.encode_utf16()
+        .flat_map(|c| c.to_le_bytes())
+        .collect();
+    
+    Md4::digest(&unicode).into()
+}
+
+/// Hash of the NT password hash, used for the authenticator response and MPPE keys
+pub fn nt_password_hash_hash(password_hash: &[u8; 16]) -> [u8; 16] {
+    Md4::digest(password_hash).into()
+}
+
+/// Strip any Windows domain ("DOMAIN\user") from a user name
+///
+/// MS-CHAPv2 challenge hashes are computed over the bare user name.
+pub fn strip_domain(username: &str) -> &str {
+    username.rsplit('\\').next().unwrap_or(username)
+}
+
+/// ChallengeHash from RFC 2759, section 8.2
+pub fn challenge_hash(peer_challenge: &[u8], authenticator_challenge: &[u8], username: &str) -> [u8; 8] {
+    let mut hasher = Sha1::new();
+    hasher.update(peer_challenge);
+    hasher.update(authenticator_challenge);
+    hasher.update(strip_domain(username).as_bytes());
+    
+    let digest = hasher.finalize();
+    let mut challenge = [0u8; 8];
+    challenge.copy_from_slice(&digest[..8]);
+    challenge
+}
+
+/// ChallengeResponse from RFC 2759, section 8.5 (also used by MS-CHAPv1)
+pub fn challenge_response(challenge: &[u8; 8], password_hash: &[u8; 16]) -> [u8; NT_RESPONSE_LEN] {
+    let mut padded = [0u8; 21];
+    padded[..16].copy_from_slice(password_hash);
+    
+    let mut response = [0u8; NT_RESPONSE_LEN];
+    for (i, chunk) in padded.chunks(7).enumerate() {
+        let cipher = des::Des::new(GenericArray::from_slice(&des_key(chunk)));
+        let mut block = GenericArray::clone_from_slice(challenge);
+        cipher.encrypt_block(&mut block);
+        response[i * 8..(i + 1) * 8].copy_from_slice(&block);
+    }
+    
+    response
+}
+
+/// Expand 7 key bytes into a DES key (parity bits are ignored)
+fn des_key(key: &[u8]) -> [u8; 8] {
+    [
+        key[0] & 0xfe,
+        (key[0] << 7) | (key[1] >> 1),
+        (key[1] << 6) | (key[2] >> 2),
+        (key[2] << 5) | (key[3] >> 3),
+        (key[3] << 4) | (key[4] >> 4),
+        (key[4] << 3) | (key[5] >> 5),
+        (key[5] << 2) | (key[6] >> 6),
+        key[6] << 1,
+    ]
+}
+
+/// GenerateNTResponse from RFC 2759, section 8.1
+pub fn generate_nt_response(
+    authenticator_challenge: &[u8],
+    peer_challenge: &[u8],
+    username: &str,
+    password_hash: &[u8; 16],
+) -> [u8; NT_RESPONSE_LEN] {
+    let challenge = challenge_hash(peer_challenge, authenticator_challenge, username);
+    challenge_response(&challenge, password_hash)
+}
+
+/// GenerateAuthenticatorResponse from RFC 2759, section 8.7
+///
+/// # Returns
+///
+/// The "S=<40 hex digits>" string sent to the peer
+pub fn authenticator_response(
+    password_hash: &[u8; 16],
+    nt_response: &[u8],
+    peer_challenge: &[u8],
+    authenticator_challenge: &[u8],
+    username: &str,
+) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(nt_password_hash_hash(password_hash));
+    hasher.update(nt_response);
+    hasher.update(MAGIC_SERVER_SIGNING);
+    let digest = hasher.finalize();
+    
+    let mut hasher = Sha1::new();
+    hasher.update(digest);
+    hasher.update(challenge_hash(peer_challenge, authenticator_challenge, username));
+    hasher.update(MAGIC_PAD);
+    let digest = hasher.finalize();
+    
+    let hex: String = digest.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("S={}", hex)
+}
+
+/// GetMasterKey from RFC 3079, section 3.4
+pub fn master_key(password_hash: &[u8; 16], nt_response: &[u8]) -> [u8; 16] {
+    let mut hasher = Sha1::new();
+    hasher.update(nt_password_hash_hash(password_hash));
+    hasher.update(nt_response);
+    hasher.update(MAGIC_MASTER_KEY);
+    
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&hasher.finalize()[..16]);
+    key
+}
+
+/// GetAsymmetricStartKey from RFC 3079, section 3.4, for the server side
+///
+/// # Arguments
+///
+/// * `master_key` - Key from `master_key`
+/// * `send` - Whether to derive the server's send key (otherwise its receive key)
+pub fn server_start_key(master_key: &[u8; 16], send: bool) -> [u8; MPPE_KEY_LEN] {
+    let magic = if send { MAGIC_CLIENT_RECEIVE } else { MAGIC_CLIENT_SEND };
+    
+    let mut hasher = Sha1::new();
+    hasher.update(master_key);
+    hasher.update([0u8; 40]);
+    hasher.update(magic);
+    hasher.update([0xf2u8; 40]);
+    
+    let mut key = [0u8; MPPE_KEY_LEN];
+    key.copy_from_slice(&hasher.finalize()[..MPPE_KEY_LEN]);
+    key
+}
+
+/// Result of a successful MS-CHAPv2 verification
+#[derive(Debug, Clone)]
+pub struct MsChapV2Success {
+    /// Authenticator response ("S=...") proving the server knows the password
+    pub authenticator_response: String,
+    
+    /// Server MPPE send key
+    pub send_key: [u8; MPPE_KEY_LEN],
+    
+    /// Server MPPE receive key
+    pub recv_key: [u8; MPPE_KEY_LEN],
+}
+
+/// Verify an MS-CHAPv2 response
+///
+/// # Arguments
+///
+/// * `password_hash` - NT hash of the user's password
+/// * `username` - User name the peer used in the challenge hash
+/// * `authenticator_challenge` - Challenge sent by the server
+/// * `peer_challenge` - Challenge chosen by the peer
+/// * `nt_response` - NT-Response sent by the peer
+///
+/// # Returns
+///
+/// The authenticator response and MPPE keys, or None if the response is wrong
+pub fn verify_v2(
+    password_hash: &[u8; 16],
+    username: &str,
+    authenticator_challenge: &[u8],
+    peer_challenge: &[u8],
+    nt_response: &[u8],
+) -> Option<MsChapV2Success> {
+    let expected = generate_nt_response(authenticator_challenge, peer_challenge, username, password_hash);
+    
+    if !constant_time_eq(&expected, nt_response) {
+        return None;
+    }
+
+    let master = master_key(password_hash, nt_response);
+    
+    Some(MsChapV2Success {
+        authenticator_response: authenticator_response(
+            password_hash, nt_response, peer_challenge, authenticator_challenge, username,
+        ),
+        send_key: server_start_key(&master, true),
+        recv_key: server_start_key(&master, false),
+    })
+}
+
+/// Compare two byte strings in constant time (the lengths are not secret)
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
+}
+
+/// Length of the MS-CHAP-Challenge attribute value for MS-CHAPv1
+pub const V1_CHALLENGE_LEN: usize = 8;
+
+/// Length of the MS-CHAP-Response and MS-CHAP2-Response attribute values (RFC 2548, section 2.3.2)
+const MS_CHAP_RESPONSE_LEN: usize = 50;
+
+/// Length of the MS-CHAP-MPPE-Keys attribute value before encryption
+const MS_CHAP_MPPE_KEYS_LEN: usize = 32;
+
+/// Outcome of verifying the MS-CHAP attributes of a request
+#[derive(Debug, Clone, PartialEq)]
+pub enum MsChapResult {
+    /// The request carries no MS-CHAP attributes
+    NotPresent,
+    
+    /// The response is malformed or does not match the password
+    Rejected,
+    
+    /// The response is valid; reply attributes for the Access-Accept
+    Accepted(Vec<Attribute>),
+}
+
+/// MS-CHAPv2 result taken from a backend reply
+#[derive(Debug, Clone)]
+pub struct MsChapV2Reply {
+    /// Authenticator response ("S=...")
+    pub authenticator_response: String,
+    
+    /// Server MPPE send key (plaintext)
+    pub send_key: Vec<u8>,
+    
+    /// Server MPPE receive key (plaintext)
+    pub recv_key: Vec<u8>,
+}
+
+/// Whether an Access-Request carries an MS-CHAP exchange (v1 or v2)
+pub fn is_present(request: &Packet) -> bool {
+    request.get_vendor_attribute(VENDOR_MICROSOFT, "MS-CHAP-Challenge").is_some()
+}
+
+/// Verify the MS-CHAPv2 attributes of an Access-Request
+///
+/// # Arguments
+///
+/// * `request` - Access-Request carrying MS-CHAP-Challenge and MS-CHAP2-Response
+/// * `username` - User name from the request
+/// * `password_hash` - NT hash of the user's password
+///
+/// # Returns
+///
+/// On success the reply attributes: MS-CHAP2-Success and the MPPE keys in
+/// plaintext (the `AuthManager` encrypts them when the Access-Accept is built)
+pub fn verify_v2_request(request: &Packet, username: &str, password_hash: &[u8; 16]) -> MsChapResult {
+    let challenge = match request.get_vendor_attribute(VENDOR_MICROSOFT, "MS-CHAP-Challenge") {
+        Some(Attribute::Binary(_, value)) => value,
+        _ => return MsChapResult::NotPresent,
+    };
+    
+    let response = match request.get_vendor_attribute(VENDOR_MICROSOFT, "MS-CHAP2-Response") {
+        Some(Attribute::Binary(_, value)) => value,
+        _ => return MsChapResult::NotPresent,
+    };
+    
+    if challenge.len() != CHALLENGE_LEN || response.len() != MS_CHAP_RESPONSE_LEN {
+        return MsChapResult::Rejected;
+    }
+
+    // Ident(1) Flags(1) Peer-Challenge(16) Reserved(8) Response(24)
+    let ident = response[0];
+    let peer_challenge = &response[2..18];
+    let nt_response = &response[26..50];
+    
+    let success = match verify_v2(password_hash, username, challenge, peer_challenge, nt_response) {
+        Some(success) => success,
+        None => return MsChapResult::Rejected,
+    };
+    
+    let mut value = vec![ident];
+    value.extend_from_slice(success.authenticator_response.as_bytes());
+    
+    MsChapResult::Accepted(vec![Attribute::VendorSpecific(VENDOR_MICROSOFT, vec![
+        Attribute::Binary("MS-CHAP2-Success".to_string(), value),
+        Attribute::Binary("MS-MPPE-Send-Key".to_string(), success.send_key.to_vec()),
+        Attribute::Binary("MS-MPPE-Recv-Key".to_string(), success.recv_key.to_vec()),
+    ])])
+}
+
+/// Verify the MS-CHAPv1 attributes of an Access-Request (RFC 2433)
+///
+/// Only the NT-Response is accepted; peers that send just the LAN Manager
+/// response are rejected.
+///
+/// # Arguments
+///
+/// * `request` - Access-Request carrying MS-CHAP-Challenge and MS-CHAP-Response
+/// * `password_hash` - NT hash of the user's password
+///
+/// # Returns
+///
+/// On success the reply attributes: MS-CHAP-MPPE-Keys in plaintext (the
+/// `AuthManager` encrypts it when the Access-Accept is built)
+pub fn verify_v1_request(request: &Packet, password_hash: &[u8; 16]) -> MsChapResult {
+    let challenge = match request.get_vendor_attribute(VENDOR_MICROSOFT, "MS-CHAP-Challenge") {
+        Some(Attribute::Binary(_, value)) => value,
+        _ => return MsChapResult::NotPresent,
+    };
+    
+    let response = match request.get_vendor_attribute(VENDOR_MICROSOFT, "MS-CHAP-Response") {
+        Some(Attribute::Binary(_, value)) => value,
+        _ => return MsChapResult::NotPresent,
+    };
+    
+    if challenge.len() != V1_CHALLENGE_LEN || response.len() != MS_CHAP_RESPONSE_LEN {
+        return MsChapResult::Rejected;
+    }
+    
+    // Ident(1) Flags(1) LM-Response(24) NT-Response(24); flag 1 means "use the NT-Response"
+    if response[1] != 1 {
+        return MsChapResult::Rejected;
+    }
+    
+    let mut challenge_block = [0u8; V1_CHALLENGE_LEN];
+    challenge_block.copy_from_slice(challenge);
+    
+    let expected = challenge_response(&challenge_block, password_hash);
+    if !constant_time_eq(&expected, &response[26..50]) {
+        return MsChapResult::Rejected;
+    }
+    
+    // LM-Key(8) NT-Key(16) padding(8); no LAN Manager hash is kept, so the LM key is zero
+    let mut keys = vec![0u8; MS_CHAP_MPPE_KEYS_LEN];
+    keys[8..24].copy_from_slice(&nt_password_hash_hash(password_hash));
+    
+    MsChapResult::Accepted(vec![Attribute::VendorSpecific(VENDOR_MICROSOFT, vec![
+        Attribute::Binary("MS-CHAP-MPPE-Keys".to_string(), keys),
+    ])])
+}
+
+/// Build the MS-CHAP-Challenge and MS-CHAP2-Response attributes for a response
+///
+/// Used by tunneled methods to hand an MS-CHAPv2 exchange to the backends.
+pub fn v2_request_attributes(ident: u8, challenge: &[u8], peer_challenge: &[u8], nt_response: &[u8]) -> Vec<Attribute> {
+    let mut response = Vec::with_capacity(MS_CHAP_RESPONSE_LEN);
+    response.push(ident);
+    response.push(0);
+    response.extend_from_slice(peer_challenge);
+    response.extend_from_slice(&[0u8; 8]);
+    response.extend_from_slice(nt_response);
+    
+    vec![Attribute::VendorSpecific(VENDOR_MICROSOFT, vec![
+        Attribute::Binary("MS-CHAP-Challenge".to_string(), challenge.to_vec()),
+        Attribute::Binary("MS-CHAP2-Response".to_string(), response),
+    ])]
+}
+
+/// Remove the MS-CHAPv2 result from a backend's reply attributes
+///
+/// # Returns
+///
+/// The authenticator response and keys, or None if the reply has no MS-CHAP2-Success
+pub fn take_v2_reply(attributes: &mut Vec<Attribute>) -> Option<MsChapV2Reply> {
+    let mut success = None;
+    let mut send_key = Vec::new();
+    let mut recv_key = Vec::new();
+    
+    for attr in attributes.iter_mut() {
+        if let Attribute::VendorSpecific(VENDOR_MICROSOFT, attrs) = attr {
+            attrs.retain(|sub| match sub {
+                Attribute::Binary(name, value) if name == "MS-CHAP2-Success" => {
+                    // Skip the ident octet
+                    success = Some(String::from_utf8_lossy(value.get(1..).unwrap_or_default()).to_string());
+                    false
+                },
+                Attribute::Binary(name, value) if name == "MS-MPPE-Send-Key" => {
+                    send_key = value.clone();
+                    false
+                },
+                Attribute::Binary(name, value) if name == "MS-MPPE-Recv-Key" => {
+                    recv_key = value.clone();
+                    false
+                },
+                _ => true,
+            });
+        }
+    }
+    
+    attributes.retain(|attr| !matches!(attr, Attribute::VendorSpecific(_, attrs) if attrs.is_empty()));
+    
+    Some(MsChapV2Reply {
+        authenticator_response: success?,
+        send_key,
+        recv_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len()).step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+    
+    #[test]
+    fn rfc2759_test_vectors() {
+        let authenticator_challenge = hex("5B5D7C7D7B3F2F3E3C2C602132262628");
+        let peer_challenge = hex("21402324255E262A28295F2B3A337C7E");
+        let password_hash = nt_password_hash("clientPass");
+        
+        assert_eq!(password_hash.to_vec(), hex("44EBBA8D5312B8D611474411F56989AE"));
+        assert_eq!(nt_password_hash_hash(&password_hash).to_vec(), hex("41C00C584BD2D91C4017A2A12FA59F3F"));
+        assert_eq!(challenge_hash(&peer_challenge, &authenticator_challenge, "User").to_vec(), hex("D02E4386BCE91226"));
+        
+        let nt_response = generate_nt_response(&authenticator_challenge, &peer_challenge, "User", &password_hash);
+        assert_eq!(nt_response.to_vec(), hex("82309ECD8D708B5EA08FAA3981CD83544233114A3D85D6DF"));
+        
+        let success = verify_v2(&password_hash, "User", &authenticator_challenge, &peer_challenge, &nt_response).unwrap();
+        assert_eq!(success.authenticator_response, "S=407A5589115FD0D6209F510FE9C04566932CDA56");
+        
+        // RFC 3079, section 3.5.3
+        assert_eq!(master_key(&password_hash, &nt_response).to_vec(), hex("FDECE3717A8C838CB388E527AE3CDD31"));
+        assert_eq!(success.send_key.to_vec(), hex("8B7CDC149B993A1BA118CB153F56DCCB"));
+        
+        assert!(verify_v2(&nt_password_hash("wrong"), "User", &authenticator_challenge, &peer_challenge, &nt_response).is_none());
+    }
+    
+    #[test]
+    fn v1_request() {
+        let mut challenge = [0u8; 8];
+        challenge.copy_from_slice(&hex("102DB5DF085D3041"));
+        
+        let response = challenge_response(&challenge, &nt_password_hash("clientPass"));
+
+        let mut value = vec![1, 1];
+        value.extend_from_slice(&[0u8; 24]);
+        value.extend_from_slice(&response);
+        
+        let mut request = Packet::new(crate::protocol::PacketCode::AccessRequest, 1, [0u8; 16]);
+        request.add_attribute(Attribute::VendorSpecific(VENDOR_MICROSOFT, vec![
+            Attribute::Binary("MS-CHAP-Challenge".to_string(), challenge.to_vec()),
+            Attribute::Binary("MS-CHAP-Response".to_string(), value),
+        ]));
+        
+        let keys = match verify_v1_request(&request, &nt_password_hash("clientPass")) {
+            MsChapResult::Accepted(mut reply) => match reply.pop() {
+                Some(Attribute::VendorSpecific(_, mut attrs)) => attrs.pop(),
+                _ => None,
+            },
+            _ => None,
+        };
+        let mut expected = vec![0u8; 8];
+        expected.extend_from_slice(&hex("41C00C584BD2D91C4017A2A12FA59F3F"));
+        expected.extend_from_slice(&[0u8; 8]);
+        assert_eq!(keys, Some(Attribute::Binary("MS-CHAP-MPPE-Keys".to_string(), expected)));
+
+        assert_eq!(verify_v1_request(&request, &nt_password_hash("wrong")), MsChapResult::Rejected);
+    }
+}
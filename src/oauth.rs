@@ -0,0 +1,616 @@
+// oauth.rs - OAuth2 / OpenID Connect authentication for rust-radius
+//
+// Two modes are supported. In password mode the PAP credentials are checked
+// with a resource-owner password credentials grant against the IdP's token
+// endpoint. In bearer mode the password is an access token, validated with
+// token introspection (RFC 7662) or locally against the IdP's JWKS. Either way
+// the token's claims can be mapped to reply attributes.
+//
+// User-Password is limited to 128 bytes (RFC 2865), which many JWTs exceed;
+// EAP-TTLS/PAP has no such limit.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use oauth2::basic::{BasicClient, BasicErrorResponseType};
+use oauth2::{
+    AuthUrl, ClientId, ClientSecret, HttpRequest, HttpResponse, RequestTokenError, ResourceOwnerPassword,
+    ResourceOwnerUsername, Scope, TokenResponse, TokenUrl,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use tokio::sync::RwLock;
+
+use crate::protocol::Attribute;
+use crate::users::ReplyValue;
+use crate::Result;
+
+/// Token claims
+pub type Claims = Map<String, Value>;
+
+/// How long a fetched JWKS is used before it is fetched again
+const JWKS_MAX_AGE: Duration = Duration::from_secs(3600);
+
+/// Minimum time between fetches triggered by an unknown key ID
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+/// What the RADIUS password is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OAuthMode {
+    /// The user's password, checked with a password grant
+    #[default]
+    Password,
+    
+    /// An access token
+    Bearer,
+}
+
+/// Options of an OAuth backend
+#[derive(Debug, Clone, Deserialize)]
+pub struct OAuthSettings {
+    /// What the RADIUS password is
+    #[serde(default)]
+    pub mode: OAuthMode,
+    
+    /// Client ID registered with the IdP
+    pub client_id: String,
+    
+    /// Client secret, for confidential clients
+    #[serde(default)]
+    pub client_secret: Option<String>,
+    
+    /// Authorization endpoint; unused by the password grant, defaults to the token endpoint
+    #[serde(default)]
+    pub auth_url: Option<String>,
+    
+    /// Token endpoint, required in password mode
+    #[serde(default)]
+    pub token_url: Option<String>,
+    
+    /// Scopes requested by the password grant
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    
+    /// Token introspection endpoint (RFC 7662)
+    #[serde(default)]
+    pub introspection_url: Option<String>,
+    
+    /// JWKS endpoint, for verifying JWT access tokens locally
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+    
+    /// Required `iss` of JWT access tokens
+    #[serde(default)]
+    pub issuer: Option<String>,
+    
+    /// Required `aud` of JWT access tokens
+    #[serde(default)]
+    pub audience: Option<String>,
+    
+    /// UserInfo endpoint, for claims in password mode when tokens are not validated
+    #[serde(default)]
+    pub user_info_url: Option<String>,
+    
+    /// Claim that must equal User-Name in bearer mode; `""` disables the check
+    #[serde(default = "default_username_claim")]
+    pub username_claim: String,
+    
+    /// Reply attributes filled from claims (attribute name -> claim, dotted for nested claims)
+    #[serde(default)]
+    pub claims: BTreeMap<String, String>,
+    
+    /// Claim listing the user's groups, dotted for nested claims; `""` disables groups
+    #[serde(default = "default_group_claim")]
+    pub group_claim: String,
+    
+    /// HTTP timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+fn default_username_claim() -> String {
+    "preferred_username".to_string()
+}
+
+fn default_group_claim() -> String {
+    "groups".to_string()
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+/// Outcome of an OAuth authentication
+#[derive(Debug, Clone, PartialEq)]
+pub enum OAuthOutcome {
+    /// The credentials are valid
+    Authenticated(Claims),
+    
+    /// The password or token was refused
+    Rejected(String),
+}
+
+/// Fetched signing keys
+struct CachedJwks {
+    /// Keys of the IdP
+    keys: JwkSet,
+    
+    /// When they were fetched
+    fetched: Instant,
+}
+
+/// OAuth2 / OIDC identity provider client
+pub struct OAuthProvider {
+    /// Backend options
+    settings: OAuthSettings,
+    
+    /// Client for the password grant
+    client: Option<BasicClient>,
+    
+    /// HTTP client
+    http: reqwest::Client,
+    
+    /// Signing keys, once fetched
+    jwks: RwLock<Option<CachedJwks>>,
+}
+
+impl OAuthProvider {
+    /// Create an identity provider client
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - Backend options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an endpoint is missing or invalid for the mode
+    pub fn new(settings: OAuthSettings) -> Result<Self> {
+        let client = match settings.mode {
+            OAuthMode::Password => {
+                let token_url = settings.token_url.as_ref()
+                    .ok_or("OAuth password mode requires token_url")?;
+                let auth_url = settings.auth_url.as_ref().unwrap_or(token_url);
+                
+                Some(BasicClient::new(
+                    ClientId::new(settings.client_id.clone()),
+                    settings.client_secret.clone().map(ClientSecret::new),
+                    AuthUrl::new(auth_url.clone()).map_err(|e| format!("Invalid auth_url: {}", e))?,
+                    Some(TokenUrl::new(token_url.clone()).map_err(|e| format!("Invalid token_url: {}", e))?),
+                ))
+            },
+            OAuthMode::Bearer => {
+                if settings.introspection_url.is_none() && settings.jwks_url.is_none() {
+                    return Err("OAuth bearer mode requires introspection_url or jwks_url".into());
+                }
+                None
+            },
+        };
+        
+        // Redirects are not followed, so a compromised endpoint cannot point us elsewhere
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(settings.timeout))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        
+        Ok(Self {
+            settings,
+            client,
+            http,
+            jwks: RwLock::new(None),
+        })
+    }
+    
+    /// Authenticate a user
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - User-Name of the request
+    /// * `password` - Password, or access token in bearer mode
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the IdP cannot be reached or answers unexpectedly
+    pub async fn authenticate(&self, username: &str, password: &str) -> Result<OAuthOutcome> {
+        if password.is_empty() {
+            return Ok(OAuthOutcome::Rejected("Empty password".to_string()));
+        }
+        
+        match &self.client {
+            Some(client) => self.password_grant(client, username, password).await,
+            None => {
+                let claims = match self.validate_token(password).await? {
+                    Some(claims) => claims,
+                    None => return Ok(OAuthOutcome::Rejected("Invalid or expired access token".to_string())),
+                };
+                
+                let claim = &self.settings.username_claim;
+                if !claim.is_empty() && claims.get(claim).and_then(Value::as_str) != Some(username) {
+                    return Ok(OAuthOutcome::Rejected(format!("Access token was not issued to {}", username)));
+                }
+                
+                Ok(OAuthOutcome::Authenticated(claims))
+            },
+        }
+    }
+    
+    /// Reply attributes for a token's claims
+    ///
+    /// # Arguments
+    ///
+    /// * `claims` - Claims of the validated token
+    pub fn reply_attributes(&self, claims: &Claims) -> Vec<Attribute> {
+        let mut attributes = Vec::new();
+        
+        for (name, path) in &self.settings.claims {
+            if let Some(value) = claim(claims, path).and_then(ReplyValue::from_json) {
+                value.to_attributes(name, &mut attributes);
+            }
+        }
+        
+        attributes
+    }
+    
+    /// Groups listed in a token's group claim
+    ///
+    /// # Arguments
+    ///
+    /// * `claims` - Claims of the validated token
+    pub fn groups(&self, claims: &Claims) -> Vec<String> {
+        if self.settings.group_claim.is_empty() {
+            return Vec::new();
+        }
+        
+        match claim(claims, &self.settings.group_claim) {
+            Some(Value::Array(values)) => values.iter().filter_map(Value::as_str).map(str::to_string).collect(),
+            Some(Value::String(group)) => vec![group.clone()],
+            _ => Vec::new(),
+        }
+    }
+    
+    /// Check a password with a resource-owner password credentials grant
+    async fn password_grant(&self, client: &BasicClient, username: &str, password: &str) -> Result<OAuthOutcome> {
+        let username = ResourceOwnerUsername::new(username.to_string());
+        let password = ResourceOwnerPassword::new(password.to_string());
+        let scopes = self.settings.scopes.iter().map(|scope| Scope::new(scope.clone()));
+        
+        let response = client.exchange_password(&username, &password)
+            .add_scopes(scopes)
+            .request_async(|request| self.send(request)).await;
+        
+        let token = match response {
+            Ok(response) => response.access_token().secret().clone(),
+            Err(RequestTokenError::ServerResponse(error)) if *error.error() == BasicErrorResponseType::InvalidGrant => {
+                return Ok(OAuthOutcome::Rejected("Invalid username or password".to_string()));
+            },
+            Err(RequestTokenError::ServerResponse(error)) => {
+                return Err(format!("OAuth password grant failed: {}", error).into());
+            },
+            Err(e) => return Err(format!("OAuth password grant failed: {:?}", e).into()),
+        };
+        
+        // Claims come from the access token if it can be validated, otherwise from UserInfo
+        let claims = if self.settings.introspection_url.is_some() || self.settings.jwks_url.is_some() {
+            self.validate_token(&token).await?
+                .ok_or("Access token from the password grant did not validate")?
+        } else if let Some(url) = &self.settings.user_info_url {
+            self.http.get(url).bearer_auth(&token).send().await?
+                .error_for_status()?
+                .json().await?
+        } else {
+            Claims::new()
+        };
+        
+        Ok(OAuthOutcome::Authenticated(claims))
+    }
+    
+    /// Validate an access token
+    ///
+    /// # Returns
+    ///
+    /// The token's claims, or None if it is invalid, expired or revoked
+    async fn validate_token(&self, token: &str) -> Result<Option<Claims>> {
+        if let Some(url) = &self.settings.introspection_url {
+            let mut request = self.http.post(url)
+                .form(&[("token", token), ("token_type_hint", "access_token")]);
+            if let Some(secret) = &self.settings.client_secret {
+                request = request.basic_auth(&self.settings.client_id, Some(secret));
+            }
+            
+            let claims: Claims = request.send().await?
+                .error_for_status()?
+                .json().await?;
+            
+            return Ok(match claims.get("active") {
+                Some(Value::Bool(true)) => Some(claims),
+                _ => None,
+            });
+        }
+        
+        let header = match jsonwebtoken::decode_header(token) {
+            Ok(header) => header,
+            Err(_) => return Ok(None),
+        };
+        
+        // The JWKS is public, so keys from it must never be used as HMAC secrets
+        if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+            return Ok(None);
+        }
+        
+        let key = match self.signing_key(header.kid.as_deref()).await? {
+            Some(key) => key,
+            None => return Ok(None),
+        };
+        
+        let mut validation = Validation::new(header.alg);
+        validation.leeway = 60;
+        match &self.settings.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(issuer) = &self.settings.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        
+        match jsonwebtoken::decode::<Claims>(token, &key, &validation) {
+            Ok(data) => Ok(Some(data.claims)),
+            Err(e) => {
+                tracing::debug!(error = %e, "JWT access token rejected");
+                Ok(None)
+            },
+        }
+    }
+    
+    /// Find the key a JWT was signed with, fetching the JWKS when needed
+    ///
+    /// A token without a key ID can only be verified if the JWKS holds a single key.
+    async fn signing_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>> {
+        let url = match &self.settings.jwks_url {
+            Some(url) => url,
+            None => return Ok(None),
+        };
+        
+        let find = |keys: &JwkSet| match kid {
+            Some(kid) => keys.find(kid).cloned(),
+            None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+            None => None,
+        };
+        
+        // Unknown key IDs trigger a refetch (the IdP may have rotated keys), but not too often
+        let stale = {
+            let cached = self.jwks.read().await;
+            match cached.as_ref() {
+                Some(cached) => match find(&cached.keys) {
+                    Some(jwk) if cached.fetched.elapsed() < JWKS_MAX_AGE => return Ok(Some(DecodingKey::from_jwk(&jwk)?)),
+                    _ => cached.fetched.elapsed() >= JWKS_MIN_REFRESH,
+                },
+                None => true,
+            }
+        };
+        
+        if !stale {
+            return Ok(None);
+        }
+        
+        let keys: JwkSet = self.http.get(url).send().await?
+            .error_for_status()?
+            .json().await
+            .map_err(|e| format!("Invalid JWKS from {}: {}", url, e))?;
+        let jwk = find(&keys);
+        
+        *self.jwks.write().await = Some(CachedJwks {
+            keys,
+            fetched: Instant::now(),
+        });
+        
+        match jwk {
+            Some(jwk) => Ok(Some(DecodingKey::from_jwk(&jwk)?)),
+            None => Ok(None),
+        }
+    }
+    
+    /// Send a request of the oauth2 crate with our HTTP client
+    async fn send(&self, request: HttpRequest) -> std::result::Result<HttpResponse, reqwest::Error> {
+        let response = self.http.request(request.method, request.url.as_str())
+            .headers(request.headers)
+            .body(request.body)
+            .send().await?;
+        
+        Ok(HttpResponse {
+            status_code: response.status(),
+            headers: response.headers().clone(),
+            body: response.bytes().await?.to_vec(),
+        })
+    }
+}
+
+/// Look up a claim by dotted path (`realm_access.roles`)
+fn claim<'a>(claims: &'a Claims, path: &str) -> Option<&'a Value> {
+    let mut parts = path.split('.');
+    let mut value = claims.get(parts.next()?)?;
+    for part in parts {
+        value = value.get(part)?;
+    }
+    Some(value)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::{EncodingKey, Header};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    
+    /// Mock IdP: a signing key and the tokens it has issued
+    pub(crate) struct MockIdp {
+        key: EncodingKey,
+        jwks: String,
+    }
+    
+    impl MockIdp {
+        pub(crate) fn new() -> Self {
+            let pair = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256).unwrap();
+            let point = pair.public_key_raw();
+            let jwks = serde_json::json!({
+                "keys": [{
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "kid": "test",
+                    "alg": "ES256",
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                }],
+            });
+            
+            Self {
+                key: EncodingKey::from_ec_der(&pair.serialize_der()),
+                jwks: jwks.to_string(),
+            }
+        }
+        
+        /// Issue a JWT access token
+        pub(crate) fn token(&self, username: &str, lifetime: i64) -> String {
+            let claims = serde_json::json!({
+                "iss": "https://idp.test",
+                "aud": "radius",
+                "sub": format!("id-{}", username),
+                "preferred_username": username,
+                "exp": chrono::Utc::now().timestamp() + lifetime,
+                "realm_access": { "roles": ["staff", "vpn"] },
+                "vlan": 10,
+            });
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some("test".to_string());
+            jsonwebtoken::encode(&header, &claims, &self.key).unwrap()
+        }
+        
+        /// Answer one HTTP request
+        fn respond(&self, request: &str) -> (u16, String) {
+            let (head, body) = request.split_once("\r\n\r\n").unwrap_or((request, ""));
+            let path = head.split(' ').nth(1).unwrap_or("");
+            
+            match path {
+                "/token" if body.contains("grant_type=password") && body.contains("username=alice") && body.contains("password=secret") => {
+                    (200, serde_json::json!({ "access_token": self.token("alice", 300), "token_type": "bearer" }).to_string())
+                },
+                "/token" => (400, r#"{"error":"invalid_grant"}"#.to_string()),
+                "/introspect" if !head.contains("authorization: Basic") && !head.contains("Authorization: Basic") => (401, String::new()),
+                "/introspect" if body.contains("token=opaque-alice") => {
+                    (200, r#"{"active":true,"preferred_username":"alice","groups":["staff"]}"#.to_string())
+                },
+                "/introspect" => (200, r#"{"active":false}"#.to_string()),
+                "/jwks" => (200, self.jwks.clone()),
+                _ => (404, String::new()),
+            }
+        }
+        
+        /// Serve HTTP on a local port, returning its base URL
+        pub(crate) async fn serve(self) -> String {
+            let idp = Arc::new(self);
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(idp.clone().handle(stream));
+                }
+            });
+            
+            url
+        }
+        
+        async fn handle(self: Arc<Self>, mut stream: TcpStream) {
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            
+            // Read the head, then as much body as Content-Length announces
+            loop {
+                let n = stream.read(&mut buffer).await.unwrap_or(0);
+                if n == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buffer[..n]);
+                
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                    let length = head.lines()
+                        .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap_or(0)))
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+            
+            let (status, body) = self.respond(&String::from_utf8_lossy(&request));
+            let response = format!(
+                "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status, body.len(), body,
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        }
+    }
+    
+    fn settings(options: String) -> OAuthSettings {
+        let options = format!(r#"
+            client_id = "radius"
+            client_secret = "client-secret"
+            issuer = "https://idp.test"
+            audience = "radius"
+            {}
+            [claims]
+            Filter-Id = "realm_access.roles"
+            Tunnel-Private-Group-Id = "vlan"
+        "#, options);
+        toml::from_str(&options).unwrap()
+    }
+    
+    #[tokio::test]
+    async fn password_grant() {
+        let url = MockIdp::new().serve().await;
+        let provider = OAuthProvider::new(settings(format!(
+            "token_url = \"{0}/token\"\njwks_url = \"{0}/jwks\"\nscopes = [\"openid\"]", url,
+        ))).unwrap();
+        
+        let claims = match provider.authenticate("alice", "secret").await.unwrap() {
+            OAuthOutcome::Authenticated(claims) => claims,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(provider.reply_attributes(&claims), vec![
+            Attribute::String("Filter-Id".to_string(), "staff".to_string()),
+            Attribute::String("Filter-Id".to_string(), "vpn".to_string()),
+            Attribute::Integer("Tunnel-Private-Group-Id".to_string(), 10),
+        ]);
+        
+        assert!(matches!(provider.authenticate("alice", "wrong").await.unwrap(), OAuthOutcome::Rejected(_)));
+        assert!(matches!(provider.authenticate("alice", "").await.unwrap(), OAuthOutcome::Rejected(_)));
+    }
+    
+    #[tokio::test]
+    async fn bearer_tokens() {
+        let idp = MockIdp::new();
+        let valid = idp.token("alice", 300);
+        let expired = idp.token("alice", -300);
+        let url = idp.serve().await;
+        
+        // Local JWKS verification
+        let provider = OAuthProvider::new(settings(format!("mode = \"bearer\"\njwks_url = \"{}/jwks\"", url))).unwrap();
+        assert!(matches!(provider.authenticate("alice", &valid).await.unwrap(), OAuthOutcome::Authenticated(_)));
+        
+        let mut tampered = valid.clone();
+        tampered.insert_str(valid.find('.').unwrap() + 1, "eyJ4IjoxfQ");
+        for (username, token) in [("bob", &valid), ("alice", &expired), ("alice", &tampered), ("alice", &"not-a-jwt".to_string())] {
+            assert!(matches!(provider.authenticate(username, token).await.unwrap(), OAuthOutcome::Rejected(_)), "{} {}", username, token);
+        }
+        
+        // Introspection
+        let provider = OAuthProvider::new(settings(format!("mode = \"bearer\"\nintrospection_url = \"{}/introspect\"", url))).unwrap();
+        assert!(matches!(provider.authenticate("alice", "opaque-alice").await.unwrap(), OAuthOutcome::Authenticated(_)));
+        assert!(matches!(provider.authenticate("alice", "opaque-revoked").await.unwrap(), OAuthOutcome::Rejected(_)));
+    }
+}
@@ -0,0 +1,246 @@
+// password.rs - Stored credentials for rust-radius
+//
+// This module parses, verifies and produces the password entries of the local
+// users file. Entries are tagged by scheme so that hashed and MS-CHAP capable
+// credentials can live side by side, and legacy entries can be upgraded to
+// Argon2id when a user logs in.
+
+use std::fmt;
+use std::str::FromStr;
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use rand::RngCore;
+use subtle::ConstantTimeEq;
+
+use crate::mschap;
+use crate::Result;
+
+/// Prefix of explicitly cleartext entries
+const CLEARTEXT_PREFIX: &str = "{cleartext}";
+
+/// Prefix of NT hash entries
+const NT_PREFIX: &str = "{nt}";
+
+/// Hashing scheme for new users file entries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    /// Argon2id with the default parameters (recommended)
+    Argon2id,
+    
+    /// bcrypt with the default cost
+    Bcrypt,
+    
+    /// SHA-512-crypt (`$6$`) with the default rounds
+    Sha512Crypt,
+    
+    /// NT hash, required for MS-CHAP and MS-CHAPv2
+    Nt,
+    
+    /// Cleartext, required for CHAP
+    Cleartext,
+}
+
+impl FromStr for Scheme {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "argon2id" | "argon2" => Ok(Scheme::Argon2id),
+            "bcrypt" => Ok(Scheme::Bcrypt),
+            "sha512-crypt" | "sha512" => Ok(Scheme::Sha512Crypt),
+            "nt" | "nt-hash" => Ok(Scheme::Nt),
+            "cleartext" => Ok(Scheme::Cleartext),
+            _ => Err(format!("Unknown password scheme: {}", s)),
+        }
+    }
+}
+
+/// Credential stored for a local user
+///
+/// In the users file a credential is a string tagged by its scheme:
+///
+/// * `$argon2id$...` - Argon2id PHC string
+/// * `$2a$`, `$2b$`, `$2y$...` - bcrypt
+/// * `$6$...` - SHA-512-crypt
+/// * `{nt}<32 hex digits>` - NT hash (PAP, MS-CHAP and MS-CHAPv2, but not CHAP)
+/// * `{cleartext}password` - cleartext (any method, including CHAP)
+///
+/// An untagged string is a legacy cleartext password.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credential {
+    /// Untagged cleartext password from a legacy users file
+    Plaintext(String),
+    
+    /// Cleartext password, kept on purpose (e.g. for CHAP)
+    Cleartext(String),
+    
+    /// NT password hash (MD4 of the UTF-16LE password)
+    NtHash([u8; 16]),
+    
+    /// Argon2id PHC string
+    Argon2(String),
+    
+    /// bcrypt hash
+    Bcrypt(String),
+    
+    /// SHA-512-crypt hash
+    Sha512Crypt(String),
+}
+
+impl Credential {
+    /// Parse a credential from its users file representation
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a tagged entry is malformed
+    pub fn parse(value: &str) -> Result<Self> {
+        if let Some(password) = value.strip_prefix(CLEARTEXT_PREFIX) {
+            return Ok(Credential::Cleartext(password.to_string()));
+        }
+        
+        if let Some(hex) = value.strip_prefix(NT_PREFIX) {
+            let bytes = hex::decode(hex)
+                .map_err(|e| format!("Invalid NT hash: {}", e))?;
+            let hash: [u8; 16] = bytes.try_into()
+                .map_err(|_| "Invalid NT hash: expected 32 hex digits".to_string())?;
+            return Ok(Credential::NtHash(hash));
+        }
+        
+        if value.starts_with("$argon2") {
+            PasswordHash::new(value)
+                .map_err(|e| format!("Invalid Argon2 hash: {}", e))?;
+            return Ok(Credential::Argon2(value.to_string()));
+        }
+        
+        if value.starts_with("$2a$") || value.starts_with("$2b$") || value.starts_with("$2y$") {
+            return Ok(Credential::Bcrypt(value.to_string()));
+        }
+        
+        if value.starts_with("$6$") {
+            return Ok(Credential::Sha512Crypt(value.to_string()));
+        }
+        
+        if value.starts_with('$') {
+            return Err(format!("Unsupported password hash scheme: {}", value.split('$').nth(1).unwrap_or_default()).into());
+        }
+        
+        Ok(Credential::Plaintext(value.to_string()))
+    }
+    
+    /// Create a credential for a password
+    ///
+    /// # Arguments
+    ///
+    /// * `scheme` - Scheme to store the password with
+    /// * `password` - Cleartext password
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the hashing library fails
+    pub fn hash(scheme: Scheme, password: &str) -> Result<Self> {
+        match scheme {
+            Scheme::Argon2id => {
+                let mut salt = [0u8; 16];
+                rand::thread_rng().fill_bytes(&mut salt);
+                let salt = SaltString::encode_b64(&salt)
+                    .map_err(|e| format!("Failed to encode salt: {}", e))?;
+                
+                let hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+                    .map_err(|e| format!("Argon2 hashing failed: {}", e))?;
+                Ok(Credential::Argon2(hash.to_string()))
+            },
+            Scheme::Bcrypt => {
+                let hash = pwhash::bcrypt::hash(password)
+                    .map_err(|e| format!("bcrypt hashing failed: {}", e))?;
+                Ok(Credential::Bcrypt(hash))
+            },
+            Scheme::Sha512Crypt => {
+                let hash = pwhash::sha512_crypt::hash(password)
+                    .map_err(|e| format!("SHA-512-crypt hashing failed: {}", e))?;
+                Ok(Credential::Sha512Crypt(hash))
+            },
+            Scheme::Nt => Ok(Credential::NtHash(mschap::nt_password_hash(password))),
+            Scheme::Cleartext => Ok(Credential::Cleartext(password.to_string())),
+        }
+    }
+    
+    /// Cleartext password, if known
+    pub fn cleartext(&self) -> Option<&str> {
+        match self {
+            Credential::Plaintext(password) | Credential::Cleartext(password) => Some(password),
+            _ => None,
+        }
+    }
+    
+    /// NT password hash, as used by MS-CHAP, if it can be derived
+    pub fn nt_hash(&self) -> Option<[u8; 16]> {
+        match self {
+            Credential::Plaintext(password) | Credential::Cleartext(password) => Some(mschap::nt_password_hash(password)),
+            Credential::NtHash(hash) => Some(*hash),
+            _ => None,
+        }
+    }
+    
+    /// Check a PAP password against the credential in constant time
+    pub fn verify_password(&self, password: &str) -> bool {
+        match self {
+            Credential::Plaintext(stored) | Credential::Cleartext(stored) => {
+                stored.as_bytes().ct_eq(password.as_bytes()).into()
+            },
+            Credential::NtHash(hash) => hash.ct_eq(&mschap::nt_password_hash(password)).into(),
+            Credential::Argon2(hash) => match PasswordHash::new(hash) {
+                Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+                Err(_) => false,
+            },
+            Credential::Bcrypt(hash) => pwhash::bcrypt::verify(password, hash),
+            Credential::Sha512Crypt(hash) => pwhash::sha512_crypt::verify(password, hash),
+        }
+    }
+    
+    /// Whether the credential should be replaced by an Argon2id hash after a successful login
+    ///
+    /// Only legacy hashes are upgraded. Cleartext and `{nt}` entries are kept,
+    /// since CHAP and MS-CHAP cannot work with anything else.
+    pub fn needs_rehash(&self) -> bool {
+        matches!(self, Credential::Bcrypt(_) | Credential::Sha512Crypt(_))
+    }
+}
+
+impl fmt::Display for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::Plaintext(password) => write!(f, "{}", password),
+            Credential::Cleartext(password) => write!(f, "{}{}", CLEARTEXT_PREFIX, password),
+            Credential::NtHash(hash) => write!(f, "{}{}", NT_PREFIX, hex::encode_upper(hash)),
+            Credential::Argon2(hash) | Credential::Bcrypt(hash) | Credential::Sha512Crypt(hash) => write!(f, "{}", hash),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn schemes_round_trip() {
+        for scheme in [Scheme::Argon2id, Scheme::Bcrypt, Scheme::Sha512Crypt, Scheme::Nt, Scheme::Cleartext] {
+            let credential = Credential::hash(scheme, "s3cret").unwrap();
+            let parsed = Credential::parse(&credential.to_string()).unwrap();
+            
+            assert_eq!(parsed, credential);
+            assert!(parsed.verify_password("s3cret"), "{:?}", scheme);
+            assert!(!parsed.verify_password("wrong"), "{:?}", scheme);
+        }
+        
+        let nt = Credential::parse("{nt}44EBBA8D5312B8D611474411F56989AE").unwrap();
+        assert_eq!(nt.nt_hash(), Some(mschap::nt_password_hash("clientPass")));
+        assert!(nt.cleartext().is_none());
+        assert!(!nt.needs_rehash());
+        
+        assert!(Credential::parse("{nt}1234").is_err());
+        assert!(Credential::parse("$1$md5crypt").is_err());
+        assert!(!Credential::parse("secret").unwrap().needs_rehash());
+        assert!(Credential::hash(Scheme::Sha512Crypt, "secret").unwrap().needs_rehash());
+    }
+}
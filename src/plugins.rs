@@ -0,0 +1,521 @@
+// plugins.rs - WebAssembly plugins for rust-radius
+//
+// Plugins are WebAssembly components implementing a world of wit/plugin.wit.
+// A `backend` plugin sees the attributes of each Access-Request and answers
+// like any other authentication backend; a `policy-hook` plugin runs at the
+// pre-auth, post-auth and pre-acct hooks after the rules of the policy file,
+// and can accept, reject or edit attributes.
+//
+// Every call runs in a fresh instance on a blocking thread, with a fuel
+// budget, a memory cap and a deadline, so a plugin that loops, allocates
+// without bound or traps fails that call and nothing else.
+
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use wasmtime::component::{Component, Linker};
+use wasmtime::{Engine, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::auth::AuthResult;
+use crate::config::PluginConfig;
+use crate::policy::{Hook, Verdict};
+use crate::protocol::{Attribute, Packet};
+use crate::Result;
+
+/// Host bindings of the `backend` world
+mod backend {
+    wasmtime::component::bindgen!({
+        world: "backend",
+        path: "wit",
+    });
+}
+
+/// Host bindings of the `policy-hook` world, sharing the imports of the `backend` world
+mod policy_hook {
+    wasmtime::component::bindgen!({
+        world: "policy-hook",
+        path: "wit",
+        with: {
+            "rust-radius:plugin/types": super::backend::rust_radius::plugin::types,
+            "rust-radius:plugin/host": super::backend::rust_radius::plugin::host,
+        },
+    });
+}
+
+use backend::exports::rust_radius::plugin::authenticator;
+use backend::rust_radius::plugin::{host, types};
+use policy_hook::exports::rust_radius::plugin::policy;
+
+/// Interval of the clock that enforces call deadlines
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Get the engine shared by every plugin, starting its deadline clock
+fn engine() -> Result<&'static Engine> {
+    static ENGINE: OnceLock<Engine> = OnceLock::new();
+    
+    if let Some(engine) = ENGINE.get() {
+        return Ok(engine);
+    }
+    
+    let mut config = wasmtime::Config::new();
+    config.wasm_component_model(true);
+    config.consume_fuel(true);
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)
+        .map_err(|e| format!("Failed to create the WebAssembly engine: {}", e))?;
+    
+    Ok(ENGINE.get_or_init(|| {
+        let clock = engine.clone();
+        std::thread::Builder::new()
+            .name("wasm-deadlines".to_string())
+            .spawn(move || loop {
+                std::thread::sleep(EPOCH_TICK);
+                clock.increment_epoch();
+            })
+            .expect("Failed to start the WebAssembly deadline clock");
+        engine
+    }))
+}
+
+/// Data of the store of one call
+struct CallState {
+    /// Plugin name, for its log lines
+    plugin: String,
+    
+    /// Memory cap
+    limits: StoreLimits,
+}
+
+impl types::Host for CallState {}
+
+impl host::Host for CallState {
+    fn log(&mut self, level: host::Level, message: String) {
+        match level {
+            host::Level::Debug => tracing::debug!(plugin = self.plugin, "{}", message),
+            host::Level::Info => tracing::info!(plugin = self.plugin, "{}", message),
+            host::Level::Warn => tracing::warn!(plugin = self.plugin, "{}", message),
+            host::Level::Error => tracing::error!(plugin = self.plugin, "{}", message),
+        }
+    }
+}
+
+/// Compile a plugin and link it to the host functions
+///
+/// # Errors
+///
+/// Returns an error if the component cannot be read, compiled or linked
+fn load(name: &str, settings: &PluginConfig) -> Result<(Component, Linker<CallState>)> {
+    let engine = engine()?;
+    let component = Component::from_file(engine, &settings.module)
+        .map_err(|e| format!("Failed to load plugin {} from {}: {:#}", name, settings.module.display(), e))?;
+    
+    let mut linker = Linker::new(engine);
+    types::add_to_linker(&mut linker, |state: &mut CallState| state)
+        .and_then(|()| host::add_to_linker(&mut linker, |state: &mut CallState| state))
+        .map_err(|e| format!("Failed to link plugin {}: {}", name, e))?;
+    
+    Ok((component, linker))
+}
+
+/// Create the store of a call, with the plugin's limits
+fn store(name: &str, settings: &PluginConfig) -> Result<Store<CallState>> {
+    let limits = StoreLimitsBuilder::new()
+        .memory_size(settings.memory_mb.saturating_mul(1024 * 1024))
+        .build();
+    let mut store = Store::new(engine()?, CallState {
+        plugin: name.to_string(),
+        limits,
+    });
+    
+    store.limiter(|state| &mut state.limits);
+    store.set_fuel(settings.fuel)?;
+    store.set_epoch_deadline(settings.timeout_ms.div_ceil(EPOCH_TICK.as_millis() as u64).max(1));
+    
+    Ok(store)
+}
+
+/// Run a call on a blocking thread
+///
+/// # Errors
+///
+/// Returns an error naming the plugin if the call traps, runs out of fuel,
+/// memory or time, or returns something the host cannot use
+async fn call<T, F>(name: &str, settings: &PluginConfig, f: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Store<CallState>) -> wasmtime::Result<T> + Send + 'static,
+{
+    let mut store = store(name, settings)?;
+    
+    tokio::task::spawn_blocking(move || f(&mut store))
+        .await?
+        .map_err(|e| format!("Plugin {} failed: {}", name, e.root_cause()).into())
+}
+
+/// Plugin registered as an authentication backend
+pub struct BackendPlugin {
+    /// Plugin name
+    name: String,
+    
+    /// Module and limits
+    settings: PluginConfig,
+    
+    /// Component, linked and checked against the `backend` world
+    pre: backend::BackendPre<CallState>,
+}
+
+impl BackendPlugin {
+    /// Load a plugin implementing the `backend` world
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Backend name
+    /// * `settings` - Module and limits
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the component cannot be loaded or does not export `authenticator`
+    pub fn load(name: &str, settings: PluginConfig) -> Result<Self> {
+        let (component, linker) = load(name, &settings)?;
+        let pre = linker.instantiate_pre(&component)
+            .and_then(backend::BackendPre::new)
+            .map_err(|e| format!("Plugin {} is not a backend: {}", name, e))?;
+        
+        Ok(Self {
+            name: name.to_string(),
+            settings,
+            pre,
+        })
+    }
+    
+    /// Ask the plugin about a request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn authenticate(&self, request: &Packet) -> Result<AuthResult> {
+        let request = wit_request(request);
+        let pre = self.pre.clone();
+        
+        let result = call(&self.name, &self.settings, move |store| {
+            pre.instantiate(&mut *store)?
+                .rust_radius_plugin_authenticator()
+                .call_authenticate(&mut *store, &request)
+        }).await?;
+        
+        Ok(match result {
+            authenticator::AuthResult::Accept(attributes) => AuthResult::Accept {
+                attributes: from_wit(attributes),
+                groups: vec![],
+            },
+            authenticator::AuthResult::Reject(rejection) => AuthResult::Reject {
+                reason: rejection.reason,
+                attributes: from_wit(rejection.attributes),
+            },
+            authenticator::AuthResult::NotFound => AuthResult::NotFound,
+            authenticator::AuthResult::Challenge(challenge) => AuthResult::Challenge {
+                message: challenge.message,
+                state: challenge.state,
+                attributes: from_wit(challenge.attributes),
+            },
+        })
+    }
+}
+
+/// Plugin registered as a policy hook
+pub struct PolicyPlugin {
+    /// Plugin name (the module file)
+    name: String,
+    
+    /// Module and limits
+    settings: PluginConfig,
+    
+    /// Component, linked and checked against the `policy-hook` world
+    pre: policy_hook::PolicyHookPre<CallState>,
+}
+
+impl PolicyPlugin {
+    /// Load a plugin implementing the `policy-hook` world
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the component cannot be loaded or does not export `policy`
+    pub fn load(settings: &PluginConfig) -> Result<Self> {
+        let name = settings.module.display().to_string();
+        let (component, linker) = load(&name, settings)?;
+        let pre = linker.instantiate_pre(&component)
+            .and_then(policy_hook::PolicyHookPre::new)
+            .map_err(|e| format!("Plugin {} is not a policy hook: {}", name, e))?;
+        
+        Ok(Self {
+            name,
+            settings: settings.clone(),
+            pre,
+        })
+    }
+    
+    /// Get the plugin name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    
+    /// Run the plugin at a hook
+    ///
+    /// Attribute edits are applied to the request, or to the reply in post-auth.
+    /// An `accept` in post-auth changes nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Hook being run
+    /// * `request` - Request being processed
+    /// * `reply` - Access-Accept about to be sent (post-auth)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the call fails
+    pub async fn run(&self, hook: Hook, request: &mut Packet, reply: Option<&mut Packet>) -> Result<Verdict> {
+        let wit_hook = match hook {
+            Hook::PreAuth => policy::Hook::PreAuth,
+            Hook::PostAuth => policy::Hook::PostAuth,
+            Hook::PreAcct => policy::Hook::PreAcct,
+        };
+        let wit_request = wit_request(request);
+        let wit_reply = reply.as_ref().map(|reply| wit_attributes(reply.attributes())).unwrap_or_default();
+        let pre = self.pre.clone();
+        
+        let decision = call(&self.name, &self.settings, move |store| {
+            pre.instantiate(&mut *store)?
+                .rust_radius_plugin_policy()
+                .call_run(&mut *store, wit_hook, &wit_request, &wit_reply)
+        }).await?;
+        
+        let target = match reply {
+            Some(reply) => reply,
+            None => request,
+        };
+        let set = from_wit(decision.set);
+        for name in decision.remove.iter().map(String::as_str).chain(set.iter().map(Attribute::name)) {
+            target.remove_attributes(name);
+        }
+        for attribute in set {
+            target.add_attribute(attribute);
+        }
+        
+        Ok(match decision.verdict {
+            policy::Verdict::Accept if hook != Hook::PostAuth => Verdict::Accept,
+            policy::Verdict::Reject(reason) => Verdict::Reject(reason),
+            _ => Verdict::Continue,
+        })
+    }
+}
+
+/// Request as plugins see it
+fn wit_request(request: &Packet) -> types::Request {
+    types::Request {
+        client: request.source().map(|source| source.ip().to_string()).unwrap_or_default(),
+        attributes: wit_attributes(request.attributes()),
+    }
+}
+
+/// Convert attributes for plugins; vendor attributes are listed under their own names
+fn wit_attributes(attributes: &[Attribute]) -> Vec<types::Attribute> {
+    let mut converted = Vec::new();
+    
+    for attribute in attributes {
+        let value = match attribute {
+            Attribute::String(_, value) => types::Value::Text(value.clone()),
+            Attribute::Integer(_, value) => types::Value::Integer(*value),
+            Attribute::IpAddr(_, value) => types::Value::Address(value.to_string()),
+            Attribute::Ipv6Addr(_, value) => types::Value::Address(value.to_string()),
+            Attribute::Ipv6Prefix(_, value, len) => types::Value::Address(format!("{}/{}", value, len)),
+            Attribute::Binary(_, value) => types::Value::Octets(value.clone()),
+            Attribute::VendorSpecific(_, attributes) => {
+                converted.extend(wit_attributes(attributes));
+                continue;
+            },
+        };
+        
+        converted.push(types::Attribute {
+            name: attribute.name().to_string(),
+            value,
+        });
+    }
+    
+    converted
+}
+
+/// Convert attributes from plugins
+fn from_wit(attributes: Vec<types::Attribute>) -> Vec<Attribute> {
+    attributes.into_iter()
+        .map(|attribute| match attribute.value {
+            types::Value::Text(value) => Attribute::String(attribute.name, value),
+            types::Value::Integer(value) => Attribute::Integer(attribute.name, value),
+            types::Value::Address(value) => match value.parse::<IpAddr>() {
+                Ok(address) => Attribute::IpAddr(attribute.name, address),
+                Err(_) => Attribute::String(attribute.name, value),
+            },
+            types::Value::Octets(value) => Attribute::Binary(attribute.name, value),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    
+    use wit_component::{ComponentEncoder, StringEncoding};
+    use wit_parser::Resolve;
+    
+    /// Bump allocator the host lowers strings and lists with
+    const ALLOCATOR: &str = r#"
+        (memory (export "memory") 1)
+        (global $heap (mut i32) (i32.const 4096))
+        (func (export "cabi_realloc") (param i32 i32 i32 i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (i32.and
+                (i32.add (global.get $heap) (i32.sub (local.get 2) (i32.const 1)))
+                (i32.sub (i32.const 0) (local.get 2))))
+            (global.set $heap (i32.add (local.get $ptr) (local.get 3)))
+            (local.get $ptr))
+    "#;
+    
+    /// Encode a core module as a component of a world and write it to a file
+    fn component(world: &str, name: &str, wat: &str) -> PathBuf {
+        let mut resolve = Resolve::default();
+        let (package, _) = resolve.push_path(concat!(env!("CARGO_MANIFEST_DIR"), "/wit")).unwrap();
+        let world = resolve.select_world(package, Some(world)).unwrap();
+        
+        let mut module = wat::parse_str(wat).unwrap();
+        wit_component::embed_component_metadata(&mut module, &resolve, world, StringEncoding::UTF8).unwrap();
+        let component = ComponentEncoder::default().module(&module).unwrap().validate(true).encode().unwrap();
+        
+        let path = std::env::temp_dir().join(format!("rust-radius-plugin-{}-{}.wasm", std::process::id(), name));
+        std::fs::write(&path, component).unwrap();
+        path
+    }
+    
+    /// Backend whose `authenticate` runs `body` and returns the result at address 1024
+    fn backend(name: &str, body: &str) -> PathBuf {
+        component("backend", name, &format!(r#"(module {}
+            (func (export "rust-radius:plugin/authenticator@0.1.0#authenticate")
+                (param $client i32) (param $client_len i32) (param i32 i32) (result i32)
+                {}
+                (i32.const 1024)))"#, ALLOCATOR, body))
+    }
+    
+    fn settings(module: PathBuf) -> PluginConfig {
+        PluginConfig {
+            module,
+            fuel: 10_000_000,
+            memory_mb: 16,
+            timeout_ms: 100,
+        }
+    }
+    
+    fn request() -> Packet {
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.set_source("192.0.2.7:1812".parse().unwrap());
+        request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+        request.add_attribute(Attribute::String("Class".to_string(), "guest".to_string()));
+        request
+    }
+    
+    #[tokio::test]
+    async fn backend_plugin_answers() {
+        // Reject, with the client address as the reason
+        let module = backend("reject", r#"
+            (i32.store8 (i32.const 1024) (i32.const 1))
+            (i32.store (i32.const 1028) (local.get $client))
+            (i32.store (i32.const 1032) (local.get $client_len))
+            (i32.store (i32.const 1036) (i32.const 0))
+            (i32.store (i32.const 1040) (i32.const 0))
+        "#);
+        let plugin = BackendPlugin::load("wasm", settings(module)).unwrap();
+        
+        match plugin.authenticate(&request()).await.unwrap() {
+            AuthResult::Reject { reason, attributes } => {
+                assert_eq!(reason, "192.0.2.7");
+                assert!(attributes.is_empty());
+            },
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+    
+    #[tokio::test]
+    async fn runaway_plugins_are_stopped() {
+        let spin = backend("spin", "(loop (br 0))");
+        let plugin = BackendPlugin::load("spin", settings(spin.clone())).unwrap();
+        assert!(plugin.authenticate(&request()).await.unwrap_err().to_string().contains("fuel"));
+        
+        // With fuel to spare, the deadline stops it
+        let plugin = BackendPlugin::load("spin", PluginConfig {
+            fuel: u64::MAX,
+            timeout_ms: 20,
+            ..settings(spin)
+        }).unwrap();
+        let started = std::time::Instant::now();
+        assert!(plugin.authenticate(&request()).await.unwrap_err().to_string().contains("interrupt"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        
+        // Growing past the cap fails, and the guest traps on the failure
+        let grow = backend("grow", r#"
+            (if (i32.eq (memory.grow (i32.const 64)) (i32.const -1)) (then unreachable))
+        "#);
+        let plugin = BackendPlugin::load("grow", PluginConfig {
+            memory_mb: 1,
+            ..settings(grow)
+        }).unwrap();
+        assert!(plugin.authenticate(&request()).await.unwrap_err().to_string().contains("unreachable"));
+    }
+    
+    #[tokio::test]
+    async fn policy_plugin_edits_and_decides() {
+        // Set Filter-Id = "wasm" and remove Class; reject accounting
+        let module = component("policy-hook", "policy", &format!(r#"(module {}
+            (data (i32.const 100) "no accounting")
+            (data (i32.const 120) "Filter-Id")
+            (data (i32.const 130) "wasm")
+            (data (i32.const 140) "Class")
+            (func (export "rust-radius:plugin/policy@0.1.0#run")
+                (param $hook i32) (param i32 i32 i32 i32 i32 i32) (result i32)
+                (if (i32.eq (local.get $hook) (i32.const 2))
+                    (then
+                        (i32.store8 (i32.const 1024) (i32.const 2))
+                        (i32.store (i32.const 1028) (i32.const 100))
+                        (i32.store (i32.const 1032) (i32.const 13)))
+                    (else
+                        (i32.store8 (i32.const 1024) (i32.const 0))))
+                (i32.store (i32.const 200) (i32.const 120))
+                (i32.store (i32.const 204) (i32.const 9))
+                (i32.store8 (i32.const 208) (i32.const 0))
+                (i32.store (i32.const 212) (i32.const 130))
+                (i32.store (i32.const 216) (i32.const 4))
+                (i32.store (i32.const 240) (i32.const 140))
+                (i32.store (i32.const 244) (i32.const 5))
+                (i32.store (i32.const 1036) (i32.const 200))
+                (i32.store (i32.const 1040) (i32.const 1))
+                (i32.store (i32.const 1044) (i32.const 240))
+                (i32.store (i32.const 1048) (i32.const 1))
+                (i32.const 1024)))"#, ALLOCATOR));
+        let plugin = PolicyPlugin::load(&settings(module)).unwrap();
+        
+        let mut request = request();
+        assert_eq!(plugin.run(Hook::PreAuth, &mut request, None).await.unwrap(), Verdict::Continue);
+        assert_eq!(request.get_text("Filter-Id").as_deref(), Some("wasm"));
+        assert!(request.get_attribute("Class").is_none());
+        
+        // Post-auth edits go to the reply
+        let mut request = self::request();
+        let mut reply = request.create_response(Packet::ACCESS_ACCEPT);
+        reply.add_attribute(Attribute::String("Class".to_string(), "staff".to_string()));
+        plugin.run(Hook::PostAuth, &mut request, Some(&mut reply)).await.unwrap();
+        assert_eq!(reply.get_text("Filter-Id").as_deref(), Some("wasm"));
+        assert!(reply.get_attribute("Class").is_none());
+        assert_eq!(request.get_text("Class").as_deref(), Some("guest"));
+        
+        let verdict = plugin.run(Hook::PreAcct, &mut request, None).await.unwrap();
+        assert_eq!(verdict, Verdict::Reject("no accounting".to_string()));
+    }
+}
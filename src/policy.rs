@@ -0,0 +1,1112 @@
+// policy.rs - Policy rules for rust-radius
+//
+// A small rule language for what backends and group profiles cannot express:
+// strip a realm from User-Name, send one NAS's guests to a particular backend,
+// refuse logins outside office hours or trim the reply for some groups. Rules
+// are read from the file named in `[policy]` and run at three hooks of the
+// server pipeline:
+//
+//     pre-auth {
+//         if User-Name =~ "^([^@]+)@corp\.example\.com$" {
+//             rewrite "$1"
+//         }
+//         if nas == "lobby-ap" and not group "staff" {
+//             backend "vouchers"
+//         } else if client in 10.20.0.0/16 and not time "Mon-Fri 08:00-18:00" {
+//             reject "Outside office hours"
+//         }
+//     }
+//     post-auth {
+//         if group "contractors" {
+//             set Session-Timeout = 3600
+//             remove Class
+//         }
+//     }
+//     pre-acct {
+//         if not Acct-Session-Id { reject "No session id" }
+//     }
+//
+// The language is sandboxed by construction: it has no loops, variables or
+// I/O, regular expressions run in linear time with a bounded size, and nesting
+// is limited, so no rules file can hang or crash the server.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::path::Path;
+use std::str::FromStr;
+
+use chrono::{DateTime, Local};
+use regex::{Regex, RegexBuilder};
+
+use crate::config::Config;
+use crate::protocol::{Attribute, Packet};
+use crate::users::TimeWindow;
+use crate::Result;
+
+/// Deepest nesting of blocks, `else if`, `not` and parentheses
+const MAX_DEPTH: usize = 32;
+
+/// Largest compiled size of a regular expression, in bytes
+const MAX_REGEX_SIZE: usize = 1 << 20;
+
+/// Reply-Message of a `reject` without a reason
+const DEFAULT_REJECT_REASON: &str = "Rejected by policy";
+
+/// Point of the server pipeline where rules run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    /// Before an Access-Request reaches the backends
+    PreAuth,
+    
+    /// Before an Access-Accept is sent
+    PostAuth,
+    
+    /// Before an Accounting-Request is processed
+    PreAcct,
+}
+
+impl Hook {
+    /// Name of the hook's section in a rules file
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreAuth => "pre-auth",
+            Hook::PostAuth => "post-auth",
+            Hook::PreAcct => "pre-acct",
+        }
+    }
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Hook {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "pre-auth" => Ok(Hook::PreAuth),
+            "post-auth" => Ok(Hook::PostAuth),
+            "pre-acct" => Ok(Hook::PreAcct),
+            _ => Err(format!("Unknown hook {}, expected pre-auth, post-auth or pre-acct", s)),
+        }
+    }
+}
+
+/// What the rules of a hook decided
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Verdict {
+    /// No rule decided; the request goes on through the pipeline
+    #[default]
+    Continue,
+    
+    /// Accept without asking a backend (pre-auth), or acknowledge without processing (pre-acct)
+    ///
+    /// A pre-auth Accept still goes through lockout, group profiles, MFA, the
+    /// session limit, quotas and the post-auth rules.
+    Accept,
+    
+    /// Reject the request, or discard an accounting request
+    Reject(String),
+}
+
+/// Result of running the rules of a hook
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Outcome {
+    /// Decision of the rules
+    pub verdict: Verdict,
+    
+    /// Backend that authenticates the request instead of the whole chain
+    pub backend: Option<String>,
+    
+    /// Realm the request is proxied to
+    pub realm: Option<String>,
+}
+
+/// Rules loaded from a policy file
+#[derive(Debug, Default)]
+pub struct Policy {
+    /// Statements of each hook present in the file
+    hooks: HashMap<Hook, Vec<Statement>>,
+}
+
+impl Policy {
+    /// Load the rules named in the configuration
+    ///
+    /// Without a rules file the policy is empty.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the rules file cannot be read or parsed, or if a rule
+    /// chooses a backend that is not configured
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let policy = match config.policy.as_ref().and_then(|policy| policy.file.as_ref()) {
+            Some(file) => Self::load(file)?,
+            None => return Ok(Self::default()),
+        };
+        
+        let mut backends = Vec::new();
+        for statements in policy.hooks.values() {
+            chosen_backends(statements, &mut backends);
+        }
+        if let Some(backend) = backends.iter().find(|backend| !config.auth_backends.contains_key(*backend)) {
+            return Err(format!("Policy chooses unknown backend {}", backend).into());
+        }
+        
+        Ok(policy)
+    }
+    
+    /// Load rules from a file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Rules file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read policy file {}: {}", path.display(), e))?;
+        
+        Self::parse(&text).map_err(|e| format!("{}: {}", path.display(), e).into())
+    }
+    
+    /// Parse rules
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the line of the first mistake
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            position: 0,
+            hook: Hook::PreAuth,
+            depth: 0,
+        };
+        let mut hooks = HashMap::new();
+        
+        while parser.peek().is_some() {
+            let name = parser.word()?;
+            let hook = name.parse().map_err(|e| parser.error(e))?;
+            if hooks.contains_key(&hook) {
+                return Err(parser.error(format!("{} appears twice", hook)));
+            }
+            
+            parser.hook = hook;
+            hooks.insert(hook, parser.block()?);
+        }
+        
+        Ok(Self { hooks })
+    }
+    
+    /// Check whether a hook has no rules
+    pub fn is_empty(&self, hook: Hook) -> bool {
+        self.hooks.get(&hook).is_none_or(Vec::is_empty)
+    }
+    
+    /// Check whether the rules of a hook test group membership
+    ///
+    /// Groups are looked up in the backends, so callers only do it when needed.
+    pub fn uses_groups(&self, hook: Hook) -> bool {
+        self.hooks.get(&hook).is_some_and(|statements| statements.iter().any(Statement::uses_groups))
+    }
+    
+    /// Run the rules of a hook
+    ///
+    /// Rules run in file order until one accepts or rejects. Attribute actions
+    /// edit the request, except in post-auth where they edit the reply.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Hook to run
+    /// * `request` - Request being processed
+    /// * `reply` - Access-Accept about to be sent (post-auth)
+    /// * `groups` - Groups of the user, for `group` conditions
+    /// * `now` - Time for `time` conditions
+    pub fn run(&self, hook: Hook, request: &mut Packet, reply: Option<&mut Packet>, groups: &[String], now: &DateTime<Local>) -> Outcome {
+        let mut run = Run {
+            request,
+            reply,
+            groups,
+            now,
+            captures: Vec::new(),
+            outcome: Outcome::default(),
+        };
+        
+        if let Some(statements) = self.hooks.get(&hook) {
+            run.execute(statements);
+        }
+        
+        run.outcome
+    }
+}
+
+/// Rule statement
+#[derive(Debug)]
+enum Statement {
+    /// Run `then` if the condition holds and `otherwise` if not
+    If {
+        condition: Condition,
+        then: Vec<Statement>,
+        otherwise: Vec<Statement>,
+    },
+    
+    /// Single action
+    Action(Action),
+}
+
+impl Statement {
+    /// Whether the statement tests group membership
+    fn uses_groups(&self) -> bool {
+        match self {
+            Statement::If { condition, then, otherwise } => {
+                condition.uses_groups() || then.iter().chain(otherwise).any(Statement::uses_groups)
+            },
+            Statement::Action(_) => false,
+        }
+    }
+}
+
+/// Collect the backends chosen by `backend` actions
+fn chosen_backends(statements: &[Statement], backends: &mut Vec<String>) {
+    for statement in statements {
+        match statement {
+            Statement::If { then, otherwise, .. } => {
+                chosen_backends(then, backends);
+                chosen_backends(otherwise, backends);
+            },
+            Statement::Action(Action::Backend(name)) => backends.push(name.clone()),
+            Statement::Action(_) => {},
+        }
+    }
+}
+
+/// Value a condition looks at
+#[derive(Debug)]
+enum Operand {
+    /// Request attribute
+    Request(String),
+    
+    /// Reply attribute, written `reply:Name` (post-auth)
+    Reply(String),
+    
+    /// Address the request came from
+    Client,
+    
+    /// NAS-Identifier, or NAS-IP-Address if the NAS sends no identifier
+    Nas,
+}
+
+/// Comparison operator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    /// Operator written as `symbol`
+    fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "==" => Some(Comparison::Equal),
+            "!=" => Some(Comparison::NotEqual),
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterEqual),
+            _ => None,
+        }
+    }
+    
+    /// Compare two values; numbers compare as numbers, text only for equality
+    fn holds(self, actual: &str, expected: &str) -> bool {
+        match (actual.parse::<i64>(), expected.parse::<i64>()) {
+            (Ok(actual), Ok(expected)) => match self {
+                Comparison::Equal => actual == expected,
+                Comparison::NotEqual => actual != expected,
+                Comparison::Less => actual < expected,
+                Comparison::LessEqual => actual <= expected,
+                Comparison::Greater => actual > expected,
+                Comparison::GreaterEqual => actual >= expected,
+            },
+            _ => match self {
+                Comparison::Equal => actual == expected,
+                Comparison::NotEqual => actual != expected,
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Condition of an `if`
+#[derive(Debug)]
+enum Condition {
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+    
+    /// The operand has a value
+    Exists(Operand),
+    
+    /// The operand compares to a literal; false if it has no value
+    Compare(Operand, Comparison, String),
+    
+    /// The operand matches a regular expression, whose groups become `$1`, `$2`, ...
+    Matches(Operand, Regex),
+    
+    /// The operand is an address inside a network
+    InNetwork(Operand, Network),
+    
+    /// The current time is inside a window such as "Mon-Fri 08:00-18:00"
+    Time(TimeWindow),
+    
+    /// The user is a member of a group (compared without regard to case)
+    Group(String),
+}
+
+impl Condition {
+    /// Whether the condition tests group membership
+    fn uses_groups(&self) -> bool {
+        match self {
+            Condition::Not(condition) => condition.uses_groups(),
+            Condition::And(left, right) | Condition::Or(left, right) => left.uses_groups() || right.uses_groups(),
+            Condition::Group(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// IP network such as 10.0.0.0/8 or 2001:db8::/32
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Network {
+    address: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    /// Parse a network; a bare address is a network of one
+    fn parse(text: &str) -> std::result::Result<Self, String> {
+        let (address, prefix) = text.split_once('/').map_or((text, None), |(address, prefix)| (address, Some(prefix)));
+        let address: IpAddr = address.parse().map_err(|_| format!("invalid network {}", text))?;
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().ok().filter(|prefix| *prefix <= max)
+                .ok_or_else(|| format!("invalid prefix length in {}", text))?,
+            None => max,
+        };
+        
+        Ok(Self { address, prefix })
+    }
+    
+    /// Whether an address is inside the network
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+/// Value given to `set` and `add`
+#[derive(Debug)]
+enum Value {
+    Integer(i32),
+    Address(IpAddr),
+    
+    /// Text, with `$1` and `%{Attribute}` expanded
+    Text(String),
+}
+
+/// Rule action
+#[derive(Debug)]
+enum Action {
+    /// Accept without asking a backend (pre-auth) or acknowledge accounting (pre-acct)
+    Accept,
+    
+    /// Reject, with an optional Reply-Message
+    Reject(Option<String>),
+    
+    /// Replace every occurrence of an attribute
+    Set(String, Value),
+    
+    /// Add an occurrence of an attribute
+    Add(String, Value),
+    
+    /// Remove every occurrence of an attribute
+    Remove(String),
+    
+    /// Replace the User-Name of the request
+    Rewrite(String),
+    
+    /// Authenticate with this backend only
+    Backend(String),
+    
+    /// Proxy to this realm
+    Realm(String),
+}
+
+impl Action {
+    /// Whether the action can be used in a hook
+    fn allowed(&self, hook: Hook) -> bool {
+        match self {
+            Action::Accept | Action::Rewrite(_) => hook != Hook::PostAuth,
+            Action::Backend(_) | Action::Realm(_) => hook == Hook::PreAuth,
+            _ => true,
+        }
+    }
+}
+
+/// State of one run of a hook
+struct Run<'a> {
+    request: &'a mut Packet,
+    reply: Option<&'a mut Packet>,
+    groups: &'a [String],
+    now: &'a DateTime<Local>,
+    
+    /// Groups of the last successful regular expression match
+    captures: Vec<String>,
+    
+    outcome: Outcome,
+}
+
+impl Run<'_> {
+    /// Execute statements until one accepts or rejects
+    ///
+    /// # Returns
+    ///
+    /// Whether a verdict was reached
+    fn execute(&mut self, statements: &[Statement]) -> bool {
+        for statement in statements {
+            let decided = match statement {
+                Statement::If { condition, then, otherwise } => {
+                    if self.holds(condition) {
+                        self.execute(then)
+                    } else {
+                        self.execute(otherwise)
+                    }
+                },
+                Statement::Action(action) => self.apply(action),
+            };
+            
+            if decided {
+                return true;
+            }
+        }
+        
+        false
+    }
+    
+    /// Evaluate a condition
+    fn holds(&mut self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Not(condition) => !self.holds(condition),
+            Condition::And(left, right) => self.holds(left) && self.holds(right),
+            Condition::Or(left, right) => self.holds(left) || self.holds(right),
+            Condition::Exists(operand) => self.value(operand).is_some(),
+            Condition::Compare(operand, comparison, expected) => self.value(operand)
+                .is_some_and(|actual| comparison.holds(&actual, expected)),
+            Condition::Matches(operand, regex) => {
+                let actual = match self.value(operand) {
+                    Some(actual) => actual,
+                    None => return false,
+                };
+                match regex.captures(&actual) {
+                    Some(captures) => {
+                        self.captures = captures.iter()
+                            .map(|group| group.map(|group| group.as_str().to_string()).unwrap_or_default())
+                            .collect();
+                        true
+                    },
+                    None => false,
+                }
+            },
+            Condition::InNetwork(operand, network) => self.value(operand)
+                .and_then(|actual| actual.parse().ok())
+                .is_some_and(|address| network.contains(address)),
+            Condition::Time(window) => window.contains(self.now),
+            Condition::Group(name) => self.groups.iter().any(|group| group.eq_ignore_ascii_case(name)),
+        }
+    }
+    
+    /// Get the value of an operand as text
+    fn value(&self, operand: &Operand) -> Option<String> {
+        match operand {
+            Operand::Request(name) => self.request.get_attribute(name).and_then(text),
+            Operand::Reply(name) => self.reply.as_ref()?.get_attribute(name).and_then(text),
+            Operand::Client => self.request.source().map(|source| source.ip().to_string()),
+            Operand::Nas => self.request.get_text("NAS-Identifier")
+                .or_else(|| self.request.get_attribute("NAS-IP-Address").and_then(text)),
+        }
+    }
+    
+    /// Apply an action
+    ///
+    /// # Returns
+    ///
+    /// Whether the action is a verdict
+    fn apply(&mut self, action: &Action) -> bool {
+        match action {
+            Action::Accept => {
+                self.outcome.verdict = Verdict::Accept;
+                return true;
+            },
+            Action::Reject(reason) => {
+                let reason = reason.as_deref().map_or(DEFAULT_REJECT_REASON.to_string(), |reason| self.expand(reason));
+                self.outcome.verdict = Verdict::Reject(reason);
+                return true;
+            },
+            Action::Set(name, value) => {
+                let attribute = self.attribute(name, value);
+                let target = self.target();
+                target.remove_attributes(name);
+                target.add_attribute(attribute);
+            },
+            Action::Add(name, value) => {
+                let attribute = self.attribute(name, value);
+                self.target().add_attribute(attribute);
+            },
+            Action::Remove(name) => self.target().remove_attributes(name),
+            Action::Rewrite(template) => {
+                let username = self.expand(template);
+                tracing::debug!(username = username, "Policy rewrote User-Name");
+                self.request.remove_attributes("User-Name");
+                self.request.add_attribute(Attribute::String("User-Name".to_string(), username));
+            },
+            Action::Backend(name) => self.outcome.backend = Some(name.clone()),
+            Action::Realm(name) => self.outcome.realm = Some(name.clone()),
+        }
+        
+        false
+    }
+    
+    /// Packet that attribute actions edit
+    fn target(&mut self) -> &mut Packet {
+        match &mut self.reply {
+            Some(reply) => reply,
+            None => self.request,
+        }
+    }
+    
+    /// Build the attribute of a `set` or `add`
+    fn attribute(&self, name: &str, value: &Value) -> Attribute {
+        match value {
+            Value::Integer(value) => Attribute::Integer(name.to_string(), *value),
+            Value::Address(value) => Attribute::IpAddr(name.to_string(), *value),
+            Value::Text(template) => Attribute::String(name.to_string(), self.expand(template)),
+        }
+    }
+    
+    /// Expand `$0` to `$9` with the groups of the last match and `%{Name}` with request attributes
+    fn expand(&self, template: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = template;
+        
+        while let Some(position) = rest.find(['$', '%']) {
+            expanded.push_str(&rest[..position]);
+            let tail = &rest[position..];
+            
+            if let Some(index) = tail.strip_prefix('$').and_then(|tail| tail.chars().next()).and_then(|c| c.to_digit(10)) {
+                expanded.push_str(self.captures.get(index as usize).map_or("", String::as_str));
+                rest = &tail[2..];
+            } else if let Some((name, after)) = tail.strip_prefix("%{").and_then(|tail| tail.split_once('}')) {
+                expanded.push_str(&self.request.get_attribute(name).and_then(text).unwrap_or_default());
+                rest = after;
+            } else {
+                expanded.push_str(&tail[..1]);
+                rest = &tail[1..];
+            }
+        }
+        
+        expanded.push_str(rest);
+        expanded
+    }
+}
+
+/// Text of an attribute value, for comparisons and templates
+fn text(attribute: &Attribute) -> Option<String> {
+    match attribute {
+        Attribute::String(_, value) => Some(value.clone()),
+        Attribute::Binary(_, value) => Some(String::from_utf8_lossy(value).to_string()),
+        Attribute::Integer(_, value) => Some(value.to_string()),
+        Attribute::IpAddr(_, value) => Some(value.to_string()),
+        Attribute::Ipv6Addr(_, value) => Some(value.to_string()),
+        Attribute::Ipv6Prefix(_, value, len) => Some(format!("{}/{}", value, len)),
+        Attribute::VendorSpecific(..) => None,
+    }
+}
+
+/// Token of a rules file
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// Keyword, attribute name, number, address or network
+    Word(String),
+    
+    /// Quoted string
+    Text(String),
+    
+    /// Operator or brace
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Word(word) => f.write_str(word),
+            Token::Text(text) => write!(f, "{:?}", text),
+            Token::Symbol(symbol) => f.write_str(symbol),
+        }
+    }
+}
+
+/// Operators and braces, longest first
+const SYMBOLS: [&str; 13] = ["==", "!=", "=~", "!~", "<=", ">=", "{", "}", "(", ")", "<", ">", "="];
+
+/// Whether a character can be part of a word
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "-_.:/".contains(c)
+}
+
+/// Split a rules file into tokens with their line numbers
+///
+/// `#` starts a comment. In quoted strings `\"` and `\\` are escapes; any other
+/// backslash is kept, so regular expressions need no double escaping.
+fn tokenize(text: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut line = 1;
+    let mut rest = text;
+    
+    while let Some(c) = rest.chars().next() {
+        if c == '\n' {
+            line += 1;
+            rest = &rest[1..];
+        } else if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        } else if c == '#' {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if c == '"' {
+            let mut value = String::new();
+            let mut chars = rest[1..].char_indices();
+            let end = loop {
+                match chars.next() {
+                    Some((index, '"')) => break index + 2,
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped @ ('"' | '\\'))) => value.push(escaped),
+                        Some((_, other)) if other != '\n' => {
+                            value.push('\\');
+                            value.push(other);
+                        },
+                        _ => return Err(format!("line {}: unterminated string", line).into()),
+                    },
+                    Some((_, '\n')) | None => return Err(format!("line {}: unterminated string", line).into()),
+                    Some((_, other)) => value.push(other),
+                }
+            };
+            
+            tokens.push((Token::Text(value), line));
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push((Token::Symbol(symbol), line));
+            rest = &rest[symbol.len()..];
+        } else if is_word_char(c) {
+            let end = rest.find(|c| !is_word_char(c)).unwrap_or(rest.len());
+            tokens.push((Token::Word(rest[..end].to_string()), line));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("line {}: unexpected character {:?}", line, c).into());
+        }
+    }
+    
+    Ok(tokens)
+}
+
+/// Recursive descent parser for rules
+///
+/// ```text
+/// file      = { hook block }
+/// block     = "{" { statement } "}"
+/// statement = "if" condition block [ "else" ( "if" ... | block ) ] | action
+/// condition = and { "or" and }
+/// and       = unary { "and" unary }
+/// unary     = "not" unary | "(" condition ")" | test
+/// test      = "time" STRING | "group" STRING
+///           | operand [ ( "==" | "!=" | "<" | "<=" | ">" | ">=" ) literal
+///                     | ( "=~" | "!~" ) STRING | "in" NETWORK ]
+/// operand   = "client" | "nas" | ATTRIBUTE | "reply:" ATTRIBUTE
+/// action    = "accept" | "reject" [ STRING ] | "set" ATTRIBUTE "=" value
+///           | "add" ATTRIBUTE "=" value | "remove" ATTRIBUTE
+///           | "rewrite" STRING | "backend" STRING | "realm" STRING
+/// ```
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    
+    /// Hook whose section is being parsed
+    hook: Hook,
+    
+    /// Current nesting depth
+    depth: usize,
+}
+
+impl Parser {
+    /// Error at the line of the last token read
+    fn error(&self, message: impl fmt::Display) -> Box<dyn std::error::Error + Send + Sync> {
+        let line = self.tokens.get(self.position.saturating_sub(1)).map_or(1, |(_, line)| *line);
+        format!("line {}: {}", line, message).into()
+    }
+    
+    /// Look at the next token
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+    
+    /// Read the next token
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("unexpected end of file"))?;
+        self.position += 1;
+        Ok(token)
+    }
+    
+    /// Read a word
+    fn word(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Word(word) => Ok(word),
+            other => Err(self.error(format!("expected a name, found {}", other))),
+        }
+    }
+    
+    /// Read a quoted string
+    fn text(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Text(text) => Ok(text),
+            other => Err(self.error(format!("expected a quoted string, found {}", other))),
+        }
+    }
+    
+    /// Read a symbol
+    fn expect(&mut self, symbol: &str) -> Result<()> {
+        match self.next()? {
+            Token::Symbol(found) if found == symbol => Ok(()),
+            other => Err(self.error(format!("expected {}, found {}", symbol, other))),
+        }
+    }
+    
+    /// Skip a keyword if it comes next
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(found)) if found == word);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+    
+    /// Skip a symbol if it comes next
+    fn eat_symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(found)) if *found == symbol);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+    
+    /// Go one level deeper
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("rules are nested too deeply"));
+        }
+        Ok(())
+    }
+    
+    /// Parse a block of statements
+    fn block(&mut self) -> Result<Vec<Statement>> {
+        self.expect("{")?;
+        self.enter()?;
+        
+        let mut statements = Vec::new();
+        while !self.eat_symbol("}") {
+            statements.push(self.statement()?);
+        }
+        
+        self.depth -= 1;
+        Ok(statements)
+    }
+    
+    /// Parse a statement
+    fn statement(&mut self) -> Result<Statement> {
+        let word = self.word()?;
+        if word == "if" {
+            return self.conditional();
+        }
+        
+        let action = match word.as_str() {
+            "accept" => Action::Accept,
+            "reject" => match self.peek() {
+                Some(Token::Text(_)) => Action::Reject(Some(self.text()?)),
+                _ => Action::Reject(None),
+            },
+            "set" | "add" => {
+                let name = self.attribute()?;
+                self.expect("=")?;
+                let value = self.value()?;
+                if word == "set" {
+                    Action::Set(name, value)
+                } else {
+                    Action::Add(name, value)
+                }
+            },
+            "remove" => Action::Remove(self.attribute()?),
+            "rewrite" => Action::Rewrite(self.text()?),
+            "backend" => Action::Backend(self.text()?),
+            "realm" => Action::Realm(self.text()?),
+            other => return Err(self.error(format!("unknown action {}", other))),
+        };
+        
+        if !action.allowed(self.hook) {
+            return Err(self.error(format!("{} cannot be used in {}", word, self.hook)));
+        }
+        Ok(Statement::Action(action))
+    }
+    
+    /// Parse the rest of an `if`
+    fn conditional(&mut self) -> Result<Statement> {
+        self.enter()?;
+        let condition = self.condition()?;
+        let then = self.block()?;
+        let otherwise = if !self.eat_word("else") {
+            vec![]
+        } else if self.eat_word("if") {
+            vec![self.conditional()?]
+        } else {
+            self.block()?
+        };
+        
+        self.depth -= 1;
+        Ok(Statement::If { condition, then, otherwise })
+    }
+    
+    /// Parse a condition: conjunctions joined by `or`
+    fn condition(&mut self) -> Result<Condition> {
+        let mut condition = self.conjunction()?;
+        while self.eat_word("or") {
+            condition = Condition::Or(Box::new(condition), Box::new(self.conjunction()?));
+        }
+        Ok(condition)
+    }
+    
+    /// Parse tests joined by `and`
+    fn conjunction(&mut self) -> Result<Condition> {
+        let mut condition = self.unary()?;
+        while self.eat_word("and") {
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+    
+    /// Parse a negation, a parenthesized condition or a test
+    fn unary(&mut self) -> Result<Condition> {
+        if self.eat_word("not") {
+            self.enter()?;
+            let condition = Condition::Not(Box::new(self.unary()?));
+            self.depth -= 1;
+            return Ok(condition);
+        }
+        
+        if self.eat_symbol("(") {
+            self.enter()?;
+            let condition = self.condition()?;
+            self.expect(")")?;
+            self.depth -= 1;
+            return Ok(condition);
+        }
+        
+        self.test()
+    }
+    
+    /// Parse a single test
+    fn test(&mut self) -> Result<Condition> {
+        let word = self.word()?;
+        let operand = match word.as_str() {
+            "time" => {
+                let window = self.text()?;
+                return TimeWindow::parse(&window).map(Condition::Time).map_err(|e| self.error(e));
+            },
+            "group" => return Ok(Condition::Group(self.text()?)),
+            "client" => Operand::Client,
+            "nas" => Operand::Nas,
+            name => match name.strip_prefix("reply:") {
+                Some(_) if self.hook != Hook::PostAuth => {
+                    return Err(self.error("reply attributes can only be tested in post-auth"));
+                },
+                Some(name) => Operand::Reply(name.to_string()),
+                None => Operand::Request(name.to_string()),
+            },
+        };
+        
+        if self.eat_word("in") {
+            let network = self.word()?;
+            let network = Network::parse(&network).map_err(|e| self.error(e))?;
+            return Ok(Condition::InNetwork(operand, network));
+        }
+        
+        let symbol = match self.peek() {
+            Some(Token::Symbol(symbol)) => *symbol,
+            _ => return Ok(Condition::Exists(operand)),
+        };
+        
+        if symbol == "=~" || symbol == "!~" {
+            self.position += 1;
+            let pattern = self.text()?;
+            let regex = RegexBuilder::new(&pattern)
+                .size_limit(MAX_REGEX_SIZE)
+                .build()
+                .map_err(|e| self.error(format!("invalid regular expression: {}", e)))?;
+            
+            let condition = Condition::Matches(operand, regex);
+            return Ok(if symbol == "!~" { Condition::Not(Box::new(condition)) } else { condition });
+        }
+        
+        match Comparison::from_symbol(symbol) {
+            Some(comparison) => {
+                self.position += 1;
+                let expected = match self.next()? {
+                    Token::Word(word) | Token::Text(word) => word,
+                    other => return Err(self.error(format!("expected a value, found {}", other))),
+                };
+                Ok(Condition::Compare(operand, comparison, expected))
+            },
+            None => Ok(Condition::Exists(operand)),
+        }
+    }
+    
+    /// Read an attribute name for an action
+    fn attribute(&mut self) -> Result<String> {
+        let name = self.word()?;
+        if name.contains(':') || !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(self.error(format!("invalid attribute name {}", name)));
+        }
+        Ok(name)
+    }
+    
+    /// Read the value of a `set` or `add`
+    fn value(&mut self) -> Result<Value> {
+        match self.next()? {
+            Token::Text(text) => Ok(Value::Text(text)),
+            Token::Word(word) => {
+                if let Ok(number) = word.parse() {
+                    Ok(Value::Integer(number))
+                } else if let Ok(address) = word.parse() {
+                    Ok(Value::Address(address))
+                } else {
+                    Err(self.error(format!("expected a quoted string, number or address, found {}", word)))
+                }
+            },
+            other => Err(self.error(format!("expected a value, found {}", other))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    
+    const RULES: &str = r#"
+        # Strip the corporate realm, send lobby guests to the vouchers
+        pre-auth {
+            if User-Name =~ "^([^@]+)@corp\.example\.com$" {
+                rewrite "$1"
+            }
+            if nas == "lobby-ap" and not group "staff" {
+                backend "vouchers"
+            } else if client in 10.20.0.0/16 and not time "Mon-Fri 08:00-18:00" {
+                reject "Outside office hours"
+            }
+            if User-Name =~ "@partner\.example$" { realm "partner.example" }
+        }
+        post-auth {
+            if group "contractors" or reply:Session-Timeout > 28800 {
+                set Session-Timeout = 3600
+                add Reply-Message = "Hello %{User-Name}"
+                remove Class
+            }
+        }
+        pre-acct {
+            if not Acct-Session-Id { reject }
+        }
+    "#;
+    
+    fn request(attributes: &[(&str, &str)], client: &str) -> Packet {
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0; 16]);
+        request.set_source(format!("{}:1812", client).parse().unwrap());
+        for (name, value) in attributes {
+            request.add_attribute(Attribute::String(name.to_string(), value.to_string()));
+        }
+        request
+    }
+    
+    #[test]
+    fn rules_edit_and_route() {
+        let policy = Policy::parse(RULES).unwrap();
+        let tuesday_night = Local.with_ymd_and_hms(2024, 3, 5, 23, 0, 0).unwrap();
+        let tuesday_noon = Local.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap();
+        assert!(policy.uses_groups(Hook::PreAuth) && !policy.uses_groups(Hook::PreAcct));
+        
+        // The realm is stripped and lobby guests are routed
+        let mut guest = request(&[("User-Name", "alice@corp.example.com"), ("NAS-Identifier", "lobby-ap")], "192.0.2.1");
+        let outcome = policy.run(Hook::PreAuth, &mut guest, None, &[], &tuesday_night);
+        assert_eq!(outcome, Outcome { backend: Some("vouchers".to_string()), ..Default::default() });
+        assert_eq!(guest.get_text("User-Name").as_deref(), Some("alice"));
+        
+        // Staff in the office network are only let in during office hours
+        let mut staff = request(&[("User-Name", "bob"), ("NAS-Identifier", "lobby-ap")], "10.20.1.1");
+        let groups = vec!["Staff".to_string()];
+        assert_eq!(policy.run(Hook::PreAuth, &mut staff, None, &groups, &tuesday_night).verdict, Verdict::Reject("Outside office hours".to_string()));
+        assert_eq!(policy.run(Hook::PreAuth, &mut staff, None, &groups, &tuesday_noon), Outcome::default());
+        
+        let mut partner = request(&[("User-Name", "carol@partner.example")], "192.0.2.1");
+        assert_eq!(policy.run(Hook::PreAuth, &mut partner, None, &[], &tuesday_noon).realm.as_deref(), Some("partner.example"));
+        
+        // Post-auth edits the reply
+        let mut reply = staff.create_response(Packet::ACCESS_ACCEPT);
+        reply.add_attribute(Attribute::Integer("Session-Timeout".to_string(), 86400));
+        reply.add_attribute(Attribute::String("Class".to_string(), "full".to_string()));
+        assert_eq!(policy.run(Hook::PostAuth, &mut staff, Some(&mut reply), &[], &tuesday_noon), Outcome::default());
+        assert_eq!(reply.attributes(), &[
+            Attribute::Integer("Session-Timeout".to_string(), 3600),
+            Attribute::String("Reply-Message".to_string(), "Hello bob".to_string()),
+        ]);
+        
+        let mut accounting = request(&[("User-Name", "bob")], "10.20.1.1");
+        assert_eq!(policy.run(Hook::PreAcct, &mut accounting, None, &[], &tuesday_noon).verdict, Verdict::Reject(DEFAULT_REJECT_REASON.to_string()));
+    }
+    
+    #[test]
+    fn mistakes_are_reported_by_line() {
+        let error = |rules: &str| Policy::parse(rules).unwrap_err().to_string();
+        
+        assert_eq!(error("pre-auth {\n  accept\n}\npost-auth {\n  accept\n}"), "line 5: accept cannot be used in post-auth");
+        assert_eq!(error("on-auth { }"), "line 1: Unknown hook on-auth, expected pre-auth, post-auth or pre-acct");
+        assert_eq!(error("pre-auth {\n  if User-Name == \"bob { reject }\n}"), "line 2: unterminated string");
+        assert_eq!(error("pre-acct {\n  if reply:Class { reject }\n}"), "line 2: reply attributes can only be tested in post-auth");
+        assert_eq!(error("pre-auth {\n  if client in 10.0.0.0/33 { reject }\n}"), "line 2: invalid prefix length in 10.0.0.0/33");
+        assert!(error("pre-auth { if User-Name =~ \"(\" { reject } }").starts_with("line 1: invalid regular expression"));
+        assert!(error(&format!("pre-auth {{ if {} User-Name {{ }} }}", "not ".repeat(40))).ends_with("nested too deeply"));
+        assert_eq!(error("pre-auth {\n  set User-Name \"bob\"\n}"), "line 2: expected =, found \"bob\"");
+    }
+}
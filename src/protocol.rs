@@ -3,11 +3,13 @@
 // This module handles the RADIUS protocol implementation, including
 // packet parsing, attribute handling, and protocol-specific logic.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
 
 use bytes::{Bytes, BytesMut};
+use serde::{Deserialize, Serialize};
 // We'll use a simple implementation instead of ring for now
 
 use crate::config::Config;
@@ -80,10 +82,17 @@ impl PacketCode {
             _ => None,
         }
     }
+    
+    /// Check whether the code is that of a response, signed with a Response Authenticator
+    pub fn is_response(self) -> bool {
+        matches!(self,
+            Self::AccessAccept | Self::AccessReject | Self::AccessChallenge | Self::AccountingResponse
+            | Self::DisconnectAck | Self::DisconnectNak | Self::CoaAck | Self::CoaNak)
+    }
 }
 
 /// RADIUS attribute types
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Attribute {
     /// String attribute
     String(String, String),
@@ -134,8 +143,8 @@ pub struct Packet {
     /// Authenticator (16 bytes)
     authenticator: [u8; 16],
     
-    /// Packet attributes
-    attributes: HashMap<String, Attribute>,
+    /// Packet attributes, in wire order (an attribute may repeat)
+    attributes: Vec<Attribute>,
     
     /// Raw packet data
     raw_data: Option<Bytes>,
@@ -157,6 +166,30 @@ impl Packet {
     /// Access-Challenge packet code
     pub const ACCESS_CHALLENGE: PacketCode = PacketCode::AccessChallenge;
     
+    /// Accounting-Request packet code
+    pub const ACCOUNTING_REQUEST: PacketCode = PacketCode::AccountingRequest;
+    
+    /// Accounting-Response packet code
+    pub const ACCOUNTING_RESPONSE: PacketCode = PacketCode::AccountingResponse;
+    
+    /// Disconnect-Request packet code
+    pub const DISCONNECT_REQUEST: PacketCode = PacketCode::DisconnectRequest;
+    
+    /// Disconnect-ACK packet code
+    pub const DISCONNECT_ACK: PacketCode = PacketCode::DisconnectAck;
+    
+    /// Disconnect-NAK packet code
+    pub const DISCONNECT_NAK: PacketCode = PacketCode::DisconnectNak;
+    
+    /// CoA-Request packet code
+    pub const COA_REQUEST: PacketCode = PacketCode::CoaRequest;
+    
+    /// CoA-ACK packet code
+    pub const COA_ACK: PacketCode = PacketCode::CoaAck;
+    
+    /// CoA-NAK packet code
+    pub const COA_NAK: PacketCode = PacketCode::CoaNak;
+
     /// Create a new RADIUS packet
     ///
     /// # Arguments
@@ -173,7 +206,7 @@ impl Packet {
             code,
             identifier,
             authenticator,
-            attributes: HashMap::new(),
+            attributes: Vec::new(),
             raw_data: None,
             source: None,
         }
@@ -181,6 +214,9 @@ impl Packet {
     
     /// Create a response packet for a request
     ///
+    /// The response holds the Request Authenticator, which `PacketProcessor::encode`
+    /// signs it with and replaces with the Response Authenticator.
+    ///
     /// # Arguments
     ///
     /// * `code` - Response packet code
@@ -193,7 +229,7 @@ impl Packet {
             code,
             identifier: self.identifier,
             authenticator: self.authenticator,
-            attributes: HashMap::new(),
+            attributes: Vec::new(),
             raw_data: None,
             source: self.source,
         }
@@ -205,11 +241,22 @@ impl Packet {
     ///
     /// * `attribute` - Attribute to add
     pub fn add_attribute(&mut self, attribute: Attribute) {
-        self.attributes.insert(attribute.name().to_string(), attribute);
+        self.attributes.push(attribute);
+    }
+    
+    /// Remove every occurrence of an attribute from the packet
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Attribute name
+    pub fn remove_attributes(&mut self, name: &str) {
+        self.attributes.retain(|attr| attr.name() != name);
     }
     
     /// Get an attribute from the packet
     ///
+    /// If the attribute occurs more than once, the first occurrence is returned.
+    ///
     /// # Arguments
     ///
     /// * `name` - Attribute name
@@ -218,7 +265,69 @@ impl Packet {
     ///
     /// Attribute if present, None otherwise
     pub fn get_attribute(&self, name: &str) -> Option<&Attribute> {
-        self.attributes.get(name)
+        self.attributes.iter().find(|attr| attr.name() == name)
+    }
+    
+    /// Get every occurrence of an attribute, in packet order
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Attribute name
+    pub fn get_attributes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Attribute> + 'a {
+        self.attributes.iter().filter(move |attr| attr.name() == name)
+    }
+    
+    /// Get a text attribute from the packet
+    ///
+    /// Attributes without a dedicated parser arrive as binary values, so both
+    /// string and binary attributes are returned as (lossy) UTF-8.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Attribute name
+    pub fn get_text(&self, name: &str) -> Option<String> {
+        match self.get_attribute(name)? {
+            Attribute::String(_, value) => Some(value.clone()),
+            Attribute::Binary(_, value) => Some(String::from_utf8_lossy(value).to_string()),
+            _ => None,
+        }
+    }
+    
+    /// Get an integer attribute from the packet
+    ///
+    /// Attributes without a dedicated parser arrive as binary values, so a
+    /// 4-byte binary value is read as a big-endian integer.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Attribute name
+    pub fn get_integer(&self, name: &str) -> Option<u32> {
+        match self.get_attribute(name)? {
+            Attribute::Integer(_, value) => Some(*value as u32),
+            Attribute::Binary(_, value) => Some(u32::from_be_bytes(value.as_slice().try_into().ok()?)),
+            _ => None,
+        }
+    }
+    
+    /// Get a vendor-specific sub-attribute from the packet
+    ///
+    /// # Arguments
+    ///
+    /// * `vendor_id` - IANA enterprise number of the vendor
+    /// * `name` - Sub-attribute name
+    pub fn get_vendor_attribute(&self, vendor_id: u32, name: &str) -> Option<&Attribute> {
+        self.attributes.iter()
+            .filter_map(|attr| match attr {
+                Attribute::VendorSpecific(id, attrs) if *id == vendor_id => Some(attrs),
+                _ => None,
+            })
+            .flatten()
+            .find(|attr| attr.name() == name)
+    }
+    
+    /// Get all attributes in packet order
+    pub fn attributes(&self) -> &[Attribute] {
+        &self.attributes
     }
     
     /// Get the packet code
@@ -260,6 +369,21 @@ pub struct PacketProcessor {
     dictionary: RadiusDictionary,
 }
 
+/// IANA enterprise number for Microsoft vendor-specific attributes
+pub const VENDOR_MICROSOFT: u32 = 311;
+
+/// IANA enterprise number for Cisco vendor-specific attributes
+pub const VENDOR_CISCO: u32 = 9;
+
+/// IANA enterprise number for Aruba vendor-specific attributes
+pub const VENDOR_ARUBA: u32 = 14823;
+
+/// IANA enterprise number for WISPr vendor-specific attributes
+pub const VENDOR_WISPR: u32 = 14122;
+
+/// IANA enterprise number for Mikrotik vendor-specific attributes
+pub const VENDOR_MIKROTIK: u32 = 14988;
+
 /// RADIUS dictionary for mapping attribute names to codes
 struct RadiusDictionary {
     /// Attribute name to code mapping
@@ -318,12 +442,34 @@ impl Default for RadiusDictionary {
             ("Framed-AppleTalk-Link", 37),
             ("Framed-AppleTalk-Network", 38),
             ("Framed-AppleTalk-Zone", 39),
+            ("Acct-Status-Type", 40),
+            ("Acct-Delay-Time", 41),
+            ("Acct-Input-Octets", 42),
+            ("Acct-Output-Octets", 43),
+            ("Acct-Session-Id", 44),
+            ("Acct-Authentic", 45),
+            ("Acct-Session-Time", 46),
+            ("Acct-Input-Packets", 47),
+            ("Acct-Output-Packets", 48),
+            ("Acct-Terminate-Cause", 49),
+            ("Acct-Multi-Session-Id", 50),
+            ("Acct-Link-Count", 51),
+            ("Acct-Input-Gigawords", 52),
+            ("Acct-Output-Gigawords", 53),
+            ("Event-Timestamp", 55),
             ("CHAP-Challenge", 60),
             ("NAS-Port-Type", 61),
             ("Port-Limit", 62),
             ("Login-LAT-Port", 63),
+            ("Tunnel-Type", 64),
+            ("Tunnel-Medium-Type", 65),
             ("Connect-Info", 77),
+            ("EAP-Message", 79),
             ("Message-Authenticator", 80),
+            ("Tunnel-Private-Group-Id", 81),
+            ("Acct-Interim-Interval", 85),
+            ("NAS-Port-Id", 87),
+            ("Error-Cause", 101),
         ];
         
         for (name, code) in standard_attributes.iter() {
@@ -331,9 +477,45 @@ impl Default for RadiusDictionary {
             attribute_names.insert(*code, name.to_string());
         }
         
-        // Vendor-specific attributes would be defined here
-        let vendor_attributes = HashMap::new();
+        // Vendor-specific attributes
+        let mut vendor_attributes = HashMap::new();
+        
+        // Microsoft (RFC 2548)
+        let microsoft_attributes = [
+            ("MS-CHAP-Response", 1),
+            ("MS-CHAP-Error", 2),
+            ("MS-MPPE-Encryption-Policy", 7),
+            ("MS-MPPE-Encryption-Types", 8),
+            ("MS-CHAP-Challenge", 11),
+            ("MS-CHAP-MPPE-Keys", 12),
+            ("MS-MPPE-Send-Key", 16),
+            ("MS-MPPE-Recv-Key", 17),
+            ("MS-CHAP2-Response", 25),
+            ("MS-CHAP2-Success", 26),
+        ];
+        vendor_attributes.insert(VENDOR_MICROSOFT, microsoft_attributes.iter()
+            .map(|(name, code)| (*code, name.to_string()))
+            .collect());
         
+        // NAS vendors, for guest redirects and role assignment
+        let nas_attributes: [(u32, &[(&str, u8)]); 4] = [
+            (VENDOR_CISCO, &[("Cisco-AVPair", 1)]),
+            (VENDOR_ARUBA, &[("Aruba-User-Role", 1)]),
+            (VENDOR_WISPR, &[
+                ("WISPr-Redirection-URL", 4),
+                ("WISPr-Bandwidth-Min-Up", 5),
+                ("WISPr-Bandwidth-Min-Down", 6),
+                ("WISPr-Bandwidth-Max-Up", 7),
+                ("WISPr-Bandwidth-Max-Down", 8),
+            ]),
+            (VENDOR_MIKROTIK, &[("Mikrotik-Rate-Limit", 8), ("Mikrotik-Address-List", 19)]),
+        ];
+        for (vendor_id, names) in nas_attributes {
+            vendor_attributes.insert(vendor_id, names.iter()
+                .map(|(name, code)| (*code, name.to_string()))
+                .collect());
+        }
+
         Self {
             attributes,
             attribute_names,
@@ -419,6 +601,18 @@ impl PacketProcessor {
             return Err("Missing Message-Authenticator attribute".into());
         }
         
+        // A request with a wrong Message-Authenticator is silently discarded (RFC 3579, section 3.2)
+        if code == PacketCode::AccessRequest
+            && packet.get_attribute("Message-Authenticator").is_some()
+            && !self.verify_message_authenticator(&packet, &self.config.server.secret) {
+            return Err("Invalid Message-Authenticator attribute".into());
+        }
+        
+        // Accounting from a client without the secret is silently discarded (RFC 2866, section 3)
+        if code == PacketCode::AccountingRequest && !verify_request(self.config.server.secret.as_bytes(), &data[..length]) {
+            return Err("Invalid Accounting-Request authenticator".into());
+        }
+        
         Ok(packet)
     }
     
@@ -463,9 +657,12 @@ impl PacketProcessor {
                     let username = String::from_utf8_lossy(value).to_string();
                     packet.add_attribute(Attribute::String("User-Name".to_string(), username));
                 },
-                2 => { // User-Password (encrypted)
-                    // In a real implementation, we would decrypt the password here
-                    let password = String::from_utf8_lossy(value).to_string();
+                2 => { // User-Password, hidden with the shared secret (RFC 2865, section 5.2)
+                    if value.is_empty() || value.len() > 128 || !value.len().is_multiple_of(16) {
+                        return Err(format!("Invalid User-Password length: {}", value.len()).into());
+                    }
+                    let plain = decrypt_password(self.config.server.secret.as_bytes(), &packet.authenticator, value);
+                    let password = String::from_utf8_lossy(&plain).to_string();
                     packet.add_attribute(Attribute::String("User-Password".to_string(), password));
                 },
                 18 => { // Reply-Message
@@ -479,11 +676,9 @@ impl PacketProcessor {
                     }
                     
                     let vendor_id = u32::from_be_bytes([value[0], value[1], value[2], value[3]]);
-                    let _vendor_data = &value[4..];
+                    let attrs = self.parse_vendor_attributes(vendor_id, &value[4..])?;
                     
-                    // In a real implementation, we would parse vendor-specific attributes here
-                    // For now, just add the raw vendor-specific attribute
-                    packet.add_attribute(Attribute::VendorSpecific(vendor_id, vec![]));
+                    packet.add_attribute(Attribute::VendorSpecific(vendor_id, attrs));
                 },
                 80 => { // Message-Authenticator
                     packet.add_attribute(Attribute::Binary("Message-Authenticator".to_string(), value.to_vec()));
@@ -491,7 +686,7 @@ impl PacketProcessor {
                 _ => {
                     // Look up attribute name
                     let attr_name = self.dictionary.attribute_names.get(&attr_type)
-                        .map(|s| s.clone())
+                        .cloned()
                         .unwrap_or_else(|| format!("Unknown-{}", attr_type));
                     
                     // Add as binary attribute
@@ -505,6 +700,38 @@ impl PacketProcessor {
         Ok(())
     }
     
+    /// Parse the sub-attributes of a Vendor-Specific attribute
+    ///
+    /// Sub-attributes use the RFC 2865 suggested format (1-byte type, 1-byte length).
+    /// Unknown sub-attributes are kept as binary values.
+    fn parse_vendor_attributes(&self, vendor_id: u32, data: &[u8]) -> Result<Vec<Attribute>> {
+        let mut attrs = Vec::new();
+        let mut offset = 0;
+        
+        while offset < data.len() {
+            if offset + 2 > data.len() {
+                return Err("Incomplete vendor-specific sub-attribute".into());
+            }
+            
+            let vendor_type = data[offset];
+            let length = data[offset + 1] as usize;
+            
+            if length < 2 || offset + length > data.len() {
+                return Err(format!("Invalid vendor-specific sub-attribute length: {}", length).into());
+            }
+            
+            let name = self.dictionary.vendor_attributes.get(&vendor_id)
+                .and_then(|names| names.get(&vendor_type))
+                .cloned()
+                .unwrap_or_else(|| format!("Unknown-{}-{}", vendor_id, vendor_type));
+            
+            attrs.push(Attribute::Binary(name, data[offset + 2..offset + length].to_vec()));
+            offset += length;
+        }
+        
+        Ok(attrs)
+    }
+    
     /// Encode a RADIUS packet to bytes
     ///
     /// # Arguments
@@ -519,13 +746,59 @@ impl PacketProcessor {
     ///
     /// Returns an error if the packet cannot be encoded
     pub fn encode(&self, packet: &Packet) -> Result<Vec<u8>> {
+        let mut data = self.encode_unsigned(packet)?;
+        let secret = self.config.server.secret.as_bytes();
+        
+        // The Message-Authenticator is computed first, as the Response Authenticator covers it
+        if let Some(offset) = message_authenticator_offset(&data) {
+            let value = message_authenticator(secret, &data);
+            data[offset..offset + 16].copy_from_slice(&value);
+        }
+        if packet.code.is_response() {
+            let value = response_authenticator(secret, &data);
+            data[4..20].copy_from_slice(&value);
+        }
+        
+        Ok(data)
+    }
+    
+    /// Encode a packet as it is, with a zero Message-Authenticator and the authenticator it holds
+    ///
+    /// Responses to Access-Requests get a Message-Authenticator, as every EAP
+    /// reply needs one (RFC 3579, section 3.2) and NASes may insist on it for others.
+    fn encode_unsigned(&self, packet: &Packet) -> Result<Vec<u8>> {
         // GOAL: High-Performance and Concurrency
         // Efficient packet encoding with minimal allocations
         
+        // Reply attributes may name vendor attributes directly (Mikrotik-Rate-Limit = "10M/10M")
+        let mut attributes: Vec<Cow<'_, Attribute>> = packet.attributes.iter()
+            .map(|attr| match self.wrap_vendor_attribute(attr) {
+                Some(wrapped) => Cow::Owned(wrapped),
+                None => Cow::Borrowed(attr),
+            })
+            .collect();
+        
+        // User-Password goes on the wire hidden
+        if packet.code == PacketCode::AccessRequest {
+            for attr in attributes.iter_mut() {
+                if let Attribute::String(name, password) = attr.as_ref() {
+                    if name == "User-Password" {
+                        let hidden = encrypt_password(self.config.server.secret.as_bytes(), &packet.authenticator, password.as_bytes());
+                        *attr = Cow::Owned(Attribute::Binary(name.clone(), hidden));
+                    }
+                }
+            }
+        }
+        
+        let access_response = matches!(packet.code, PacketCode::AccessAccept | PacketCode::AccessReject | PacketCode::AccessChallenge);
+        if access_response && packet.get_attribute("Message-Authenticator").is_none() {
+            attributes.insert(0, Cow::Owned(Attribute::Binary("Message-Authenticator".to_string(), vec![0; 16])));
+        }
+        
         // Calculate packet size
         let mut size = 20; // Header size
         
-        for attr in packet.attributes.values() {
+        for attr in &attributes {
             size += self.calculate_attribute_size(attr);
         }
         
@@ -542,15 +815,36 @@ impl PacketProcessor {
         buffer.extend_from_slice(&(size as u16).to_be_bytes());
         buffer.extend_from_slice(&packet.authenticator);
         
-        // Write attributes
-        for attr in packet.attributes.values() {
-            self.encode_attribute(&mut buffer, attr)?;
+        // Write attributes; the Message-Authenticator is zero until the packet is signed
+        for attr in &attributes {
+            match attr.as_ref() {
+                Attribute::Binary(name, _) if name == "Message-Authenticator" => {
+                    buffer.extend_from_slice(&[80, 18]);
+                    buffer.extend_from_slice(&[0; 16]);
+                },
+                attr => self.encode_attribute(&mut buffer, attr)?,
+            }
         }
         
         // Return encoded packet
         Ok(buffer.to_vec())
     }
     
+    /// Wrap an attribute named after a vendor-specific attribute in a Vendor-Specific attribute
+    ///
+    /// # Returns
+    ///
+    /// None if the attribute is a standard or already wrapped one
+    fn wrap_vendor_attribute(&self, attr: &Attribute) -> Option<Attribute> {
+        if matches!(attr, Attribute::VendorSpecific(..)) || self.dictionary.attributes.contains_key(attr.name()) {
+            return None;
+        }
+        
+        self.dictionary.vendor_attributes.iter()
+            .find(|(_, names)| names.values().any(|name| name == attr.name()))
+            .map(|(vendor_id, _)| Attribute::VendorSpecific(*vendor_id, vec![attr.clone()]))
+    }
+    
     /// Calculate the size of an attribute
     ///
     /// # Arguments
@@ -658,6 +952,40 @@ impl PacketProcessor {
                     },
                 }
             },
+            Attribute::Binary(_name, value) => {
+                // Get attribute type
+                let attr_type = match self.dictionary.attributes.get(_name) {
+                    Some(code) => *code,
+                    None => return Err(format!("Unknown attribute: {}", _name).into()),
+                };
+                
+                // Calculate attribute length
+                let attr_length = 2 + value.len();
+                
+                if attr_length > 255 {
+                    return Err(format!("Attribute {} value too long", _name).into());
+                }
+                
+                // Write attribute header and value
+                buffer.extend_from_slice(&[attr_type, attr_length as u8]);
+                buffer.extend_from_slice(value);
+            },
+            Attribute::VendorSpecific(vendor_id, attrs) => {
+                let attr_length = self.calculate_attribute_size(attr);
+                
+                if attr_length > 255 {
+                    return Err(format!("Vendor-Specific attribute for vendor {} too long", vendor_id).into());
+                }
+                
+                // Write attribute header and vendor id
+                buffer.extend_from_slice(&[26, attr_length as u8]);
+                buffer.extend_from_slice(&vendor_id.to_be_bytes());
+                
+                // Write sub-attributes
+                for sub_attr in attrs {
+                    self.encode_vendor_attribute(buffer, *vendor_id, sub_attr)?;
+                }
+            },
             // Implement other attribute types as needed
             _ => {
                 return Err(format!("Unsupported attribute type: {:?}", attr).into());
@@ -667,6 +995,32 @@ impl PacketProcessor {
         Ok(())
     }
     
+    /// Encode a vendor-specific sub-attribute
+    ///
+    /// # Arguments
+    ///
+    /// * `buffer` - Buffer to write to
+    /// * `vendor_id` - Vendor the sub-attribute belongs to
+    /// * `attr` - Sub-attribute to encode
+    fn encode_vendor_attribute(&self, buffer: &mut BytesMut, vendor_id: u32, attr: &Attribute) -> Result<()> {
+        let vendor_type = self.dictionary.vendor_attributes.get(&vendor_id)
+            .and_then(|names| names.iter().find(|(_, name)| name.as_str() == attr.name()))
+            .map(|(code, _)| *code)
+            .ok_or_else(|| format!("Unknown vendor attribute: {} (vendor {})", attr.name(), vendor_id))?;
+        
+        let value = match attr {
+            Attribute::String(_, value) => value.as_bytes().to_vec(),
+            Attribute::Integer(_, value) => value.to_be_bytes().to_vec(),
+            Attribute::Binary(_, value) => value.clone(),
+            _ => return Err(format!("Unsupported vendor attribute type: {:?}", attr).into()),
+        };
+        
+        buffer.extend_from_slice(&[vendor_type, (2 + value.len()) as u8]);
+        buffer.extend_from_slice(&value);
+        
+        Ok(())
+    }
+    
     /// Calculate Message-Authenticator for a packet
     ///
     /// # Arguments
@@ -677,18 +1031,18 @@ impl PacketProcessor {
     /// # Returns
     ///
     /// Message-Authenticator value
-    pub fn calculate_message_authenticator(&self, _packet: &Packet, _secret: &str) -> Vec<u8> {
+    pub fn calculate_message_authenticator(&self, packet: &Packet, secret: &str) -> Vec<u8> {
         // GOAL: Security by Design
-        // Implement secure Message-Authenticator calculation
-        
-        // In a real implementation, we would:
-        // 1. Create a copy of the packet with a zero-filled Message-Authenticator
-        // 2. Calculate HMAC-MD5 of the packet using the shared secret
-        // 3. Return the HMAC-MD5 digest
+        // A received packet is checked as it arrived; others as they would be sent
+        let data = match &packet.raw_data {
+            Some(raw) => raw.to_vec(),
+            None => match self.encode_unsigned(packet) {
+                Ok(data) => data,
+                Err(_) => return Vec::new(),
+            },
+        };
         
-        // For now, return a dummy value for testing purposes
-        // In production, this should use a proper HMAC implementation
-        vec![0; 16]
+        message_authenticator(secret.as_bytes(), &data).to_vec()
     }
     
     /// Verify Message-Authenticator for a packet
@@ -715,6 +1069,369 @@ impl PacketProcessor {
         let expected = self.calculate_message_authenticator(packet, secret);
         
         // Compare Message-Authenticator values
-        message_authenticator == &expected
+        crate::mschap::constant_time_eq(message_authenticator, &expected)
+    }
+}
+
+/// Encrypt an MS-MPPE-Send-Key or MS-MPPE-Recv-Key value (RFC 2548, section 2.4.2)
+///
+/// The key is prefixed with its length, padded to a multiple of 16 bytes and
+/// hidden with an MD5 keystream derived from the shared secret, the request
+/// authenticator and a random salt.
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `request_authenticator` - Authenticator of the Access-Request being answered
+/// * `key` - Key material to encrypt
+///
+/// # Returns
+///
+/// Salt followed by the encrypted key, ready to be used as the attribute value
+pub fn encrypt_mppe_key(secret: &[u8], request_authenticator: &[u8; 16], key: &[u8]) -> Vec<u8> {
+    use md5::{Digest, Md5};
+    use rand::Rng;
+    
+    // The most significant bit of the salt must be set
+    let mut salt: [u8; 2] = rand::thread_rng().gen();
+    salt[0] |= 0x80;
+    
+    // Plaintext is the key length followed by the key, zero-padded
+    let mut plain = Vec::with_capacity(key.len() + 16);
+    plain.push(key.len() as u8);
+    plain.extend_from_slice(key);
+    while !plain.len().is_multiple_of(16) {
+        plain.push(0);
+    }
+    
+    let mut result = salt.to_vec();
+    let mut previous: Vec<u8> = [request_authenticator.as_slice(), &salt].concat();
+    
+    for chunk in plain.chunks(16) {
+        let mut hasher = Md5::new();
+        hasher.update(secret);
+        hasher.update(&previous);
+        let b = hasher.finalize();
+        
+        let cipher: Vec<u8> = chunk.iter().zip(b.iter()).map(|(p, k)| p ^ k).collect();
+        result.extend_from_slice(&cipher);
+        previous = cipher;
+    }
+    
+    result
+}
+
+/// Find the value of the Message-Authenticator attribute of an encoded packet
+///
+/// # Returns
+///
+/// The offset of the 16-byte value, or None if the packet has none
+fn message_authenticator_offset(data: &[u8]) -> Option<usize> {
+    let mut offset = 20;
+    while offset + 2 <= data.len() {
+        let length = data[offset + 1] as usize;
+        if length < 2 {
+            return None;
+        }
+        if data[offset] == 80 && length == 18 && offset + 18 <= data.len() {
+            return Some(offset + 2);
+        }
+        offset += length;
+    }
+    
+    None
+}
+
+/// Compute the Message-Authenticator of an encoded packet (RFC 3579, section 3.2)
+///
+/// The HMAC-MD5 covers the packet with the Message-Authenticator value zeroed.
+/// The authenticator field must hold the Request Authenticator: the one of the
+/// packet for Access-Requests, of the request answered for responses.
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `data` - Encoded packet
+pub fn message_authenticator(secret: &[u8], data: &[u8]) -> [u8; 16] {
+    use hmac::{Hmac, Mac};
+    
+    let mut zeroed = data.to_vec();
+    if let Some(offset) = message_authenticator_offset(data) {
+        zeroed[offset..offset + 16].fill(0);
+    }
+    
+    let mut mac = Hmac::<md5::Md5>::new_from_slice(secret).expect("HMAC takes keys of any length");
+    mac.update(&zeroed);
+    mac.finalize().into_bytes().into()
+}
+
+/// Compute the Response Authenticator of an encoded response (RFC 2865, section 3)
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `data` - Encoded response, holding the Request Authenticator of the request it answers
+pub fn response_authenticator(secret: &[u8], data: &[u8]) -> [u8; 16] {
+    use md5::{Digest, Md5};
+    
+    let mut hasher = Md5::new();
+    hasher.update(data);
+    hasher.update(secret);
+    hasher.finalize().into()
+}
+
+/// Sign an encoded Accounting, Disconnect or CoA request (RFC 2866 section 3, RFC 5176 section 2.3)
+///
+/// The Request Authenticator is the MD5 of the packet with a zero
+/// authenticator, followed by the shared secret.
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `data` - Encoded packet, whose authenticator is overwritten
+pub fn sign_request(secret: &[u8], data: &mut [u8]) {
+    use md5::{Digest, Md5};
+    
+    if data.len() < 20 {
+        return;
+    }
+    
+    data[4..20].fill(0);
+    let mut hasher = Md5::new();
+    hasher.update(&data[..]);
+    hasher.update(secret);
+    data[4..20].copy_from_slice(&hasher.finalize());
+}
+
+/// Check the Request Authenticator of an Accounting, Disconnect or CoA request
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `data` - Encoded request
+pub fn verify_request(secret: &[u8], data: &[u8]) -> bool {
+    if data.len() < 20 {
+        return false;
+    }
+    
+    let mut expected = data.to_vec();
+    sign_request(secret, &mut expected);
+    crate::mschap::constant_time_eq(&expected[4..20], &data[4..20])
+}
+
+/// Check the Response Authenticator of an answer (RFC 2865 section 3)
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `request_authenticator` - Authenticator of the request being answered
+/// * `data` - Encoded answer
+pub fn verify_response(secret: &[u8], request_authenticator: &[u8; 16], data: &[u8]) -> bool {
+    use md5::{Digest, Md5};
+    
+    if data.len() < 20 {
+        return false;
+    }
+    
+    let mut hasher = Md5::new();
+    hasher.update(&data[..4]);
+    hasher.update(request_authenticator);
+    hasher.update(&data[20..]);
+    hasher.update(secret);
+    
+    crate::mschap::constant_time_eq(&hasher.finalize(), &data[4..20])
+}
+
+/// Hide a value the way User-Password is hidden (RFC 2865, section 5.2)
+///
+/// Also used for MS-CHAP-MPPE-Keys (RFC 2548, section 2.4.1).
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `request_authenticator` - Authenticator of the Access-Request being answered
+/// * `value` - Plaintext value, zero-padded to a multiple of 16 bytes
+///
+/// # Returns
+///
+/// The encrypted value
+pub fn encrypt_password(secret: &[u8], request_authenticator: &[u8; 16], value: &[u8]) -> Vec<u8> {
+    use md5::{Digest, Md5};
+    
+    let mut plain = value.to_vec();
+    while plain.is_empty() || !plain.len().is_multiple_of(16) {
+        plain.push(0);
+    }
+    
+    let mut result = Vec::with_capacity(plain.len());
+    let mut previous = request_authenticator.to_vec();
+    
+    for chunk in plain.chunks(16) {
+        let mut hasher = Md5::new();
+        hasher.update(secret);
+        hasher.update(&previous);
+        let b = hasher.finalize();
+        
+        let cipher: Vec<u8> = chunk.iter().zip(b.iter()).map(|(p, k)| p ^ k).collect();
+        result.extend_from_slice(&cipher);
+        previous = cipher;
+    }
+    
+    result
+}
+
+/// Reveal a value hidden the way User-Password is (RFC 2865, section 5.2)
+///
+/// # Arguments
+///
+/// * `secret` - RADIUS shared secret
+/// * `request_authenticator` - Authenticator of the Access-Request carrying the value
+/// * `value` - Hidden value, a multiple of 16 bytes
+///
+/// # Returns
+///
+/// The plaintext, without its zero padding
+pub fn decrypt_password(secret: &[u8], request_authenticator: &[u8; 16], value: &[u8]) -> Vec<u8> {
+    use md5::{Digest, Md5};
+    
+    let mut plain = Vec::with_capacity(value.len());
+    let mut previous: &[u8] = request_authenticator;
+    
+    for chunk in value.chunks(16) {
+        let mut hasher = Md5::new();
+        hasher.update(secret);
+        hasher.update(previous);
+        let b = hasher.finalize();
+        
+        plain.extend(chunk.iter().zip(b.iter()).map(|(c, k)| c ^ k));
+        previous = chunk;
+    }
+    
+    while plain.last() == Some(&0) {
+        plain.pop();
+    }
+    plain
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use md5::{Digest, Md5};
+    
+    fn processor() -> PacketProcessor {
+        let mut config = Config::default();
+        config.server.secret = "testing123".to_string();
+        PacketProcessor::new(Arc::new(config))
+    }
+    
+    #[test]
+    fn responses_are_signed() {
+        let processor = processor();
+        let request_authenticator = [7u8; 16];
+        let request = Packet::new(Packet::ACCESS_REQUEST, 42, request_authenticator);
+        
+        let mut challenge = request.create_response(Packet::ACCESS_CHALLENGE);
+        challenge.add_attribute(Attribute::Binary("EAP-Message".to_string(), vec![1, 2, 0, 6, 13, 32]));
+        challenge.add_attribute(Attribute::Binary("State".to_string(), vec![9; 8]));
+        let data = processor.encode(&challenge).unwrap();
+        
+        // The Message-Authenticator comes first, and is the HMAC-MD5 of the packet with
+        // the Request Authenticator and a zero Message-Authenticator
+        assert_eq!(&data[20..22], &[80, 18]);
+        let mut unsigned = data.clone();
+        unsigned[4..20].copy_from_slice(&request_authenticator);
+        unsigned[22..38].fill(0);
+        let mut mac = Hmac::<Md5>::new_from_slice(b"testing123").unwrap();
+        mac.update(&unsigned);
+        assert_eq!(&data[22..38], mac.finalize().into_bytes().as_slice());
+        
+        // The Response Authenticator covers the signed attributes
+        let mut hasher = Md5::new();
+        hasher.update(&data[..4]);
+        hasher.update(request_authenticator);
+        hasher.update(&data[20..]);
+        hasher.update(b"testing123");
+        assert_eq!(&data[4..20], hasher.finalize().as_slice());
+        assert!(verify_response(b"testing123", &request_authenticator, &data));
+        assert!(!verify_response(b"wrong-secret", &request_authenticator, &data));
+        
+        // Requests keep their authenticator
+        let data = processor.encode(&request).unwrap();
+        assert_eq!(&data[4..20], &request_authenticator);
+    }
+    
+    #[test]
+    fn request_message_authenticator_is_checked() {
+        let processor = processor();
+        let source: SocketAddr = "192.0.2.1:1812".parse().unwrap();
+        
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [3u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+        request.add_attribute(Attribute::Binary("Message-Authenticator".to_string(), vec![0; 16]));
+        let mut data = processor.encode(&request).unwrap();
+        assert!(processor.parse(&data, source).is_ok());
+        
+        let parsed = processor.parse(&data, source).unwrap();
+        assert!(processor.verify_message_authenticator(&parsed, "testing123"));
+        assert!(!processor.verify_message_authenticator(&parsed, "wrong-secret"));
+        
+        // A changed attribute no longer matches
+        data[23] ^= 1;
+        assert!(processor.parse(&data, source).is_err());
+    }
+    
+    #[test]
+    fn accounting_authenticator_is_checked() {
+        let processor = processor();
+        let source: SocketAddr = "192.0.2.1:1813".parse().unwrap();
+        
+        let mut request = Packet::new(Packet::ACCOUNTING_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), "alice".to_string()));
+        let unsigned = processor.encode(&request).unwrap().to_vec();
+        assert!(processor.parse(&unsigned, source).is_err());
+        
+        let mut data = unsigned.clone();
+        sign_request(b"testing123", &mut data);
+        assert!(processor.parse(&data, source).is_ok());
+        
+        // Signed with another secret, or changed afterwards
+        let mut other = unsigned;
+        sign_request(b"another-secret", &mut other);
+        assert!(processor.parse(&other, source).is_err());
+        data[23] ^= 1;
+        assert!(processor.parse(&data, source).is_err());
+    }
+    
+    #[test]
+    fn user_password_is_hidden() {
+        let authenticator = [0x5a; 16];
+        for password in ["", "secret", "exactly16bytes!!", "a much longer passphrase of forty bytes"] {
+            let hidden = encrypt_password(b"testing123", &authenticator, password.as_bytes());
+            assert!(hidden.len().is_multiple_of(16) && !hidden.is_empty());
+            if !password.is_empty() {
+                assert_ne!(&hidden[..password.len()], password.as_bytes());
+            }
+            assert_eq!(decrypt_password(b"testing123", &authenticator, &hidden), password.as_bytes());
+        }
+        
+        // The processor hides the password on the way out and reveals it on the way in
+        let processor = processor();
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, authenticator);
+        request.add_attribute(Attribute::String("User-Password".to_string(), "secret".to_string()));
+        request.add_attribute(Attribute::Binary("Message-Authenticator".to_string(), vec![0; 16]));
+        let data = processor.encode(&request).unwrap();
+        assert_eq!(&data[20..22], &[2, 18]);
+        assert!(!data.windows(6).any(|window| window == b"secret"));
+        
+        let parsed = processor.parse(&data, "192.0.2.1:1812".parse().unwrap()).unwrap();
+        assert_eq!(parsed.get_text("User-Password").as_deref(), Some("secret"));
+        
+        // Truncated values are refused
+        let mut short = data[..30].to_vec();
+        short[3] = 30;
+        short[21] = 10;
+        let error = processor.parse(&short, "192.0.2.1:1812".parse().unwrap()).unwrap_err();
+        assert!(error.to_string().contains("User-Password length"));
     }
 }
@@ -0,0 +1,682 @@
+// quotas.rs - Data and time quotas for rust-radius
+//
+// A quota plan limits the data and session time a user has per day, week or
+// month. Plans are given to the members of a group, or to single users. Usage
+// is counted from the Interim-Update and Stop records of every session. It is
+// kept in memory and written to a JSON file a few seconds after it changes,
+// and on shutdown, so it survives restarts. At login the time left is sent
+// as Session-Timeout, and a user whose quota is used up is rejected or given
+// the throttled profile. When an Interim-Update shows a session has gone over,
+// the server ends it with a Disconnect-Request or moves it to the throttled
+// profile with a CoA-Request.
+
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::{Config, QuotaAction, QuotaPlan, QuotaReset};
+use crate::dynauth::{DynAuthClient, DynAuthReply};
+use crate::protocol::{Attribute, Packet};
+use crate::sessions;
+use crate::store::JsonFile;
+use crate::units::{parse_bytes, parse_duration};
+use crate::users;
+use crate::Result;
+
+/// Acct-Status-Type values (RFC 2866, section 5.1)
+const ACCT_START: u32 = 1;
+const ACCT_STOP: u32 = 2;
+const ACCT_INTERIM_UPDATE: u32 = 3;
+
+/// Time changed usage waits before it is written, so a burst of updates is written once
+const FLUSH_DELAY: Duration = Duration::from_secs(5);
+
+/// Limits of a quota plan, in bytes and seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// Bytes per period, in and out
+    pub data: Option<u64>,
+    
+    /// Seconds of session time per period
+    pub time: Option<u64>,
+    
+    /// When the counters start again
+    pub reset: QuotaReset,
+}
+
+impl Limits {
+    /// Parse the limits of a configured plan
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data amount or time is invalid
+    pub fn parse(plan: &QuotaPlan) -> std::result::Result<Self, String> {
+        Ok(Self {
+            data: plan.data.as_deref().map(parse_bytes).transpose()?,
+            time: plan.time.as_deref().map(parse_duration).transpose()?,
+            reset: plan.reset,
+        })
+    }
+}
+
+/// Get the start of the period that contains `now`, in local time
+///
+/// # Returns
+///
+/// The start, or None if the counters never reset
+pub fn period_start(reset: QuotaReset, now: DateTime<Local>) -> Option<DateTime<Utc>> {
+    let today = now.date_naive();
+    let first_day = match reset {
+        QuotaReset::Daily => today,
+        QuotaReset::Weekly => today - chrono::Days::new(u64::from(today.weekday().num_days_from_monday())),
+        QuotaReset::Monthly => today.with_day(1)?,
+        QuotaReset::Never => return None,
+    };
+    
+    // Midnight can be skipped by a daylight saving change, so take the first time that exists
+    Local.from_local_datetime(&first_day.and_time(NaiveTime::MIN)).earliest()
+        .or_else(|| Local.from_local_datetime(&first_day.and_hms_opt(1, 0, 0)?).earliest())
+        .map(|start| start.with_timezone(&Utc))
+}
+
+/// Totals last reported for a session
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SessionUsage {
+    /// Bytes, in and out
+    pub octets: u64,
+    
+    /// Seconds
+    pub seconds: u64,
+    
+    /// Last accounting record
+    pub updated: DateTime<Utc>,
+    
+    /// Whether the session was disconnected or throttled for going over
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub enforced: bool,
+}
+
+/// Usage of one user in the current period
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Usage {
+    /// Plan the user had at the last login; None for a quota of their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plan: Option<String>,
+    
+    /// Start of the period the counters are for
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<DateTime<Utc>>,
+    
+    /// Bytes used in the period
+    #[serde(default)]
+    pub octets: u64,
+    
+    /// Seconds used in the period
+    #[serde(default)]
+    pub seconds: u64,
+    
+    /// Open sessions by Acct-Session-Id
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sessions: BTreeMap<String, SessionUsage>,
+}
+
+impl Usage {
+    /// Start a new period if the current one is over
+    ///
+    /// Sessions not heard from during the whole of the last period lost
+    /// their Stop, and are forgotten.
+    pub fn roll(&mut self, reset: QuotaReset, now: DateTime<Utc>) {
+        let start = period_start(reset, now.with_timezone(&Local));
+        if start == self.period {
+            return;
+        }
+        
+        if let Some(previous) = self.period {
+            self.sessions.retain(|_, session| session.updated >= previous);
+        }
+        for session in self.sessions.values_mut() {
+            session.enforced = false;
+        }
+        self.period = start;
+        self.octets = 0;
+        self.seconds = 0;
+    }
+    
+    /// Count the totals of a session reported by accounting
+    ///
+    /// The NAS reports totals since the session started, so only the growth
+    /// since the last report is added to the period.
+    pub fn record(&mut self, session: &str, octets: u64, seconds: u64, now: DateTime<Utc>) -> &mut SessionUsage {
+        let last = self.sessions.entry(session.to_string()).or_insert(SessionUsage {
+            octets: 0,
+            seconds: 0,
+            updated: now,
+            enforced: false,
+        });
+        
+        self.octets = self.octets.saturating_add(octets.saturating_sub(last.octets));
+        self.seconds = self.seconds.saturating_add(seconds.saturating_sub(last.seconds));
+        last.octets = last.octets.max(octets);
+        last.seconds = last.seconds.max(seconds);
+        last.updated = now;
+        
+        last
+    }
+    
+    /// Get the seconds left, None if time is unlimited
+    pub fn time_left(&self, limits: &Limits) -> Option<u64> {
+        limits.time.map(|time| time.saturating_sub(self.seconds))
+    }
+    
+    /// Get what is used up, if anything
+    pub fn exhausted(&self, limits: &Limits) -> Option<&'static str> {
+        if limits.data.is_some_and(|data| self.octets >= data) {
+            Some("Data quota is used up")
+        } else if self.time_left(limits) == Some(0) {
+            Some("Time quota is used up")
+        } else {
+            None
+        }
+    }
+}
+
+/// Usage by user name, as stored in the quotas file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct QuotaUsage {
+    /// Usage of each user
+    pub users: BTreeMap<String, Usage>,
+}
+
+impl JsonFile for QuotaUsage {
+    const WHAT: &'static str = "quotas";
+}
+
+/// Usage in memory, written behind to the quotas file
+#[derive(Debug)]
+struct UsageStore {
+    /// Quotas file
+    file: PathBuf,
+    
+    /// Current usage
+    usage: Mutex<QuotaUsage>,
+    
+    /// Whether a write is scheduled
+    scheduled: AtomicBool,
+    
+    /// Serializes writes, so an older snapshot never replaces a newer one
+    writing: Mutex<()>,
+}
+
+impl UsageStore {
+    /// Schedule a write of changed usage
+    fn changed(self: &Arc<Self>) {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        
+        let store = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(FLUSH_DELAY).await;
+            if let Err(e) = store.flush().await {
+                tracing::error!(file = %store.file.display(), error = %e, "Failed to write quotas");
+            }
+        });
+    }
+    
+    /// Write the usage now, without blocking the runtime
+    async fn flush(self: &Arc<Self>) -> Result<()> {
+        // Changes from here on schedule another write
+        self.scheduled.store(false, Ordering::Release);
+        
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let _writing = store.writing.lock().unwrap();
+            let usage = store.usage.lock().unwrap().clone();
+            usage.save(&store.file)
+        }).await?
+    }
+}
+
+/// Checks quotas at login and enforces them on accounting
+pub struct QuotaEnforcer {
+    /// Usage, shared with the enforcer of the next configuration
+    store: Arc<UsageStore>,
+    
+    /// Limits of each plan
+    plans: BTreeMap<String, Limits>,
+    
+    /// Limits of single users
+    users: BTreeMap<String, Limits>,
+    
+    /// What happens to a session that goes over
+    action: QuotaAction,
+    
+    /// Attributes of the throttled profile
+    throttle: Vec<Attribute>,
+    
+    /// Sends Disconnect and CoA requests to NASes
+    client: Arc<DynAuthClient>,
+}
+
+impl QuotaEnforcer {
+    /// Create an enforcer from the server configuration
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a plan is invalid or the quotas file cannot be loaded
+    pub fn new(config: Arc<Config>) -> Result<Self> {
+        let parse = |kind: &str, plans: &BTreeMap<String, QuotaPlan>| {
+            plans.iter()
+                .map(|(name, plan)| Limits::parse(plan)
+                    .map(|limits| (name.clone(), limits))
+                    .map_err(|e| format!("Invalid quota of {} {}: {}", kind, name, e)))
+                .collect::<std::result::Result<BTreeMap<_, _>, _>>()
+        };
+        let quotas = &config.quotas;
+        let plans = parse("plan", &quotas.plans)?;
+        let users = parse("user", &quotas.users)?;
+        
+        let usage = if plans.is_empty() && users.is_empty() {
+            QuotaUsage::default()
+        } else {
+            QuotaUsage::load_or_default(&quotas.file)?
+        };
+        
+        Ok(Self {
+            store: Arc::new(UsageStore {
+                file: quotas.file.clone(),
+                usage: Mutex::new(usage),
+                scheduled: AtomicBool::new(false),
+                writing: Mutex::new(()),
+            }),
+            plans,
+            users,
+            action: quotas.on_exhausted,
+            throttle: users::reply_attributes(&quotas.throttle),
+            client: Arc::new(DynAuthClient::new(config.clone())),
+        })
+    }
+    
+    /// Check whether no quota is configured
+    pub fn is_empty(&self) -> bool {
+        self.plans.is_empty() && self.users.is_empty()
+    }
+    
+    /// Keep the usage of the enforcer this one replaces, unless the quotas file changed
+    pub fn adopt(&mut self, previous: &QuotaEnforcer) {
+        if self.store.file == previous.store.file {
+            self.store = previous.store.clone();
+        }
+    }
+    
+    /// Write the usage to the quotas file now, e.g. on shutdown
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the quotas file cannot be written
+    pub async fn flush(&self) -> Result<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        self.store.flush().await
+    }
+    
+    /// Get the limits of a user: their own quota, else their plan's
+    fn limits(&self, username: &str, plan: Option<&str>) -> Option<(Option<String>, Limits)> {
+        match self.users.get(username) {
+            Some(limits) => Some((None, *limits)),
+            None => plan.and_then(|plan| self.plans.get(plan)).map(|limits| (plan.map(str::to_string), *limits)),
+        }
+    }
+    
+    /// Check a user's quota at login
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - Authenticated user
+    /// * `plan` - Plan of the user's groups, if any
+    /// * `attributes` - Reply attributes so far
+    ///
+    /// # Returns
+    ///
+    /// The reply attributes, with Session-Timeout capped at the time left or
+    /// the throttled profile applied, or the reason to reject
+    pub fn admit(&self, username: &str, plan: Option<&str>, mut attributes: Vec<Attribute>) -> std::result::Result<Vec<Attribute>, String> {
+        let (plan, limits) = match self.limits(username, plan) {
+            Some(found) => found,
+            None => return Ok(attributes),
+        };
+        
+        let mut usage = self.store.usage.lock().unwrap();
+        let user = usage.users.entry(username.to_string()).or_default();
+        let before = user.clone();
+        user.plan = plan;
+        user.roll(limits.reset, Utc::now());
+        
+        let outcome = match (user.exhausted(&limits), self.action) {
+            (Some(reason), QuotaAction::Disconnect) => {
+                tracing::info!(username = username, reason = reason, "Rejecting user over quota");
+                Err(reason.to_string())
+            },
+            (Some(reason), QuotaAction::Throttle) => {
+                tracing::info!(username = username, reason = reason, "Throttling user over quota");
+                attributes.retain(|attribute| !self.throttle.iter().any(|throttle| throttle.name() == attribute.name()));
+                attributes.extend(self.throttle.iter().cloned());
+                Ok(attributes)
+            },
+            (None, _) => {
+                if let Some(left) = user.time_left(&limits) {
+                    let left = left.min(i32::MAX as u64) as i32;
+                    let timeout = attributes.iter().find_map(|attribute| match attribute {
+                        Attribute::Integer(name, timeout) if name == "Session-Timeout" => Some(*timeout),
+                        _ => None,
+                    });
+                    if timeout.is_none_or(|timeout| timeout > left) {
+                        attributes.retain(|attribute| attribute.name() != "Session-Timeout");
+                        attributes.push(Attribute::Integer("Session-Timeout".to_string(), left));
+                    }
+                }
+                Ok(attributes)
+            },
+        };
+        
+        let changed = *user != before;
+        drop(usage);
+        if changed {
+            self.store.changed();
+        }
+        
+        outcome
+    }
+    
+    /// Count an Accounting-Request, and act on a session that has gone over
+    ///
+    /// The usage is recorded at once. A session that has gone over is
+    /// disconnected or throttled in the background, so the Accounting-Response
+    /// never waits for the NAS.
+    pub fn account(&self, request: &Packet) {
+        let status = request.get_integer("Acct-Status-Type");
+        if self.is_empty() || !matches!(status, Some(ACCT_START | ACCT_INTERIM_UPDATE | ACCT_STOP)) {
+            return;
+        }
+        let (username, session) = match (request.get_text("User-Name"), request.get_text("Acct-Session-Id")) {
+            (Some(username), Some(session)) => (username, session),
+            _ => return,
+        };
+        
+        let counter = |octets: &str, gigawords: &str| {
+            (u64::from(request.get_integer(gigawords).unwrap_or(0)) << 32) | u64::from(request.get_integer(octets).unwrap_or(0))
+        };
+        let octets = counter("Acct-Input-Octets", "Acct-Input-Gigawords")
+            .saturating_add(counter("Acct-Output-Octets", "Acct-Output-Gigawords"));
+        let seconds = u64::from(request.get_integer("Acct-Session-Time").unwrap_or(0));
+        let now = Utc::now();
+        
+        let reason = {
+            let mut usage = self.store.usage.lock().unwrap();
+            
+            // Users get a usage entry, with their plan, when they log in
+            let user = match usage.users.get_mut(&username) {
+                Some(user) => user,
+                None => return,
+            };
+            let limits = match self.limits(&username, user.plan.as_deref()) {
+                Some((_, limits)) => limits,
+                None => return,
+            };
+            
+            user.roll(limits.reset, now);
+            let enforced = user.record(&session, octets, seconds, now).enforced;
+            let reason = user.exhausted(&limits).filter(|_| !enforced && status != Some(ACCT_STOP));
+            if status == Some(ACCT_STOP) {
+                user.sessions.remove(&session);
+            }
+            
+            reason
+        };
+        self.store.changed();
+        
+        if let Some(reason) = reason {
+            let overrun = Overrun {
+                client: self.client.clone(),
+                store: self.store.clone(),
+                action: self.action,
+                throttle: self.throttle.clone(),
+                nas: sessions::nas_address(request),
+                identity: sessions::identity(request),
+                username,
+                session,
+                reason,
+            };
+            tokio::spawn(async move {
+                if let Err(e) = overrun.enforce().await {
+                    tracing::warn!(error = %e, "Failed to act on session over quota");
+                }
+            });
+        }
+    }
+}
+
+/// A session that has gone over its quota, with what is needed to end or throttle it
+struct Overrun {
+    /// Sends Disconnect and CoA requests to NASes
+    client: Arc<DynAuthClient>,
+    
+    /// Usage, where the session is marked as dealt with
+    store: Arc<UsageStore>,
+    
+    /// Disconnect or throttle
+    action: QuotaAction,
+    
+    /// Attributes of the throttled profile
+    throttle: Vec<Attribute>,
+    
+    /// Address the session's accounting came from
+    nas: IpAddr,
+    
+    /// Attributes identifying the session to the NAS
+    identity: Vec<Attribute>,
+    
+    /// User of the session
+    username: String,
+    
+    /// Acct-Session-Id
+    session: String,
+    
+    /// Which quota is used up
+    reason: &'static str,
+}
+
+impl Overrun {
+    /// Disconnect or throttle the session
+    ///
+    /// The session is only marked as dealt with once the NAS acknowledges, so
+    /// the next Interim-Update tries again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the Disconnect or CoA request cannot be sent
+    async fn enforce(self) -> Result<()> {
+        let (nas, username, session) = (self.nas, self.username.as_str(), self.session.as_str());
+        
+        let reply = match self.action {
+            QuotaAction::Disconnect => self.client.disconnect(nas, &self.identity).await?,
+            QuotaAction::Throttle => self.client.change(nas, &self.identity, self.throttle.clone()).await?,
+        };
+        if reply != DynAuthReply::Ack {
+            tracing::warn!(username = username, session = session, nas = %nas, reply = ?reply, action = ?self.action, "NAS did not act on session over quota");
+            return Ok(());
+        }
+        tracing::info!(username = username, session = session, nas = %nas, reason = self.reason, action = ?self.action, "Session over quota");
+        
+        let enforced = {
+            let mut usage = self.store.usage.lock().unwrap();
+            let open = usage.users.get_mut(username).and_then(|user| user.sessions.get_mut(session));
+            open.map(|open| open.enforced = true).is_some()
+        };
+        if enforced {
+            self.store.changed();
+        }
+        
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UdpSocket;
+    
+    #[test]
+    fn periods_and_usage() {
+        let now = Local.with_ymd_and_hms(2025, 6, 4, 15, 30, 0).unwrap();
+        let midnight = |day: u32| Local.with_ymd_and_hms(2025, 6, day, 0, 0, 0).unwrap().with_timezone(&Utc);
+        assert_eq!(period_start(QuotaReset::Daily, now), Some(midnight(4)));
+        assert_eq!(period_start(QuotaReset::Weekly, now), Some(midnight(2)));
+        assert_eq!(period_start(QuotaReset::Monthly, now), Some(midnight(1)));
+        assert_eq!(period_start(QuotaReset::Never, now), None);
+        
+        let limits = Limits::parse(&QuotaPlan {
+            data: Some("1K".to_string()),
+            time: Some("1h".to_string()),
+            reset: QuotaReset::Daily,
+        }).unwrap();
+        assert_eq!(limits.data, Some(1024));
+        
+        // Only the growth of each session's totals is counted
+        let mut usage = Usage::default();
+        let at = now.with_timezone(&Utc);
+        usage.roll(limits.reset, at);
+        usage.record("s1", 300, 600, at);
+        usage.record("s1", 500, 1200, at);
+        usage.record("s2", 100, 600, at);
+        assert_eq!((usage.octets, usage.seconds), (600, 1800));
+        assert_eq!(usage.time_left(&limits), Some(1800));
+        usage.record("s2", 600, 600, at);
+        assert_eq!(usage.exhausted(&limits), Some("Data quota is used up"));
+        
+        // The next day starts from zero, and a session's later totals only add their growth
+        let tomorrow = at + chrono::Duration::days(1);
+        usage.roll(limits.reset, tomorrow);
+        assert_eq!((usage.octets, usage.exhausted(&limits)), (0, None));
+        usage.record("s1", 700, 1200, tomorrow);
+        assert_eq!(usage.octets, 200);
+    }
+    
+    #[tokio::test]
+    async fn disconnect_over_quota() {
+        use md5::{Digest, Md5};
+        
+        // A NAS that acknowledges one Disconnect-Request
+        let nas = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = nas.local_addr().unwrap().port();
+        let answered = tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            let (received, from) = nas.recv_from(&mut buffer).await.unwrap();
+            let mut answer = vec![41, buffer[1], 0, 20];
+            let mut hasher = Md5::new();
+            hasher.update(&answer[..4]);
+            hasher.update(&buffer[4..20]);
+            hasher.update(b"testing123");
+            answer.extend_from_slice(&hasher.finalize());
+            nas.send_to(&answer, from).await.unwrap();
+            buffer[..received].to_vec()
+        });
+        
+        let file = std::env::temp_dir().join(format!("rust-radius-quotas-{}.json", std::process::id()));
+        let mut config = Config::default();
+        config.server.secret = "testing123".to_string();
+        config.dynamic_authorization.port = port;
+        config.quotas = toml::from_str(&format!(r#"
+            file = "{}"
+            [plans.cafe]
+            data = "1000"
+            time = "1h"
+        "#, file.display())).unwrap();
+        let enforcer_config = Arc::new(config);
+        let enforcer = QuotaEnforcer::new(enforcer_config.clone()).unwrap();
+        
+        // The time left caps a longer Session-Timeout
+        let reply = vec![Attribute::Integer("Session-Timeout".to_string(), 86400)];
+        assert_eq!(enforcer.admit("guest", Some("cafe"), reply),
+            Ok(vec![Attribute::Integer("Session-Timeout".to_string(), 3600)]));
+        assert_eq!(enforcer.admit("staff", None, vec![]), Ok(vec![]));
+        
+        for (octets, seconds) in [(400, 60), (1200, 120)] {
+            let mut request = Packet::new(Packet::ACCOUNTING_REQUEST, 2, [0u8; 16]);
+            request.set_source("127.0.0.1:40000".parse().unwrap());
+            request.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), ACCT_INTERIM_UPDATE as i32));
+            request.add_attribute(Attribute::String("User-Name".to_string(), "guest".to_string()));
+            request.add_attribute(Attribute::String("Acct-Session-Id".to_string(), "s1".to_string()));
+            request.add_attribute(Attribute::Integer("Acct-Input-Octets".to_string(), octets));
+            request.add_attribute(Attribute::Integer("Acct-Session-Time".to_string(), seconds));
+            enforcer.account(&request);
+        }
+        
+        // The update that went over ended the session, and the user cannot log in again today
+        let request = answered.await.unwrap();
+        assert_eq!(request[0], 40);
+        assert!(request.windows(5).any(|window| window == b"guest"));
+        assert_eq!(enforcer.admit("guest", Some("cafe"), vec![]), Err("Data quota is used up".to_string()));
+        
+        // The session is marked as dealt with once the acknowledgement is in
+        let enforced = || enforcer.store.usage.lock().unwrap().users["guest"].sessions["s1"].enforced;
+        for _ in 0..100 {
+            if enforced() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(enforced());
+        
+        // Usage is written behind, and a flush writes it at once
+        enforcer.flush().await.unwrap();
+        let usage = &QuotaUsage::load(&file).unwrap().users["guest"];
+        assert_eq!((usage.octets, usage.seconds, usage.sessions["s1"].enforced), (1200, 120, true));
+        
+        // The next configuration carries on with the same usage
+        let mut next = QuotaEnforcer::new(enforcer_config.clone()).unwrap();
+        std::fs::remove_file(&file).unwrap();
+        next.adopt(&enforcer);
+        assert_eq!(next.admit("guest", Some("cafe"), vec![]), Err("Data quota is used up".to_string()));
+    }
+    
+    #[tokio::test]
+    async fn accounting_does_not_wait_for_the_nas() {
+        // A NAS that never answers, with the default timeout and retries
+        let nas = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let file = std::env::temp_dir().join(format!("rust-radius-quotas-silent-{}.json", std::process::id()));
+        let mut config = Config::default();
+        config.dynamic_authorization.port = nas.local_addr().unwrap().port();
+        config.quotas = toml::from_str(&format!(r#"
+            file = "{}"
+            [plans.cafe]
+            data = "1000"
+        "#, file.display())).unwrap();
+        let enforcer = QuotaEnforcer::new(Arc::new(config)).unwrap();
+        enforcer.admit("guest", Some("cafe"), vec![]).unwrap();
+        
+        let mut request = Packet::new(Packet::ACCOUNTING_REQUEST, 2, [0u8; 16]);
+        request.set_source("127.0.0.1:40000".parse().unwrap());
+        request.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), ACCT_INTERIM_UPDATE as i32));
+        request.add_attribute(Attribute::String("User-Name".to_string(), "guest".to_string()));
+        request.add_attribute(Attribute::String("Acct-Session-Id".to_string(), "s1".to_string()));
+        request.add_attribute(Attribute::Integer("Acct-Input-Octets".to_string(), 2000));
+        
+        let started = std::time::Instant::now();
+        enforcer.account(&request);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        
+        // The usage is counted even though the NAS has not answered
+        assert_eq!(enforcer.admit("guest", Some("cafe"), vec![]), Err("Data quota is used up".to_string()));
+        let _ = std::fs::remove_file(&file);
+    }
+}
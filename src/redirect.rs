@@ -0,0 +1,264 @@
+// redirect.rs - Captive portal redirects for unknown devices
+//
+// When the MAB backend lets an unknown device on, it puts it in the guest VLAN
+// and tells the NAS to send it to the captive portal. NAS vendors disagree on
+// how that is expressed, so the attribute set comes from a per-backend profile.
+
+use std::str::FromStr;
+
+use crate::config::{AuthBackendConfig, CaptivePortalConfig};
+use crate::mac::MacAddr;
+use crate::protocol::{Attribute, VENDOR_ARUBA, VENDOR_CISCO, VENDOR_MIKROTIK, VENDOR_WISPR};
+use crate::Result;
+
+/// Redirect URL used when the backend does not configure one
+const DEFAULT_URL: &str = "http://{portal_host}:{portal_port}/login?mac={mac}";
+
+/// Guest VLAN used when the backend does not configure one
+const DEFAULT_VLAN: i64 = 99;
+
+/// Tunnel-Type value for VLANs (RFC 3580)
+const TUNNEL_TYPE_VLAN: i32 = 13;
+
+/// Tunnel-Medium-Type value for IEEE 802 media (RFC 3580)
+const TUNNEL_MEDIUM_802: i32 = 6;
+
+/// Vendor attribute set telling the NAS where to send a guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectProfile {
+    /// Cisco `url-redirect` and `url-redirect-acl` AVPairs
+    Cisco,
+    
+    /// Aruba-User-Role; the role's captive portal profile does the redirect
+    Aruba,
+    
+    /// WISPr-Redirection-URL (UniFi and other WISPr NASes)
+    Wispr,
+    
+    /// Mikrotik-Address-List; a hotspot or firewall rule on the list does the redirect
+    Mikrotik,
+}
+
+impl FromStr for RedirectProfile {
+    type Err = String;
+    
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cisco" => Ok(RedirectProfile::Cisco),
+            "aruba" => Ok(RedirectProfile::Aruba),
+            "wispr" | "unifi" | "ubiquiti" => Ok(RedirectProfile::Wispr),
+            "mikrotik" => Ok(RedirectProfile::Mikrotik),
+            _ => Err(format!("Unknown redirect profile: {}", s)),
+        }
+    }
+}
+
+/// Guest access settings of a MAB backend
+///
+/// Backend options:
+///
+/// * `redirect_profile` - `cisco`, `aruba`, `wispr` (default) or `mikrotik`
+/// * `redirect_url` - URL template; `{portal_host}` and `{portal_port}` come from
+///   `[captive_portal]`, `{mac}` is the device's MAC address
+/// * `guest_vlan` - VLAN ID or name for guests (default 99); `0` or `""` sends no VLAN
+/// * `guest_role` - Aruba role or Mikrotik address list (default `guest`)
+/// * `redirect_acl` - Cisco ACL selecting the traffic to redirect (default `ACL-WEBAUTH-REDIRECT`)
+#[derive(Debug, Clone)]
+pub struct GuestRedirect {
+    /// Vendor attribute set
+    profile: RedirectProfile,
+    
+    /// Redirect URL with the portal address filled in; `{mac}` is left for each request
+    url: String,
+    
+    /// Guest VLAN, if any
+    vlan: Option<String>,
+    
+    /// Role or address list for profiles that redirect by role
+    role: String,
+    
+    /// Cisco redirect ACL
+    acl: String,
+}
+
+impl GuestRedirect {
+    /// Read the guest settings of a backend
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Backend configuration
+    /// * `portal` - Captive portal configuration, for the redirect URL
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an option is invalid, or if the URL refers to the
+    /// captive portal while none is configured
+    pub fn from_config(config: &AuthBackendConfig, portal: Option<&CaptivePortalConfig>) -> Result<Self> {
+        let text = |key: &str| match config.config.get(key) {
+            Some(toml::Value::String(value)) => Ok(Some(value.clone())),
+            Some(other) => Err(format!("{} must be a string, not {}", key, other.type_str())),
+            None => Ok(None),
+        };
+        
+        let profile = match text("redirect_profile")? {
+            Some(name) => name.parse()?,
+            None => RedirectProfile::Wispr,
+        };
+        
+        let template = text("redirect_url")?.unwrap_or_else(|| DEFAULT_URL.to_string());
+        let url = match portal {
+            Some(portal) => template
+                .replace("{portal_host}", &portal.host)
+                .replace("{portal_port}", &portal.port.to_string()),
+            None if template.contains("{portal_host}") || template.contains("{portal_port}") => {
+                return Err("redirect_url refers to the captive portal, but [captive_portal] is not configured".into());
+            },
+            None => template,
+        };
+        
+        let vlan = match config.config.get("guest_vlan") {
+            None => Some(DEFAULT_VLAN.to_string()),
+            Some(toml::Value::Integer(0)) => None,
+            Some(toml::Value::Integer(id)) if (1..4095).contains(id) => Some(id.to_string()),
+            Some(toml::Value::String(name)) if name.is_empty() => None,
+            Some(toml::Value::String(name)) => Some(name.clone()),
+            Some(other) => return Err(format!("Invalid guest_vlan: {}", other).into()),
+        };
+        
+        Ok(Self {
+            profile,
+            url,
+            vlan,
+            role: text("guest_role")?.unwrap_or_else(|| "guest".to_string()),
+            acl: text("redirect_acl")?.unwrap_or_else(|| "ACL-WEBAUTH-REDIRECT".to_string()),
+        })
+    }
+    
+    /// Get the redirect URL for a device
+    pub fn url(&self, mac: &MacAddr) -> String {
+        self.url.replace("{mac}", &mac.to_string())
+    }
+    
+    /// Build the Access-Accept attributes for an unknown device
+    ///
+    /// # Arguments
+    ///
+    /// * `mac` - MAC address of the device
+    pub fn attributes(&self, mac: &MacAddr) -> Vec<Attribute> {
+        let mut attributes = Vec::new();
+        
+        if let Some(vlan) = &self.vlan {
+            attributes.push(Attribute::Integer("Tunnel-Type".to_string(), TUNNEL_TYPE_VLAN));
+            attributes.push(Attribute::Integer("Tunnel-Medium-Type".to_string(), TUNNEL_MEDIUM_802));
+            attributes.push(Attribute::String("Tunnel-Private-Group-Id".to_string(), vlan.clone()));
+        }
+        
+        let vendor = match self.profile {
+            RedirectProfile::Cisco => Attribute::VendorSpecific(VENDOR_CISCO, vec![
+                Attribute::String("Cisco-AVPair".to_string(), format!("url-redirect-acl={}", self.acl)),
+                Attribute::String("Cisco-AVPair".to_string(), format!("url-redirect={}", self.url(mac))),
+            ]),
+            RedirectProfile::Aruba => Attribute::VendorSpecific(VENDOR_ARUBA, vec![
+                Attribute::String("Aruba-User-Role".to_string(), self.role.clone()),
+            ]),
+            RedirectProfile::Wispr => Attribute::VendorSpecific(VENDOR_WISPR, vec![
+                Attribute::String("WISPr-Redirection-URL".to_string(), self.url(mac)),
+            ]),
+            RedirectProfile::Mikrotik => Attribute::VendorSpecific(VENDOR_MIKROTIK, vec![
+                Attribute::String("Mikrotik-Address-List".to_string(), self.role.clone()),
+            ]),
+        };
+        attributes.push(vendor);
+        
+        attributes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use crate::config::Config;
+    use crate::protocol::{Packet, PacketProcessor};
+    
+    #[test]
+    fn cisco_profile() {
+        let mut options = HashMap::new();
+        options.insert("redirect_profile".to_string(), toml::Value::String("cisco".to_string()));
+        options.insert("guest_vlan".to_string(), toml::Value::String("guests".to_string()));
+        let config = AuthBackendConfig {
+            backend_type: "mac".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: options,
+        };
+        
+        let portal: CaptivePortalConfig = toml::from_str("host = \"portal.example.net\"\nport = 8443\ntemplate_dir = \"templates\"").unwrap();
+        let redirect = GuestRedirect::from_config(&config, Some(&portal)).unwrap();
+        let mac = "aabb.ccdd.eeff".parse().unwrap();
+        
+        let attributes = redirect.attributes(&mac);
+        assert!(attributes.contains(&Attribute::String("Tunnel-Private-Group-Id".to_string(), "guests".to_string())));
+        assert!(attributes.contains(&Attribute::VendorSpecific(VENDOR_CISCO, vec![
+            Attribute::String("Cisco-AVPair".to_string(), "url-redirect-acl=ACL-WEBAUTH-REDIRECT".to_string()),
+            Attribute::String("Cisco-AVPair".to_string(), "url-redirect=http://portal.example.net:8443/login?mac=aa:bb:cc:dd:ee:ff".to_string()),
+        ])));
+        
+        // Every attribute is in the dictionary
+        let mut response = Packet::new(Packet::ACCESS_ACCEPT, 1, [0u8; 16]);
+        for attribute in attributes {
+            response.add_attribute(attribute);
+        }
+        PacketProcessor::new(Arc::new(Config::default())).encode(&response).unwrap();
+        
+        // The default URL needs the portal's address
+        assert!(GuestRedirect::from_config(&config, None).is_err());
+    }
+    
+    #[test]
+    fn guest_options() {
+        let backend = |options: &[(&str, toml::Value)]| AuthBackendConfig {
+            backend_type: "mac".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: options.iter().map(|(key, value)| (key.to_string(), value.clone())).collect(),
+        };
+        let url = ("redirect_url", toml::Value::String("https://guest.example.net/?mac={mac}".to_string()));
+        let mac = "aa:bb:cc:dd:ee:ff".parse().unwrap();
+        
+        // A URL of its own needs no portal; VLAN 0 and "" send no VLAN
+        for vlan in [toml::Value::Integer(0), toml::Value::String(String::new())] {
+            let redirect = GuestRedirect::from_config(&backend(&[url.clone(), ("guest_vlan", vlan)]), None).unwrap();
+            assert_eq!(redirect.attributes(&mac), vec![Attribute::VendorSpecific(VENDOR_WISPR, vec![
+                Attribute::String("WISPr-Redirection-URL".to_string(), "https://guest.example.net/?mac=aa:bb:cc:dd:ee:ff".to_string()),
+            ])]);
+        }
+        
+        let redirect = GuestRedirect::from_config(&backend(std::slice::from_ref(&url)), None).unwrap();
+        assert!(redirect.attributes(&mac).contains(&Attribute::String("Tunnel-Private-Group-Id".to_string(), "99".to_string())));
+        
+        // VLAN IDs run from 1 to 4094
+        for vlan in [toml::Value::Integer(-1), toml::Value::Integer(4095), toml::Value::Float(20.0), toml::Value::Boolean(true)] {
+            assert!(GuestRedirect::from_config(&backend(&[url.clone(), ("guest_vlan", vlan.clone())]), None).is_err(), "{}", vlan);
+        }
+        
+        assert!(GuestRedirect::from_config(&backend(&[url.clone(), ("redirect_profile", toml::Value::String("juniper".to_string()))]), None).is_err());
+        assert!(GuestRedirect::from_config(&backend(&[url.clone(), ("guest_role", toml::Value::Integer(1))]), None).is_err());
+        
+        // Role-based profiles carry the role, not the URL
+        let options = [url.clone(), ("redirect_profile", toml::Value::String("Aruba".to_string())), ("guest_role", toml::Value::String("visitor".to_string()))];
+        let redirect = GuestRedirect::from_config(&backend(&options), None).unwrap();
+        assert!(redirect.attributes(&mac).contains(&Attribute::VendorSpecific(VENDOR_ARUBA, vec![
+            Attribute::String("Aruba-User-Role".to_string(), "visitor".to_string()),
+        ])));
+        
+        let options = [url.clone(), ("redirect_profile", toml::Value::String("mikrotik".to_string()))];
+        let redirect = GuestRedirect::from_config(&backend(&options), None).unwrap();
+        assert!(redirect.attributes(&mac).contains(&Attribute::VendorSpecific(VENDOR_MIKROTIK, vec![
+            Attribute::String("Mikrotik-Address-List".to_string(), "guest".to_string()),
+        ])));
+        
+        assert_eq!("UniFi".parse::<RedirectProfile>(), Ok(RedirectProfile::Wispr));
+    }
+}
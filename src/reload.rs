@@ -0,0 +1,199 @@
+// reload.rs - Hot reload of backend data for rust-radius
+//
+// This module watches the files backends load their data from (users file, MAC
+// list, ...) and reloads the affected backends when one changes, so helpdesk
+// edits take effect without a restart. The lockouts file is watched too, so a
+// lock lifted from the command line is taken in. SIGHUP is handled by the server, which
+// reloads the whole configuration (see `server.rs`).
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::auth::AuthManager;
+use crate::Result;
+
+/// Time to wait for a burst of file events (editors often write several times) to settle
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+/// Running reload watcher; reloading stops when it is dropped
+pub struct ReloadWatcher {
+    /// File system watcher, kept alive for as long as events are wanted
+    _watcher: RecommendedWatcher,
+    
+    /// Task reloading backends on file events
+    task: JoinHandle<()>,
+}
+
+impl Drop for ReloadWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Start watching the backends' files
+///
+/// Directories rather than files are watched, because editors and
+/// configuration management usually replace a file instead of writing to it.
+///
+/// # Arguments
+///
+/// * `manager` - Authentication manager whose backends are reloaded
+///
+/// # Errors
+///
+/// Returns an error if the file watcher cannot be set up
+pub fn watch(manager: Arc<AuthManager>) -> Result<ReloadWatcher> {
+    // Watched file -> indices of the backends that load it
+    let mut files: HashMap<PathBuf, Vec<usize>> = HashMap::new();
+    for (index, backend) in manager.backends().iter().enumerate() {
+        for file in backend.watched_files() {
+            files.entry(absolute(&file)).or_default().push(index);
+        }
+    }
+    let lockouts = manager.lockout().watched_file().map(absolute);
+    
+    let (tx, mut rx) = mpsc::unbounded_channel::<PathBuf>();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        match event {
+            Ok(event) => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            },
+            Err(e) => tracing::warn!(error = %e, "File watcher error"),
+        }
+    })?;
+    
+    let directories: HashSet<PathBuf> = files.keys()
+        .chain(lockouts.iter())
+        .filter_map(|file| file.parent().map(Path::to_path_buf))
+        .collect();
+    for directory in &directories {
+        watcher.watch(directory, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Failed to watch {}: {}", directory.display(), e))?;
+    }
+    
+tracing::info!(files = files.len(), "Watching backend files for changes");
+    
+    let task = tokio::spawn(async move {
+        loop {
+            let first = match rx.recv().await {
+                Some(path) => path,
+                None => break,
+            };
+
+            // Collect the rest of the burst before reloading
+            tokio::time::sleep(DEBOUNCE).await;
+            let mut changed = vec![first];
+            while let Ok(path) = rx.try_recv() {
+                changed.push(path);
+            }
+            
+            let backends: HashSet<usize> = changed.iter()
+                .filter_map(|path| files.get(path))
+                .flatten()
+                .copied()
+                .collect();
+            
+            if lockouts.as_ref().is_some_and(|lockouts| changed.contains(lockouts)) {
+                if let Err(e) = manager.lockout().reload().await {
+                    tracing::error!(error = %e, "Failed to reload lockouts; keeping current locks");
+                }
+            }
+            
+            for index in backends {
+                let backend = &manager.backends()[index];
+                tracing::info!(backend = backend.name(), "Backend file changed, reloading");
+                
+                if let Err(e) = backend.reload().await {
+                    tracing::error!(backend = backend.name(), error = %e, "Failed to reload backend; keeping previous data");
+                }
+            }
+        }
+    });
+    
+    Ok(ReloadWatcher {
+        _watcher: watcher,
+        task,
+    })
+}
+
+/// Make a path absolute so it can be compared with the paths in file events
+fn absolute(path: &Path) -> PathBuf {
+    // The file itself may be missing for a moment while it is replaced, but its directory is not
+    let directory = path.parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let directory = directory.canonicalize().unwrap_or_else(|_| directory.to_path_buf());
+    
+    match path.file_name() {
+        Some(name) => directory.join(name),
+        None => directory,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::AuthResult;
+    use crate::config::{AuthBackendConfig, Config};
+    use crate::protocol::{Attribute, Packet};
+    
+    async fn login(manager: &AuthManager, username: &str, password: &str) -> bool {
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+        request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+        
+        matches!(manager.backends()[0].authenticate(&request).await.unwrap(), AuthResult::Accept { .. })
+    }
+    
+    #[tokio::test]
+    async fn users_file_change_reloads_backend() {
+        let directory = std::env::temp_dir().join(format!("rust-radius-reload-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let users_file = directory.join("users.json");
+        std::fs::write(&users_file, r#"{"alice": "{cleartext}one"}"#).unwrap();
+        
+        let mut config = Config::default();
+        let mut backend_config = HashMap::new();
+        backend_config.insert("users_file".to_string(), toml::Value::String(users_file.display().to_string()));
+        config.auth_backends.insert("local".to_string(), AuthBackendConfig {
+            backend_type: "local".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: backend_config,
+        });
+        
+        let manager = Arc::new(AuthManager::new(Arc::new(config)).await.unwrap());
+        let _watcher = watch(manager.clone()).unwrap();
+        assert!(login(&manager, "alice", "one").await);
+        
+        // A broken file keeps the previous users
+        std::fs::write(&users_file, "{ not json").unwrap();
+        tokio::time::sleep(DEBOUNCE * 4).await;
+        assert!(login(&manager, "alice", "one").await);
+        
+        // Replace the file the way editors do
+        let temp_file = directory.join("users.json.new");
+        std::fs::write(&temp_file, r#"{"alice": "{cleartext}two"}"#).unwrap();
+        std::fs::rename(&temp_file, &users_file).unwrap();
+        
+        let mut reloaded = false;
+        for _ in 0..40 {
+            tokio::time::sleep(DEBOUNCE / 2).await;
+            if login(&manager, "alice", "two").await {
+                reloaded = true;
+                break;
+            }
+        }
+        
+        std::fs::remove_dir_all(&directory).unwrap();
+        assert!(reloaded);
+    }
+}
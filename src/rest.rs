@@ -0,0 +1,494 @@
+// rest.rs - HTTP authentication for rust-radius
+//
+// The rest backend leaves the decision to an HTTP service. Each Access-Request
+// is POSTed as a JSON body built from a template whose `{Attribute-Name}`
+// placeholders are filled from the request. The status code and the fields of
+// the JSON answer decide between Accept, Reject and Challenge, and carry the
+// reply attributes.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use rand::RngCore;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::auth::AuthResult;
+use crate::protocol::{Attribute, Packet};
+use crate::users::ReplyValue;
+use crate::Result;
+
+/// Options of a REST backend
+#[derive(Debug, Clone, Deserialize)]
+pub struct RestSettings {
+    /// URL the requests are POSTed to
+    pub url: String,
+    
+    /// JSON body template; strings may contain `{Attribute-Name}` placeholders
+    #[serde(default = "default_body")]
+    pub body: Value,
+    
+    /// Extra request headers (`Authorization`, API keys, ...)
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    
+    /// Timeout of each attempt in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    
+    /// Retries after a connection error, timeout or 5xx answer
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    
+    /// Delay before the first retry in milliseconds, doubled for each further retry
+    #[serde(default = "default_retry_delay")]
+    pub retry_delay: u64,
+    
+    /// CA certificate (PEM) trusted instead of the built-in roots
+    #[serde(default)]
+    pub ca_file: Option<PathBuf>,
+    
+    /// Client certificate (PEM) for mutual TLS
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    
+    /// Private key (PEM) of the client certificate
+    #[serde(default)]
+    pub client_key: Option<PathBuf>,
+    
+    /// Answer field holding `accept`, `reject`, `notfound` or `challenge`
+    #[serde(default = "default_result_field")]
+    pub result_field: String,
+    
+    /// Answer field holding the Reject reason or challenge prompt
+    #[serde(default = "default_message_field")]
+    pub message_field: String,
+    
+    /// Answer field holding the challenge State
+    #[serde(default = "default_state_field")]
+    pub state_field: String,
+    
+    /// Answer field holding an object of reply attributes
+    #[serde(default = "default_reply_field")]
+    pub reply_field: String,
+}
+
+fn default_body() -> Value {
+    serde_json::json!({
+        "username": "{User-Name}",
+        "password": "{User-Password}",
+        "nas_ip_address": "{NAS-IP-Address}",
+        "nas_identifier": "{NAS-Identifier}",
+        "calling_station_id": "{Calling-Station-Id}",
+        "state": "{State}",
+    })
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_retry_delay() -> u64 {
+    200
+}
+
+fn default_result_field() -> String {
+    "result".to_string()
+}
+
+fn default_message_field() -> String {
+    "message".to_string()
+}
+
+fn default_state_field() -> String {
+    "state".to_string()
+}
+
+fn default_reply_field() -> String {
+    "reply".to_string()
+}
+
+/// Client of an HTTP authentication service
+pub struct RestClient {
+    /// Backend options
+    settings: RestSettings,
+    
+    /// HTTP client, with the configured headers and TLS identity
+    http: reqwest::Client,
+}
+
+impl RestClient {
+    /// Create a client for an authentication service
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - Backend options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a header, certificate or key is invalid
+    pub fn new(settings: RestSettings) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &settings.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid header name: {}", name))?;
+            let mut value = HeaderValue::from_str(value)
+                .map_err(|_| format!("Invalid value for header {}", name))?;
+            value.set_sensitive(true);
+            headers.insert(name, value);
+        }
+        
+        // Redirects are not followed, so passwords are only ever sent to the configured URL
+        let mut builder = reqwest::Client::builder()
+            .timeout(Duration::from_secs(settings.timeout))
+            .redirect(reqwest::redirect::Policy::none())
+            .default_headers(headers);
+        
+        if let Some(path) = &settings.ca_file {
+            let pem = std::fs::read(path)
+                .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+            builder = builder
+                .tls_built_in_root_certs(false)
+                .add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+        }
+        
+        match (&settings.client_cert, &settings.client_key) {
+            (Some(cert), Some(key)) => {
+                let mut pem = std::fs::read(cert)
+                    .map_err(|e| format!("Cannot read {}: {}", cert.display(), e))?;
+                pem.extend(std::fs::read(key)
+                    .map_err(|e| format!("Cannot read {}: {}", key.display(), e))?);
+                builder = builder.identity(reqwest::Identity::from_pem(&pem)?);
+            },
+            (None, None) => {},
+            _ => return Err("client_cert and client_key must be configured together".into()),
+        }
+        
+        Ok(Self {
+            settings,
+            http: builder.build()?,
+        })
+    }
+    
+    /// Ask the service about an Access-Request
+    ///
+    /// Without a result field, 2xx answers accept, 401 and 403 reject, and 404
+    /// means the service does not know the user.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the service cannot be reached after the retries, or
+    /// answers with another status or an unexpected body
+    pub async fn authenticate(&self, request: &Packet) -> Result<AuthResult> {
+        let body = render(&self.settings.body, request);
+        let (status, answer) = self.post(&body).await?;
+        
+        let field = |name: &str| answer.get(name).and_then(Value::as_str);
+        let message = field(&self.settings.message_field).map(str::to_string);
+        let attributes = match answer.get(&self.settings.reply_field) {
+            Some(Value::Object(reply)) => reply_attributes(reply),
+            Some(Value::Null) | None => Vec::new(),
+            Some(other) => return Err(format!("{} of the answer is not an object: {}", self.settings.reply_field, other).into()),
+        };
+        
+        let reject = |attributes| Ok(AuthResult::Reject {
+            reason: message.clone().unwrap_or_else(|| format!("Rejected by {} ({})", self.settings.url, status)),
+            attributes,
+        });
+        
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return reject(attributes);
+        }
+        if status == StatusCode::NOT_FOUND {
+            return Ok(AuthResult::NotFound);
+        }
+        if !status.is_success() {
+            return Err(format!("{} answered {}", self.settings.url, status).into());
+        }
+        
+        match field(&self.settings.result_field).map(str::to_ascii_lowercase).as_deref() {
+            None | Some("accept") => Ok(AuthResult::Accept { attributes, groups: vec![] }),
+            Some("reject") => reject(attributes),
+            Some("notfound") => Ok(AuthResult::NotFound),
+            Some("challenge") => {
+                // The service gets the State back in the next request; without one it must rely on User-Name
+                let state = match field(&self.settings.state_field) {
+                    Some(state) => state.as_bytes().to_vec(),
+                    None => {
+                        let mut state = vec![0u8; 16];
+                        rand::thread_rng().fill_bytes(&mut state);
+                        state
+                    },
+                };
+                
+                Ok(AuthResult::Challenge {
+                    message: message.unwrap_or_default(),
+                    state,
+                    attributes,
+                })
+            },
+            Some(other) => Err(format!("Unknown {} from {}: {}", self.settings.result_field, self.settings.url, other).into()),
+        }
+    }
+    
+    /// POST a body, retrying connection errors, timeouts and server errors
+    ///
+    /// # Returns
+    ///
+    /// The final status and the answer's JSON object (empty if there is no body)
+    async fn post(&self, body: &Value) -> Result<(StatusCode, Map<String, Value>)> {
+        let mut delay = Duration::from_millis(self.settings.retry_delay);
+        let mut attempt = 0;
+        
+        loop {
+            let error = match self.http.post(&self.settings.url).json(body).send().await {
+                Ok(response) if !response.status().is_server_error() => {
+                    let status = response.status();
+                    let bytes = response.bytes().await?;
+                    if bytes.iter().all(u8::is_ascii_whitespace) {
+                        return Ok((status, Map::new()));
+                    }
+                    
+                    return match serde_json::from_slice(&bytes) {
+                        Ok(Value::Object(answer)) => Ok((status, answer)),
+                        // Error pages of rejecting proxies and frameworks are not JSON
+                        _ if !status.is_success() => Ok((status, Map::new())),
+                        _ => Err(format!("{} answered {} without a JSON object", self.settings.url, status).into()),
+                    };
+                },
+                Ok(response) => format!("{} answered {}", self.settings.url, response.status()),
+                Err(e) => e.to_string(),
+            };
+            
+            if attempt == self.settings.retries {
+                return Err(format!("REST request failed after {} attempts: {}", attempt + 1, error).into());
+            }
+            
+            tracing::warn!(url = %self.settings.url, error = %error, "REST request failed, retrying");
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+/// Fill the placeholders of a body template
+///
+/// A string that is a single placeholder becomes the attribute's JSON value
+/// (null if the request lacks it); placeholders within longer strings are
+/// replaced by the attribute's text (empty if the request lacks it).
+fn render(template: &Value, request: &Packet) -> Value {
+    match template {
+        Value::String(text) => {
+            if let Some(name) = text.strip_prefix('{').and_then(|rest| rest.strip_suffix('}')).filter(|name| is_attribute_name(name)) {
+                return request.get_attribute(name).map(attribute_json).unwrap_or(Value::Null);
+            }
+            
+            let mut rendered = String::new();
+            let mut rest = text.as_str();
+            while let Some(start) = rest.find('{') {
+                rendered.push_str(&rest[..start]);
+                rest = &rest[start..];
+                
+                match rest.find('}').map(|end| (&rest[1..end], end)) {
+                    Some((name, end)) if is_attribute_name(name) => {
+                        if let Some(attribute) = request.get_attribute(name) {
+                            match attribute_json(attribute) {
+                                Value::String(value) => rendered.push_str(&value),
+                                value => rendered.push_str(&value.to_string()),
+                            }
+                        }
+                        rest = &rest[end + 1..];
+                    },
+                    _ => {
+                        rendered.push('{');
+                        rest = &rest[1..];
+                    },
+                }
+            }
+            rendered.push_str(rest);
+            
+            Value::String(rendered)
+        },
+        Value::Array(values) => Value::Array(values.iter().map(|value| render(value, request)).collect()),
+        Value::Object(fields) => Value::Object(fields.iter()
+            .map(|(key, value)| (key.clone(), render(value, request)))
+            .collect()),
+        other => other.clone(),
+    }
+}
+
+/// Whether a placeholder names an attribute (letters, digits and dashes)
+fn is_attribute_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+}
+
+/// JSON value of a request attribute
+///
+/// Binary values are sent as text if they are valid UTF-8 (State issued by the
+/// service comes back as it was sent), and hex-encoded otherwise.
+fn attribute_json(attribute: &Attribute) -> Value {
+    match attribute {
+        Attribute::String(_, value) => Value::String(value.clone()),
+        Attribute::Integer(_, value) => Value::from(*value),
+        Attribute::IpAddr(_, address) => Value::String(address.to_string()),
+        Attribute::Ipv6Addr(_, address) => Value::String(address.to_string()),
+        Attribute::Ipv6Prefix(_, address, length) => Value::String(format!("{}/{}", address, length)),
+        Attribute::Binary(_, value) => match std::str::from_utf8(value) {
+            Ok(text) => Value::String(text.to_string()),
+            Err(_) => Value::String(hex::encode(value)),
+        },
+        Attribute::VendorSpecific(..) => Value::Null,
+    }
+}
+
+/// Convert the reply object of an answer to attributes
+fn reply_attributes(reply: &Map<String, Value>) -> Vec<Attribute> {
+    let mut attributes = Vec::new();
+    for (name, value) in reply {
+        if let Some(value) = ReplyValue::from_json(value) {
+            value.to_attributes(name, &mut attributes);
+        }
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    
+    /// Stub service: alice/secret is accepted, bob gets an OTP challenge, and
+    /// the first request to /flaky fails
+    async fn serve() -> String {
+        async fn auth(Json(body): Json<Value>) -> (StatusCode, Json<Value>) {
+            let field = |name: &str| body[name].as_str().unwrap_or("").to_string();
+            
+            match (field("username").as_str(), field("password").as_str(), field("state").as_str()) {
+                ("alice", "secret", _) => (StatusCode::OK, Json(serde_json::json!({
+                    "reply": {
+                        "Session-Timeout": 3600,
+                        "Filter-Id": ["staff", format!("nas-{}", field("nas"))],
+                    },
+                }))),
+                ("bob", "secret", "") => (StatusCode::OK, Json(serde_json::json!({
+                    "result": "challenge",
+                    "message": "Enter your one-time code",
+                    "state": "otp-bob",
+                }))),
+                ("bob", "123456", "otp-bob") => (StatusCode::OK, Json(serde_json::json!({ "result": "accept" }))),
+                ("carol", _, _) => (StatusCode::OK, Json(serde_json::json!({ "result": "reject", "message": "Account disabled" }))),
+                ("dave", _, _) => (StatusCode::NOT_FOUND, Json(serde_json::json!({}))),
+                _ => (StatusCode::UNAUTHORIZED, Json(serde_json::json!({ "message": "Invalid credentials" }))),
+            }
+        }
+        
+        async fn flaky(State(calls): State<Arc<AtomicUsize>>) -> StatusCode {
+            match calls.fetch_add(1, Ordering::SeqCst) {
+                0 => StatusCode::SERVICE_UNAVAILABLE,
+                _ => StatusCode::NO_CONTENT,
+            }
+        }
+        
+        let router = Router::new()
+            .route("/auth", post(auth))
+            .route("/flaky", post(flaky))
+            .with_state(Arc::new(AtomicUsize::new(0)));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        
+        url
+    }
+    
+    fn client(url: String) -> RestClient {
+        let options = format!(r#"
+            url = "{}"
+            retry_delay = 10
+            [body]
+            username = "{{User-Name}}"
+            password = "{{User-Password}}"
+            state = "{{State}}"
+            nas = "{{NAS-Identifier}}-{{NAS-Port}}"
+        "#, url);
+        RestClient::new(toml::from_str(&options).unwrap()).unwrap()
+    }
+    
+    fn request(username: &str, password: &str, state: Option<&[u8]>) -> Packet {
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+        request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+        request.add_attribute(Attribute::String("NAS-Identifier".to_string(), "ap1".to_string()));
+        request.add_attribute(Attribute::Integer("NAS-Port".to_string(), 7));
+        if let Some(state) = state {
+            request.add_attribute(Attribute::Binary("State".to_string(), state.to_vec()));
+        }
+        request
+    }
+    
+    #[tokio::test]
+    async fn accept_reject_challenge() {
+        let url = serve().await;
+        let client = client(format!("{}/auth", url));
+        
+        match client.authenticate(&request("alice", "secret", None)).await.unwrap() {
+            AuthResult::Accept { attributes, .. } => assert_eq!(attributes, vec![
+                Attribute::String("Filter-Id".to_string(), "staff".to_string()),
+                Attribute::String("Filter-Id".to_string(), "nas-ap1-7".to_string()),
+                Attribute::Integer("Session-Timeout".to_string(), 3600),
+            ]),
+            other => panic!("{:?}", other),
+        }
+        
+        // Status 401 and an explicit result both reject, with the service's message
+        for (username, reason) in [("alice", "Invalid credentials"), ("carol", "Account disabled")] {
+            match client.authenticate(&request(username, "wrong", None)).await.unwrap() {
+                AuthResult::Reject { reason: actual, .. } => assert_eq!(actual, reason),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(client.authenticate(&request("dave", "secret", None)).await.unwrap(), AuthResult::NotFound);
+        
+        // The State of the challenge comes back to the service
+        let state = match client.authenticate(&request("bob", "secret", None)).await.unwrap() {
+            AuthResult::Challenge { message, state, .. } => {
+                assert_eq!(message, "Enter your one-time code");
+                state
+            },
+            other => panic!("{:?}", other),
+        };
+        assert!(matches!(client.authenticate(&request("bob", "123456", Some(&state))).await.unwrap(), AuthResult::Accept { .. }));
+    }
+    
+    #[tokio::test]
+    async fn retries() {
+        let url = serve().await;
+        
+        // The first attempt gets a 503, the retry a 204
+        let client = client(format!("{}/flaky", url));
+        assert!(matches!(client.authenticate(&request("alice", "secret", None)).await.unwrap(), AuthResult::Accept { .. }));
+        
+        // Unreachable services are an error, not a Reject
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = format!("http://{}/auth", listener.local_addr().unwrap());
+        drop(listener);
+        let settings = toml::from_str(&format!("url = \"{}\"\nretries = 1\nretry_delay = 10", closed)).unwrap();
+        assert!(RestClient::new(settings).unwrap().authenticate(&request("alice", "secret", None)).await.is_err());
+    }
+}
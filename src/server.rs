@@ -2,27 +2,43 @@
 //
 // This module implements the main RADIUS server functionality with focus on
 // high-performance, async processing, and concurrency.
+//
+// Everything built from the configuration file lives in a `ServerState` held
+// behind an `ArcSwap`. SIGHUP reloads the file into a new state and swaps it
+// in; requests already being processed finish on the state they started with.
+//
+// Policy rules run at three points: before an Access-Request reaches the
+// backends, before an Access-Accept is sent, and before an Accounting-Request
+// is processed. WebAssembly policy plugins run after the rules at each point.
 
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::Instant;
 
-use log::{debug, error, info, warn};
+use arc_swap::ArcSwap;
+use async_trait::async_trait;
 use tokio::net::UdpSocket;
 use tokio::sync::mpsc;
 use tokio::time::{self, Duration};
 
 use crate::auth::{AuthManager, AuthResult};
 use crate::config::Config;
-use crate::metrics::MetricsManager;
-use crate::protocol::{Packet, PacketProcessor};
+use crate::eap;
+use crate::metrics::MetricsCollector;
+#[cfg(feature = "wasm-plugins")]
+use crate::plugins::PolicyPlugin;
+use crate::policy::{Hook, Outcome, Policy, Verdict};
+use crate::protocol::{Attribute, Packet, PacketProcessor};
+use crate::reload::{self, ReloadWatcher};
 use crate::Result;
 
 /// Trait defining the core functionality for a RADIUS server handler
-/// 
+///
 /// This trait allows for different server implementations (e.g., standard, high-performance, test mock)
 /// while maintaining a consistent interface
+#[async_trait]
 pub trait RadiusServerHandler {
     /// Handle an authentication request
     async fn handle_auth_request(&self, request: &Packet) -> Result<Packet>;
@@ -34,179 +50,283 @@ pub trait RadiusServerHandler {
     async fn handle_coa_request(&self, request: &Packet) -> Result<Packet>;
 }
 
-impl RadiusServerHandler for Server {
-    /// Process an incoming RADIUS packet using the RadiusServerHandler trait
-    async fn process_packet(&self, buf: &[u8], src: SocketAddr) -> Result<Vec<u8>> {
+/// Maximum UDP packet size for RADIUS (RFC 2865)
+const MAX_PACKET_SIZE: usize = 4096;
+
+/// Everything the server builds from its configuration
+///
+/// A request takes the current state when it arrives and keeps it until its
+/// response is sent, so a reload never changes the configuration under it.
+pub struct ServerState {
+    /// Server configuration
+    config: Arc<Config>,
+    
+    /// Packet processor for encoding/decoding, holding the dictionary
+    processor: PacketProcessor,
+    
+    /// Authentication manager
+    auth_manager: Arc<AuthManager>,
+    
+    /// Policy rules
+    policy: Policy,
+    
+    /// Policy plugins, run in order after the rules
+    #[cfg(feature = "wasm-plugins")]
+    plugins: Vec<PolicyPlugin>,
+
+    /// Metrics collector, shared by every state
+    metrics: Arc<MetricsCollector>,
+    
+    /// Reloads the backends' data files; stops when the state is dropped
+    _watcher: ReloadWatcher,
+}
+
+impl ServerState {
+    /// Build the state for a configuration
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration
+    /// * `metrics` - Metrics collector
+    /// * `previous` - State being replaced, whose EAP conversations are continued
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a backend fails to initialize, its files cannot be
+    /// watched or the policy rules or plugins cannot be loaded
+    pub async fn build(config: Arc<Config>, metrics: Arc<MetricsCollector>, previous: Option<&ServerState>) -> Result<Self> {
+        let policy = Policy::from_config(&config)?;
+        let plugin_configs = config.policy.as_ref().map(|policy| policy.plugins.as_slice()).unwrap_or_default();
+        #[cfg(feature = "wasm-plugins")]
+        let plugins = plugin_configs.iter()
+            .map(PolicyPlugin::load)
+            .collect::<Result<Vec<_>>>()?;
+        #[cfg(not(feature = "wasm-plugins"))]
+        if !plugin_configs.is_empty() {
+            return Err("Policy plugins require the wasm-plugins feature".into());
+        }
+
+        let mut auth_manager = AuthManager::new(config.clone()).await?;
+        if let Some(previous) = previous {
+            auth_manager.adopt_conversations(&previous.auth_manager);
+        }
+        
+        let auth_manager = Arc::new(auth_manager);
+        let watcher = reload::watch(auth_manager.clone())?;
+        
+        Ok(Self {
+            processor: PacketProcessor::new(config.clone()),
+            config,
+            auth_manager,
+            policy,
+            #[cfg(feature = "wasm-plugins")]
+            plugins,
+            metrics,
+            _watcher: watcher,
+        })
+    }
+    
+    /// Get the configuration the state was built from
+    pub fn config(&self) -> &Arc<Config> {
+        &self.config
+    }
+    
+    /// Get the authentication manager
+    pub fn auth_manager(&self) -> &Arc<AuthManager> {
+        &self.auth_manager
+    }
+    
+    /// Process an incoming RADIUS packet
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - Raw packet data
+    /// * `src` - Source address
+    ///
+    /// # Returns
+    ///
+    /// Encoded response packet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the packet cannot be parsed or handled
+    pub async fn process_packet(&self, data: &[u8], src: SocketAddr) -> Result<Vec<u8>> {
         // GOAL: High-Performance and Concurrency
-        // Process incoming packets efficiently using the trait-based approach
-        
-        // Increment active connections counter
-        self.connections.fetch_add(1, Ordering::SeqCst);
-        
-        // Parse the incoming packet
-        let packet = match self.packet_processor.decode(buf, Some(src)) {
-            Ok(p) => p,
-            Err(e) => {
-                error!("Failed to decode packet: {}", e);
-                self.connections.fetch_sub(1, Ordering::SeqCst);
-                return Err(e);
-            }
+        // Process packets efficiently with minimal allocations
+        
+        let request = self.processor.parse(data, src)?;
+        
+        // Log request (debug level to avoid excessive logging)
+        tracing::debug!(
+            code = ?request.code(),
+            packet_id = request.identifier(),
+            src = ?src,
+            "Processing request"
+        );
+        
+        let response = match request.code() {
+            Packet::ACCESS_REQUEST => self.handle_auth_request(&request).await?,
+            Packet::ACCOUNTING_REQUEST => self.handle_acct_request(&request).await?,
+            Packet::COA_REQUEST => self.handle_coa_request(&request).await?,
+            code => return Err(format!("Unsupported packet type: {:?}", code).into()),
         };
         
-        // Log the request
-        debug!("Received {:?} packet from {}", packet.code(), src);
+        tracing::debug!(
+            code = ?response.code(),
+            packet_id = response.identifier(),
+            src = ?src,
+            "Sending response"
+        );
         
-        // Use our trait-based handler methods for each packet type
-        let response = match packet.code() {
-            Packet::ACCESS_REQUEST => {
-                match self.handle_auth_request(&packet).await {
-                    Ok(resp) => resp,
-                    Err(e) => {
-                        error!("Authentication error: {}", e);
-                        self.connections.fetch_sub(1, Ordering::SeqCst);
-                        return Err(e);
-                    }
-                }
-            },
-            Packet::ACCOUNTING_REQUEST => {
-                match self.handle_acct_request(&packet).await {
-                    Ok(resp) => resp,
-                    Err(e) => {
-                        error!("Accounting error: {}", e);
-                        self.connections.fetch_sub(1, Ordering::SeqCst);
-                        return Err(e);
-                    }
-                }
-            },
-            Packet::COA_REQUEST => {
-                match self.handle_coa_request(&packet).await {
-                    Ok(resp) => resp,
-                    Err(e) => {
-                        error!("CoA error: {}", e);
-                        self.connections.fetch_sub(1, Ordering::SeqCst);
-                        return Err(e);
-                    }
-                }
-            },
-            _ => {
-                // Unsupported packet type
-                let err = format!("Unsupported packet type: {:?}", packet.code());
-                error!("{}", err);
-                self.connections.fetch_sub(1, Ordering::SeqCst);
-                return Err(err.into());
-            }
+        self.processor.encode(&response)
+    }
+    
+    /// Run the policy rules of a hook, then its plugins until one decides
+    ///
+    /// A plugin that fails rejects the request.
+    ///
+    /// # Arguments
+    ///
+    /// * `hook` - Hook to run
+    /// * `request` - Request, edited by the rules except in post-auth
+    /// * `reply` - Access-Accept, edited by the post-auth rules
+    #[cfg_attr(not(feature = "wasm-plugins"), allow(unused_mut, clippy::needless_option_as_deref))]
+    async fn run_policy(&self, hook: Hook, request: &mut Packet, mut reply: Option<&mut Packet>) -> Outcome {
+        let mut outcome = if self.policy.is_empty(hook) {
+            Outcome::default()
+        } else {
+            // Groups come from the backends, so they are only looked up for rules that test them
+            let groups = if self.policy.uses_groups(hook) {
+                let username = request.get_text("User-Name").unwrap_or_default();
+                self.auth_manager.groups(request, &username).await
+            } else {
+                vec![]
+            };
+            
+            let outcome = self.policy.run(hook, request, reply.as_deref_mut(), &groups, &chrono::Local::now());
+            tracing::debug!(
+                hook = %hook,
+                username = ?request.get_text("User-Name"),
+                outcome = ?outcome,
+                "Policy rules applied"
+            );
+            outcome
         };
         
-        // Encode the response packet
-        let response_buf = match self.packet_processor.encode(&response) {
-            Ok(buf) => buf,
-            Err(e) => {
-                error!("Failed to encode response: {}", e);
-                self.connections.fetch_sub(1, Ordering::SeqCst);
-                return Err(e);
+        #[cfg(feature = "wasm-plugins")]
+        for plugin in &self.plugins {
+            if outcome.verdict != Verdict::Continue {
+                break;
             }
+            
+            outcome.verdict = match plugin.run(hook, request, reply.as_deref_mut()).await {
+                Ok(verdict) => verdict,
+                Err(e) => {
+                    tracing::error!(hook = %hook, plugin = plugin.name(), error = %e, "Policy plugin failed");
+                    Verdict::Reject("Policy plugin error".to_string())
+                },
+            };
+            tracing::debug!(
+                hook = %hook,
+                plugin = plugin.name(),
+                verdict = ?outcome.verdict,
+                "Policy plugin applied"
+            );
+        }
+        
+        outcome
+    }
+    
+    /// Authenticate a request between the pre-auth and post-auth rules
+    async fn authenticate(&self, request: &Packet) -> Result<Packet> {
+        let mut request = request.clone();
+        let outcome = self.run_policy(Hook::PreAuth, &mut request, None).await;
+        
+        let mut response = match (outcome.verdict, outcome.realm) {
+            (Verdict::Accept, _) => self.auth_manager.accept(&request).await?,
+            (Verdict::Reject(reason), _) => {
+                tracing::info!(username = ?request.get_text("User-Name"), reason = reason, "Policy rejected request");
+                self.auth_manager.respond(&request, AuthResult::Reject { reason, attributes: vec![] })?
+            },
+            (Verdict::Continue, Some(target)) => self.auth_manager.respond(&request, AuthResult::Forward { target })?,
+            (Verdict::Continue, None) => self.auth_manager.authenticate_with(&request, outcome.backend.as_deref()).await?,
         };
         
-        // Log the response
-        debug!("Sending {:?} response to {}", response.code(), src);
+        if response.code() != Packet::ACCESS_ACCEPT {
+            return Ok(response);
+        }
         
-        // Decrement active connections counter
-        self.connections.fetch_sub(1, Ordering::SeqCst);
+        if let Verdict::Reject(reason) = self.run_policy(Hook::PostAuth, &mut request, Some(&mut response)).await.verdict {
+            tracing::info!(username = ?request.get_text("User-Name"), reason = reason, "Policy rejected accepted user");
+            
+            // An EAP peer told of success would wait for a session that never comes
+            let attributes = match response.get_attribute("EAP-Message") {
+                Some(Attribute::Binary(_, data)) => eap::EapPacket::parse(data)
+                    .map(|success| eap::fragment(&eap::EapPacket::failure(success.identifier).to_bytes()))
+                    .unwrap_or_default(),
+                _ => vec![],
+            };
+            response = self.auth_manager.respond(&request, AuthResult::Reject { reason, attributes })?;
+        }
         
-        Ok(response_buf)
+        Ok(response)
     }
+}
 
+#[async_trait]
+impl RadiusServerHandler for ServerState {
     /// Handle an authentication request by routing it to the appropriate authentication backend
     async fn handle_auth_request(&self, request: &Packet) -> Result<Packet> {
-        // Record request metrics
-        self.metrics.record_auth_request();
+        self.metrics.increment_auth_requests();
         let start_time = Instant::now();
         
-        // Process the authentication request through the auth manager
-        let result = match self.auth_manager.authenticate(request).await {
-            Ok(auth_result) => {
-                match auth_result {
-                    AuthResult::Accept { attributes } => {
-                        // Create accept response
-                        let mut response = request.create_response(Packet::ACCESS_ACCEPT);
-                        for attr in attributes {
-                            response.add_attribute(attr);
-                        }
-                        self.metrics.record_auth_success();
-                        Ok(response)
-                    },
-                    AuthResult::Reject { reason, attributes } => {
-                        // Create reject response
-                        let mut response = request.create_response(Packet::ACCESS_REJECT);
-                        for attr in attributes {
-                            response.add_attribute(attr);
-                        }
-                        self.metrics.record_auth_failure();
-                        Ok(response)
-                    },
-                    AuthResult::Challenge { state, attributes } => {
-                        // Create challenge response
-                        let mut response = request.create_response(Packet::ACCESS_CHALLENGE);
-                        for attr in attributes {
-                            response.add_attribute(attr);
-                        }
-                        self.metrics.record_auth_challenge();
-                        Ok(response)
-                    },
-                }
-            },
-            Err(err) => {
-                // Handle error
-                self.metrics.record_auth_error();
-                Err(err)
-            }
-        };
+        let response = self.authenticate(request).await?;
         
-        // Record request processing time
-        let duration = start_time.elapsed();
-        self.metrics.record_request_latency(duration.as_millis() as u64);
+        let result = match response.code() {
+            Packet::ACCESS_ACCEPT => "accept",
+            Packet::ACCESS_CHALLENGE => "challenge",
+            _ => "reject",
+        };
+        self.metrics.increment_auth_responses(result);
+        self.metrics.record_request_latency(start_time.elapsed().as_millis() as u64);
         
-        result
+        Ok(response)
     }
-
+    
     /// Handle an accounting request
     async fn handle_acct_request(&self, request: &Packet) -> Result<Packet> {
-        // Record metrics
-        self.metrics.record_acct_request();
+        self.metrics.increment_acct_requests();
+        
+        // A request the policy rejects is discarded; without a response the NAS retries or gives up
+        let mut request = request.clone();
+        if let Verdict::Reject(reason) = self.run_policy(Hook::PreAcct, &mut request, None).await.verdict {
+            return Err(format!("Accounting request discarded by policy: {}", reason).into());
+        }
         
-        // For now, just acknowledge the accounting request
-        // In a real implementation, we would process and store accounting data
-        let response = request.create_response(Packet::ACCOUNTING_RESPONSE);
+        // Backends that keep usage, such as vouchers with a data budget, record it
+        self.auth_manager.account(&request).await;
         
-        Ok(response)
+        Ok(request.create_response(Packet::ACCOUNTING_RESPONSE))
     }
-
+    
     /// Handle a Change of Authorization (CoA) request
     async fn handle_coa_request(&self, request: &Packet) -> Result<Packet> {
         // For now, return a CoA-NAK (Not Acknowledged) response
         // In a real implementation, we would validate and process the CoA request
-        let response = request.create_response(Packet::COA_NAK);
-        
-        Ok(response)
+        Ok(request.create_response(Packet::COA_NAK))
     }
 }
 
-/// Maximum UDP packet size for RADIUS (RFC 2865)
-const MAX_PACKET_SIZE: usize = 4096;
-
 /// Main RADIUS server implementation
 pub struct Server {
-    /// Server configuration
-    config: Arc<Config>,
-    
-    /// Authentication manager
-    auth_manager: Arc<AuthManager>,
+    /// Current configuration-dependent state, swapped on reload
+    state: Arc<ArcSwap<ServerState>>,
     
-    /// Packet processor for encoding/decoding
-    packet_processor: Arc<PacketProcessor>,
+    /// Configuration file re-read on SIGHUP
+    config_path: Option<PathBuf>,
     
     /// Metrics collector
-    metrics: Arc<MetricsManager>,
+    metrics: Arc<MetricsCollector>,
     
     /// Authentication socket
     auth_socket: Option<UdpSocket>,
@@ -214,9 +334,6 @@ pub struct Server {
     /// Accounting socket
     acct_socket: Option<UdpSocket>,
     
-    /// CoA socket
-    coa_socket: Option<UdpSocket>,
-    
     /// Shutdown signal
     shutdown: Option<mpsc::Receiver<()>>,
     
@@ -227,8 +344,8 @@ pub struct Server {
 /// Builder for Server configuration
 pub struct ServerBuilder {
     config: Config,
-    auth_manager: Option<AuthManager>,
-    metrics: Option<MetricsManager>,
+    config_path: Option<PathBuf>,
+    metrics: Option<MetricsCollector>,
 }
 
 impl ServerBuilder {
@@ -236,53 +353,48 @@ impl ServerBuilder {
     pub fn new(config: Config) -> Self {
         Self {
             config,
-            auth_manager: None,
+            config_path: None,
             metrics: None,
         }
     }
     
-    /// Set a custom authentication manager
-    pub fn with_auth_manager(mut self, auth_manager: AuthManager) -> Self {
-        self.auth_manager = Some(auth_manager);
+    /// Set the configuration file to re-read on SIGHUP
+    pub fn with_config_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.config_path = Some(path.as_ref().to_path_buf());
         self
     }
     
-    /// Set a custom metrics manager
-    pub fn with_metrics(mut self, metrics: MetricsManager) -> Self {
+    /// Set a custom metrics collector
+    pub fn with_metrics(mut self, metrics: MetricsCollector) -> Self {
         self.metrics = Some(metrics);
         self
     }
     
     /// Build the Server instance
-    pub fn build(self) -> Server {
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the authentication manager cannot be created
+    pub async fn build(self) -> Result<Server> {
         let config = Arc::new(self.config);
         
-        // Create default auth manager if none provided
-        let auth_manager = match self.auth_manager {
-            Some(am) => Arc::new(am),
-            None => Arc::new(AuthManager::new(config.clone())),
-        };
-        
-        // Create default metrics manager if none provided
+        // Create default metrics collector if none provided
         let metrics = match self.metrics {
             Some(m) => Arc::new(m),
-            None => Arc::new(MetricsManager::new(config.clone())),
+            None => Arc::new(MetricsCollector::new(config.clone())),
         };
         
-        // Create packet processor
-        let packet_processor = Arc::new(PacketProcessor::new(config.clone()));
+        let state = ServerState::build(config, metrics.clone(), None).await?;
         
-        Server {
-            config,
-            auth_manager,
-            packet_processor,
+        Ok(Server {
+            state: Arc::new(ArcSwap::from_pointee(state)),
+            config_path: self.config_path,
             metrics,
             auth_socket: None,
             acct_socket: None,
-            coa_socket: None,
             shutdown: None,
             connections: Arc::new(AtomicU64::new(0)),
-        }
+        })
     }
 }
 
@@ -296,7 +408,50 @@ impl Server {
     /// # Returns
     ///
     /// A new Server instance
-
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the authentication manager cannot be created
+    pub async fn new(config: Config) -> Result<Self> {
+        ServerBuilder::new(config).build().await
+    }
+    
+    /// Create a RADIUS server from a configuration file, reloaded on SIGHUP
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the configuration file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration is invalid or the server cannot be created
+    pub async fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let config = Config::from_file(&path)?;
+        ServerBuilder::new(config).with_config_path(path).build().await
+    }
+    
+    /// Create a new ServerBuilder
+    pub fn builder(config: Config) -> ServerBuilder {
+        ServerBuilder::new(config)
+    }
+    
+    /// Get the current state
+    pub fn state(&self) -> Arc<ServerState> {
+        self.state.load_full()
+    }
+    
+    /// Re-read the configuration file and swap in the new state
+    ///
+    /// The current state is kept if the configuration is invalid or cannot be
+    /// loaded. Without a configuration file only the backends' data is reloaded.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the configuration or the new state cannot be built
+    pub async fn reload(&self) -> Result<()> {
+        Self::reload_state(&self.state, self.config_path.as_deref()).await
+    }
+    
     /// Bind to the authentication and accounting ports
     ///
     /// # Returns
@@ -305,9 +460,10 @@ impl Server {
     pub async fn bind(&mut self) -> Result<()> {
         // GOAL: High-Performance and Concurrency
         // Use socket2 for advanced socket options to optimize performance
-        let auth_addr = format!("{}:{}", self.config.server.host, self.config.server.auth_port);
-        let acct_addr = format!("{}:{}", self.config.server.host, self.config.server.acct_port);
-
+        let config = self.state.load().config.clone();
+        let auth_addr = format!("{}:{}", config.server.host, config.server.auth_port);
+        let acct_addr = format!("{}:{}", config.server.host, config.server.acct_port);
+        
         // Create and configure authentication socket
         let auth_socket = UdpSocket::bind(&auth_addr).await?;
         
@@ -326,14 +482,14 @@ impl Server {
         self.acct_socket = Some(acct_socket);
         
         tracing::info!(
-            auth_port = self.config.server.auth_port,
-            acct_port = self.config.server.acct_port,
+            auth_port = config.server.auth_port,
+            acct_port = config.server.acct_port,
             "RADIUS server bound to ports"
         );
-
+        
         Ok(())
     }
-
+    
     /// Run the RADIUS server
     ///
     /// This method starts the server and processes incoming requests
@@ -352,30 +508,41 @@ impl Server {
         }
         
         // Unwrap sockets - we know they're initialized from the check above
-        let auth_socket = self.auth_socket.take().unwrap();
-        let acct_socket = self.acct_socket.take().unwrap();
+        let auth_socket = Arc::new(self.auth_socket.take().unwrap());
+        let acct_socket = Arc::new(self.acct_socket.take().unwrap());
         
         // Create a channel for shutdown signaling
         let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
         self.shutdown = Some(shutdown_rx);
         
-        // Register signal handlers for graceful shutdown
-        Self::register_shutdown_handler(shutdown_tx.clone());
+        // Register signal handlers for graceful shutdown and reload
+        Self::register_shutdown_handler(shutdown_tx);
+        Self::register_reload_handler(self.state.clone(), self.config_path.clone());
         
-        // Create shared sockets
-        let auth_socket = Arc::new(auth_socket);
-        let acct_socket = Arc::new(acct_socket);
+        // The admin API serves files rather than the state, so it is not restarted on reload
+        if let Some(admin) = self.state.load().config.admin.clone().filter(|admin| admin.enabled) {
+            #[cfg(feature = "admin-api")]
+            tokio::spawn(async move {
+                if let Err(e) = crate::admin::serve(admin).await {
+                    tracing::error!(error = %e, "Admin API stopped");
+                }
+            });
+            
+            #[cfg(not(feature = "admin-api"))]
+            tracing::warn!(port = admin.port, "Admin API is configured but the admin-api feature is disabled");
+        }
         
         // GOAL: Comprehensive Observability
         // Start metrics reporter task
         let metrics = self.metrics.clone();
-        let config = self.config.clone();
+        let connections = self.connections.clone();
+        let interval = Duration::from_secs(self.state.load().config.metrics.interval_secs.max(1));
         tokio::spawn(async move {
-            let interval = Duration::from_secs(config.metrics.interval_secs);
             let mut interval_timer = time::interval(interval);
             
             loop {
                 interval_timer.tick().await;
+                metrics.set_active_connections(connections.load(Ordering::Relaxed));
                 if let Err(e) = metrics.report().await {
                     tracing::error!(?e, "Failed to report metrics");
                 }
@@ -383,82 +550,13 @@ impl Server {
         });
         
         // Spawn worker tasks based on CPU cores
-        let worker_count = self.config.server.worker_threads.unwrap_or_else(num_cpus::get);
+        let worker_count = self.state.load().config.server.worker_threads.unwrap_or_else(num_cpus::get);
         tracing::info!(workers = worker_count, "Starting RADIUS server workers");
         
-        // Spawn authentication worker tasks
         for i in 0..worker_count {
-            // Clone required components
-            let auth_socket = auth_socket.clone();
-            let processor = self.packet_processor.clone();
-            let auth_manager = self.auth_manager.clone();
-            let metrics = self.metrics.clone();
-            let active_connections = self.active_connections.clone();
-            
-            // Spawn authentication worker
-            tokio::spawn(async move {
-                let worker_id = format!("auth-{}", i);
-                tracing::debug!(worker = worker_id, "Authentication worker started");
-                
-                // Allocate buffer for receiving packets
-                let mut buf = vec![0u8; MAX_PACKET_SIZE];
-                
-                loop {
-                    // Receive packet
-                    let (size, src_addr) = match auth_socket.recv_from(&mut buf).await {
-                        Ok((size, addr)) => (size, addr),
-                        Err(e) => {
-                            tracing::error!(?e, "Failed to receive packet");
-                            continue;
-                        }
-                    };
-                    
-                    // Update active connections metric
-                    {
-                        let mut count = active_connections.lock().await;
-                        *count += 1;
-                    }
-                    
-                    // Start timing for request latency
-                    let start_time = std::time::Instant::now();
-                    
-                    // Process the packet
-                    if let Err(e) = Self::process_auth_packet(
-                        &worker_id,
-                        &buf[..size],
-                        src_addr,
-                        &auth_socket,
-                        &processor,
-                        &auth_manager,
-                        &metrics,
-                    ).await {
-                        tracing::error!(?e, src=?src_addr, "Failed to process authentication packet");
-                    }
-                    
-                    // Record request latency
-                    let elapsed = start_time.elapsed();
-                    metrics.record_request_latency(elapsed.as_millis() as u64);
-                    
-                    // Update active connections metric
-                    {
-                        let mut count = active_connections.lock().await;
-                        *count -= 1;
-                    }
-                }
-            });
+            Self::spawn_worker(format!("auth-{}", i), auth_socket.clone(), self.state.clone(), self.connections.clone());
         }
-        
-        // Similar pattern for accounting packets (simplified here)
-        tokio::spawn(async move {
-            let mut buf = vec![0u8; MAX_PACKET_SIZE];
-            
-            loop {
-                if let Ok((size, src)) = acct_socket.recv_from(&mut buf).await {
-                    tracing::debug!(bytes = size, src = ?src, "Received accounting packet");
-                    // Process accounting packet...
-                }
-            }
-        });
+        Self::spawn_worker("acct".to_string(), acct_socket, self.state.clone(), self.connections.clone());
         
         // Wait for shutdown signal
         if let Some(mut shutdown) = self.shutdown.take() {
@@ -472,58 +570,99 @@ impl Server {
         Ok(())
     }
     
-    /// Process an authentication packet
-    ///
-    /// This method processes a RADIUS authentication packet and sends a response.
-    async fn process_auth_packet(
-        worker_id: &str,
-        data: &[u8],
-        src_addr: SocketAddr,
-        socket: &UdpSocket,
-        processor: &PacketProcessor,
-        auth_manager: &AuthManager,
-        metrics: &MetricsCollector,
-    ) -> Result<()> {
-        // GOAL: High-Performance and Concurrency
-        // Process packets efficiently with minimal allocations
-
-        // Parse the packet
-        let request = processor.parse(data, src_addr)?;
-        
-        // Log request (debug level to avoid excessive logging)
-        tracing::debug!(
-            worker = worker_id,
-            packet_id = request.identifier(),
-            src = ?src_addr,
-            "Processing authentication request"
-        );
-        
-        // Record the request in metrics
-        metrics.increment_auth_requests();
-        
-        // Authenticate the request
-        let response = auth_manager.authenticate(&request).await?;
-        
-        // Encode the response packet
-        let response_data = processor.encode(&response)?;
+    /// Spawn a task answering the packets received on a socket
+    fn spawn_worker(worker_id: String, socket: Arc<UdpSocket>, state: Arc<ArcSwap<ServerState>>, connections: Arc<AtomicU64>) {
+        tokio::spawn(async move {
+            tracing::debug!(worker = worker_id, "Worker started");
+            
+            // Allocate buffer for receiving packets
+            let mut buf = vec![0u8; MAX_PACKET_SIZE];
+            
+            loop {
+                let (size, src_addr) = match socket.recv_from(&mut buf).await {
+                    Ok((size, addr)) => (size, addr),
+                    Err(e) => {
+                        tracing::error!(?e, "Failed to receive packet");
+                        continue;
+                    }
+                };
+                
+                connections.fetch_add(1, Ordering::SeqCst);
+                
+                // The request finishes on this state even if a reload swaps it meanwhile
+                let current = state.load_full();
+                match current.process_packet(&buf[..size], src_addr).await {
+                    Ok(response) => {
+                        if let Err(e) = socket.send_to(&response, src_addr).await {
+                            tracing::error!(?e, src = ?src_addr, "Failed to send response");
+                        }
+                    },
+                    Err(e) => tracing::error!(worker = worker_id, error = %e, src = ?src_addr, "Failed to process packet"),
+                }
+                
+                connections.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+    }
+    
+    /// Re-read the configuration file into a new state and swap it in
+    async fn reload_state(state: &ArcSwap<ServerState>, config_path: Option<&Path>) -> Result<()> {
+        let current = state.load_full();
+        
+        let path = match config_path {
+            Some(path) => path,
+            None => {
+                tracing::info!("No configuration file to reload; reloading backend data");
+                current.auth_manager.reload_backends().await;
+                return Ok(());
+            },
+        };
         
-        // Send the response
-        socket.send_to(&response_data, src_addr).await?;
+        // Parses and validates the configuration
+        let config = Config::from_file(path)?;
         
-        // Record the response in metrics
-        metrics.increment_auth_responses();
+        if config.server.host != current.config.server.host
+            || config.server.auth_port != current.config.server.auth_port
+            || config.server.acct_port != current.config.server.acct_port
+        {
+            tracing::warn!("Listening address changes take effect only after a restart");
+        }
         
-        // Log response
-        tracing::debug!(
-            worker = worker_id,
-            packet_id = response.identifier(),
-            src = ?src_addr,
-            "Sent authentication response"
-        );
+        let next = ServerState::build(Arc::new(config), current.metrics.clone(), Some(&current)).await?;
+        state.store(Arc::new(next));
         
+        tracing::info!(path = %path.display(), "Configuration reloaded");
         Ok(())
     }
     
+    /// Register a SIGHUP handler that reloads the configuration
+    fn register_reload_handler(state: Arc<ArcSwap<ServerState>>, config_path: Option<PathBuf>) {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+            
+            tokio::spawn(async move {
+                let mut sighup = match signal(SignalKind::hangup()) {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        tracing::error!("Failed to setup SIGHUP handler: {}", err);
+                        return;
+                    }
+                };
+                
+                while sighup.recv().await.is_some() {
+                    tracing::info!("Received SIGHUP, reloading configuration");
+                    if let Err(e) = Self::reload_state(&state, config_path.as_deref()).await {
+                        tracing::error!(error = %e, "Failed to reload configuration; keeping the current one");
+                    }
+                }
+            });
+        }
+        
+        #[cfg(not(unix))]
+        let _ = (state, config_path);
+    }
+    
     /// Register signal handlers for graceful shutdown
     fn register_shutdown_handler(shutdown_tx: mpsc::Sender<()>) {
         // Setup Ctrl+C handler
@@ -570,10 +709,10 @@ impl Server {
         
         // Wait for active connections to complete (with timeout)
         let start = std::time::Instant::now();
-        let timeout = Duration::from_secs(self.config.server.shutdown_timeout_secs);
+        let timeout = Duration::from_secs(self.state.load().config.server.shutdown_timeout_secs);
         
         loop {
-            let active = *self.active_connections.lock().await;
+            let active = self.connections.load(Ordering::SeqCst);
             if active == 0 {
                 break;
             }
@@ -590,7 +729,139 @@ impl Server {
             time::sleep(Duration::from_millis(100)).await;
         }
         
+        // Quota usage and locks are written behind, so write what is still pending
+        self.state.load().auth_manager.flush().await;
+        
         tracing::info!("Server shutdown complete");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[tokio::test]
+    async fn reload_swaps_valid_configuration_only() {
+        let directory = std::env::temp_dir().join(format!("rust-radius-server-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let config_file = directory.join("radius.toml");
+        
+        let users_file = directory.join("users.json");
+        std::fs::write(&users_file, r#"{"alice": "{cleartext}one"}"#).unwrap();
+        let config_text = |secret: &str| {
+            let mut config = Config::default();
+            config.server.secret = secret.to_string();
+            // RadSec defaults on with the radsec feature, and needs certificates this test has none of
+            config.security.radsec_enabled = false;
+            
+            let mut backend_config = std::collections::HashMap::new();
+            backend_config.insert("users_file".to_string(), toml::Value::String(users_file.display().to_string()));
+            config.auth_backends.insert("local".to_string(), crate::config::AuthBackendConfig {
+                backend_type: "local".to_string(),
+                enabled: true,
+                chain: Default::default(),
+                config: backend_config,
+            });
+            
+            toml::to_string(&config).unwrap()
+        };
+        
+        std::fs::write(&config_file, config_text("first-shared-secret")).unwrap();
+        let server = Server::from_file(&config_file).await.unwrap();
+        let before = server.state();
+        
+        std::fs::write(&config_file, config_text("second-shared-secret")).unwrap();
+        server.reload().await.unwrap();
+        assert_eq!(server.state().config().server.secret, "second-shared-secret");
+        
+        // The replaced state stays usable by requests that still hold it
+        assert_eq!(before.config().server.secret, "first-shared-secret");
+        
+        // A secret that fails validation keeps the current configuration
+        std::fs::write(&config_file, config_text("short")).unwrap();
+        assert!(server.reload().await.is_err());
+        assert_eq!(server.state().config().server.secret, "second-shared-secret");
+        
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn policy_hooks_in_pipeline() {
+        let directory = std::env::temp_dir().join(format!("rust-radius-policy-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("users.json"), r#"{"alice": "{cleartext}one", "mallory": "{cleartext}one"}"#).unwrap();
+        std::fs::write(directory.join("policy.rules"), r#"
+            pre-auth {
+                if User-Name =~ "^([^@]+)@corp\.example$" { rewrite "$1" }
+                if User-Name == "kiosk" { accept }
+            }
+            post-auth {
+                if User-Name == "mallory" { reject "Not today" }
+                set Session-Timeout = 3600
+            }
+            pre-acct {
+                if not Acct-Session-Id { reject }
+                if NAS-Identifier == "retired" { reject "Retired NAS" }
+            }
+        "#).unwrap();
+        
+        let mut config = Config::default();
+        config.security.require_message_authenticator = false;
+        config.auth_backends = toml::from_str(&format!(r#"
+            [local]
+            backend_type = "local"
+            users_file = {:?}
+        "#, directory.join("users.json"))).unwrap();
+        config.policy = Some(crate::config::PolicyConfig {
+            file: Some(directory.join("policy.rules")),
+            ..Default::default()
+        });
+        let config = Arc::new(config);
+        let state = ServerState::build(config.clone(), Arc::new(MetricsCollector::new(config)), None).await.unwrap();
+        
+        let request = |code, attributes: &[(&str, &str)]| {
+            let mut request = Packet::new(code, 1, [0u8; 16]);
+            for (name, value) in attributes {
+                request.add_attribute(Attribute::String(name.to_string(), value.to_string()));
+            }
+            request
+        };
+        
+        // The realm is stripped before the backend sees the name, and post-auth edits the reply
+        let accept = state.handle_auth_request(&request(Packet::ACCESS_REQUEST, &[("User-Name", "alice@corp.example"), ("User-Password", "one")])).await.unwrap();
+        assert_eq!(accept.code(), Packet::ACCESS_ACCEPT);
+        assert_eq!(accept.get_attribute("Session-Timeout"), Some(&Attribute::Integer("Session-Timeout".to_string(), 3600)));
+        
+        let kiosk = state.handle_auth_request(&request(Packet::ACCESS_REQUEST, &[("User-Name", "kiosk")])).await.unwrap();
+        assert_eq!(kiosk.code(), Packet::ACCESS_ACCEPT);
+        
+        let wrong = state.handle_auth_request(&request(Packet::ACCESS_REQUEST, &[("User-Name", "alice"), ("User-Password", "two")])).await.unwrap();
+        assert_eq!(wrong.code(), Packet::ACCESS_REJECT);
+        assert!(wrong.get_attribute("Session-Timeout").is_none());
+        
+        // A post-auth reject turns the backend's Accept around, without the edited reply
+        let refused = state.handle_auth_request(&request(Packet::ACCESS_REQUEST, &[("User-Name", "mallory"), ("User-Password", "one")])).await.unwrap();
+        assert_eq!(refused.code(), Packet::ACCESS_REJECT);
+        assert_eq!(refused.get_text("Reply-Message").as_deref(), Some("Not today"));
+        assert!(refused.get_attribute("Session-Timeout").is_none());
+        
+        // Accounting the policy rejects gets no response at all
+        let error = state.handle_acct_request(&request(Packet::ACCOUNTING_REQUEST, &[("User-Name", "alice")])).await.unwrap_err();
+        assert_eq!(error.to_string(), "Accounting request discarded by policy: Rejected by policy");
+        let error = state.handle_acct_request(&request(Packet::ACCOUNTING_REQUEST, &[("Acct-Session-Id", "1"), ("NAS-Identifier", "retired")])).await.unwrap_err();
+        assert_eq!(error.to_string(), "Accounting request discarded by policy: Retired NAS");
+        let response = state.handle_acct_request(&request(Packet::ACCOUNTING_REQUEST, &[("Acct-Session-Id", "1")])).await.unwrap();
+        assert_eq!(response.code(), Packet::ACCOUNTING_RESPONSE);
+        
+        // Nor is it recorded, so it cannot open a session
+        for (nas, sessions) in [("retired", 0), ("ap-1", 1)] {
+            let mut start = request(Packet::ACCOUNTING_REQUEST, &[("User-Name", "alice"), ("Acct-Session-Id", "2"), ("NAS-Identifier", nas)]);
+            start.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), 1));
+            let _ = state.handle_acct_request(&start).await;
+            assert_eq!(state.auth_manager.sessions().user_sessions("alice").len(), sessions, "{}", nas);
+        }
+        
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
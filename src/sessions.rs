@@ -0,0 +1,613 @@
+// sessions.rs - Live session table and Simultaneous-Use for rust-radius
+//
+// Accounting Start and Interim-Update records add or refresh a session, Stop
+// removes it, and Accounting-On/Off clears every session of the NAS. Right
+// before an Access-Accept a user's open sessions are counted against `max_sessions`.
+// At the limit the login is rejected, or the oldest sessions are ended with a
+// Disconnect-Request to make room.
+//
+// A session the NAS has not reported on for `stale_after_secs` is stale: its
+// Stop may have been lost. Stale sessions are dropped, or with `confirm_stale`
+// the NAS is first sent a CoA-Request carrying only the session identity; the
+// session keeps counting if the NAS acknowledges it, and is dropped if the NAS
+// refuses it or does not answer.
+//
+// Sessions are keyed by the address the accounting came from, which is also
+// where Disconnect requests go; a NAS-IP-Address in the request only
+// identifies the session to the NAS and is never used as a destination.
+//
+// A login that is admitted counts as a reservation until its accounting
+// arrives, and a user's logins are admitted one at a time, so logins racing
+// each other cannot all get in under the limit.
+//
+// The table lives in memory. A reload keeps it, but after a restart it is
+// empty and fills again as Interim-Updates arrive.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{Config, LimitAction};
+use crate::dynauth::{DynAuthClient, DynAuthReply, SESSION_NOT_FOUND};
+use crate::mac::MacAddr;
+use crate::protocol::{Attribute, Packet};
+
+/// Acct-Status-Type values (RFC 2866, section 5.1)
+const ACCT_START: u32 = 1;
+const ACCT_STOP: u32 = 2;
+const ACCT_INTERIM_UPDATE: u32 = 3;
+const ACCT_ON: u32 = 7;
+const ACCT_OFF: u32 = 8;
+
+/// Time an admitted login counts for while its accounting has not arrived
+const RESERVATION_TTL: Duration = Duration::from_secs(60);
+
+/// Attributes kept from accounting to identify a session to its NAS (RFC 5176, section 3)
+const IDENTITY_ATTRIBUTES: [&str; 8] = [
+    "User-Name",
+    "Acct-Session-Id",
+    "NAS-IP-Address",
+    "NAS-Identifier",
+    "NAS-Port",
+    "NAS-Port-Id",
+    "Calling-Station-Id",
+    "Framed-IP-Address",
+];
+
+/// One open session, as reported by accounting
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    /// User-Name
+    pub username: String,
+    
+    /// Acct-Session-Id
+    pub session_id: String,
+    
+    /// Address the accounting came from
+    pub nas: IpAddr,
+    
+    /// Calling-Station-Id, normalized if it is a MAC address
+    pub device: Option<String>,
+    
+    /// Attributes identifying the session in Disconnect and CoA requests
+    pub identity: Vec<Attribute>,
+    
+    /// Accounting Start, or the first record seen
+    pub started: Instant,
+    
+    /// Last accounting record
+    pub updated: Instant,
+}
+
+/// A login that was admitted and has not been reported by accounting yet
+#[derive(Debug, Clone, PartialEq)]
+struct Reservation {
+    /// User-Name
+    username: String,
+    
+    /// Device that logged in
+    device: Option<String>,
+    
+    /// When the login stops counting if no accounting arrives
+    expires: Instant,
+}
+
+/// Sessions by NAS and Acct-Session-Id
+#[derive(Debug, Default)]
+pub struct SessionTable {
+    /// Open sessions
+    sessions: Mutex<HashMap<(IpAddr, String), Session>>,
+    
+    /// Admitted logins waiting for their accounting
+    reservations: Mutex<Vec<Reservation>>,
+    
+    /// Locks taken while a user's login is admitted, by user
+    admissions: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl SessionTable {
+    /// Create an empty table
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Update the table from an Accounting-Request
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Accounting-Request received
+    /// * `now` - Time it was received
+    pub fn record(&self, request: &Packet, now: Instant) {
+        let nas = nas_address(request);
+        let mut sessions = self.sessions.lock().unwrap();
+        
+        match request.get_integer("Acct-Status-Type") {
+            Some(ACCT_ON | ACCT_OFF) => {
+                // The NAS restarted, so none of its sessions survived
+                sessions.retain(|(address, _), _| *address != nas);
+            },
+            Some(status @ (ACCT_START | ACCT_INTERIM_UPDATE | ACCT_STOP)) => {
+                let (username, session_id) = match (request.get_text("User-Name"), request.get_text("Acct-Session-Id")) {
+                    (Some(username), Some(session_id)) => (username, session_id),
+                    _ => return,
+                };
+                let key = (nas, session_id.clone());
+                
+                if status == ACCT_STOP {
+                    sessions.remove(&key);
+                    return;
+                }
+                
+                // The login is now counted as a session
+                let device = device(request);
+                let mut reservations = self.reservations.lock().unwrap();
+                if let Some(index) = reservations.iter().position(|r| r.username == username && r.device == device) {
+                    reservations.remove(index);
+                }
+                
+                // An Interim-Update for a session whose Start was lost opens it
+                let started = sessions.get(&key).map_or(now, |session| session.started);
+                sessions.insert(key, Session {
+                    username,
+                    session_id,
+                    nas,
+                    device,
+                    identity: identity(request),
+                    started,
+                    updated: now,
+                });
+            },
+            _ => {},
+        }
+    }
+    
+    /// Get a user's sessions, oldest first
+    pub fn user_sessions(&self, username: &str) -> Vec<Session> {
+        let mut found: Vec<Session> = self.sessions.lock().unwrap().values()
+            .filter(|session| session.username == username)
+            .cloned()
+            .collect();
+        found.sort_by_key(|session| session.started);
+        found
+    }
+    
+    /// Get the devices of a user's logins still waiting for accounting
+    fn reserved(&self, username: &str, now: Instant) -> Vec<Option<String>> {
+        let mut reservations = self.reservations.lock().unwrap();
+        reservations.retain(|reservation| reservation.expires > now);
+        reservations.iter()
+            .filter(|reservation| reservation.username == username)
+            .map(|reservation| reservation.device.clone())
+            .collect()
+    }
+    
+    /// Count an admitted login until its accounting arrives
+    fn reserve(&self, username: &str, device: Option<String>, now: Instant) {
+        self.reservations.lock().unwrap().push(Reservation {
+            username: username.to_string(),
+            device,
+            expires: now + RESERVATION_TTL,
+        });
+    }
+    
+    /// Stop counting an admitted login that was rejected after all
+    fn release(&self, username: &str, device: &Option<String>) {
+        let mut reservations = self.reservations.lock().unwrap();
+        if let Some(index) = reservations.iter().rposition(|r| r.username == username && r.device == *device) {
+            reservations.remove(index);
+        }
+    }
+    
+    /// Get the lock held while one of a user's logins is admitted
+    fn admission(&self, username: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut admissions = self.admissions.lock().unwrap();
+        admissions.retain(|_, lock| Arc::strong_count(lock) > 1);
+        admissions.entry(username.to_string()).or_default().clone()
+    }
+    
+    /// Forget a session
+    pub fn remove(&self, session: &Session) {
+        self.sessions.lock().unwrap().remove(&(session.nas, session.session_id.clone()));
+    }
+    
+    /// Mark a session as reported on at `now`
+    pub fn touch(&self, session: &Session, now: Instant) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(&(session.nas, session.session_id.clone())) {
+            session.updated = now;
+        }
+    }
+    
+    /// Get the number of open sessions
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+    
+    /// Check whether no session is open
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Get the address of the NAS a request came from
+///
+/// The NAS-IP-Address attribute is not used: any client with the secret
+/// could name another NAS in it and have its sessions disconnected.
+pub fn nas_address(request: &Packet) -> IpAddr {
+    request.source().map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |source| source.ip())
+}
+
+/// Get the attributes of an Accounting-Request that identify its session to the NAS
+pub fn identity(request: &Packet) -> Vec<Attribute> {
+    request.attributes().iter()
+        .filter(|attribute| IDENTITY_ATTRIBUTES.contains(&attribute.name()))
+        .cloned()
+        .collect()
+}
+
+/// Device of a request: its Calling-Station-Id, normalized if it is a MAC address
+fn device(request: &Packet) -> Option<String> {
+    request.get_text("Calling-Station-Id").map(|station| match station.parse::<MacAddr>() {
+        Ok(mac) => mac.to_string(),
+        Err(_) => station.trim().to_string(),
+    })
+}
+
+/// Enforces Simultaneous-Use against the session table
+pub struct SessionLimiter {
+    /// Sessions reported by accounting
+    table: Arc<SessionTable>,
+    
+    /// Sends Disconnect and CoA requests to NASes
+    client: DynAuthClient,
+    
+    /// What happens to a login over the limit
+    action: LimitAction,
+    
+    /// Silence after which a session is stale
+    stale_after: Duration,
+    
+    /// Whether the NAS is asked about stale sessions
+    confirm_stale: bool,
+}
+
+impl SessionLimiter {
+    /// Create a limiter over a session table
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - Server configuration
+    /// * `table` - Session table, shared with accounting
+    pub fn new(config: Arc<Config>, table: Arc<SessionTable>) -> Self {
+        Self {
+            table,
+            action: config.sessions.at_limit,
+            stale_after: Duration::from_secs(config.sessions.stale_after_secs),
+            confirm_stale: config.sessions.confirm_stale,
+            client: DynAuthClient::new(config),
+        }
+    }
+    
+    /// Get the session table
+    pub fn table(&self) -> &Arc<SessionTable> {
+        &self.table
+    }
+    
+    /// Keep the session table of the limiter this one replaces
+    pub fn adopt(&mut self, previous: &SessionLimiter) {
+        self.table = previous.table.clone();
+    }
+    
+    /// Check whether a user may open another session
+    ///
+    /// A session from the device that is logging in does not count, so a
+    /// re-authentication is never refused. An admitted login counts as one of
+    /// the user's sessions until its accounting arrives.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request being authorized
+    /// * `username` - Authenticated user
+    /// * `limit` - Sessions the user may have
+    ///
+    /// # Returns
+    ///
+    /// Ok if the login may go ahead, otherwise the reason to reject
+    pub async fn admit(&self, request: &Packet, username: &str, limit: u32) -> std::result::Result<(), String> {
+        let device = device(request);
+        let admission = self.table.admission(username);
+        let _admitting = admission.lock().await;
+        let now = Instant::now();
+        
+        let mut open = Vec::new();
+        let mut reauthenticating = false;
+        for session in self.table.user_sessions(username) {
+            if device.is_some() && session.device == device {
+                reauthenticating = true;
+                continue;
+            }
+            if now.duration_since(session.updated) >= self.stale_after && !self.confirm(&session, now).await {
+                continue;
+            }
+            open.push(session);
+        }
+        let pending = self.table.reserved(username, now).into_iter()
+            .filter(|reserved| device.is_none() || *reserved != device)
+            .count();
+        let count = open.len() + pending;
+        
+        if count >= limit as usize {
+            // Logins still waiting for accounting cannot be disconnected, so only older sessions make room
+            let excess = count + 1 - limit as usize;
+            if limit == 0 || self.action == LimitAction::Reject || excess > open.len() {
+                tracing::info!(username = username, sessions = open.len(), pending = pending, limit = limit, "Session limit reached");
+                return Err(format!("Too many sessions ({} allowed)", limit));
+            }
+            
+            for session in &open[..excess] {
+                self.disconnect(session).await?;
+            }
+        }
+        
+        // A re-authentication is already counted by its session
+        if !reauthenticating {
+            self.table.reserve(username, device, now);
+        }
+        Ok(())
+    }
+    
+    /// Stop counting a login admitted by `admit` that is rejected after all
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request that was admitted
+    /// * `username` - User it was admitted for
+    pub fn release(&self, request: &Packet, username: &str) {
+        self.table.release(username, &device(request));
+    }
+    
+    /// Decide whether a stale session still counts
+    async fn confirm(&self, session: &Session, now: Instant) -> bool {
+        if !self.confirm_stale {
+            tracing::debug!(username = session.username, session = session.session_id, nas = %session.nas, "Dropping stale session");
+            self.table.remove(session);
+            return false;
+        }
+        
+        // A CoA-Request with only the session identity changes nothing
+        match self.client.change(session.nas, &session.identity, vec![]).await {
+            Ok(DynAuthReply::Ack) => {
+                self.table.touch(session, now);
+                true
+            },
+            Ok(reply) => {
+                tracing::debug!(username = session.username, session = session.session_id, nas = %session.nas, reply = ?reply, "NAS did not confirm stale session");
+                self.table.remove(session);
+                false
+            },
+            Err(e) => {
+                // The NAS was never asked, so the session keeps counting
+                tracing::warn!(username = session.username, session = session.session_id, nas = %session.nas, error = %e, "Could not ask NAS about stale session");
+                true
+            },
+        }
+    }
+    
+    /// End a session to make room for a new one
+    async fn disconnect(&self, session: &Session) -> std::result::Result<(), String> {
+        match self.client.disconnect(session.nas, &session.identity).await {
+            Ok(DynAuthReply::Ack | DynAuthReply::Nak(Some(SESSION_NOT_FOUND))) => {
+                tracing::info!(username = session.username, session = session.session_id, nas = %session.nas, "Disconnected oldest session");
+                self.table.remove(session);
+                Ok(())
+            },
+            reply => {
+                tracing::warn!(username = session.username, session = session.session_id, nas = %session.nas, reply = ?reply, "Could not disconnect session");
+                Err("Too many sessions, and the oldest could not be ended".to_string())
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    
+    use tokio::net::UdpSocket;
+    
+    fn accounting(status: u32, username: &str, session: &str, station: &str) -> Packet {
+        let mut request = Packet::new(Packet::ACCOUNTING_REQUEST, 1, [0u8; 16]);
+        request.set_source("127.0.0.1:40000".parse().unwrap());
+        request.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), status as i32));
+        request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+        request.add_attribute(Attribute::String("Acct-Session-Id".to_string(), session.to_string()));
+        request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), station.to_string()));
+        request
+    }
+    
+    fn login(username: &str, station: &str) -> Packet {
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+        request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), station.to_string()));
+        request
+    }
+    
+    fn limiter(sessions: &str, nas_port: u16) -> SessionLimiter {
+        let mut config = Config::default();
+        config.server.secret = "testing123".to_string();
+        config.sessions = toml::from_str(sessions).unwrap();
+        config.dynamic_authorization.port = nas_port;
+        config.dynamic_authorization.timeout_ms = 200;
+        config.dynamic_authorization.retries = 0;
+        SessionLimiter::new(Arc::new(config), Arc::new(SessionTable::new()))
+    }
+    
+    #[tokio::test]
+    async fn sessions_from_accounting() {
+        let limiter = limiter("", 3799);
+        let table = limiter.table();
+        let now = Instant::now();
+        
+        table.record(&accounting(ACCT_START, "alice", "s1", "AA-BB-CC-DD-EE-01"), now);
+        table.record(&accounting(ACCT_INTERIM_UPDATE, "alice", "s2", "aa:bb:cc:dd:ee:02"), now + Duration::from_secs(1));
+        table.record(&accounting(ACCT_START, "bob", "s3", "AA-BB-CC-DD-EE-03"), now);
+        assert_eq!(table.user_sessions("alice").iter().map(|s| s.session_id.as_str()).collect::<Vec<_>>(), ["s1", "s2"]);
+        assert_eq!(table.user_sessions("alice")[1].device.as_deref(), Some("aa:bb:cc:dd:ee:02"));
+        
+        // The session belongs to the client that sent the accounting, whatever NAS-IP-Address says
+        let mut claimed = accounting(ACCT_START, "carol", "s4", "AA-BB-CC-DD-EE-04");
+        claimed.add_attribute(Attribute::IpAddr("NAS-IP-Address".to_string(), "198.51.100.1".parse().unwrap()));
+        table.record(&claimed, now);
+        assert_eq!(table.user_sessions("carol")[0].nas, "127.0.0.1".parse::<IpAddr>().unwrap());
+        
+        // The device logging in again does not count against itself
+        assert_eq!(limiter.admit(&login("alice", "aa-bb-cc-dd-ee-01"), "alice", 2).await, Ok(()));
+        assert!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 2).await.is_err());
+        
+        table.record(&accounting(ACCT_STOP, "alice", "s1", "AA-BB-CC-DD-EE-01"), now);
+        assert_eq!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 2).await, Ok(()));
+        
+        let mut restart = Packet::new(Packet::ACCOUNTING_REQUEST, 2, [0u8; 16]);
+        restart.set_source("127.0.0.1:40000".parse().unwrap());
+        restart.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), ACCT_ON as i32));
+        table.record(&restart, now);
+        assert!(table.is_empty());
+        
+        // Stale sessions are dropped without confirmation
+        let limiter = self::limiter("stale_after_secs = 0", 3799);
+        limiter.table().record(&accounting(ACCT_START, "alice", "s1", "AA-BB-CC-DD-EE-01"), now);
+        assert_eq!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 1).await, Ok(()));
+        assert!(limiter.table().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn oldest_session_is_disconnected() {
+        use md5::{Digest, Md5};
+        
+        // A NAS that acknowledges one Disconnect-Request
+        let nas = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = nas.local_addr().unwrap().port();
+        let answered = tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            let (received, from): (usize, SocketAddr) = nas.recv_from(&mut buffer).await.unwrap();
+            let request = buffer[..received].to_vec();
+            
+            let mut answer = vec![41, request[1], 0, 20];
+            let mut hasher = Md5::new();
+            hasher.update(&answer[..4]);
+            hasher.update(&request[4..20]);
+            hasher.update(b"testing123");
+            answer.extend_from_slice(&hasher.finalize());
+            nas.send_to(&answer, from).await.unwrap();
+            request
+        });
+        
+        let limiter = limiter("at_limit = \"disconnect-oldest\"", port);
+        let now = Instant::now();
+        limiter.table().record(&accounting(ACCT_START, "alice", "old", "AA-BB-CC-DD-EE-01"), now);
+        limiter.table().record(&accounting(ACCT_START, "alice", "new", "AA-BB-CC-DD-EE-02"), now + Duration::from_secs(1));
+        
+        assert_eq!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-03"), "alice", 2).await, Ok(()));
+        assert_eq!(limiter.table().user_sessions("alice").iter().map(|s| s.session_id.as_str()).collect::<Vec<_>>(), ["new"]);
+        
+        // The request names the oldest session
+        let request = answered.await.unwrap();
+        assert_eq!(request[0], 40);
+        assert!(request.windows(3).any(|window| window == b"old"));
+        
+        // Nobody answers for the next one, so the login is refused
+        assert!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-03"), "alice", 1).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn stale_sessions_are_confirmed() {
+        use md5::{Digest, Md5};
+        
+        // A NAS that acknowledges the first CoA-Request, refuses the second and ignores the rest
+        let nas = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = nas.local_addr().unwrap().port();
+        let answered = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for code in [44u8, 45] {
+                let mut buffer = [0u8; 4096];
+                let (received, from): (usize, SocketAddr) = nas.recv_from(&mut buffer).await.unwrap();
+                let request = buffer[..received].to_vec();
+                
+                let mut answer = vec![code, request[1], 0, 20];
+                let mut hasher = Md5::new();
+                hasher.update(&answer[..4]);
+                hasher.update(&request[4..20]);
+                hasher.update(b"testing123");
+                answer.extend_from_slice(&hasher.finalize());
+                nas.send_to(&answer, from).await.unwrap();
+                requests.push(request);
+            }
+            (nas, requests)
+        });
+        
+        let limiter = limiter("stale_after_secs = 0\nconfirm_stale = true", port);
+        let now = Instant::now();
+        limiter.table().record(&accounting(ACCT_START, "alice", "s1", "AA-BB-CC-DD-EE-01"), now);
+        
+        // Acknowledged: the session is still up and keeps counting
+        assert!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 1).await.is_err());
+        assert_eq!(limiter.table().len(), 1);
+        
+        // Refused: the NAS no longer has it
+        assert_eq!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 1).await, Ok(()));
+        assert!(limiter.table().is_empty());
+        
+        // The probe is a CoA-Request carrying the session identity and nothing else
+        let (nas, requests) = answered.await.unwrap();
+        for request in &requests {
+            assert_eq!(request[0], 43);
+            assert!(request.windows(2).any(|window| window == b"s1"));
+            
+            let mut types = Vec::new();
+            let mut offset = 20;
+            while offset < request.len() {
+                types.push(request[offset]);
+                offset += request[offset + 1] as usize;
+            }
+            // User-Name, Acct-Session-Id, Calling-Station-Id and Message-Authenticator
+            assert!(types.iter().all(|kind| [1, 44, 31, 80].contains(kind)), "{:?}", types);
+        }
+        
+        // No answer: dropped as well
+        limiter.table().record(&accounting(ACCT_START, "bob", "s2", "AA-BB-CC-DD-EE-02"), now);
+        assert_eq!(limiter.admit(&login("bob", "AA-BB-CC-DD-EE-09"), "bob", 1).await, Ok(()));
+        assert!(limiter.table().is_empty());
+        drop(nas);
+    }
+    
+    #[tokio::test]
+    async fn admitted_logins_count_until_accounting() {
+        let limiter = Arc::new(limiter("", 3799));
+        
+        // Logins racing each other are admitted one at a time, and only one fits
+        let first = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.admit(&login("alice", "AA-BB-CC-DD-EE-01"), "alice", 1).await }
+        });
+        let second = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.admit(&login("alice", "AA-BB-CC-DD-EE-02"), "alice", 1).await }
+        });
+        let results = [first.await.unwrap(), second.await.unwrap()];
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        let device = if results[0].is_ok() { "AA-BB-CC-DD-EE-01" } else { "AA-BB-CC-DD-EE-02" };
+        
+        // Accounting turns the reservation into the session it stands for
+        let now = Instant::now();
+        limiter.table().record(&accounting(ACCT_START, "alice", "s1", device), now);
+        assert!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 1).await.is_err());
+        limiter.table().record(&accounting(ACCT_STOP, "alice", "s1", device), now);
+        assert_eq!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-09"), "alice", 1).await, Ok(()));
+        
+        // A login rejected after admission stops counting
+        assert!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-10"), "alice", 1).await.is_err());
+        limiter.release(&login("alice", "AA-BB-CC-DD-EE-09"), "alice");
+        assert_eq!(limiter.admit(&login("alice", "AA-BB-CC-DD-EE-10"), "alice", 1).await, Ok(()));
+    }
+}
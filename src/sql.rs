@@ -0,0 +1,564 @@
+// sql.rs - SQL authentication and authorization for rust-radius
+//
+// Users, their check items and reply items live in a database, in the spirit
+// of FreeRADIUS' radcheck / radreply / radgroupcheck / radgroupreply /
+// radusergroup tables (see `migrations/`). Every query is configurable, so an
+// existing schema can be used instead. SQLite is always available; Postgres
+// and MySQL are enabled with the `sql-postgres` and `sql-mysql` features.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use serde::Deserialize;
+use sqlx::any::AnyPoolOptions;
+use sqlx::migrate::Migrator;
+use sqlx::AnyPool;
+
+use crate::password::Credential;
+use crate::protocol::{Attribute, Packet};
+use crate::users;
+use crate::Result;
+
+/// Schema for SQLite databases
+static SQLITE_MIGRATIONS: Migrator = sqlx::migrate!("migrations/sqlite");
+
+/// Schema for PostgreSQL databases
+#[cfg(feature = "sql-postgres")]
+static POSTGRES_MIGRATIONS: Migrator = sqlx::migrate!("migrations/postgres");
+
+/// Schema for MySQL databases
+#[cfg(feature = "sql-mysql")]
+static MYSQL_MIGRATIONS: Migrator = sqlx::migrate!("migrations/mysql");
+
+/// Standard attributes with integer values; everything else is sent as text
+const INTEGER_ATTRIBUTES: [&str; 12] = [
+    "Service-Type", "Framed-Protocol", "Framed-Routing", "Framed-MTU", "Framed-Compression",
+    "Login-Service", "Session-Timeout", "Idle-Timeout", "Termination-Action", "Port-Limit",
+    "Tunnel-Type", "Tunnel-Medium-Type",
+];
+
+/// Named values of integer attributes, as written in FreeRADIUS tables
+const NAMED_VALUES: [(&str, &str, i32); 8] = [
+    ("Service-Type", "Login-User", 1),
+    ("Service-Type", "Framed-User", 2),
+    ("Service-Type", "Administrative-User", 6),
+    ("Framed-Protocol", "PPP", 1),
+    ("Termination-Action", "Default", 0),
+    ("Termination-Action", "RADIUS-Request", 1),
+    ("Tunnel-Type", "VLAN", 13),
+    ("Tunnel-Medium-Type", "IEEE-802", 6),
+];
+
+/// Standard attributes with IPv4 address values
+const ADDRESS_ATTRIBUTES: [&str; 2] = ["Framed-IP-Address", "Framed-IP-Netmask"];
+
+/// Options of an SQL backend
+#[derive(Debug, Clone, Deserialize)]
+pub struct SqlSettings {
+    /// Database URL (`sqlite://radius.db?mode=rwc`, `postgres://...`, `mysql://...`)
+    pub url: String,
+    
+    /// Create the tables in `migrations/` on startup (default: only for SQLite)
+    #[serde(default)]
+    pub migrate: Option<bool>,
+    
+    /// Maximum number of connections
+    #[serde(default = "default_pool_size")]
+    pub pool_size: u32,
+    
+    /// Connection timeout in seconds
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    
+    /// Query returning the user's stored password in the first column, for
+    /// schemas that keep it outside the check items
+    #[serde(default)]
+    pub password_query: Option<String>,
+    
+    /// Query returning (attribute, op, value) check items of a user
+    #[serde(default = "default_check_query")]
+    pub check_query: String,
+    
+    /// Query returning (attribute, op, value) reply items of a user
+    #[serde(default = "default_reply_query")]
+    pub reply_query: String,
+    
+    /// Query returning the names of a user's groups, in priority order; `""` disables groups
+    #[serde(default = "default_group_membership_query")]
+    pub group_membership_query: String,
+    
+    /// Query returning (attribute, op, value) check items of a group
+    #[serde(default = "default_group_check_query")]
+    pub group_check_query: String,
+    
+    /// Query returning (attribute, op, value) reply items of a group
+    #[serde(default = "default_group_reply_query")]
+    pub group_reply_query: String,
+}
+
+fn default_pool_size() -> u32 {
+    5
+}
+
+fn default_timeout() -> u64 {
+    5
+}
+
+fn default_check_query() -> String {
+    "SELECT attribute, op, value FROM radcheck WHERE username = ? ORDER BY id".to_string()
+}
+
+fn default_reply_query() -> String {
+    "SELECT attribute, op, value FROM radreply WHERE username = ? ORDER BY id".to_string()
+}
+
+fn default_group_membership_query() -> String {
+    "SELECT groupname FROM radusergroup WHERE username = ? ORDER BY priority".to_string()
+}
+
+fn default_group_check_query() -> String {
+    "SELECT attribute, op, value FROM radgroupcheck WHERE groupname = ? ORDER BY id".to_string()
+}
+
+fn default_group_reply_query() -> String {
+    "SELECT attribute, op, value FROM radgroupreply WHERE groupname = ? ORDER BY id".to_string()
+}
+
+/// Check or reply item
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeRow {
+    /// Attribute name
+    pub attribute: String,
+    
+    /// Operator (`==`, `:=`, `+=`, ...)
+    pub op: String,
+    
+    /// Value as stored
+    pub value: String,
+}
+
+impl From<(String, String, String)> for AttributeRow {
+    fn from((attribute, op, value): (String, String, String)) -> Self {
+        Self { attribute, op, value }
+    }
+}
+
+/// Group a user belongs to
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlGroup {
+    /// Group name
+    pub name: String,
+    
+    /// Conditions for the group's reply items
+    pub check: Vec<AttributeRow>,
+    
+    /// Reply items of the group
+    pub reply: Vec<AttributeRow>,
+}
+
+/// User as stored in the database
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlUser {
+    /// Stored credential, if any
+    pub credential: Option<Credential>,
+    
+    /// Check items other than the credential
+    pub check: Vec<AttributeRow>,
+    
+    /// Reply items of the user
+    pub reply: Vec<AttributeRow>,
+    
+    /// Groups, in priority order
+    pub groups: Vec<SqlGroup>,
+}
+
+impl SqlUser {
+    /// Evaluate the user's check items against a request
+    ///
+    /// # Errors
+    ///
+    /// Returns the reason to reject if a check item does not match
+    pub fn check(&self, request: &Packet, now: DateTime<Local>) -> std::result::Result<(), String> {
+        check_items(&self.check, request, now)
+    }
+    
+    /// Build the reply attributes
+    ///
+    /// User reply items come first, then those of each group whose check
+    /// items match. `=` only adds an attribute that is not there yet, `:=`
+    /// replaces it and `+=` always adds it (as in FreeRADIUS).
+    pub fn reply_attributes(&self, request: &Packet, now: DateTime<Local>) -> Vec<Attribute> {
+        let mut attributes = Vec::new();
+        apply_reply(&mut attributes, &self.reply);
+        
+        for group in &self.groups {
+            if check_items(&group.check, request, now).is_ok() {
+                apply_reply(&mut attributes, &group.reply);
+            }
+        }
+        
+        attributes
+    }
+}
+
+/// Database of users
+pub struct SqlStore {
+    /// Backend options
+    settings: SqlSettings,
+    
+    /// Connection pool
+    pool: AnyPool,
+}
+
+impl SqlStore {
+    /// Connect to the database, creating the tables if configured to
+    ///
+    /// # Arguments
+    ///
+    /// * `settings` - Backend options
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be reached or migrated
+    pub async fn connect(settings: SqlSettings) -> Result<Self> {
+        sqlx::any::install_default_drivers();
+        
+        let pool = AnyPoolOptions::new()
+            .max_connections(settings.pool_size)
+            .acquire_timeout(Duration::from_secs(settings.timeout))
+            .connect(&settings.url).await
+            .map_err(|e| format!("Failed to connect to database {}: {}", redact(&settings.url), e))?;
+        
+        let scheme = settings.url.split(':').next().unwrap_or_default().to_ascii_lowercase();
+        if settings.migrate.unwrap_or(scheme == "sqlite") {
+            let migrator = match scheme.as_str() {
+                "sqlite" => &SQLITE_MIGRATIONS,
+                #[cfg(feature = "sql-postgres")]
+                "postgres" | "postgresql" => &POSTGRES_MIGRATIONS,
+                #[cfg(feature = "sql-mysql")]
+                "mysql" | "mariadb" => &MYSQL_MIGRATIONS,
+                other => return Err(format!("No migrations for database type {}", other).into()),
+            };
+            
+            migrator.run(&pool).await
+                .map_err(|e| format!("Failed to migrate database {}: {}", redact(&settings.url), e))?;
+        }
+        
+        Ok(Self { settings, pool })
+    }
+    
+    /// Look up a user with their groups
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - User-Name of the request
+    ///
+    /// # Returns
+    ///
+    /// None if the database knows nothing about the user
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a query fails or a stored password cannot be parsed
+    pub async fn lookup(&self, username: &str) -> Result<Option<SqlUser>> {
+        let mut credential = match &self.settings.password_query {
+            Some(query) => {
+                let row: Option<(String,)> = sqlx::query_as(query).bind(username).fetch_optional(&self.pool).await?;
+                row.map(|(password,)| Credential::parse(&password)).transpose()?
+            },
+            None => None,
+        };
+        
+        // Password check items become the credential, the rest are conditions
+        let mut check = Vec::new();
+        for row in self.rows(&self.settings.check_query, username).await? {
+            match password_item(&row)? {
+                Some(stored) => credential = Some(stored),
+                None => check.push(row),
+            }
+        }
+        
+        let reply = self.rows(&self.settings.reply_query, username).await?;
+        
+        let mut groups = Vec::new();
+        for name in self.groups(username).await? {
+            groups.push(SqlGroup {
+                check: self.rows(&self.settings.group_check_query, &name).await?,
+                reply: self.rows(&self.settings.group_reply_query, &name).await?,
+                name,
+            });
+        }
+        
+        if credential.is_none() && check.is_empty() && reply.is_empty() && groups.is_empty() {
+            return Ok(None);
+        }
+        
+        Ok(Some(SqlUser { credential, check, reply, groups }))
+    }
+    
+    /// Names of a user's groups, in priority order
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the group membership query fails
+    pub async fn groups(&self, username: &str) -> Result<Vec<String>> {
+        if self.settings.group_membership_query.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let names: Vec<(String,)> = sqlx::query_as(&self.settings.group_membership_query)
+            .bind(username)
+            .fetch_all(&self.pool).await?;
+        Ok(names.into_iter().map(|(name,)| name).collect())
+    }
+    
+    /// Run an (attribute, op, value) query
+    async fn rows(&self, query: &str, key: &str) -> Result<Vec<AttributeRow>> {
+        if query.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let rows: Vec<(String, String, String)> = sqlx::query_as(query).bind(key).fetch_all(&self.pool).await?;
+        Ok(rows.into_iter().map(AttributeRow::from).collect())
+    }
+}
+
+/// Turn a password check item into a credential
+///
+/// # Returns
+///
+/// None if the row is not a password item
+fn password_item(row: &AttributeRow) -> Result<Option<Credential>> {
+    let credential = match row.attribute.as_str() {
+        "Cleartext-Password" | "User-Password" => Credential::Cleartext(row.value.clone()),
+        "NT-Password" => {
+            let hex = row.value.trim_start_matches("0x");
+            Credential::parse(&format!("{{nt}}{}", hex))?
+        },
+        "Crypt-Password" if row.value.starts_with('$') => Credential::parse(&row.value)?,
+        "Crypt-Password" => return Err("Crypt-Password must be an Argon2, bcrypt or SHA-512-crypt hash".into()),
+        _ => return Ok(None),
+    };
+    
+    Ok(Some(credential))
+}
+
+/// Evaluate check items against a request
+fn check_items(rows: &[AttributeRow], request: &Packet, now: DateTime<Local>) -> std::result::Result<(), String> {
+    for row in rows {
+        match (row.attribute.as_str(), row.op.as_str()) {
+            ("Expiration", _) => {
+                let expires = users::parse_expiry(&row.value).map_err(|e| e.to_string())?;
+                if now >= expires {
+                    return Err("Account expired".to_string());
+                }
+            },
+            ("Auth-Type", _) if row.value.eq_ignore_ascii_case("Reject") => {
+                return Err("Auth-Type is Reject".to_string());
+            },
+            // Assignments set server-side options in FreeRADIUS; none of them apply here
+            (_, ":=" | "=" | "+=") => {},
+            (name, op) => {
+                let value = request_value(request, name);
+                let matches = match op {
+                    "==" => value.as_deref() == Some(row.value.as_str()),
+                    "!=" => value.as_deref() != Some(row.value.as_str()),
+                    "=*" => value.is_some(),
+                    "!*" => value.is_none(),
+                    ">" | ">=" | "<" | "<=" => {
+                        let (actual, expected) = match (value.and_then(|v| v.parse::<i64>().ok()), row.value.parse::<i64>()) {
+                            (Some(actual), Ok(expected)) => (actual, expected),
+                            _ => return Err(format!("{} {} {} does not match", name, op, row.value)),
+                        };
+                        match op {
+                            ">" => actual > expected,
+                            ">=" => actual >= expected,
+                            "<" => actual < expected,
+                            _ => actual <= expected,
+                        }
+                    },
+                    _ => return Err(format!("Unsupported check operator {} for {}", op, name)),
+                };
+                
+                if !matches {
+                    return Err(format!("{} {} {} does not match", name, op, row.value));
+                }
+            },
+        }
+    }
+    
+    Ok(())
+}
+
+/// Value of a request attribute as text
+fn request_value(request: &Packet, name: &str) -> Option<String> {
+    match request.get_attribute(name)? {
+        Attribute::Integer(_, value) => Some(value.to_string()),
+        Attribute::IpAddr(_, value) => Some(value.to_string()),
+        _ => request.get_text(name),
+    }
+}
+
+/// Add reply items to a list of attributes
+fn apply_reply(attributes: &mut Vec<Attribute>, rows: &[AttributeRow]) {
+    for row in rows {
+        let attribute = reply_attribute(row);
+        let present = attributes.iter().any(|existing| existing.name() == row.attribute);
+        
+        match row.op.as_str() {
+            "=" if present => {},
+            ":=" => {
+                attributes.retain(|existing| existing.name() != row.attribute);
+                attributes.push(attribute);
+            },
+            _ => attributes.push(attribute),
+        }
+    }
+}
+
+/// Convert a reply item to an attribute of the right type
+fn reply_attribute(row: &AttributeRow) -> Attribute {
+    let name = row.attribute.clone();
+    
+    if INTEGER_ATTRIBUTES.contains(&row.attribute.as_str()) {
+        let named = NAMED_VALUES.iter()
+            .find(|(attribute, value, _)| *attribute == row.attribute && value.eq_ignore_ascii_case(&row.value))
+            .map(|(_, _, number)| *number);
+        if let Some(value) = named.or_else(|| row.value.parse().ok()) {
+            return Attribute::Integer(name, value);
+        }
+    }
+    
+    if ADDRESS_ATTRIBUTES.contains(&row.attribute.as_str()) {
+        if let Ok(address) = row.value.parse::<IpAddr>() {
+            return Attribute::IpAddr(name, address);
+        }
+    }
+    
+    Attribute::String(name, row.value.clone())
+}
+
+/// Hide the password in a database URL for logging
+fn redact(url: &str) -> String {
+    match (url.find("://"), url.rfind('@')) {
+        (Some(scheme), Some(at)) if at > scheme => match url[scheme + 3..at].split_once(':') {
+            Some((user, _)) => format!("{}{}:***{}", &url[..scheme + 3], user, &url[at..]),
+            None => url.to_string(),
+        },
+        _ => url.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::auth::{AuthBackend, AuthResult, SqlAuthBackend};
+    use crate::config::AuthBackendConfig;
+    
+    #[tokio::test]
+    async fn radcheck_schema() {
+        let path = std::env::temp_dir().join(format!("radius-sql-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        
+        // The tables are created on first use
+        let mut options = HashMap::new();
+        options.insert("url".to_string(), toml::Value::String(url.clone()));
+        let backend = SqlAuthBackend::new("sql".to_string(), &AuthBackendConfig {
+            backend_type: "sql".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: options,
+        }).await.unwrap();
+        
+        let pool = AnyPool::connect(&url).await.unwrap();
+        for statement in [
+            "INSERT INTO radcheck (username, attribute, op, value) VALUES ('alice', 'Cleartext-Password', ':=', 'secret')",
+            "INSERT INTO radcheck (username, attribute, op, value) VALUES ('alice', 'NAS-Identifier', '==', 'ap-1')",
+            "INSERT INTO radreply (username, attribute, op, value) VALUES ('alice', 'Session-Timeout', ':=', '3600')",
+            "INSERT INTO radusergroup (username, groupname, priority) VALUES ('alice', 'staff', 1)",
+            "INSERT INTO radgroupreply (groupname, attribute, op, value) VALUES ('staff', 'Session-Timeout', '=', '600')",
+            "INSERT INTO radgroupreply (groupname, attribute, op, value) VALUES ('staff', 'Tunnel-Type', ':=', 'VLAN')",
+            "INSERT INTO radgroupreply (groupname, attribute, op, value) VALUES ('staff', 'Tunnel-Private-Group-Id', ':=', '10')",
+            "INSERT INTO radcheck (username, attribute, op, value) VALUES ('bob', 'Crypt-Password', ':=', '$2b$04$invalid')",
+            "INSERT INTO radcheck (username, attribute, op, value) VALUES ('bob', 'Auth-Type', ':=', 'Reject')",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        
+        let request = |username: &str, password: &str, nas: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+            request.add_attribute(Attribute::String("NAS-Identifier".to_string(), nas.to_string()));
+            request
+        };
+        
+        // The user's Session-Timeout wins over the group's "=", the group adds its VLAN
+        let result = backend.authenticate(&request("alice", "secret", "ap-1")).await.unwrap();
+        assert_eq!(result, AuthResult::Accept {
+            attributes: vec![
+                Attribute::Integer("Session-Timeout".to_string(), 3600),
+                Attribute::Integer("Tunnel-Type".to_string(), 13),
+                Attribute::String("Tunnel-Private-Group-Id".to_string(), "10".to_string()),
+            ],
+            groups: vec![],
+        });
+        
+        for (username, password, nas) in [("alice", "wrong", "ap-1"), ("alice", "secret", "ap-2")] {
+            assert!(matches!(backend.authenticate(&request(username, password, nas)).await.unwrap(), AuthResult::Reject { .. }), "{} {}", username, nas);
+        }
+        assert_eq!(backend.authenticate(&request("carol", "secret", "ap-1")).await.unwrap(), AuthResult::NotFound);
+        
+        let bob = lookup(&url, "bob").await;
+        assert_eq!(bob.check(&request("bob", "", "ap-1"), Local::now()), Err("Auth-Type is Reject".to_string()));
+        
+        let _ = std::fs::remove_file(&path);
+    }
+    
+    #[tokio::test]
+    async fn database_errors() {
+        let dir = std::env::temp_dir().join(format!("radius-sql-errors-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let url = format!("sqlite://{}?mode=rwc", dir.join("radius.db").display());
+        
+        // A database that cannot be opened stops the backend from starting
+        let missing: SqlSettings = toml::from_str(&format!("url = \"sqlite://{}?mode=rwc\"", dir.join("missing").join("radius.db").display())).unwrap();
+        let error = SqlStore::connect(missing).await.err().unwrap();
+        assert!(error.to_string().starts_with("Failed to connect to database"), "{}", error);
+        
+        // A stored password the backend cannot use is an error, not a wrong password
+        let mut options = HashMap::new();
+        options.insert("url".to_string(), toml::Value::String(url.clone()));
+        let backend = SqlAuthBackend::new("sql".to_string(), &AuthBackendConfig {
+            backend_type: "sql".to_string(),
+            enabled: true,
+            chain: Default::default(),
+            config: options,
+        }).await.unwrap();
+        let pool = AnyPool::connect(&url).await.unwrap();
+        sqlx::query("INSERT INTO radcheck (username, attribute, op, value) VALUES ('bob', 'Crypt-Password', ':=', 'plaintext')")
+            .execute(&pool).await.unwrap();
+        let login = |username: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), "plaintext".to_string()));
+            request
+        };
+        let error = backend.authenticate(&login("bob")).await.unwrap_err();
+        assert!(error.to_string().starts_with("Crypt-Password must be"), "{}", error);
+        
+        // So is a query the database refuses, whoever logs in
+        sqlx::query("DROP TABLE radreply").execute(&pool).await.unwrap();
+        assert!(backend.authenticate(&login("carol")).await.is_err());
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    async fn lookup(url: &str, username: &str) -> SqlUser {
+        let settings: SqlSettings = toml::from_str(&format!("url = \"{}\"", url)).unwrap();
+        SqlStore::connect(settings).await.unwrap().lookup(username).await.unwrap().unwrap()
+    }
+}
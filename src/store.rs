@@ -0,0 +1,162 @@
+// store.rs - JSON data files for rust-radius
+//
+// The MAC registry, OTP tokens, vouchers, quota usage, locks and pending
+// challenges are each kept in a JSON file that is read whole and replaced
+// whole. A file is written to a temporary file next to it first and then
+// renamed over it, so a crash never leaves it truncated. The server, the CLI
+// and the admin API may write the same file at once, so every write gets a
+// temporary file of its own. Files holding secrets or usage are readable only
+// by their owner on Unix.
+
+use std::io::Write;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::Result;
+
+/// A value kept in a JSON file
+pub trait JsonFile: Default + Serialize + DeserializeOwned {
+    /// What the file holds, for error messages, e.g. "vouchers"
+    const WHAT: &'static str;
+    
+    /// Whether only the owner may read the file on Unix
+    const PRIVATE: bool = true;
+    
+    /// Read the value from the content of a file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content is not a valid value
+    fn from_json(content: &str) -> Result<Self> {
+        Ok(serde_json::from_str(content)?)
+    }
+    
+    /// Check the value before it is written
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value must not be written
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+    
+    /// Load a file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed
+    fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {} {}: {}", Self::WHAT, path.display(), e))?;
+        
+        Self::from_json(&content)
+            .map_err(|e| format!("Failed to parse {} {}: {}", Self::WHAT, path.display(), e).into())
+    }
+    
+    /// Load a file, or start with the default value if it does not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an existing file cannot be read or parsed
+    fn load_or_default<P: AsRef<Path>>(path: P) -> Result<Self> {
+        if path.as_ref().exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+    
+    /// Write the value to a file
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value fails its check or the file cannot be written
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.check()?;
+        
+        let content = serde_json::to_string_pretty(self)?;
+        replace(path.as_ref(), content.as_bytes(), Self::PRIVATE, Self::WHAT)
+    }
+}
+
+/// Replace a file with new content, through a temporary file
+///
+/// # Arguments
+///
+/// * `path` - File to replace
+/// * `content` - New content
+/// * `private` - Whether only the owner may read the file on Unix
+/// * `what` - What the file holds, for error messages
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be written
+pub fn replace(path: &Path, content: &[u8], private: bool, what: &str) -> Result<()> {
+    // Hidden, and unique to this process and write
+    let name = path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+    let temp_file = path.with_file_name(format!(".{}.{}.{:016x}.tmp", name, std::process::id(), rand::random::<u64>()));
+    
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    
+    if let Err(e) = options.open(&temp_file).and_then(|mut file| file.write_all(content)) {
+        let _ = std::fs::remove_file(&temp_file);
+        return Err(format!("Failed to write {}: {}", temp_file.display(), e).into());
+    }
+    if let Err(e) = std::fs::rename(&temp_file, path) {
+        let _ = std::fs::remove_file(&temp_file);
+        return Err(format!("Failed to replace {} {}: {}", what, path.display(), e).into());
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn concurrent_writers() {
+        let dir = std::env::temp_dir().join(format!("rust-radius-store-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("vouchers.json");
+        
+        // Every write lands whole, whichever writer renames last
+        let writers: Vec<_> = (0..8u8).map(|writer| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    replace(&path, &[b'a' + writer; 4096], true, "vouchers").unwrap();
+                }
+            })
+        }).collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(content.len(), 4096);
+        assert!(content.iter().all(|&byte| byte == content[0]));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        
+        // No temporary file is left behind
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        assert!(replace(&dir.join("missing").join("vouchers.json"), b"{}", true, "vouchers").is_err());
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+// units.rs - Durations and data amounts for rust-radius
+//
+// Voucher and quota limits are written the way people say them: 30m, 24h or
+// 7d of access, 500M or 2G of data. The configuration and the CLI share these
+// parsers, so a limit reads the same wherever it is set.
+
+/// Parse a duration such as `90`, `30m`, `24h` or `7d` into seconds
+///
+/// # Errors
+///
+/// Returns an error if the number or unit is invalid
+pub fn parse_duration(value: &str) -> std::result::Result<u64, String> {
+    let trimmed = value.trim();
+    let (number, multiplier) = match trimmed.char_indices().last() {
+        Some((i, 's')) => (&trimmed[..i], 1),
+        Some((i, 'm')) => (&trimmed[..i], 60),
+        Some((i, 'h')) => (&trimmed[..i], 3600),
+        Some((i, 'd')) => (&trimmed[..i], 86400),
+        Some((i, 'w')) => (&trimmed[..i], 7 * 86400),
+        _ => (trimmed, 1),
+    };
+    
+    number.trim().parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| format!("Invalid duration {}, expected e.g. 90s, 30m, 24h or 7d", value))
+}
+
+/// Parse a data amount such as `500M` or `2G` (powers of 1024) into bytes
+///
+/// # Errors
+///
+/// Returns an error if the number or unit is invalid
+pub fn parse_bytes(value: &str) -> std::result::Result<u64, String> {
+    let trimmed = value.trim().trim_end_matches(['B', 'b']);
+    let (number, shift) = match trimmed.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&trimmed[..i], 10),
+        Some((i, 'M' | 'm')) => (&trimmed[..i], 20),
+        Some((i, 'G' | 'g')) => (&trimmed[..i], 30),
+        Some((i, 'T' | 't')) => (&trimmed[..i], 40),
+        _ => (trimmed, 0),
+    };
+    
+    number.trim().parse::<u64>().ok()
+        .and_then(|number| number.checked_mul(1 << shift))
+        .ok_or_else(|| format!("Invalid data amount {}, expected e.g. 500M or 2G", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn durations_and_amounts() {
+        assert_eq!(parse_duration("24h"), Ok(86400));
+        assert_eq!(parse_duration("90"), Ok(90));
+        assert_eq!(parse_duration(" 2w "), Ok(14 * 86400));
+        assert!(parse_duration("1y").is_err());
+        assert!(parse_duration("h").is_err());
+        assert_eq!(parse_bytes("500M"), Ok(500 << 20));
+        assert_eq!(parse_bytes("2GB"), Ok(2 << 30));
+        assert_eq!(parse_bytes("1024"), Ok(1024));
+        assert!(parse_bytes("5P").is_err());
+        assert!(parse_bytes("99999999999T").is_err());
+    }
+}
@@ -0,0 +1,492 @@
+// users.rs - Local users file for rust-radius
+//
+// The users file maps user names either to a bare credential (the original flat
+// format) or to an entry with check items, reply attributes, group membership
+// and an expiry date. JSON, TOML and YAML are accepted, chosen by the file
+// extension.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::password::Credential;
+use crate::protocol::{Attribute, Packet};
+use crate::Result;
+
+/// Serialization format of a users file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsersFormat {
+    /// JSON (`.json` and anything unrecognised)
+    Json,
+    
+    /// TOML (`.toml`)
+    Toml,
+    
+    /// YAML (`.yaml`, `.yml`)
+    Yaml,
+}
+
+impl UsersFormat {
+    /// Pick the format from a file's extension
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => UsersFormat::Toml,
+            Some("yaml") | Some("yml") => UsersFormat::Yaml,
+            _ => UsersFormat::Json,
+        }
+    }
+}
+
+/// A user as written in the users file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UserRecord {
+    /// Bare credential (see `Credential` for the accepted schemes)
+    Password(String),
+    
+    /// Full entry
+    Entry(UserEntry),
+}
+
+impl UserRecord {
+    /// Stored credential string
+    pub fn password(&self) -> &str {
+        match self {
+            UserRecord::Password(password) => password,
+            UserRecord::Entry(entry) => &entry.password,
+        }
+    }
+    
+    /// Replace the stored credential, keeping the rest of the entry
+    pub fn set_password(&mut self, password: String) {
+        match self {
+            UserRecord::Password(current) => *current = password,
+            UserRecord::Entry(entry) => entry.password = password,
+        }
+    }
+}
+
+/// Full users file entry
+///
+/// ```json
+/// "alice": {
+///     "password": "$argon2id$v=19$...",
+///     "groups": ["staff"],
+///     "expires": "2025-12-31",
+///     "check": { "ssid": ["Corp"], "time": "Mon-Fri 07:00-19:00" },
+///     "reply": { "Session-Timeout": 28800, "Tunnel-Private-Group-Id": "20" }
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserEntry {
+    /// Credential (see `Credential` for the accepted schemes)
+    pub password: String,
+    
+    /// Groups the user belongs to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+    
+    /// Expiry as an RFC 3339 timestamp, or a date (valid through the end of that day, UTC)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+    
+    /// Conditions the request must meet
+    #[serde(default, skip_serializing_if = "CheckItems::is_empty")]
+    pub check: CheckItems,
+    
+    /// Attributes added to the Access-Accept, by attribute name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reply: BTreeMap<String, ReplyValue>,
+}
+
+/// Conditions a request must meet for the user to be accepted
+///
+/// Empty lists match anything.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CheckItems {
+    /// Allowed NAS-Identifier values
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nas_identifier: Vec<String>,
+    
+    /// Allowed SSIDs, taken from Called-Station-Id ("AA-BB-CC-DD-EE-FF:SSID")
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssid: Vec<String>,
+    
+    /// Allowed login times in server local time, e.g. "Mon-Fri 08:00-18:00"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+}
+
+impl CheckItems {
+    /// Whether no conditions are set
+    pub fn is_empty(&self) -> bool {
+        self.nas_identifier.is_empty() && self.ssid.is_empty() && self.time.is_none()
+    }
+}
+
+/// Value of a reply attribute
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ReplyValue {
+    /// Integer attribute (Session-Timeout, Tunnel-Type, ...)
+    Integer(i32),
+    
+    /// Text attribute (Filter-Id, Tunnel-Private-Group-Id, ...)
+    Text(String),
+    
+    /// The attribute repeated once per value
+    List(Vec<ReplyValue>),
+}
+
+impl ReplyValue {
+    /// Convert to attributes named `name`
+    pub(crate) fn to_attributes(&self, name: &str, attributes: &mut Vec<Attribute>) {
+        match self {
+            ReplyValue::Integer(value) => attributes.push(Attribute::Integer(name.to_string(), *value)),
+            ReplyValue::Text(value) => attributes.push(Attribute::String(name.to_string(), value.clone())),
+            ReplyValue::List(values) => {
+                for value in values {
+                    value.to_attributes(name, attributes);
+                }
+            },
+        }
+    }
+    
+    /// Convert a JSON value; arrays repeat the attribute, objects and null have no value
+    #[cfg(any(feature = "oauth-auth", feature = "rest-auth"))]
+    pub(crate) fn from_json(value: &serde_json::Value) -> Option<Self> {
+        match value {
+            serde_json::Value::String(text) => Some(ReplyValue::Text(text.clone())),
+            serde_json::Value::Number(number) => match number.as_i64().and_then(|n| i32::try_from(n).ok()) {
+                Some(n) => Some(ReplyValue::Integer(n)),
+                None => Some(ReplyValue::Text(number.to_string())),
+            },
+            serde_json::Value::Bool(flag) => Some(ReplyValue::Text(flag.to_string())),
+            serde_json::Value::Array(values) => Some(ReplyValue::List(values.iter().filter_map(Self::from_json).collect())),
+            _ => None,
+        }
+    }
+}
+
+/// Convert a map of reply values to attributes
+pub fn reply_attributes(reply: &BTreeMap<String, ReplyValue>) -> Vec<Attribute> {
+    let mut attributes = Vec::new();
+    for (name, value) in reply {
+        value.to_attributes(name, &mut attributes);
+    }
+    attributes
+}
+
+/// Days and hours during which a login is allowed
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeWindow {
+    /// Allowed weekdays, Monday first
+    days: [bool; 7],
+    
+    /// Allowed hours; an end before the start spans midnight
+    hours: Option<(NaiveTime, NaiveTime)>,
+}
+
+/// Weekday abbreviations, Monday first
+const WEEKDAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+impl TimeWindow {
+    /// Parse a window such as "Mon-Fri 08:00-18:00", "Sat,Sun", "22:00-06:00" or "Any"
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a day or time cannot be parsed
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut days = None;
+        let mut hours = None;
+        
+        for token in spec.split_whitespace() {
+            if token.contains(':') {
+                let (start, end) = token.split_once('-')
+                    .ok_or_else(|| format!("Invalid time range: {}", token))?;
+                let parse = |time: &str| NaiveTime::parse_from_str(time, "%H:%M")
+                    .map_err(|e| format!("Invalid time {}: {}", time, e));
+                hours = Some((parse(start)?, parse(end)?));
+            } else if token.eq_ignore_ascii_case("any") {
+                days = Some([true; 7]);
+            } else {
+                let mut allowed = [false; 7];
+                for item in token.split(',') {
+                    let (first, last) = item.split_once('-').unwrap_or((item, item));
+                    let (first, last) = (weekday(first)?, weekday(last)?);
+                    
+                    // Ranges may wrap around the week, e.g. Fri-Mon
+                    let mut day = first;
+                    loop {
+                        allowed[day] = true;
+                        if day == last {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                }
+                days = Some(allowed);
+            }
+        }
+        
+        Ok(Self {
+            days: days.unwrap_or([true; 7]),
+            hours,
+        })
+    }
+    
+    /// Whether the window contains a point in time
+    pub fn contains(&self, now: &DateTime<Local>) -> bool {
+        if !self.days[now.weekday().num_days_from_monday() as usize] {
+            return false;
+        }
+        
+        match self.hours {
+            Some((start, end)) if start <= end => now.time() >= start && now.time() < end,
+            Some((start, end)) => now.time() >= start || now.time() < end,
+            None => true,
+        }
+    }
+}
+
+/// Index (Monday = 0) of a weekday abbreviation
+fn weekday(name: &str) -> Result<usize> {
+    let name = name.to_ascii_lowercase();
+    WEEKDAYS.iter()
+        .position(|day| name.starts_with(day))
+        .ok_or_else(|| format!("Invalid weekday: {}", name).into())
+}
+
+/// A user ready for authentication
+#[derive(Debug, Clone)]
+pub struct LocalUser {
+    /// Stored credential
+    pub credential: Credential,
+    
+    /// Groups the user belongs to
+    pub groups: Vec<String>,
+    
+    /// Moment the account stops working
+    pub expires: Option<DateTime<Utc>>,
+    
+    /// Allowed NAS-Identifier values (empty for any)
+    pub nas_identifiers: Vec<String>,
+    
+    /// Allowed SSIDs (empty for any)
+    pub ssids: Vec<String>,
+    
+    /// Allowed login times
+    pub time: Option<TimeWindow>,
+    
+    /// Attributes added to the Access-Accept
+    pub reply: Vec<Attribute>,
+}
+
+impl LocalUser {
+    /// Build a user from its users file record
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the credential, expiry or time window is malformed
+    pub fn from_record(record: &UserRecord) -> Result<Self> {
+        let credential = Credential::parse(record.password())?;
+        
+        let entry = match record {
+            UserRecord::Password(_) => return Ok(Self {
+                credential,
+                groups: Vec::new(),
+                expires: None,
+                nas_identifiers: Vec::new(),
+                ssids: Vec::new(),
+                time: None,
+                reply: Vec::new(),
+            }),
+            UserRecord::Entry(entry) => entry,
+        };
+        
+        let expires = entry.expires.as_deref()
+            .map(parse_expiry)
+            .transpose()?;
+        
+        let time = entry.check.time.as_deref()
+            .map(TimeWindow::parse)
+            .transpose()?;
+        
+        let reply = reply_attributes(&entry.reply);
+
+        Ok(Self {
+            credential,
+            groups: entry.groups.clone(),
+            expires,
+            nas_identifiers: entry.check.nas_identifier.clone(),
+            ssids: entry.check.ssid.clone(),
+            time,
+            reply,
+        })
+    }
+    
+    /// Check the account's expiry and check items against a request
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - Access-Request being authenticated
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    ///
+    /// The reject reason if a condition is not met
+    pub fn check(&self, request: &Packet, now: DateTime<Local>) -> std::result::Result<(), String> {
+        if let Some(expires) = self.expires {
+            if now >= expires {
+                return Err("Account expired".to_string());
+            }
+        }
+        
+        if !self.nas_identifiers.is_empty() {
+            let nas = request.get_text("NAS-Identifier").unwrap_or_default();
+            if !self.nas_identifiers.contains(&nas) {
+                return Err(format!("Login not allowed from NAS {}", nas));
+            }
+        }
+        
+        if !self.ssids.is_empty() {
+            let ssid = request.get_text("Called-Station-Id")
+                .and_then(|station| ssid(&station).map(str::to_string))
+                .unwrap_or_default();
+            if !self.ssids.contains(&ssid) {
+                return Err(format!("Login not allowed on SSID {}", ssid));
+            }
+        }
+        
+        if let Some(window) = &self.time {
+            if !window.contains(&now) {
+                return Err("Login not allowed at this time".to_string());
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+/// Parse an expiry timestamp or date
+pub fn parse_expiry(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|e| format!("Invalid expiry {}: {}", value, e))?;
+    let end_of_day = date.succ_opt()
+        .and_then(|next| next.and_hms_opt(0, 0, 0))
+        .ok_or_else(|| format!("Invalid expiry {}", value))?;
+    
+    Ok(end_of_day.and_utc())
+}
+
+/// SSID from a Called-Station-Id of the form "AA-BB-CC-DD-EE-FF:SSID"
+fn ssid(called_station_id: &str) -> Option<&str> {
+    // The MAC is 17 characters whichever separator the NAS uses
+    match called_station_id.as_bytes().get(17) {
+        Some(b':') => called_station_id.get(18..),
+        _ => None,
+    }
+}
+
+/// Parse the contents of a users file
+///
+/// # Errors
+///
+/// Returns an error if the content is not a valid users file in `format`
+pub fn parse(content: &str, format: UsersFormat) -> Result<BTreeMap<String, UserRecord>> {
+    let records = match format {
+        UsersFormat::Json => serde_json::from_str(content)?,
+        UsersFormat::Toml => toml::from_str(content)?,
+        UsersFormat::Yaml => serde_yaml::from_str(content)?,
+    };
+    
+    Ok(records)
+}
+
+/// Serialize users file records
+///
+/// # Errors
+///
+/// Returns an error if the records cannot be represented in `format`
+pub fn serialize(records: &BTreeMap<String, UserRecord>, format: UsersFormat) -> Result<String> {
+    let content = match format {
+        UsersFormat::Json => serde_json::to_string_pretty(records)?,
+        UsersFormat::Toml => toml::to_string_pretty(records)?,
+        UsersFormat::Yaml => serde_yaml::to_string(records)?,
+    };
+    
+    Ok(content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    
+    #[test]
+    fn rich_entries() {
+        let records = parse(r#"
+            bob = "legacy"
+            
+            [alice]
+            password = "{cleartext}secret"
+            groups = ["staff"]
+            expires = "2030-01-31"
+            reply = { Session-Timeout = 3600, Filter-Id = ["a", "b"] }
+            
+            [alice.check]
+            ssid = ["Corp"]
+            time = "Mon-Fri 08:00-18:00"
+        "#, UsersFormat::Toml).unwrap();
+        
+        assert_eq!(records["bob"], UserRecord::Password("legacy".to_string()));
+        
+        let alice = LocalUser::from_record(&records["alice"]).unwrap();
+        assert_eq!(alice.groups, vec!["staff".to_string()]);
+        assert_eq!(alice.reply, vec![
+            Attribute::String("Filter-Id".to_string(), "a".to_string()),
+            Attribute::String("Filter-Id".to_string(), "b".to_string()),
+            Attribute::Integer("Session-Timeout".to_string(), 3600),
+        ]);
+        
+        let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        request.add_attribute(Attribute::Binary("Called-Station-Id".to_string(), b"00-11-22-33-44-55:Corp".to_vec()));
+        
+        // Wednesday, within hours
+        let wednesday = Local.with_ymd_and_hms(2030, 1, 2, 9, 30, 0).unwrap();
+        assert_eq!(alice.check(&request, wednesday), Ok(()));
+        
+        let saturday = Local.with_ymd_and_hms(2030, 1, 5, 9, 30, 0).unwrap();
+        assert!(alice.check(&request, saturday).is_err());
+        
+        let expired = Local.with_ymd_and_hms(2030, 2, 6, 9, 30, 0).unwrap();
+        assert_eq!(alice.check(&request, expired), Err("Account expired".to_string()));
+        
+        let mut guest = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+        guest.add_attribute(Attribute::Binary("Called-Station-Id".to_string(), b"00:11:22:33:44:55:Guest".to_vec()));
+        assert!(alice.check(&guest, wednesday).is_err());
+        
+        // Round trip through another format
+        let yaml = serialize(&records, UsersFormat::Yaml).unwrap();
+        assert_eq!(parse(&yaml, UsersFormat::Yaml).unwrap(), records);
+    }
+    
+    #[test]
+    fn overnight_window() {
+        let window = TimeWindow::parse("Fri-Mon 22:00-06:00").unwrap();
+        
+        assert!(window.contains(&Local.with_ymd_and_hms(2030, 1, 4, 23, 0, 0).unwrap()));
+        assert!(window.contains(&Local.with_ymd_and_hms(2030, 1, 7, 5, 0, 0).unwrap()));
+        assert!(!window.contains(&Local.with_ymd_and_hms(2030, 1, 7, 12, 0, 0).unwrap()));
+        assert!(!window.contains(&Local.with_ymd_and_hms(2030, 1, 8, 23, 0, 0).unwrap()));
+        assert!(TimeWindow::parse("Someday").is_err());
+    }
+}
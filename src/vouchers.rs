@@ -0,0 +1,438 @@
+// vouchers.rs - Guest access vouchers for rust-radius
+//
+// A voucher is a random code a guest enters as both user name and password.
+// It can be limited to a validity window, to a time budget that starts at
+// first use, to a data budget counted from accounting, and to a number of
+// devices (Calling-Station-Id). Whatever time is left is sent as
+// Session-Timeout. Codes and their usage are kept in a JSON file, created
+// with `rust-radius vouchers generate` and updated by the server.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use subtle::ConstantTimeEq;
+use tokio::sync::Mutex;
+
+use crate::auth::AuthResult;
+use crate::mac::MacAddr;
+use crate::protocol::{Attribute, Packet};
+use crate::store::JsonFile;
+use crate::Result;
+
+/// Characters of generated codes; 0/O and 1/I/L are left out so codes can be read aloud
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+
+/// Acct-Status-Type of Stop and Interim-Update
+const ACCT_STOP: u32 = 2;
+const ACCT_INTERIM_UPDATE: u32 = 3;
+
+/// Options of a voucher backend
+#[derive(Debug, Clone, Deserialize)]
+pub struct VoucherSettings {
+    /// Vouchers file, managed with `rust-radius vouchers` (default: config/vouchers.json)
+    #[serde(default = "default_file")]
+    pub file: PathBuf,
+}
+
+fn default_file() -> PathBuf {
+    PathBuf::from("config/vouchers.json")
+}
+
+/// One voucher and its usage so far
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Voucher {
+    /// Not usable before this time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_from: Option<DateTime<Utc>>,
+    
+    /// Not usable after this time, however much budget is left
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<DateTime<Utc>>,
+    
+    /// Seconds of access from first use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    
+    /// Bytes that can be transferred, in and out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_limit: Option<u64>,
+    
+    /// Devices that can use the voucher; 0 for any number
+    #[serde(default = "default_max_devices")]
+    pub max_devices: u32,
+    
+    /// First use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub activated: Option<DateTime<Utc>>,
+    
+    /// Devices that have used the voucher, by Calling-Station-Id
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub devices: BTreeSet<String>,
+    
+    /// Bytes transferred in each accounting session
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sessions: BTreeMap<String, u64>,
+}
+
+fn default_max_devices() -> u32 {
+    1
+}
+
+impl Default for Voucher {
+    fn default() -> Self {
+        Self {
+            valid_from: None,
+            valid_until: None,
+            duration: None,
+            data_limit: None,
+            max_devices: default_max_devices(),
+            activated: None,
+            devices: BTreeSet::new(),
+            sessions: BTreeMap::new(),
+        }
+    }
+}
+
+impl Voucher {
+    /// Get the bytes transferred so far
+    pub fn data_used(&self) -> u64 {
+        self.sessions.values().fold(0, |total, octets| total.saturating_add(*octets))
+    }
+    
+    /// Get the time the voucher stops working, if activated at `activated`
+    fn expiry(&self, activated: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let used_up = self.duration
+            .and_then(|duration| i64::try_from(duration).ok())
+            .and_then(chrono::Duration::try_seconds)
+            .and_then(|duration| activated.checked_add_signed(duration));
+        
+        match (used_up, self.valid_until) {
+            (Some(used_up), Some(valid_until)) => Some(used_up.min(valid_until)),
+            (used_up, valid_until) => used_up.or(valid_until),
+        }
+    }
+    
+    /// Check whether the voucher can no longer be used, whatever the device
+    pub fn is_spent(&self, now: DateTime<Utc>) -> bool {
+        self.expiry(self.activated.unwrap_or(now)).is_some_and(|expiry| expiry <= now)
+            || self.data_limit.is_some_and(|limit| self.data_used() >= limit)
+    }
+    
+    /// Use the voucher from a device, activating it on first use
+    ///
+    /// # Arguments
+    ///
+    /// * `device` - Calling-Station-Id of the device, if the NAS sent one
+    /// * `now` - Current time
+    ///
+    /// # Returns
+    ///
+    /// The seconds of access left (None if unlimited), or the reason to reject
+    pub fn redeem(&mut self, device: Option<&str>, now: DateTime<Utc>) -> std::result::Result<Option<u64>, String> {
+        if self.valid_from.is_some_and(|valid_from| now < valid_from) {
+            return Err("Voucher is not valid yet".to_string());
+        }
+        if self.is_spent(now) {
+            return Err(match self.data_limit {
+                Some(limit) if self.data_used() >= limit => "Voucher data budget is used up",
+                _ => "Voucher has expired",
+            }.to_string());
+        }
+        
+        if let Some(device) = device {
+            if !self.devices.contains(device) {
+                if self.max_devices != 0 && self.devices.len() >= self.max_devices as usize {
+                    return Err(format!("Voucher is already in use on {} device(s)", self.devices.len()));
+                }
+                self.devices.insert(device.to_string());
+            }
+        }
+        
+        let activated = *self.activated.get_or_insert(now);
+        
+        Ok(self.expiry(activated).map(|expiry| (expiry - now).num_seconds().max(1) as u64))
+    }
+}
+
+/// Normalize a code as entered: case, spaces and dashes do not matter
+pub fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .collect::<String>()
+        .to_ascii_uppercase()
+}
+
+/// Parse the start of a validity window, a date (from midnight UTC) or an RFC 3339 timestamp
+///
+/// # Errors
+///
+/// Returns an error if the value is neither
+pub fn parse_start(value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    
+    chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|midnight| midnight.and_utc())
+        .ok_or_else(|| format!("Invalid start {}, expected a date or RFC 3339 timestamp", value).into())
+}
+
+/// Vouchers by code, as stored in the vouchers file
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Vouchers {
+    /// Voucher of each code
+    pub codes: BTreeMap<String, Voucher>,
+}
+
+impl JsonFile for Vouchers {
+    const WHAT: &'static str = "vouchers";
+}
+
+impl Vouchers {
+    /// Add vouchers with new random codes
+    ///
+    /// # Arguments
+    ///
+    /// * `count` - Number of vouchers
+    /// * `length` - Characters per code
+    /// * `template` - Limits of every new voucher
+    ///
+    /// # Returns
+    ///
+    /// The new codes
+    pub fn generate(&mut self, count: usize, length: usize, template: &Voucher) -> Vec<String> {
+        let mut rng = rand::thread_rng();
+        let mut codes = Vec::with_capacity(count);
+        
+        while codes.len() < count {
+            let code: String = (0..length)
+                .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+                .collect();
+            
+            if !self.codes.contains_key(&code) {
+                self.codes.insert(code.clone(), template.clone());
+                codes.push(code);
+            }
+        }
+        
+        codes
+    }
+}
+
+/// Vouchers file shared by the server and the CLI
+pub struct VoucherStore {
+    /// Vouchers file
+    file: PathBuf,
+    
+    /// Serializes updates of the vouchers file
+    lock: Mutex<()>,
+}
+
+impl VoucherStore {
+    /// Open the vouchers file of a backend
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file exists but cannot be loaded
+    pub fn new(settings: VoucherSettings) -> Result<Self> {
+        Vouchers::load_or_default(&settings.file)?;
+        
+        Ok(Self {
+            file: settings.file,
+            lock: Mutex::new(()),
+        })
+    }
+    
+    /// Get the vouchers file
+    pub fn file(&self) -> &Path {
+        &self.file
+    }
+    
+    /// Redeem the voucher a request logs in with
+    ///
+    /// The file is read on every login, so new codes work at once, and written
+    /// back when a voucher is activated or sees a new device.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vouchers file cannot be read or written
+    pub async fn authenticate(&self, request: &Packet) -> Result<AuthResult> {
+        let code = match request.get_text("User-Name") {
+            Some(username) => normalize(&username),
+            None => return Ok(AuthResult::NotFound),
+        };
+        
+        let _guard = self.lock.lock().await;
+        let mut vouchers = Vouchers::load_or_default(&self.file)?;
+        let voucher = match vouchers.codes.get_mut(&code) {
+            Some(voucher) => voucher,
+            None => return Ok(AuthResult::NotFound),
+        };
+        
+        let password = match request.get_attribute("User-Password") {
+            Some(Attribute::String(_, password)) => normalize(password),
+            _ => return Ok(reject("Vouchers require PAP")),
+        };
+        if !bool::from(password.as_bytes().ct_eq(code.as_bytes())) {
+            return Ok(reject("Invalid voucher"));
+        }
+        
+        let device = request.get_text("Calling-Station-Id").map(|station| match station.parse::<MacAddr>() {
+            Ok(mac) => mac.to_string(),
+            Err(_) => station.trim().to_string(),
+        });
+        
+        let before = voucher.clone();
+        let remaining = match voucher.redeem(device.as_deref(), Utc::now()) {
+            Ok(remaining) => remaining,
+            Err(reason) => return Ok(reject(&reason)),
+        };
+        if *voucher != before {
+            vouchers.save(&self.file)?;
+        }
+        
+        let attributes = remaining.into_iter()
+            .map(|seconds| Attribute::Integer("Session-Timeout".to_string(), seconds.min(i32::MAX as u64) as i32))
+            .collect();
+        
+        Ok(AuthResult::Accept { attributes, groups: vec![] })
+    }
+    
+    /// Count the data of an Interim-Update or Stop against the voucher's budget
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the vouchers file cannot be read or written
+    pub async fn account(&self, request: &Packet) -> Result<()> {
+        if !matches!(request.get_integer("Acct-Status-Type"), Some(ACCT_INTERIM_UPDATE | ACCT_STOP)) {
+            return Ok(());
+        }
+        let (code, session) = match (request.get_text("User-Name"), request.get_text("Acct-Session-Id")) {
+            (Some(username), Some(session)) => (normalize(&username), session),
+            _ => return Ok(()),
+        };
+        
+        // The NAS reports totals since the session started, so each update replaces the last
+        let counter = |octets: &str, gigawords: &str| {
+            (u64::from(request.get_integer(gigawords).unwrap_or(0)) << 32) | u64::from(request.get_integer(octets).unwrap_or(0))
+        };
+        let octets = counter("Acct-Input-Octets", "Acct-Input-Gigawords")
+            .saturating_add(counter("Acct-Output-Octets", "Acct-Output-Gigawords"));
+        
+        let _guard = self.lock.lock().await;
+        let mut vouchers = Vouchers::load_or_default(&self.file)?;
+        if let Some(voucher) = vouchers.codes.get_mut(&code) {
+            if voucher.data_limit.is_some() && voucher.sessions.get(&session) != Some(&octets) {
+                voucher.sessions.insert(session, octets);
+                vouchers.save(&self.file)?;
+            }
+        }
+        
+        Ok(())
+    }
+}
+
+/// Build a Reject without reply attributes
+fn reject(reason: &str) -> AuthResult {
+    AuthResult::Reject {
+        reason: reason.to_string(),
+        attributes: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn at(time: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(time).unwrap().with_timezone(&Utc)
+    }
+    
+    #[test]
+    fn budgets_and_devices() {
+        let mut voucher = Voucher {
+            valid_until: Some(at("2025-06-30T00:00:00Z")),
+            duration: Some(24 * 3600),
+            max_devices: 2,
+            ..Default::default()
+        };
+        
+        // The day starts at first use
+        assert_eq!(voucher.redeem(Some("aa:bb:cc:dd:ee:01"), at("2025-06-01T12:00:00Z")), Ok(Some(86400)));
+        assert_eq!(voucher.activated, Some(at("2025-06-01T12:00:00Z")));
+        assert_eq!(voucher.redeem(Some("aa:bb:cc:dd:ee:02"), at("2025-06-01T18:00:00Z")), Ok(Some(64800)));
+        assert!(voucher.redeem(Some("aa:bb:cc:dd:ee:03"), at("2025-06-01T18:00:00Z")).is_err());
+        assert!(voucher.redeem(Some("aa:bb:cc:dd:ee:01"), at("2025-06-02T12:00:00Z")).is_err());
+        
+        // The validity window caps the time left
+        let mut late = Voucher {
+            valid_until: Some(at("2025-06-30T00:00:00Z")),
+            duration: Some(24 * 3600),
+            ..Default::default()
+        };
+        assert_eq!(late.redeem(None, at("2025-06-29T23:00:00Z")), Ok(Some(3600)));
+        
+        let mut early = Voucher {
+            valid_from: Some(at("2025-06-01T00:00:00Z")),
+            ..Default::default()
+        };
+        assert!(early.redeem(None, at("2025-05-31T23:00:00Z")).is_err());
+        assert_eq!(early.redeem(None, at("2025-06-01T00:00:00Z")), Ok(None));
+    }
+    
+    #[tokio::test]
+    async fn redeem_and_account() {
+        let file = std::env::temp_dir().join(format!("rust-radius-vouchers-{}.json", std::process::id()));
+        let mut vouchers = Vouchers::default();
+        let code = vouchers.generate(1, 10, &Voucher {
+            duration: Some(3600),
+            data_limit: Some(1000),
+            ..Default::default()
+        }).remove(0);
+        assert_eq!(code.len(), 10);
+        vouchers.save(&file).unwrap();
+        
+        let store = VoucherStore::new(VoucherSettings { file: file.clone() }).unwrap();
+        let login = |username: &str, password: &str| {
+            let mut request = Packet::new(Packet::ACCESS_REQUEST, 1, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), username.to_string()));
+            request.add_attribute(Attribute::String("User-Password".to_string(), password.to_string()));
+            request.add_attribute(Attribute::String("Calling-Station-Id".to_string(), "AA-BB-CC-DD-EE-FF".to_string()));
+            request
+        };
+        
+        // Codes are accepted whatever their case and dashes
+        let spaced = format!("{}-{}", &code[..5], code[5..].to_lowercase());
+        assert_eq!(store.authenticate(&login(&spaced, &spaced)).await.unwrap(), AuthResult::Accept {
+            attributes: vec![Attribute::Integer("Session-Timeout".to_string(), 3600)],
+            groups: vec![],
+        });
+        assert!(matches!(store.authenticate(&login(&code, "wrong")).await.unwrap(), AuthResult::Reject { .. }));
+        assert_eq!(store.authenticate(&login("UNKNOWN", "UNKNOWN")).await.unwrap(), AuthResult::NotFound);
+        
+        let voucher = &Vouchers::load(&file).unwrap().codes[&code];
+        assert!(voucher.activated.is_some());
+        assert_eq!(voucher.devices, BTreeSet::from(["aa:bb:cc:dd:ee:ff".to_string()]));
+        
+        // Interim updates replace the session's total; the budget is checked at the next login
+        for (octets, status) in [(600, ACCT_INTERIM_UPDATE), (1200, ACCT_STOP)] {
+            let mut request = Packet::new(Packet::ACCOUNTING_REQUEST, 2, [0u8; 16]);
+            request.add_attribute(Attribute::String("User-Name".to_string(), code.clone()));
+            request.add_attribute(Attribute::String("Acct-Session-Id".to_string(), "s1".to_string()));
+            request.add_attribute(Attribute::Integer("Acct-Status-Type".to_string(), status as i32));
+            request.add_attribute(Attribute::Integer("Acct-Input-Octets".to_string(), octets));
+            store.account(&request).await.unwrap();
+        }
+        assert_eq!(Vouchers::load(&file).unwrap().codes[&code].data_used(), 1200);
+        assert!(matches!(store.authenticate(&login(&code, &code)).await.unwrap(), AuthResult::Reject { .. }));
+        
+        std::fs::remove_file(&file).unwrap();
+    }
+}